@@ -1,6 +1,6 @@
 #![allow(unused)]
 
-use sealfs::rpc::client::{RpcClient, TcpStreamCreator};
+use sealfs::rpc::client::{RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
 use std::{sync::Arc, time::Duration};
 
 pub fn cli(total: u32) {
@@ -24,13 +24,8 @@ pub async fn run_cli_without_data(total: u32) {
     let mut handles = Vec::with_capacity(total as usize);
 
     let server_address = "127.0.0.1:50052";
-    let client: Arc<
-        RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    > = Arc::new(RpcClient::new());
+    let client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>> =
+        Arc::new(RpcClient::new());
     client.add_connection(server_address).await;
 
     for i in 0..total {
@@ -86,13 +81,8 @@ async fn run_cli_with_data_size(total: u32, size: usize) {
     let mut handles = Vec::with_capacity(total as usize);
 
     let server_address = "127.0.0.1:50052";
-    let client: Arc<
-        RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    > = Arc::new(RpcClient::new());
+    let client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>> =
+        Arc::new(RpcClient::new());
     client.add_connection(server_address).await;
     let data = vec![0u8; size];
     for i in 0..total {
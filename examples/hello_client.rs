@@ -10,7 +10,7 @@
 //!     cargo run --example hello_client --features=disk-db
 
 use log::debug;
-use sealfs::rpc::client::{RpcClient, TcpStreamCreator};
+use sealfs::rpc::client::{RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -27,13 +27,8 @@ pub async fn main() {
 }
 
 pub async fn cli(total: u32) -> Duration {
-    let client: Arc<
-        RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    > = Arc::new(RpcClient::default());
+    let client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>> =
+        Arc::new(RpcClient::default());
     let server_address = "127.0.0.1:50051";
     client.add_connection(server_address).await.unwrap();
     // sleep for 1 second to wait for server to start
@@ -5,6 +5,7 @@
 use async_trait::async_trait;
 use sealfs::common::util::{empty_file, path_split};
 use spin::RwLock;
+use std::mem::offset_of;
 use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -14,27 +15,22 @@ use lazy_static::lazy_static;
 use libc::{dirent64, iovec, O_CREAT};
 use log::{error, info};
 use sealfs::common::byte::CHUNK_SIZE;
-use sealfs::common::errors::{status_to_string, CONNECTION_ERROR};
+use sealfs::common::errors::{status_to_string, CONNECTION_ERROR, THROTTLED};
 use sealfs::common::hash_ring::HashRing;
 use sealfs::common::info_syncer::{ClientStatusMonitor, InfoSyncer};
 use sealfs::common::sender::{Sender, REQUEST_TIMEOUT};
 use sealfs::common::serialization::{
     file_attr_as_bytes_mut, tostat, tostatx, ClusterStatus, CreateDirSendMetaData,
-    CreateFileSendMetaData, DeleteDirSendMetaData, DeleteFileSendMetaData, LinuxDirent,
-    OpenFileSendMetaData, OperationType, ReadDirSendMetaData, ReadFileSendMetaData,
-    TruncateFileSendMetaData,
+    CreateFileSendMetaData, DeleteDirSendMetaData, DeleteFileSendMetaData,
+    GetClusterStatusRecvMetaData, LinuxDirent, OpenFileSendMetaData, OperationType,
+    ReadDirSendMetaData, ReadFileSendMetaData, TruncateFileSendMetaData,
 };
-use sealfs::rpc::client::TcpStreamCreator;
-use sealfs::{offset_of, rpc};
+use sealfs::common::varint;
+use sealfs::rpc::client::{TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
+use sealfs::rpc;
 pub struct Client {
     // TODO replace with a thread safe data structure
-    pub client: Arc<
-        rpc::client::RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    >,
+    pub client: Arc<rpc::client::RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>>,
     pub sender: Arc<Sender>,
     pub inodes: DashMap<String, u64>,
     pub inodes_reverse: DashMap<u64, String>,
@@ -55,9 +51,9 @@ impl Default for Client {
 
 #[async_trait]
 impl InfoSyncer for Client {
-    async fn get_cluster_status(&self) -> Result<ClusterStatus, i32> {
+    async fn get_cluster_status_info(&self) -> Result<GetClusterStatusRecvMetaData, i32> {
         self.sender
-            .get_cluster_status(&self.manager_address.lock().await)
+            .get_cluster_status_info(&self.manager_address.lock().await)
             .await
     }
 
@@ -122,6 +118,7 @@ impl Client {
         self.sender
             .init_volume(&self.get_connection_address(volume_name), volume_name)
             .await
+            .map(|_max_write_size| ())
     }
 
     pub async fn init(&'static self) -> Result<(), String> {
@@ -138,20 +135,21 @@ impl Client {
 
         let result = async {
             loop {
-                let result = self.get_cluster_status().await;
+                let result = self.get_cluster_status_info().await;
                 match result {
-                    Ok(status) => match status.try_into().unwrap() {
+                    Ok(info) => match info.status {
                         ClusterStatus::Idle => {
-                            self.cluster_status.store(status.into(), Ordering::Release);
+                            self.cluster_status
+                                .store(info.status.into(), Ordering::Release);
                             return self.get_hash_ring_info().await;
                         }
                         ClusterStatus::Initializing => {
                             info!("cluster is initalling, wait for a while");
-                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            tokio::time::sleep(info.poll_interval()).await;
                         }
                         ClusterStatus::PreFinish => {
                             info!("cluster is initalling, wait for a while");
-                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            tokio::time::sleep(info.poll_interval()).await;
                         }
                         s => {
                             error!("invalid cluster status: {}", s);
@@ -197,6 +195,7 @@ impl Client {
                 umask: 0,
                 mode,
                 name,
+                tmpfile: false,
             })
             .unwrap();
             if self
@@ -387,6 +386,7 @@ impl Client {
         let md = ReadDirSendMetaData {
             offset: dirp_offset as i64,
             size: dirp.len() as u32,
+            cursor: None,
         };
         let send_meta_data = bincode::serialize(&md).unwrap();
 
@@ -396,6 +396,7 @@ impl Client {
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = vec![0u8; 1024];
         let mut recv_data = vec![0u8; dirp.len()];
 
         if let Err(_) = self.handle.block_on(self.client.call_remote(
@@ -409,7 +410,7 @@ impl Client {
             &mut rsp_flags,
             &mut recv_meta_data_length,
             &mut recv_data_length,
-            &mut [],
+            &mut recv_meta_data,
             &mut recv_data,
             REQUEST_TIMEOUT,
         )) {
@@ -429,11 +430,10 @@ impl Client {
             let r#type =
                 u8::from_le_bytes(recv_data[recv_total..recv_total + 1].try_into().unwrap());
 
-            let name_len = u16::from_le_bytes(
-                recv_data[recv_total + 1..recv_total + 3]
-                    .try_into()
-                    .unwrap(),
-            );
+            let (name_len, prefix_len) =
+                varint::decode_len(&recv_data[recv_total + 1..recv_data_length])?;
+            let name_start = recv_total + 1 + prefix_len;
+            let name_len = name_len as u16;
             info!(
                 "type: {}, {}, {}, {}, {}",
                 r#type,
@@ -450,8 +450,7 @@ impl Client {
             dirp.d_reclen = offset_of!(LinuxDirent, d_name) as u16 + name_len + 2;
             unsafe {
                 std::ptr::copy(
-                    recv_data[recv_total + 3..recv_total + 3 + name_len as usize].as_ptr()
-                        as *const i8,
+                    recv_data[name_start..name_start + name_len as usize].as_ptr() as *const i8,
                     dirp.d_name.as_mut_ptr(),
                     name_len as usize,
                 );
@@ -462,7 +461,7 @@ impl Client {
             }
             offset += 1;
             total += dirp.d_reclen as usize;
-            recv_total += (name_len + 3) as usize;
+            recv_total = name_start + name_len as usize;
         }
         info!("getdents_remote {}", pathname);
         Ok((total as isize, offset))
@@ -479,6 +478,7 @@ impl Client {
         let md = ReadDirSendMetaData {
             offset: dirp_offset as i64,
             size: dirp.len() as u32,
+            cursor: None,
         };
         let send_meta_data = bincode::serialize(&md).unwrap();
 
@@ -488,6 +488,7 @@ impl Client {
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = vec![0u8; 1024];
         let mut recv_data = vec![0u8; dirp.len()];
 
         if let Err(_) = self.handle.block_on(self.client.call_remote(
@@ -501,7 +502,7 @@ impl Client {
             &mut rsp_flags,
             &mut recv_meta_data_length,
             &mut recv_data_length,
-            &mut [],
+            &mut recv_meta_data,
             &mut recv_data,
             REQUEST_TIMEOUT,
         )) {
@@ -520,11 +521,10 @@ impl Client {
             let dirp = unsafe { (dirp_ptr as *mut dirent64).as_mut().unwrap() };
             let r#type =
                 u8::from_le_bytes(recv_data[recv_total..recv_total + 1].try_into().unwrap());
-            let name_len = u16::from_le_bytes(
-                recv_data[recv_total + 1..recv_total + 3]
-                    .try_into()
-                    .unwrap(),
-            );
+            let (name_len, prefix_len) =
+                varint::decode_len(&recv_data[recv_total + 1..recv_data_length])?;
+            let name_start = recv_total + 1 + prefix_len;
+            let name_len = name_len as u16;
             if total + offset_of!(dirent64, d_name) + name_len as usize + 1 > dirp_len {
                 break;
             }
@@ -534,8 +534,7 @@ impl Client {
             dirp.d_type = r#type;
             unsafe {
                 std::ptr::copy(
-                    recv_data[recv_total + 3..recv_total + 3 + name_len as usize].as_ptr()
-                        as *const i8,
+                    recv_data[name_start..name_start + name_len as usize].as_ptr() as *const i8,
                     dirp.d_name.as_mut_ptr(),
                     name_len as usize,
                 );
@@ -545,7 +544,7 @@ impl Client {
             }
             offset += 1;
             total += dirp.d_reclen as usize;
-            recv_total += (name_len + 3) as usize;
+            recv_total = name_start + name_len as usize;
         }
         Ok((total as isize, offset))
     }
@@ -679,28 +678,36 @@ impl Client {
                 let send_meta_data = bincode::serialize(&ReadFileSendMetaData {
                     offset: chunk_left,
                     size: chunk_buf.len() as u32,
+                    verify_checksum: false,
                 })
                 .unwrap();
-                if let Err(_) = self
-                    .client
-                    .call_remote(
-                        &server_address,
-                        OperationType::ReadFile.into(),
-                        0,
-                        &pathname,
-                        &send_meta_data,
-                        &[],
-                        &mut status,
-                        &mut rsp_flags,
-                        &mut recv_meta_data_length,
-                        &mut recv_data_length,
-                        &mut [],
-                        chunk_buf,
-                        REQUEST_TIMEOUT,
-                    )
-                    .await
-                {
-                    return Err(libc::EIO);
+                loop {
+                    if let Err(_) = self
+                        .client
+                        .call_remote(
+                            &server_address,
+                            OperationType::ReadFile.into(),
+                            0,
+                            &pathname,
+                            &send_meta_data,
+                            &[],
+                            &mut status,
+                            &mut rsp_flags,
+                            &mut recv_meta_data_length,
+                            &mut recv_data_length,
+                            &mut [],
+                            chunk_buf,
+                            REQUEST_TIMEOUT,
+                        )
+                        .await
+                    {
+                        return Err(libc::EIO);
+                    }
+                    // the flags of a throttled request hold the milliseconds to wait
+                    if status != THROTTLED {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(rsp_flags.max(1) as u64)).await;
                 }
                 if status != 0 {
                     return Err(status);
@@ -743,26 +750,32 @@ impl Client {
                 let mut recv_data_length = 0usize;
 
                 let mut recv_meta_data = [0u8; std::mem::size_of::<isize>()];
-                if let Err(_) = self
-                    .client
-                    .call_remote(
-                        &server_address,
-                        OperationType::WriteFile.into(),
-                        0,
-                        &pathname,
-                        &chunk_left.to_le_bytes(),
-                        chunk_buf,
-                        &mut status,
-                        &mut rsp_flags,
-                        &mut recv_meta_data_length,
-                        &mut recv_data_length,
-                        &mut recv_meta_data,
-                        &mut [],
-                        REQUEST_TIMEOUT,
-                    )
-                    .await
-                {
-                    return Err(libc::EIO);
+                loop {
+                    if let Err(_) = self
+                        .client
+                        .call_remote(
+                            &server_address,
+                            OperationType::WriteFile.into(),
+                            0,
+                            &pathname,
+                            &chunk_left.to_le_bytes(),
+                            chunk_buf,
+                            &mut status,
+                            &mut rsp_flags,
+                            &mut recv_meta_data_length,
+                            &mut recv_data_length,
+                            &mut recv_meta_data,
+                            &mut [],
+                            REQUEST_TIMEOUT,
+                        )
+                        .await
+                    {
+                        return Err(libc::EIO);
+                    }
+                    if status != THROTTLED {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(rsp_flags.max(1) as u64)).await;
                 }
                 if status != 0 {
                     return Err(status);
@@ -4,7 +4,10 @@
 
 use clap::Parser;
 use log::warn;
-use sealfs::{manager::manager_service::ManagerService, rpc::server::RpcServer};
+use sealfs::{
+    manager::manager_service::ManagerService,
+    rpc::{server::RpcServer, tls::TlsOptions},
+};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::Read;
@@ -27,6 +30,12 @@ struct Args {
     all_servers_address: Option<Vec<String>>,
     #[arg(long)]
     virtual_nodes: Option<usize>,
+    #[arg(long)]
+    tls_cert: Option<String>,
+    #[arg(long)]
+    tls_key: Option<String>,
+    #[arg(long)]
+    tls_ca: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,6 +44,12 @@ struct Properties {
     all_servers_address: Vec<String>,
     virtual_nodes: usize,
     log_level: String,
+    #[serde(default)]
+    tls_cert: Option<String>,
+    #[serde(default)]
+    tls_key: Option<String>,
+    #[serde(default)]
+    tls_ca: Option<String>,
 }
 
 #[tokio::main]
@@ -90,18 +105,27 @@ async fn main() -> anyhow::Result<()> {
                 .virtual_nodes
                 .unwrap_or(default_properties.virtual_nodes),
             log_level: args.log_level.unwrap_or(default_properties.log_level),
+            tls_cert: args.tls_cert.or(default_properties.tls_cert),
+            tls_key: args.tls_key.or(default_properties.tls_key),
+            tls_ca: args.tls_ca.or(default_properties.tls_ca),
         },
     };
 
     let address = properties.address;
 
-    let servers_address = properties
-        .all_servers_address
-        .iter()
-        .map(|s| (s.to_string(), properties.virtual_nodes))
-        .collect::<Vec<(String, usize)>>();
-
-    let server = RpcServer::new(Arc::new(ManagerService::new(servers_address)), &address);
+    let tls = TlsOptions {
+        cert: properties.tls_cert,
+        key: properties.tls_key,
+        ca: properties.tls_ca,
+    };
+    let mut server = RpcServer::new(
+        Arc::new(ManagerService::new(
+            properties.all_servers_address,
+            properties.virtual_nodes,
+        )),
+        &address,
+    );
+    server.set_tls(tls.acceptor().map_err(anyhow::Error::msg)?);
     server.run().await?;
     Ok(())
 }
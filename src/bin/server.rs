@@ -4,9 +4,12 @@
 
 use clap::Parser;
 use log::{info, warn};
-use sealfs::server;
+use sealfs::rpc::protocol::MAX_DATA_LENGTH;
+use sealfs::rpc::tls::TlsOptions;
+use sealfs::server::{self, ServerOptions};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::str::FromStr;
 
 const _SERVER_FLAG: u32 = 1;
@@ -30,6 +33,38 @@ struct Args {
     heartbeat: Option<bool>,
     #[arg(long)]
     log_level: Option<String>,
+    #[arg(long)]
+    client_bandwidth_limit: Option<u64>,
+    #[arg(long)]
+    transfer_rate_limit: Option<u64>,
+    #[arg(long)]
+    max_write_size: Option<u32>,
+    #[arg(long)]
+    flush_interval_ms: Option<u64>,
+    #[arg(long)]
+    max_in_flight: Option<usize>,
+    #[arg(long)]
+    dir_shard_threshold: Option<u32>,
+    #[arg(long)]
+    write_combine_size: Option<usize>,
+    #[arg(long)]
+    thorough_check: Option<bool>,
+    #[arg(long)]
+    manager_failure_limit: Option<u32>,
+    #[arg(long)]
+    inline_threshold: Option<usize>,
+    #[arg(long)]
+    slow_op_threshold_ms: Option<u64>,
+    #[arg(long)]
+    metrics_address: Option<String>,
+    #[arg(long)]
+    tls_cert: Option<String>,
+    #[arg(long)]
+    tls_key: Option<String>,
+    #[arg(long)]
+    tls_ca: Option<String>,
+    #[arg(long)]
+    admin_hosts: Option<Vec<IpAddr>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,6 +77,22 @@ struct Properties {
     storage_path: String,
     heartbeat: bool,
     log_level: String,
+    client_bandwidth_limit: u64,
+    transfer_rate_limit: u64,
+    max_write_size: u32,
+    flush_interval_ms: u64,
+    max_in_flight: usize,
+    dir_shard_threshold: u32,
+    write_combine_size: usize,
+    thorough_check: bool,
+    manager_failure_limit: u32,
+    inline_threshold: usize,
+    slow_op_threshold_ms: u64,
+    metrics_address: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_ca: Option<String>,
+    admin_hosts: Vec<IpAddr>,
 }
 
 #[tokio::main]
@@ -58,6 +109,22 @@ async fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
         storage_path: args.storage_path.unwrap(),
         heartbeat: args.heartbeat.unwrap_or(false),
         log_level: args.log_level.unwrap_or("warn".to_owned()),
+        client_bandwidth_limit: args.client_bandwidth_limit.unwrap_or(0),
+        transfer_rate_limit: args.transfer_rate_limit.unwrap_or(0),
+        max_write_size: args.max_write_size.unwrap_or(MAX_DATA_LENGTH as u32),
+        flush_interval_ms: args.flush_interval_ms.unwrap_or(0),
+        max_in_flight: args.max_in_flight.unwrap_or(0),
+        dir_shard_threshold: args.dir_shard_threshold.unwrap_or(0),
+        write_combine_size: args.write_combine_size.unwrap_or(0),
+        thorough_check: args.thorough_check.unwrap_or(false),
+        manager_failure_limit: args.manager_failure_limit.unwrap_or(3),
+        inline_threshold: args.inline_threshold.unwrap_or(0),
+        slow_op_threshold_ms: args.slow_op_threshold_ms.unwrap_or(0),
+        metrics_address: args.metrics_address,
+        tls_cert: args.tls_cert,
+        tls_key: args.tls_key,
+        tls_ca: args.tls_ca,
+        admin_hosts: args.admin_hosts.unwrap_or_default(),
     };
 
     let mut builder = env_logger::Builder::from_default_env();
@@ -81,14 +148,33 @@ async fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
     info!("server_address: {}", server_address.clone());
 
     info!("Start Server");
-    server::run(
-        properties.database_path,
-        properties.storage_path,
+    server::run(ServerOptions {
+        database_path: properties.database_path,
+        storage_path: properties.storage_path,
         server_address,
         manager_address,
-        properties.cache_capacity,
-        properties.write_buffer_size,
-    )
+        cache_capacity: properties.cache_capacity,
+        write_buffer_size: properties.write_buffer_size,
+        client_bandwidth_limit: properties.client_bandwidth_limit,
+        transfer_rate_limit: properties.transfer_rate_limit,
+        max_write_size: properties.max_write_size,
+        flush_interval_ms: properties.flush_interval_ms,
+        max_in_flight: properties.max_in_flight,
+        dir_shard_threshold: properties.dir_shard_threshold,
+        heartbeat: properties.heartbeat,
+        write_combine_size: properties.write_combine_size,
+        thorough_check: properties.thorough_check,
+        manager_failure_limit: properties.manager_failure_limit,
+        inline_threshold: properties.inline_threshold,
+        slow_op_threshold_ms: properties.slow_op_threshold_ms,
+        metrics_address: properties.metrics_address,
+        tls: TlsOptions {
+            cert: properties.tls_cert,
+            key: properties.tls_key,
+            ca: properties.tls_ca,
+        },
+        admin_hosts: properties.admin_hosts,
+    })
     .await?;
     Ok(())
 }
@@ -11,10 +11,16 @@ use async_trait::async_trait;
 use dashmap::DashMap;
 use fuser::{BackgroundSession, MountOption};
 use log::{error, info, warn};
+use parking_lot::Mutex;
 
 use crate::{
     common::{
-        errors::CONNECTION_ERROR, sender::REQUEST_TIMEOUT, serialization::MountVolumeSendMetaData,
+        errors::CONNECTION_ERROR,
+        sender::REQUEST_TIMEOUT,
+        serialization::{
+            InvalidateClientCacheSendMetaData, LazyUnmountSendMetaData, ManagerOperationType,
+            MountVolumeSendMetaData,
+        },
     },
     rpc::{
         client::{RpcClient, UnixStreamCreator},
@@ -27,11 +33,14 @@ const MOUNT: u32 = 1;
 const PROBE: u32 = 2;
 const UMOUNT: u32 = 3;
 const LIST_MOUNTPOINTS: u32 = 4;
+const LAZY_UMOUNT: u32 = 5;
 
 pub struct SealfsFused {
     pub client: Arc<Client>,
     pub mount_points: DashMap<String, (String, bool, BackgroundSession)>,
     pub index_file: String,
+    // sessions detached from the namespace that still serve open handles
+    pub detached: Mutex<Vec<(String, BackgroundSession)>>,
 }
 
 // TODO: remove this
@@ -46,6 +55,7 @@ impl SealfsFused {
             client,
             mount_points: DashMap::new(),
             index_file,
+            detached: Mutex::new(Vec::new()),
         }
     }
 
@@ -65,10 +75,10 @@ impl SealfsFused {
         options.push(MountOption::AllowRoot);
         let result = self.client.init_volume(&volume_name).await;
         match result {
-            Ok(inode) => {
+            Ok((inode, max_write_size)) => {
                 info!("mounting volume {} to {}", volume_name, mountpoint);
                 match fuser::spawn_mount2(
-                    SealFS::new(self.client.clone(), inode),
+                    SealFS::new(self.client.clone(), inode, max_write_size),
                     &mountpoint,
                     &options,
                 ) {
@@ -101,6 +111,59 @@ impl SealfsFused {
         }
     }
 
+    // like `umount -l`: new lookups fail at once, the fuse session keeps
+    // serving open handles until the kernel releases the connection
+    pub fn lazy_unmount(&self, volume_name: &str) -> Result<(), i32> {
+        let mountpoints: Vec<String> = self
+            .mount_points
+            .iter()
+            .filter(|kv| kv.value().0 == volume_name)
+            .map(|kv| kv.key().clone())
+            .collect();
+        if mountpoints.is_empty() {
+            error!("volume {} is not mounted", volume_name);
+            return Err(libc::ENOENT);
+        }
+        for mountpoint in mountpoints {
+            match std::process::Command::new("umount")
+                .arg("-l")
+                .arg(&mountpoint)
+                .output()
+            {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => {
+                    error!(
+                        "lazy umount {} error: {}",
+                        mountpoint,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    return Err(libc::EIO);
+                }
+                Err(e) => {
+                    error!("lazy umount {} error: {}", mountpoint, e);
+                    return Err(libc::EIO);
+                }
+            }
+            if let Some((mountpoint, (_, _, session))) = self.mount_points.remove(&mountpoint) {
+                info!("volume {} detached from {}", volume_name, mountpoint);
+                self.detached.lock().push((mountpoint, session));
+            }
+        }
+        Ok(())
+    }
+
+    // drop the detached sessions whose last handle has been released
+    pub fn reap_detached(&self) {
+        self.detached.lock().retain(|(mountpoint, session)| {
+            if session.guard.is_finished() {
+                info!("detached mountpoint {} released", mountpoint);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     pub fn list_mountpoints(&self) -> Vec<(String, String)> {
         let mut result = Vec::new();
         for k in self.mount_points.iter() {
@@ -182,6 +245,7 @@ impl Handler for SealfsFused {
         _data: Vec<u8>,
         metadata: Vec<u8>,
     ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+        self.reap_detached();
         match operation_type {
             MOUNT => {
                 let send_meta_data: MountVolumeSendMetaData =
@@ -218,6 +282,18 @@ impl Handler for SealfsFused {
                     }
                 }
             }
+            LAZY_UMOUNT => {
+                let send_meta_data: LazyUnmountSendMetaData =
+                    bincode::deserialize(&metadata).unwrap();
+                info!("lazy unmounting volume {}", send_meta_data.volume);
+                match self.lazy_unmount(&send_meta_data.volume) {
+                    Ok(_) => {
+                        self.sync_index_file();
+                        Ok((0, 0, 0, 0, vec![], vec![]))
+                    }
+                    Err(e) => Ok((e, 0, 0, 0, vec![], vec![])),
+                }
+            }
             LIST_MOUNTPOINTS => {
                 let result = self.list_mountpoints();
                 Ok((0, 0, 0, 0, vec![], bincode::serialize(&result).unwrap()))
@@ -226,6 +302,16 @@ impl Handler for SealfsFused {
                 info!("probe");
                 Ok((0, 0, 0, 0, vec![], vec![]))
             }
+            op if op == u32::from(ManagerOperationType::InvalidateClientCache) => {
+                let send_meta_data: InvalidateClientCacheSendMetaData =
+                    bincode::deserialize(&metadata).unwrap();
+                info!(
+                    "invalidate cache of volume {}, ino {:?}",
+                    send_meta_data.volume, send_meta_data.ino
+                );
+                self.client.invalidate_cache(&send_meta_data);
+                Ok((0, 0, 0, 0, vec![], vec![]))
+            }
             _ => {
                 error!("operation_type not found: {}", operation_type);
                 Err(anyhow::anyhow!("operation_type not found"))
@@ -350,6 +436,95 @@ impl LocalCli {
         }
     }
 
+    pub async fn lazy_umount(&self, volume_name: &str) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let send_meta_data = bincode::serialize(&LazyUnmountSendMetaData {
+            volume: volume_name.to_string(),
+        })
+        .unwrap();
+
+        let result = self
+            .client
+            .call_remote(
+                &self.path,
+                LAZY_UMOUNT,
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("lazy umount volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn invalidate_cache(&self, volume_name: &str, ino: Option<u64>) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let send_meta_data = bincode::serialize(&InvalidateClientCacheSendMetaData {
+            volume: volume_name.to_string(),
+            ino,
+        })
+        .unwrap();
+
+        let result = self
+            .client
+            .call_remote(
+                &self.path,
+                ManagerOperationType::InvalidateClientCache.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("invalidate cache failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
     pub async fn list_mountpoints(&self) -> Result<Vec<(String, String)>, i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
@@ -2,50 +2,103 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::common::errors::CONNECTION_ERROR;
+use crate::common::cache::LRUCache;
+use crate::common::dirent::DirentDecoder;
+use crate::common::errors::{CONNECTION_ERROR, MOVED, THROTTLED};
 use crate::common::hash_ring::HashRing;
 use crate::common::info_syncer::{ClientStatusMonitor, InfoSyncer};
 use crate::common::sender::{Sender, REQUEST_TIMEOUT};
 use crate::common::serialization::{
-    file_attr_as_bytes_mut, ClusterStatus, CreateDirSendMetaData, CreateFileSendMetaData,
-    DeleteDirSendMetaData, DeleteFileSendMetaData, OpenFileSendMetaData, OperationType,
-    ReadDirSendMetaData, ReadFileSendMetaData, Volume, WriteFileSendMetaData,
+    file_attr_as_bytes_mut, AppendFileRecvMetaData, AtimePolicy, Capabilities, ClusterStatus,
+    CopyFileRangeSendMetaData, CreateDirSendMetaData, CreateFileSendMetaData,
+    CreateSymlinkSendMetaData, DeleteDirSendMetaData, DeleteFileSendMetaData,
+    FallocateSendMetaData, GetClusterStatusRecvMetaData, GetDrainStatusRecvMetaData,
+    GetRingLayoutRecvData, InvalidateClientCacheSendMetaData, MembershipLogEntry,
+    MovedRecvMetaData, OpenFileSendMetaData, OperationType, ReadDirSendMetaData,
+    ReadFileRecvMetaData, ReadFileSendMetaData, RenameSendMetaData, ServerConfig,
+    SetAttrSendMetaData, StatFsRecvMetaData, Volume, WriteFileSendMetaData, MOVED_ALLOWED,
 };
 use crate::common::util::{empty_dir, empty_file};
 use crate::rpc;
-use crate::rpc::client::TcpStreamCreator;
+use crate::rpc::client::{RetryPolicy, TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
+use crate::rpc::protocol::MAX_DATA_LENGTH;
 use async_trait::async_trait;
 use dashmap::DashMap;
 use fuser::{
-    ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen,
-    ReplyWrite,
+    FileAttr, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen,
+    ReplyStatfs, ReplyWrite,
 };
-use libc::{mode_t, DT_DIR, DT_LNK, DT_REG};
+use libc::{mode_t, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK};
 use log::{debug, error, info};
 use spin::RwLock;
 use std::ffi::{OsStr, OsString};
 use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
 use std::sync::atomic::AtomicI32;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 const TTL: Duration = Duration::from_secs(1); // 1 second
+const DENTRY_CACHE_CAPACITY: usize = 65536;
+const READAHEAD_BLOCKS: u32 = 4;
+// servers a request is sent to by MOVED answers before the first one is left to
+// forward it
+const MAX_MOVES: usize = 3;
+// room for the MovedRecvMetaData of a server address
+const MOVED_METADATA_LENGTH: usize = 256;
+// reads are retried for a few seconds while their server can not be reached,
+// such as while it restarts
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    base_delay: Duration::from_millis(100),
+    max_delay: Duration::from_secs(2),
+    jitter: 0.2,
+};
+
+// the reads of a file fetched ahead of its reader
+#[derive(Default)]
+pub struct Readahead {
+    // where the last read ended
+    next: i64,
+    // in offset order
+    blocks: Vec<ReadaheadBlock>,
+}
+
+#[derive(Clone)]
+struct ReadaheadBlock {
+    offset: i64,
+    size: u32,
+    data: Arc<tokio::sync::OnceCell<Result<Vec<u8>, i32>>>,
+}
 
 pub struct Client {
-    pub client: Arc<
-        rpc::client::RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    >,
+    pub client: Arc<rpc::client::RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>>,
     pub sender: Arc<Sender>,
     pub inodes: DashMap<String, u64>,
     pub inodes_reverse: DashMap<u64, String>,
     pub inode_counter: std::sync::atomic::AtomicU64,
+    // ask the servers for the checksum of every read and check it
+    pub verify_reads: std::sync::atomic::AtomicBool,
+    // attrs fetched by lookup and getattr, by inode, with when they were fetched
+    pub attr_cache: DashMap<u64, (FileAttr, Instant)>,
+    // the inode a name in a directory was looked up as, by the parent's inode and the
+    // name, with when it was looked up
+    pub dentry_cache: LRUCache<(u64, Instant)>,
+    // how long a dentry answers lookups without asking the server, 0 turns it off
+    pub dentry_ttl_ms: std::sync::atomic::AtomicU64,
+    // by inode, dropped when this client changes the file or closes it
+    pub readahead: DashMap<u64, Readahead>,
+    // how many reads the size of its last one a sequential reader is read ahead by,
+    // 0 turns readahead off
+    pub readahead_blocks: std::sync::atomic::AtomicU32,
+    // where the last readdir of a directory stopped, by inode: the offset handed to
+    // the kernel and the name of the last entry, the cursor to continue from
+    pub readdir_cursors: DashMap<u64, (i64, String)>,
     pub fd_counter: std::sync::atomic::AtomicU64,
     pub handle: tokio::runtime::Handle,
     pub cluster_status: AtomicI32,
     pub hash_ring: Arc<RwLock<Option<HashRing>>>,
+    // the newest ring epoch a MOVED answer named, hash_ring was fetched again for it
+    pub ring_epoch: std::sync::atomic::AtomicU64,
     pub new_hash_ring: Arc<RwLock<Option<HashRing>>>,
     pub manager_address: Arc<tokio::sync::Mutex<String>>,
 }
@@ -58,9 +111,9 @@ impl Default for Client {
 
 #[async_trait]
 impl InfoSyncer for Client {
-    async fn get_cluster_status(&self) -> Result<ClusterStatus, i32> {
+    async fn get_cluster_status_info(&self) -> Result<GetClusterStatusRecvMetaData, i32> {
         self.sender
-            .get_cluster_status(&self.manager_address.lock().await)
+            .get_cluster_status_info(&self.manager_address.lock().await)
             .await
     }
 
@@ -97,16 +150,25 @@ impl ClientStatusMonitor for Client {
 impl Client {
     pub fn new() -> Self {
         let client = Arc::new(rpc::client::RpcClient::default());
+        client.set_retry_policy(RETRY_POLICY);
         Self {
             client: client.clone(),
             sender: Arc::new(Sender::new(client)),
             inodes: DashMap::new(),
             inodes_reverse: DashMap::new(),
             inode_counter: std::sync::atomic::AtomicU64::new(1),
+            verify_reads: std::sync::atomic::AtomicBool::new(false),
+            attr_cache: DashMap::new(),
+            dentry_cache: LRUCache::new(DENTRY_CACHE_CAPACITY),
+            dentry_ttl_ms: std::sync::atomic::AtomicU64::new(TTL.as_millis() as u64),
+            readahead: DashMap::new(),
+            readahead_blocks: std::sync::atomic::AtomicU32::new(READAHEAD_BLOCKS),
+            readdir_cursors: DashMap::new(),
             fd_counter: std::sync::atomic::AtomicU64::new(1),
             handle: tokio::runtime::Handle::current(),
             cluster_status: AtomicI32::new(ClusterStatus::Initializing.into()),
             hash_ring: Arc::new(RwLock::new(None)),
+            ring_epoch: std::sync::atomic::AtomicU64::new(0),
             new_hash_ring: Arc::new(RwLock::new(None)),
             manager_address: Arc::new(tokio::sync::Mutex::new("".to_string())),
         }
@@ -131,14 +193,39 @@ impl Client {
             .fetch_add(1, std::sync::atomic::Ordering::AcqRel)
     }
 
-    pub async fn init_volume(&self, volume_name: &str) -> Result<u64, i32> {
+    // returns the root inode and the max write size advertised by the server
+    pub async fn init_volume(&self, volume_name: &str) -> Result<(u64, u32), i32> {
         let inode = self.get_new_inode();
         self.inodes_reverse.insert(inode, volume_name.to_string());
         self.inodes.insert(volume_name.to_string(), inode);
-        self.sender
+        let max_write_size = self
+            .sender
             .init_volume(&self.get_connection_address(volume_name), volume_name)
             .await?;
-        Ok(inode)
+        Ok((inode, max_write_size))
+    }
+
+    // resolves the inode of the parent directory and the name of ino in it,
+    // the root of a volume is its own parent
+    pub async fn get_parent(&self, ino: u64) -> Result<(u64, String), i32> {
+        let path = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.clone(),
+            None => return Err(libc::ENOENT),
+        };
+        let (parent, name) = self
+            .sender
+            .get_parent(&self.get_connection_address(&path), &path)
+            .await?;
+        let parent_ino = match self.inodes.get(&parent) {
+            Some(parent_ino) => *parent_ino,
+            None => {
+                let parent_ino = self.get_new_inode();
+                self.inodes.insert(parent.clone(), parent_ino);
+                self.inodes_reverse.insert(parent_ino, parent);
+                parent_ino
+            }
+        };
+        Ok((parent_ino, name))
     }
 
     pub async fn list_volumes(&self) -> Result<Vec<Volume>, i32> {
@@ -157,14 +244,78 @@ impl Client {
             .await
     }
 
+    pub async fn drain_server(&self, server_address: &str) -> Result<(), i32> {
+        self.sender
+            .drain_server(&self.manager_address.lock().await, server_address)
+            .await
+    }
+
+    pub async fn get_drain_status(
+        &self,
+        server_address: &str,
+    ) -> Result<GetDrainStatusRecvMetaData, i32> {
+        self.sender
+            .get_drain_status(&self.manager_address.lock().await, server_address)
+            .await
+    }
+
+    pub async fn get_server_config(&self, server_address: &str) -> Result<ServerConfig, i32> {
+        if let Err(e) = self.client.add_connection(server_address).await {
+            error!("add connection to {} failed: {}", server_address, e);
+            return Err(CONNECTION_ERROR);
+        }
+        self.sender.get_server_config(server_address).await
+    }
+
+    pub async fn get_capabilities(&self, server_address: &str) -> Result<Capabilities, i32> {
+        if let Err(e) = self.client.add_connection(server_address).await {
+            error!("add connection to {} failed: {}", server_address, e);
+            return Err(CONNECTION_ERROR);
+        }
+        self.sender.get_capabilities(server_address).await
+    }
+
+    pub async fn get_membership_log(&self) -> Result<Vec<MembershipLogEntry>, i32> {
+        self.sender
+            .get_membership_log(&self.manager_address.lock().await)
+            .await
+            .map(|md| md.entries)
+    }
+
+    pub async fn get_ring_layout(&self) -> Result<GetRingLayoutRecvData, i32> {
+        self.sender
+            .get_ring_layout(&self.manager_address.lock().await)
+            .await
+    }
+
     pub fn get_full_path(&self, parent: &str, name: &OsStr) -> String {
         let path = format!("{}/{}", parent, name.to_str().unwrap());
         path
     }
 
-    pub async fn create_volume(&self, name: &str, size: u64) -> Result<(), i32> {
+    pub async fn create_volume(
+        &self,
+        name: &str,
+        size: u64,
+        atime_policy: AtimePolicy,
+        max_file_size: Option<u64>,
+        allowed_ops: Option<Vec<u32>>,
+    ) -> Result<(), i32> {
+        self.sender
+            .create_volume(
+                &self.get_connection_address(name),
+                name,
+                size,
+                atime_policy,
+                max_file_size,
+                allowed_ops,
+            )
+            .await
+    }
+
+    pub async fn resize_volume(&self, name: &str, new_size: u64) -> Result<(), i32> {
         self.sender
-            .create_volume(&self.get_connection_address(name), name, size)
+            .resize_volume(&self.get_connection_address(name), name, new_size)
             .await
     }
 
@@ -180,71 +331,130 @@ impl Client {
             parent,
             name.to_str().unwrap()
         );
-        let path = match self.inodes_reverse.get(&parent) {
-            Some(parent_path) => self.get_full_path(parent_path.deref(), &name),
-            None => {
-                reply.error(libc::ENOENT);
-                info!("lookup_remote error: {:?}", libc::ENOENT);
-                return;
+        match self.lookup(parent, &name).await {
+            Ok(file_attr) => reply.entry(&TTL, &file_attr, 0),
+            Err(e) => {
+                debug!("lookup_remote status: {}", e);
+                reply.error(e);
+            }
+        }
+    }
+
+    // the attr of name in the directory parent. a fresh dentry answers without a request
+    // while the attr of its inode is cached, dropping that attr drops the answer too
+    pub async fn lookup(&self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+        let key = dentry_key(parent, name);
+        let ttl = Duration::from_millis(
+            self.dentry_ttl_ms
+                .load(std::sync::atomic::Ordering::Relaxed),
+        );
+        if let Some((ino, looked_up)) = self.dentry_cache.get(&key).copied() {
+            if looked_up.elapsed() < ttl {
+                if let Some(entry) = self.attr_cache.get(&ino) {
+                    return Ok(entry.0);
+                }
             }
+        }
+        let path = match self.inodes_reverse.get(&parent) {
+            Some(parent_path) => self.get_full_path(parent_path.deref(), name),
+            None => return Err(libc::ENOENT),
         };
-        let server_address = self.get_connection_address(&path);
+        let file_attr = self.get_attr(&path).await?;
+        if !ttl.is_zero() {
+            self.dentry_cache
+                .insert(&key, (file_attr.ino, Instant::now()));
+        }
+        Ok(file_attr)
+    }
+
+    // for the names this client creates, deletes or renames
+    pub fn forget_dentry(&self, parent: u64, name: &OsStr) {
+        self.dentry_cache.remove(&dentry_key(parent, name));
+    }
+
+    // the attr of the path, from the cache while it is fresh and otherwise from its server
+    pub async fn get_attr(&self, path: &str) -> Result<FileAttr, i32> {
+        if let Some(ino) = self.inodes.get(path).map(|ino| *ino) {
+            if let Some(entry) = self.attr_cache.get(&ino) {
+                if entry.1.elapsed() < TTL {
+                    return Ok(entry.0);
+                }
+            }
+        }
+        self.get_attr_version(path).await.map(|(attr, _)| attr)
+    }
+
+    // the attr of the path from its server, with the version of the file that servers
+    // send after the attr
+    pub async fn get_attr_version(&self, path: &str) -> Result<(FileAttr, Option<u64>), i32> {
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut file_attr = Box::new(empty_file());
-        let recv_meta_data = file_attr_as_bytes_mut(&mut file_attr);
+        let attr_length = std::mem::size_of::<FileAttr>();
+        let mut recv_meta_data = vec![0u8; attr_length + 8];
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::GetFileAttr.into(),
-                0,
-                &path,
+                OperationType::GetFileAttr,
+                path,
                 &[],
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                recv_meta_data,
+                &mut recv_meta_data,
                 &mut [],
-                REQUEST_TIMEOUT,
+                true,
             )
             .await;
-        match result {
-            Ok(_) => {
-                debug!("lookup_remote status: {}", status);
-                if status != 0 {
-                    reply.error(status);
-                    return;
-                }
-                debug!(
-                    "lookup_remote recv_meta_data: {:?}",
-                    &recv_meta_data[..recv_meta_data_length]
-                );
-                // let mut file_attr: FileAttr = {
-                //     let file_attr_simple: FileAttrSimple =
-                //     FileAttrSimple::from_bytes(&recv_meta_data[..recv_meta_data_length]).unwrap();
-                //     file_attr_simple.into()
-                // };
-
-                if self.inodes.contains_key(&path) {
-                    file_attr.ino = *self.inodes.get(&path).unwrap().value();
-                } else {
-                    file_attr.ino = self.get_new_inode();
-                    self.inodes.insert(path.clone(), file_attr.ino);
-                    self.inodes_reverse.insert(file_attr.ino, path.clone());
-                }
+        if let Err(e) = result {
+            error!("get attr of {} failed: {}", path, e);
+            return Err(libc::EIO);
+        }
+        if status != 0 {
+            return Err(status);
+        }
+        let mut file_attr = Box::new(empty_file());
+        file_attr_as_bytes_mut(&mut file_attr).copy_from_slice(&recv_meta_data[..attr_length]);
+        let version = recv_meta_data
+            .get(attr_length..recv_meta_data_length)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes);
+        debug!(
+            "get_attr file_attr: {:?}, version: {:?}",
+            file_attr, version
+        );
+        if self.inodes.contains_key(path) {
+            file_attr.ino = *self.inodes.get(path).unwrap().value();
+        } else {
+            file_attr.ino = self.get_new_inode();
+            self.inodes.insert(path.to_owned(), file_attr.ino);
+            self.inodes_reverse.insert(file_attr.ino, path.to_owned());
+        }
+        self.attr_cache
+            .insert(file_attr.ino, (*file_attr, Instant::now()));
+        Ok((*file_attr, version))
+    }
 
-                reply.entry(&TTL, &file_attr, 0);
+    // drops cached attrs and data read ahead, of one inode or of every inode in the
+    // volume, so that the next access fetches them from the servers
+    pub fn invalidate_cache(&self, md: &InvalidateClientCacheSendMetaData) {
+        match md.ino {
+            Some(ino) => {
+                self.attr_cache.remove(&ino);
+                self.readahead.remove(&ino);
             }
-            Err(_) => {
-                reply.error(libc::EIO);
+            None => {
+                let prefix = format!("{}/", md.volume);
+                let in_volume = |ino: &u64| {
+                    self.inodes_reverse
+                        .get(ino)
+                        .is_some_and(|path| path.as_str() == md.volume || path.starts_with(&prefix))
+                };
+                self.attr_cache.retain(|ino, _| !in_volume(ino));
+                self.readahead.retain(|ino, _| !in_volume(ino));
             }
         }
     }
@@ -267,9 +477,7 @@ impl Client {
                 return;
             }
         };
-        let server_address = self.get_connection_address(&path);
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
@@ -282,25 +490,22 @@ impl Client {
             umask,
             flags,
             name: name.to_str().unwrap().to_owned(),
+            tmpfile: false,
         })
         .unwrap();
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::CreateFile.into(),
-                0,
+                OperationType::CreateFile,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 recv_meta_data,
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
@@ -320,6 +525,7 @@ impl Client {
                 // };
 
                 file_attr.ino = self.get_new_inode();
+                self.forget_dentry(parent, &name);
 
                 let path = self.get_full_path(&path, &name);
                 self.inodes.insert(path.clone(), file_attr.ino);
@@ -344,9 +550,32 @@ impl Client {
             }
         };
         info!("getattr_remote path: {:?}", path);
-        let server_address = self.get_connection_address(&path);
+        match self.get_attr(&path).await {
+            Ok(file_attr) => {
+                reply.attr(&TTL, &file_attr);
+                debug!("getattr_remote success");
+            }
+            Err(e) => {
+                debug!("getattr_remote error");
+                reply.error(e);
+            }
+        }
+    }
+
+    pub async fn setattr_remote(&self, ino: u64, md: SetAttrSendMetaData, reply: ReplyAttr) {
+        info!("setattr_remote");
+        let path = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                info!("setattr_remote error");
+                return;
+            }
+        };
+        self.readahead.remove(&ino);
+        let send_meta_data = bincode::serialize(&md).unwrap();
+
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
@@ -355,51 +584,31 @@ impl Client {
         let recv_meta_data = file_attr_as_bytes_mut(&mut file_attr);
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::GetFileAttr.into(),
-                0,
+                OperationType::SetAttr,
                 &path,
-                &[],
+                &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 recv_meta_data,
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
-            Ok(_) => {
+            Ok(()) => {
                 if status != 0 {
                     reply.error(status);
                     return;
                 }
-                debug!(
-                    "getattr_remote recv_meta_data: {:?}",
-                    &recv_meta_data[..recv_meta_data_length]
-                );
-                // let mut file_attr: FileAttr = {
-                //     let file_attr_simple: FileAttrSimple =
-                //     FileAttrSimple::from_bytes(&recv_meta_data[..recv_meta_data_length]).unwrap();
-                //     file_attr_simple.into()
-                // };
-                debug!("getattr_remote file_attr: {:?}", file_attr);
-                if self.inodes.contains_key(&path) {
-                    file_attr.ino = *self.inodes.get(&path).unwrap().value();
-                } else {
-                    file_attr.ino = self.get_new_inode();
-                    self.inodes.insert(path.clone(), file_attr.ino);
-                    self.inodes_reverse.insert(file_attr.ino, path.clone());
-                }
+                file_attr.ino = ino;
+                self.attr_cache.insert(ino, (*file_attr, Instant::now()));
                 reply.attr(&TTL, &file_attr);
-                debug!("getattr_remote success");
             }
-            Err(_) => {
-                debug!("getattr_remote error");
+            Err(e) => {
+                debug!("setattr_remote error: {:?}", e);
                 reply.error(libc::EIO);
             }
         }
@@ -417,37 +626,39 @@ impl Client {
         };
         let size = 2048;
 
-        let server_address = self.get_connection_address(&path);
+        // a cursor from where the previous page stopped is not thrown off by entries
+        // added or removed since, any other offset is counted from the start
+        let cursor = self
+            .readdir_cursors
+            .remove_if(&ino, |_, (cursor_offset, _)| *cursor_offset == offset)
+            .map(|(_, (_, cursor))| cursor);
         let md = ReadDirSendMetaData {
             offset,
             size: size as u32,
+            cursor,
         };
         let send_meta_data = bincode::serialize(&md).unwrap();
 
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = vec![0u8; 1024];
         let mut recv_data = vec![0u8; size];
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::ReadDir.into(),
-                0,
+                OperationType::ReadDir,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                &mut [],
+                &mut recv_meta_data,
                 &mut recv_data,
-                REQUEST_TIMEOUT,
+                true,
             )
             .await;
         match result {
@@ -460,31 +671,35 @@ impl Client {
                     "readdir_remote recv_data: {:?}",
                     &recv_data[..recv_data_length]
                 );
-                let mut total = 0;
                 let mut offset = offset;
-                while total < recv_data_length {
-                    let r#type = u8::from_le_bytes(recv_data[total..total + 1].try_into().unwrap());
-                    let name_len =
-                        u16::from_le_bytes(recv_data[total + 1..total + 3].try_into().unwrap());
-                    let name = String::from_utf8(
-                        recv_data[total + 3..total + 3 + name_len as usize]
-                            .try_into()
-                            .unwrap(),
-                    )
-                    .unwrap();
+                let mut last = None;
+                for entry in DirentDecoder::new(&recv_data[..recv_data_length]) {
+                    let (r#type, name) = match entry {
+                        Ok(value) => value,
+                        Err(e) => {
+                            reply.error(e);
+                            return;
+                        }
+                    };
                     let kind = match r#type {
                         DT_REG => fuser::FileType::RegularFile,
                         DT_DIR => fuser::FileType::Directory,
                         DT_LNK => fuser::FileType::Symlink,
+                        DT_FIFO => fuser::FileType::NamedPipe,
+                        DT_CHR => fuser::FileType::CharDevice,
+                        DT_BLK => fuser::FileType::BlockDevice,
+                        DT_SOCK => fuser::FileType::Socket,
                         _ => fuser::FileType::RegularFile,
                     };
                     offset += 1;
-                    let r = reply.add(1, offset, kind, name);
-                    if r {
+                    if reply.add(1, offset, kind, &name) {
+                        offset -= 1;
                         break;
                     }
-
-                    total += 3 + name_len as usize;
+                    last = Some(name);
+                }
+                if let Some(last) = last {
+                    self.readdir_cursors.insert(ino, (offset, last));
                 }
 
                 reply.ok();
@@ -496,66 +711,288 @@ impl Client {
         }
     }
 
-    pub async fn read_remote(&self, ino: u64, offset: i64, size: u32, reply: ReplyData) {
+    pub async fn read_remote(self: &Arc<Self>, ino: u64, offset: i64, size: u32, reply: ReplyData) {
         info!("read_remote");
+        match self.read(ino, offset, size).await {
+            Ok(data) => reply.data(&data),
+            Err(e) => {
+                debug!("read_remote error: {}", e);
+                reply.error(e);
+            }
+        }
+    }
+
+    // a read starting where the last one of the file ended is sequential. the reader is
+    // kept a window of readahead_blocks reads ahead, fetched by one request while it
+    // works through the one before. any other read drops what was fetched ahead
+    pub async fn read(self: &Arc<Self>, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
         let path = match self.inodes_reverse.get(&ino) {
             Some(path) => path.clone(),
-            None => {
-                reply.error(libc::ENOENT);
-                info!("read_remote error");
-                return;
+            None => return Err(libc::ENOENT),
+        };
+        let depth = self
+            .readahead_blocks
+            .load(std::sync::atomic::Ordering::Relaxed);
+        if depth == 0 {
+            return self.read_block(&path, offset, size).await;
+        }
+        let end = offset + size as i64;
+        let (block, prefetch) = {
+            let mut readahead = self.readahead.entry(ino).or_default();
+            let sequential = offset == readahead.next;
+            readahead.next = end;
+            if !sequential {
+                readahead.blocks.clear();
             }
+            readahead
+                .blocks
+                .retain(|block| block.offset + block.size as i64 > offset);
+            let block = readahead
+                .blocks
+                .iter()
+                .find(|block| block.offset <= offset && end <= block.offset + block.size as i64)
+                .cloned();
+            // one request carries the window, so it is no larger than a request may be
+            let window = size.saturating_mul(depth).min(MAX_DATA_LENGTH as u32);
+            let ahead = readahead
+                .blocks
+                .last()
+                .map_or(end, |block| block.offset + block.size as i64);
+            let prefetch = match sequential && ahead - end < window as i64 {
+                true => {
+                    let block = ReadaheadBlock {
+                        offset: ahead,
+                        size: window,
+                        data: Arc::new(tokio::sync::OnceCell::new()),
+                    };
+                    readahead.blocks.push(block.clone());
+                    Some(block)
+                }
+                false => None,
+            };
+            (block, prefetch)
         };
-        let server_address = self.get_connection_address(&path);
+        if let Some(block) = prefetch {
+            let client = self.clone();
+            let path = path.clone();
+            self.handle.spawn(async move {
+                block
+                    .data
+                    .get_or_init(|| client.read_block(&path, block.offset, block.size))
+                    .await;
+            });
+        }
+        // a block that failed to come in is read again on its own
+        if let Some(block) = block {
+            let data = block
+                .data
+                .get_or_init(|| self.read_block(&path, block.offset, block.size))
+                .await;
+            if let Ok(data) = data {
+                let start = ((offset - block.offset) as usize).min(data.len());
+                let end = (start + size as usize).min(data.len());
+                return Ok(data[start..end].to_vec());
+            }
+        }
+        self.read_block(&path, offset, size).await
+    }
 
-        let meta_data = bincode::serialize(&ReadFileSendMetaData { offset, size }).unwrap();
+    async fn read_block(&self, path: &str, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let verify_checksum = self.verify_reads.load(std::sync::atomic::Ordering::Relaxed);
+        let meta_data = bincode::serialize(&ReadFileSendMetaData {
+            offset,
+            size,
+            verify_checksum,
+        })
+        .unwrap();
 
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = vec![0u8; 64];
         let mut recv_data = vec![0u8; size as usize];
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::ReadFile.into(),
-                0,
-                &path,
+                OperationType::ReadFile,
+                path,
                 &meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                &mut [],
+                &mut recv_meta_data,
                 &mut recv_data,
-                REQUEST_TIMEOUT,
+                true,
             )
             .await;
-        match result {
-            Ok(()) => {
-                if status != 0 {
-                    reply.error(status);
-                    return;
+        if let Err(e) = result {
+            debug!("read {} failed: {:?}", path, e);
+            return Err(libc::EIO);
+        }
+        if status != 0 {
+            return Err(status);
+        }
+        recv_data.truncate(recv_data_length);
+        if verify_checksum {
+            let md: ReadFileRecvMetaData =
+                bincode::deserialize(&recv_meta_data[..recv_meta_data_length])
+                    .map_err(|_| libc::EIO)?;
+            if !md.verify(&recv_data) {
+                error!("read checksum mismatch, path: {}", path);
+                return Err(libc::EIO);
+            }
+        }
+        debug!(
+            "read {} success, recv_data_length: {}",
+            path, recv_data_length
+        );
+        Ok(recv_data)
+    }
+
+    // call_remote on the server of path. a request the server throttles is sent
+    // again after the wait in milliseconds it answers with in the flags. one for a
+    // path that moved away from its server is sent again where it went, with the
+    // hash ring fetched again first when the server knows a newer one
+    #[allow(clippy::too_many_arguments)]
+    async fn call_remote(
+        &self,
+        operation_type: OperationType,
+        path: &str,
+        send_meta_data: &[u8],
+        send_data: &[u8],
+        status: &mut i32,
+        recv_meta_data_length: &mut usize,
+        recv_data_length: &mut usize,
+        recv_meta_data: &mut [u8],
+        recv_data: &mut [u8],
+        idempotent: bool,
+    ) -> Result<(), String> {
+        let mut server_address = self.get_connection_address(path);
+        let mut req_flags = MOVED_ALLOWED;
+        let mut visited = vec![server_address.clone()];
+        // a MOVED answer may be larger than the metadata asked for
+        let mut meta_data = vec![0u8; recv_meta_data.len().max(MOVED_METADATA_LENGTH)];
+        loop {
+            let mut rsp_flags = 0u32;
+            match idempotent {
+                true => {
+                    self.client
+                        .call_remote_idempotent(
+                            &server_address,
+                            operation_type.into(),
+                            req_flags,
+                            path,
+                            send_meta_data,
+                            send_data,
+                            status,
+                            &mut rsp_flags,
+                            recv_meta_data_length,
+                            recv_data_length,
+                            &mut meta_data,
+                            recv_data,
+                            REQUEST_TIMEOUT,
+                        )
+                        .await?
+                }
+                false => {
+                    self.client
+                        .call_remote(
+                            &server_address,
+                            operation_type.into(),
+                            req_flags,
+                            path,
+                            send_meta_data,
+                            send_data,
+                            status,
+                            &mut rsp_flags,
+                            recv_meta_data_length,
+                            recv_data_length,
+                            &mut meta_data,
+                            recv_data,
+                            REQUEST_TIMEOUT,
+                        )
+                        .await?
                 }
-                debug!(
-                    "read_remote success recv_data: {:?}",
-                    &recv_data[..recv_data_length]
-                );
-                reply.data(&recv_data);
             }
+            match *status {
+                THROTTLED => {
+                    debug!("{} throttled, retry in {} ms", path, rsp_flags);
+                    tokio::time::sleep(Duration::from_millis(rsp_flags.max(1) as u64)).await;
+                }
+                MOVED if req_flags != 0 => {
+                    let md: MovedRecvMetaData =
+                        bincode::deserialize(&meta_data[..*recv_meta_data_length])
+                            .map_err(|e| format!("decode moved response error: {}", e))?;
+                    debug!("{} moved to {}, epoch {}", path, md.new_server, md.epoch);
+                    self.refresh_hash_ring(md.epoch).await;
+                    // servers that do not agree on where the path is, the first one
+                    // forwards the request the way it does without MOVED_ALLOWED
+                    if visited.len() == MAX_MOVES || visited.contains(&md.new_server) {
+                        server_address = visited[0].clone();
+                        req_flags = 0;
+                        continue;
+                    }
+                    if !self.client.has_connection(&md.new_server) {
+                        self.client.add_connection(&md.new_server).await?;
+                    }
+                    visited.push(md.new_server.clone());
+                    server_address = md.new_server;
+                }
+                _ => {
+                    let length = *recv_meta_data_length;
+                    if length > recv_meta_data.len() {
+                        return Err(format!("response metadata of {} bytes too long", length));
+                    }
+                    recv_meta_data[..length].copy_from_slice(&meta_data[..length]);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // fetches the hash ring from the manager, once for each epoch newer than the
+    // ring in use that a server says a path moved in
+    async fn refresh_hash_ring(&self, epoch: u64) {
+        if self
+            .ring_epoch
+            .fetch_max(epoch, std::sync::atomic::Ordering::Relaxed)
+            >= epoch
+        {
+            return;
+        }
+        let manager_address = self.manager_address.lock().await.clone();
+        let hash_ring = match self.sender.get_hash_ring(&manager_address).await {
+            Ok(hash_ring) => hash_ring,
             Err(e) => {
-                debug!("read_remote error: {:?}", e);
-                reply.error(libc::EIO);
+                error!("refresh hash ring failed: {}", e);
+                return;
+            }
+        };
+        for (server_address, _) in &hash_ring.hash_ring_info {
+            if self.client.has_connection(server_address) {
+                continue;
+            }
+            if let Err(e) = self.client.add_connection(server_address).await {
+                error!("refresh hash ring failed: {}", e);
+                return;
             }
         }
+        self.hash_ring
+            .write()
+            .replace(HashRing::new(hash_ring.hash_ring_info));
     }
 
-    pub async fn write_remote(&self, ino: u64, offset: i64, data: Vec<u8>, reply: ReplyWrite) {
+    pub async fn write_remote(
+        &self,
+        ino: u64,
+        offset: i64,
+        data: Vec<u8>,
+        append: bool,
+        reply: ReplyWrite,
+    ) {
         info!("write_remote");
         let path = match self.inodes_reverse.get(&ino) {
             Some(path) => path.clone(),
@@ -566,38 +1003,53 @@ impl Client {
             }
         };
         info!("write_remote path: {:?}, data_len: {}", path, data.len());
-        let server_address = self.get_connection_address(&path);
-        let send_meta_data = bincode::serialize(&WriteFileSendMetaData { offset }).unwrap();
+        self.attr_cache.remove(&ino);
+        self.readahead.remove(&ino);
+        let send_meta_data = bincode::serialize(&WriteFileSendMetaData {
+            offset,
+            if_mtime: None,
+            checksum: Some(crc32c::crc32c(&data)),
+            append,
+        })
+        .unwrap();
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 4];
+        // an append answers with the offset as well, after the size
+        let mut recv_meta_data = vec![0u8; 12];
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::WriteFile.into(),
-                0,
+                OperationType::WriteFile,
                 &path,
                 &send_meta_data,
                 &data,
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut recv_meta_data,
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
             Ok(()) => {
-                let size: u32 =
-                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                if status != 0 {
+                    reply.error(status);
+                    return;
+                }
+                let recv_meta_data = &recv_meta_data[..recv_meta_data_length];
+                let size: u32 = match append {
+                    true => {
+                        let md: AppendFileRecvMetaData =
+                            bincode::deserialize(recv_meta_data).unwrap();
+                        debug!("write_remote appended at: {}", md.offset);
+                        md.size
+                    }
+                    false => bincode::deserialize(recv_meta_data).unwrap(),
+                };
                 debug!("write_remote success, size: {}", size);
                 reply.written(size);
             }
@@ -608,7 +1060,58 @@ impl Client {
         }
     }
 
-    pub async fn mkdir_remote(&self, parent: u64, name: OsString, _mode: u32, reply: ReplyEntry) {
+    // the server of the destination copies the data, reading the source itself or
+    // from the server holding it, so none of it passes through the client
+    pub async fn copy_file_range_remote(
+        &self,
+        ino_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        offset_out: i64,
+        len: u64,
+        reply: ReplyWrite,
+    ) {
+        info!("copy_file_range_remote");
+        let (src_path, dst_path) = match (
+            self.inodes_reverse.get(&ino_in).map(|path| path.clone()),
+            self.inodes_reverse.get(&ino_out).map(|path| path.clone()),
+        ) {
+            (Some(src_path), Some(dst_path)) => (src_path, dst_path),
+            _ => {
+                reply.error(libc::ENOENT);
+                info!("copy_file_range_remote error");
+                return;
+            }
+        };
+        self.attr_cache.remove(&ino_out);
+        self.readahead.remove(&ino_out);
+        let server_address = self.get_connection_address(&dst_path);
+        let md = CopyFileRangeSendMetaData {
+            src_ino: ino_in,
+            src_path,
+            src_offset: offset_in,
+            dst_ino: ino_out,
+            dst_offset: offset_out,
+            // the reply carries a u32
+            length: len.min(u32::MAX as u64),
+        };
+        match self
+            .sender
+            .copy_file_range(&server_address, &dst_path, &md)
+            .await
+        {
+            Ok(copied) => {
+                debug!("copy_file_range_remote success, copied: {}", copied);
+                reply.written(copied as u32);
+            }
+            Err(e) => {
+                debug!("copy_file_range_remote error: {}", e);
+                reply.error(e);
+            }
+        }
+    }
+
+    pub async fn mkdir_remote(&self, parent: u64, name: OsString, mode: u32, reply: ReplyEntry) {
         info!("mkdir_remote");
         let path = match self.inodes_reverse.get(&parent) {
             Some(parent_path) => parent_path.deref().clone(),
@@ -619,9 +1122,7 @@ impl Client {
             }
         };
         debug!("mkdir_remote ,path: {:?}", &path);
-        let server_address = self.get_connection_address(&path);
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
@@ -629,29 +1130,25 @@ impl Client {
         let mut file_attr = Box::new(empty_dir());
         let recv_meta_data = file_attr_as_bytes_mut(&mut file_attr);
 
-        let mode: mode_t = 0o755;
+        // with the type bits, so that a mode of 0 is not taken for the default
         let send_meta_data = bincode::serialize(&CreateDirSendMetaData {
-            mode,
+            mode: libc::S_IFDIR | mode,
             name: name.to_str().unwrap().to_owned(),
         })
         .unwrap();
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::CreateDir.into(),
-                0,
+                OperationType::CreateDir,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 recv_meta_data,
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
@@ -684,6 +1181,117 @@ impl Client {
         }
     }
 
+    pub async fn symlink_remote(
+        &self,
+        parent: u64,
+        name: OsString,
+        target: String,
+        reply: ReplyEntry,
+    ) {
+        info!("symlink_remote");
+        let path = match self.inodes_reverse.get(&parent) {
+            Some(parent_path) => parent_path.deref().clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                info!("symlink_remote error");
+                return;
+            }
+        };
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut file_attr = Box::new(empty_file());
+        let recv_meta_data = file_attr_as_bytes_mut(&mut file_attr);
+
+        let send_meta_data = bincode::serialize(&CreateSymlinkSendMetaData {
+            name: name.to_str().unwrap().to_owned(),
+            target,
+        })
+        .unwrap();
+
+        let result = self
+            .call_remote(
+                OperationType::CreateSymlink,
+                &path,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                recv_meta_data,
+                &mut [],
+                false,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    reply.error(status);
+                    return;
+                }
+                file_attr.ino = self.get_new_inode();
+
+                let path = self.get_full_path(&path, &name);
+                self.inodes.insert(path.clone(), file_attr.ino);
+                self.inodes_reverse.insert(file_attr.ino, path);
+
+                reply.entry(&TTL, &file_attr, 0);
+            }
+            Err(_) => {
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    pub async fn readlink_remote(&self, ino: u64, reply: ReplyData) {
+        info!("readlink_remote");
+        let path = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                info!("readlink_remote error");
+                return;
+            }
+        };
+
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_data = vec![0u8; libc::PATH_MAX as usize];
+
+        let result = self
+            .call_remote(
+                OperationType::ReadLink,
+                &path,
+                &[],
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut recv_data,
+                false,
+            )
+            .await;
+        match result {
+            Ok(()) => {
+                if status != 0 {
+                    reply.error(status);
+                    return;
+                }
+                reply.data(&recv_data[..recv_data_length]);
+            }
+            Err(e) => {
+                debug!("readlink_remote error: {:?}", e);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
     pub async fn open_remote(&self, ino: u64, flags: i32, reply: ReplyOpen) {
         info!("open_remote");
         if flags & libc::O_CREAT != 0 {
@@ -697,9 +1305,7 @@ impl Client {
                 return;
             }
         };
-        let server_address = self.get_connection_address(&path);
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
@@ -715,21 +1321,17 @@ impl Client {
         let send_meta_data = bincode::serialize(&OpenFileSendMetaData { flags, mode }).unwrap();
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::OpenFile.into(),
-                0,
+                OperationType::OpenFile,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut [],
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
@@ -743,60 +1345,303 @@ impl Client {
         }
     }
 
-    pub async fn unlink_remote(&self, parent: u64, name: OsString, reply: ReplyEmpty) {
-        info!("unlink_remote");
-        let path = match self.inodes_reverse.get(&parent) {
-            Some(parent_path) => parent_path.deref().clone(),
+    pub async fn fsync_remote(&self, ino: u64, reply: ReplyEmpty) {
+        info!("fsync_remote");
+        let path = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.clone(),
             None => {
                 reply.error(libc::ENOENT);
-                info!("unlink_remote error");
+                info!("fsync_remote error");
                 return;
             }
         };
-        let server_address = self.get_connection_address(&path);
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let send_meta_data = bincode::serialize(&DeleteFileSendMetaData {
-            name: name.to_str().unwrap().to_owned(),
+        let result = self
+            .call_remote(
+                OperationType::Fsync,
+                &path,
+                &[],
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                false,
+            )
+            .await;
+        match result {
+            Ok(()) => {
+                if status != 0 {
+                    reply.error(status);
+                } else {
+                    reply.ok();
+                }
+            }
+            Err(e) => {
+                debug!("fsync_remote error: {}", e);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    pub async fn fallocate_remote(
+        &self,
+        ino: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        info!("fallocate_remote");
+        let path = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.clone(),
+            None => {
+                reply.error(libc::ENOENT);
+                info!("fallocate_remote error");
+                return;
+            }
+        };
+        self.attr_cache.remove(&ino);
+        self.readahead.remove(&ino);
+        let send_meta_data = bincode::serialize(&FallocateSendMetaData {
+            offset,
+            length,
+            mode: mode as u32,
         })
         .unwrap();
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::DeleteFile.into(),
-                0,
+                OperationType::Fallocate,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut [],
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
-            Ok(_) => {
-                let path = self.get_full_path(&path, &name);
-                self.inodes_reverse
-                    .remove(self.inodes.get(&path).as_deref().unwrap());
-                self.inodes.remove(&path);
-                reply.ok();
+            Ok(()) => {
+                if status != 0 {
+                    reply.error(status);
+                } else {
+                    reply.ok();
+                }
             }
-            Err(_) => {
+            Err(e) => {
+                debug!("fallocate_remote error: {}", e);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    pub async fn statfs_remote(&self, ino: u64, reply: ReplyStatfs) {
+        info!("statfs_remote");
+        let volume = match self.inodes_reverse.get(&ino) {
+            Some(path) => path.split('/').next().unwrap().to_owned(),
+            None => {
+                reply.error(libc::ENOENT);
+                info!("statfs_remote error");
+                return;
+            }
+        };
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 64];
+
+        let result = self
+            .call_remote(
+                OperationType::StatFs,
+                &volume,
+                &[],
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                false,
+            )
+            .await;
+        match result {
+            Ok(()) => {
+                if status != 0 {
+                    reply.error(status);
+                    return;
+                }
+                let stat: StatFsRecvMetaData =
+                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                let block_size = stat.block_size as u64;
+                let free_blocks = stat.free_bytes / block_size;
+                reply.statfs(
+                    stat.total_bytes / block_size,
+                    free_blocks,
+                    free_blocks,
+                    stat.total_inodes,
+                    stat.free_inodes,
+                    stat.block_size,
+                    255,
+                    stat.block_size,
+                );
+            }
+            Err(e) => {
+                debug!("statfs_remote error: {}", e);
                 reply.error(libc::EIO);
             }
         }
     }
 
+    pub async fn unlink_remote(&self, parent: u64, name: OsString, reply: ReplyEmpty) {
+        info!("unlink_remote");
+        match self.delete_file(parent, &name).await {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                info!("unlink_remote error: {}", e);
+                reply.error(e);
+            }
+        }
+    }
+
+    pub async fn delete_file(&self, parent: u64, name: &OsStr) -> Result<(), i32> {
+        let path = match self.inodes_reverse.get(&parent) {
+            Some(parent_path) => parent_path.deref().clone(),
+            None => return Err(libc::ENOENT),
+        };
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let send_meta_data = bincode::serialize(&DeleteFileSendMetaData {
+            name: name.to_str().unwrap().to_owned(),
+        })
+        .unwrap();
+
+        let result = self
+            .call_remote(
+                OperationType::DeleteFile,
+                &path,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                false,
+            )
+            .await;
+        if let Err(e) = result {
+            error!("delete {} failed: {}", path, e);
+            return Err(libc::EIO);
+        }
+        if status != 0 {
+            return Err(status);
+        }
+        self.forget_dentry(parent, name);
+        let path = self.get_full_path(&path, name);
+        if let Some((_, ino)) = self.inodes.remove(&path) {
+            self.attr_cache.remove(&ino);
+            self.inodes_reverse.remove(&ino);
+        }
+        Ok(())
+    }
+
+    pub async fn rename_remote(
+        &self,
+        parent: u64,
+        name: OsString,
+        new_parent: u64,
+        new_name: OsString,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        info!("rename_remote");
+        let (path, new_parent_path) = match (
+            self.inodes_reverse.get(&parent),
+            self.inodes_reverse.get(&new_parent),
+        ) {
+            (Some(parent_path), Some(new_parent_path)) => (
+                self.get_full_path(parent_path.deref(), &name),
+                new_parent_path.deref().clone(),
+            ),
+            _ => {
+                reply.error(libc::ENOENT);
+                info!("rename_remote error");
+                return;
+            }
+        };
+        let mut status = 0i32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let send_meta_data = bincode::serialize(&RenameSendMetaData {
+            new_parent: new_parent_path.clone(),
+            new_name: new_name.to_str().unwrap().to_owned(),
+            flags,
+        })
+        .unwrap();
+
+        let mut file_attr = Box::new(empty_file());
+        let recv_meta_data = file_attr_as_bytes_mut(&mut file_attr);
+
+        let result = self
+            .call_remote(
+                OperationType::Rename,
+                &path,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                recv_meta_data,
+                &mut [],
+                false,
+            )
+            .await;
+        if let Err(e) = result {
+            error!("rename {} failed: {}", path, e);
+            reply.error(libc::EIO);
+            return;
+        }
+        if status != 0 {
+            reply.error(status);
+            return;
+        }
+
+        // the inode follows the file to its new name, a replaced file's inode is gone
+        self.forget_dentry(parent, &name);
+        self.forget_dentry(new_parent, &new_name);
+        let new_path = self.get_full_path(&new_parent_path, &new_name);
+        if let Some((_, ino)) = self.inodes.remove(&new_path) {
+            self.inodes_reverse.remove(&ino);
+            self.attr_cache.remove(&ino);
+        }
+        if let Some((_, ino)) = self.inodes.remove(&path) {
+            self.attr_cache.remove(&ino);
+            self.inodes_reverse.insert(ino, new_path.clone());
+            self.inodes.insert(new_path, ino);
+        }
+        reply.ok();
+    }
+
     pub async fn rmdir_remote(&self, parent: u64, name: OsString, reply: ReplyEmpty) {
         info!("rmdir_remote");
         let path = match self.inodes_reverse.get(&parent) {
@@ -807,9 +1652,7 @@ impl Client {
                 return;
             }
         };
-        let server_address = self.get_connection_address(&path);
         let mut status = 0i32;
-        let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
@@ -820,25 +1663,22 @@ impl Client {
         .unwrap();
 
         let result = self
-            .client
             .call_remote(
-                &server_address,
-                OperationType::DeleteDir.into(),
-                0,
+                OperationType::DeleteDir,
                 &path,
                 &send_meta_data,
                 &[],
                 &mut status,
-                &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut [],
                 &mut [],
-                REQUEST_TIMEOUT,
+                false,
             )
             .await;
         match result {
             Ok(_) => {
+                self.forget_dentry(parent, &name);
                 let path = self.get_full_path(&path, &name);
                 self.inodes_reverse
                     .remove(self.inodes.get(&path).as_deref().unwrap());
@@ -851,3 +1691,9 @@ impl Client {
         }
     }
 }
+
+fn dentry_key(parent: u64, name: &OsStr) -> Vec<u8> {
+    let mut key = parent.to_le_bytes().to_vec();
+    key.extend_from_slice(name.as_bytes());
+    key
+}
@@ -6,19 +6,26 @@ pub mod fuse_client;
 
 use clap::{Parser, Subcommand};
 use fuser::{
-    Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen,
-    ReplyWrite, Request,
+    Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, ReplyStatfs, ReplyWrite, Request, TimeOrNow,
 };
+use libc::c_int;
 use log::{error, info};
-use std::{ffi::OsStr, str::FromStr, sync::Arc};
+use std::{
+    ffi::OsStr,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     client::daemon::{LocalCli, SealfsFused},
     common::{
         errors::status_to_string,
         info_syncer::{init_network_connections, ClientStatusMonitor, InfoSyncer},
+        serialization::{AtimePolicy, ClusterStatus, SetAttrSendMetaData},
     },
-    rpc::server::RpcServer,
+    rpc::{server::RpcServer, tls::TlsOptions},
 };
 
 use self::fuse_client::Client;
@@ -35,6 +42,18 @@ struct Cli {
     /// Log level
     #[arg(long = "log-level", name = "log-level")]
     log_level: Option<String>,
+
+    /// Certificate shown to the servers, with --tls-key
+    #[arg(long = "tls-cert", name = "tls-cert", global = true)]
+    tls_cert: Option<String>,
+
+    /// Private key of the certificate
+    #[arg(long = "tls-key", name = "tls-key", global = true)]
+    tls_key: Option<String>,
+
+    /// Connect over tls, trusting servers whose certificate this ca signed
+    #[arg(long = "tls-ca", name = "tls-ca", global = true)]
+    tls_ca: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -48,6 +67,18 @@ enum Commands {
         #[arg(required = true, name = "volume-size")]
         volume_size: Option<u64>,
 
+        /// When reads update atime: noatime, relatime or strictatime
+        #[arg(long = "atime-policy", name = "atime-policy")]
+        atime_policy: Option<String>,
+
+        /// Largest size a file of the volume may grow to, unlimited if not set
+        #[arg(long = "max-file-size", name = "max-file-size")]
+        max_file_size: Option<u64>,
+
+        /// Comma separated operation types clients may run to change the volume, all if not set
+        #[arg(long = "allowed-ops", name = "allowed-ops", value_delimiter = ',')]
+        allowed_ops: Option<Vec<u32>>,
+
         /// Address of the manager
         #[arg(short = 'm', long = "manager-address", name = "manager-address")]
         manager_address: Option<String>,
@@ -61,6 +92,19 @@ enum Commands {
         #[arg(short = 'm', long = "manager-address", name = "manager-address")]
         manager_address: Option<String>,
     },
+    ResizeVolume {
+        /// Change the size of a volume, 0 for no limit
+        #[arg(required = true, name = "mount-point")]
+        mount_point: Option<String>,
+
+        /// New size of the volume
+        #[arg(required = true, name = "volume-size")]
+        volume_size: Option<u64>,
+
+        /// Address of the manager
+        #[arg(short = 'm', long = "manager-address", name = "manager-address")]
+        manager_address: Option<String>,
+    },
     Daemon {
         /// Start a daemon that hosts volumes
 
@@ -79,6 +123,18 @@ enum Commands {
         /// clean socket file
         #[arg(long = "clean-socket", name = "clean-socket")]
         clean_socket: bool,
+
+        /// check the checksum of every read against the one the server computed
+        #[arg(long = "verify-reads", name = "verify-reads")]
+        verify_reads: bool,
+
+        /// milliseconds a looked up name is trusted without asking its server, 0 turns the dentry cache off
+        #[arg(long = "dentry-ttl-ms", name = "dentry-ttl-ms")]
+        dentry_ttl_ms: Option<u64>,
+
+        /// blocks a sequential reader is read ahead by, 0 turns readahead off
+        #[arg(long = "readahead-blocks", name = "readahead-blocks")]
+        readahead_blocks: Option<u32>,
     },
     Mount {
         /// Act as a client, and mount FUSE at given path
@@ -109,9 +165,9 @@ enum Commands {
         #[arg(required = true, name = "server-address")]
         server_address: Option<String>,
 
-        /// Weight of the server
+        /// Weight of the server, it gets weight times as many virtual nodes as a server of weight 1
         #[arg(long = "weight", name = "weight")]
-        weight: Option<usize>,
+        weight: Option<u32>,
 
         /// Address of the manager
         #[arg(short = 'm', long = "manager-address", name = "manager-address")]
@@ -126,6 +182,25 @@ enum Commands {
         #[arg(short = 'm', long = "manager-address", name = "manager-address")]
         manager_address: Option<String>,
     },
+    Drain {
+        /// Move the files of a server to the others and take it out of the ring, it is
+        /// deleted once drained
+        #[arg(required = true, name = "server-address")]
+        server_address: Option<String>,
+
+        /// Address of the manager
+        #[arg(short = 'm', long = "manager-address", name = "manager-address")]
+        manager_address: Option<String>,
+    },
+    DrainStatus {
+        /// How far the drain of a server is
+        #[arg(required = true, name = "server-address")]
+        server_address: Option<String>,
+
+        /// Address of the manager
+        #[arg(short = 'm', long = "manager-address", name = "manager-address")]
+        manager_address: Option<String>,
+    },
     ListServers {
         /// List all servers in the cluster
         /// Address of the manager
@@ -138,6 +213,25 @@ enum Commands {
         #[arg(short = 'm', long = "manager-address", name = "manager-address")]
         manager_address: Option<String>,
     },
+    LazyUmount {
+        /// Detach every mount of a volume, open files stay usable until closed
+        #[arg(required = true, name = "volume-name")]
+        volume_name: Option<String>,
+
+        #[arg(long = "socket-path", name = "socket-path")]
+        socket_path: Option<String>,
+    },
+    InvalidateCache {
+        /// Drop what the mounts cache of a volume, or of one of its inodes
+        #[arg(required = true, name = "volume-name")]
+        volume_name: Option<String>,
+
+        #[arg(long = "ino", name = "ino")]
+        ino: Option<u64>,
+
+        #[arg(long = "socket-path", name = "socket-path")]
+        socket_path: Option<String>,
+    },
     ListMountpoints {
         #[arg(long = "socket-path", name = "socket-path")]
         socket_path: Option<String>,
@@ -147,28 +241,66 @@ enum Commands {
         #[arg(short = 'm', long = "manager-address", name = "manager-ddress")]
         manager_address: Option<String>,
     },
+    MembershipLog {
+        /// Servers added to and removed from the cluster, oldest first
+        /// Address of the manager
+        #[arg(short = 'm', long = "manager-address", name = "manager-address")]
+        manager_address: Option<String>,
+    },
+    RingLayout {
+        /// Position and server of every virtual node on the hash ring, in ring order
+        /// Address of the manager
+        #[arg(short = 'm', long = "manager-address", name = "manager-address")]
+        manager_address: Option<String>,
+    },
     Probe {
         #[arg(long = "socket-path", name = "socket-path")]
         socket_path: Option<String>,
         // Probe the local client
     },
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    Show {
+        /// Print the configuration a server is running with
+        #[arg(required = true, name = "server-address")]
+        server_address: Option<String>,
+    },
 }
 
 struct SealFS {
     client: Arc<Client>,
     volume_root_inode: u64,
+    max_write_size: u32,
 }
 
 impl SealFS {
-    fn new(client: Arc<Client>, volume_root_inode: u64) -> Self {
+    fn new(client: Arc<Client>, volume_root_inode: u64, max_write_size: u32) -> Self {
         Self {
             client,
             volume_root_inode,
+            max_write_size,
         }
     }
 }
 
 impl Filesystem for SealFS {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // never let the kernel send a write larger than the server accepts
+        if let Err(max) = config.set_max_write(self.max_write_size) {
+            info!(
+                "server max write size {} is above the kernel limit {}",
+                self.max_write_size, max
+            );
+        }
+        Ok(())
+    }
+
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         info!("lookup, parent = {}, name = {:?}", parent, name);
         let client = self.client.clone();
@@ -224,6 +356,48 @@ impl Filesystem for SealFS {
             .spawn(async move { client.getattr_remote(ino, reply).await });
     }
 
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        info!("setattr, ino = {}", ino);
+        let time = |time: TimeOrNow| match time {
+            TimeOrNow::SpecificTime(time) => time,
+            TimeOrNow::Now => SystemTime::now(),
+        };
+        let md = SetAttrSendMetaData {
+            perm: mode.map(|mode| (mode & 0o7777) as u16),
+            uid,
+            gid,
+            size,
+            atime: atime.map(time),
+            mtime: mtime.map(time),
+        };
+        let client = self.client.clone();
+        let ino = if ino == 1 {
+            self.volume_root_inode
+        } else {
+            ino
+        };
+        self.client
+            .handle
+            .spawn(async move { client.setattr_remote(ino, md, reply).await });
+    }
+
     fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, reply: ReplyDirectory) {
         info!("readdir, ino = {}, offset = {}", ino, offset);
         let client = self.client.clone();
@@ -268,16 +442,20 @@ impl Filesystem for SealFS {
         offset: i64,
         data: &[u8],
         _write_flags: u32,
-        _flags: i32,
+        flags: i32,
         _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
         info!(
-            "write, ino = {}, offset = {}, data_len = {}",
+            "write, ino = {}, offset = {}, data_len = {}, flags = {}",
             ino,
             offset,
-            data.len()
+            data.len(),
+            flags
         );
+        // the kernel's offset is the end of the file as this client last saw it,
+        // the server appends at the end it knows
+        let append = flags & libc::O_APPEND != 0;
         let client = self.client.clone();
         let data = data.to_owned();
         let ino = if ino == 1 {
@@ -287,7 +465,32 @@ impl Filesystem for SealFS {
         };
         self.client.handle.spawn(async move {
             client
-                .write_remote(ino, offset, data.to_owned(), reply)
+                .write_remote(ino, offset, data.to_owned(), append, reply)
+                .await
+        });
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request<'_>,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        info!(
+            "copy_file_range, ino_in = {}, offset_in = {}, ino_out = {}, offset_out = {}, len = {}",
+            ino_in, offset_in, ino_out, offset_out, len
+        );
+        let client = self.client.clone();
+        self.client.handle.spawn(async move {
+            client
+                .copy_file_range_remote(ino_in, offset_in, ino_out, offset_out, len, reply)
                 .await
         });
     }
@@ -331,6 +534,45 @@ impl Filesystem for SealFS {
             .spawn(async move { client.open_remote(ino, flags, reply).await });
     }
 
+    fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        link: &std::path::Path,
+        reply: ReplyEntry,
+    ) {
+        info!(
+            "symlink, parent = {}, name = {:?}, link = {:?}",
+            parent, name, link
+        );
+        let target = match link.to_str() {
+            Some(target) => target.to_owned(),
+            None => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+        let client = self.client.clone();
+        let name = name.to_owned();
+        let parent = if parent == 1 {
+            self.volume_root_inode
+        } else {
+            parent
+        };
+        self.client
+            .handle
+            .spawn(async move { client.symlink_remote(parent, name, target, reply).await });
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        info!("readlink, ino = {}", ino);
+        let client = self.client.clone();
+        self.client
+            .handle
+            .spawn(async move { client.readlink_remote(ino, reply).await });
+    }
+
     fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
         info!("unlink");
         let client = self.client.clone();
@@ -345,6 +587,37 @@ impl Filesystem for SealFS {
             .spawn(async move { client.unlink_remote(parent, name.to_owned(), reply).await });
     }
 
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        info!("rename");
+        let client = self.client.clone();
+        let name = name.to_owned();
+        let newname = newname.to_owned();
+        let parent = if parent == 1 {
+            self.volume_root_inode
+        } else {
+            parent
+        };
+        let newparent = if newparent == 1 {
+            self.volume_root_inode
+        } else {
+            newparent
+        };
+        self.client.handle.spawn(async move {
+            client
+                .rename_remote(parent, name, newparent, newname, flags, reply)
+                .await
+        });
+    }
+
     fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
         info!("rmdir");
         let client = self.client.clone();
@@ -358,6 +631,72 @@ impl Filesystem for SealFS {
             .handle
             .spawn(async move { client.rmdir_remote(parent, name.to_owned(), reply).await });
     }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let ino = if ino == 1 {
+            self.volume_root_inode
+        } else {
+            ino
+        };
+        self.client.readahead.remove(&ino);
+        reply.ok();
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        info!("fsync");
+        let client = self.client.clone();
+        self.client
+            .handle
+            .spawn(async move { client.fsync_remote(ino, reply).await });
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        info!(
+            "fallocate, ino = {}, offset = {}, length = {}, mode = {}",
+            ino, offset, length, mode
+        );
+        let client = self.client.clone();
+        self.client.handle.spawn(async move {
+            client
+                .fallocate_remote(ino, offset, length, mode, reply)
+                .await
+        });
+    }
+
+    // every inode of the mount is in the one volume
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        info!("statfs");
+        let client = self.client.clone();
+        let ino = self.volume_root_inode;
+        self.client
+            .handle
+            .spawn(async move { client.statfs_remote(ino, reply).await });
+    }
 }
 
 pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
@@ -376,14 +715,33 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
     info!("spawn client");
 
     let client = Arc::new(Client::new());
+    let tls = TlsOptions {
+        cert: cli.tls_cert,
+        key: cli.tls_key,
+        ca: cli.tls_ca,
+    };
+    client.client.set_tls(tls.connector()?);
 
     match cli.command {
         Commands::CreateVolume {
             mount_point,
             volume_size,
+            atime_policy,
+            max_file_size,
+            allowed_ops,
             manager_address,
         } => {
             let mountpoint = mount_point.unwrap();
+            let atime_policy = match atime_policy {
+                Some(policy) => match AtimePolicy::from_str(&policy) {
+                    Ok(policy) => policy,
+                    Err(e) => {
+                        error!("{}", e);
+                        return Ok(());
+                    }
+                },
+                None => AtimePolicy::default(),
+            };
 
             let manager_address = match manager_address {
                 Some(address) => address,
@@ -404,7 +762,13 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
 
             info!("create_volume");
             if let Err(status) = client
-                .create_volume(&mountpoint, volume_size.unwrap())
+                .create_volume(
+                    &mountpoint,
+                    volume_size.unwrap(),
+                    atime_policy,
+                    max_file_size,
+                    allowed_ops,
+                )
                 .await
             {
                 error!(
@@ -450,11 +814,52 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
 
             Ok(())
         }
+        Commands::ResizeVolume {
+            mount_point,
+            volume_size,
+            manager_address,
+        } => {
+            let mountpoint = mount_point.unwrap();
+
+            let manager_address = match manager_address {
+                Some(address) => address,
+                None => "127.0.0.1:8081".to_owned(),
+            };
+
+            info!("init client");
+            init_network_connections(manager_address, client.clone()).await;
+
+            info!("connect_servers");
+            if let Err(status) = client.connect_servers().await {
+                error!(
+                    "connect_servers failed, status = {:?}",
+                    status_to_string(status)
+                );
+                return Ok(());
+            }
+
+            info!("resize_volume");
+            if let Err(status) = client
+                .resize_volume(&mountpoint, volume_size.unwrap())
+                .await
+            {
+                error!(
+                    "resize_volume failed, status = {:?}",
+                    status_to_string(status)
+                );
+                return Ok(());
+            }
+
+            Ok(())
+        }
         Commands::Daemon {
             index_file,
             manager_address,
             socket_path,
             clean_socket,
+            verify_reads,
+            dentry_ttl_ms,
+            readahead_blocks,
         } => {
             let index_file = match index_file {
                 Some(file) => file,
@@ -465,6 +870,19 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
                 Some(address) => address,
                 None => "127.0.0.1:8081".to_owned(),
             };
+            client
+                .verify_reads
+                .store(verify_reads, std::sync::atomic::Ordering::Relaxed);
+            if let Some(dentry_ttl_ms) = dentry_ttl_ms {
+                client
+                    .dentry_ttl_ms
+                    .store(dentry_ttl_ms, std::sync::atomic::Ordering::Relaxed);
+            }
+            if let Some(readahead_blocks) = readahead_blocks {
+                client
+                    .readahead_blocks
+                    .store(readahead_blocks, std::sync::atomic::Ordering::Relaxed);
+            }
             info!("init client");
             init_network_connections(manager_address, client.clone()).await;
 
@@ -567,7 +985,7 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
             info!("init client");
             init_network_connections(manager_address, client.clone()).await;
 
-            let new_servers_info = vec![(server_address.unwrap(), weight.unwrap_or(100))];
+            let new_servers_info = vec![(server_address.unwrap(), weight.unwrap_or(1))];
             let result = client.add_new_servers(new_servers_info).await;
 
             match result {
@@ -605,6 +1023,56 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
             };
             Ok(())
         }
+        Commands::Drain {
+            server_address,
+            manager_address,
+        } => {
+            let manager_address = match manager_address {
+                Some(address) => address,
+                None => "127.0.0.1:8081".to_owned(),
+            };
+
+            info!("init client");
+            init_network_connections(manager_address, client.clone()).await;
+
+            match client.drain_server(&server_address.unwrap()).await {
+                Ok(_) => {
+                    info!("drain server started");
+                }
+                Err(e) => {
+                    info!("drain server failed, error = {}", status_to_string(e))
+                }
+            };
+            Ok(())
+        }
+        Commands::DrainStatus {
+            server_address,
+            manager_address,
+        } => {
+            let manager_address = match manager_address {
+                Some(address) => address,
+                None => "127.0.0.1:8081".to_owned(),
+            };
+
+            info!("init client");
+            init_network_connections(manager_address, client.clone()).await;
+
+            match client.get_drain_status(&server_address.unwrap()).await {
+                Ok(status) if status.drained => {
+                    println!("drained, the server can be deleted");
+                }
+                Ok(status) => {
+                    println!(
+                        "{}: {}%, {} bytes remaining",
+                        status.status, status.progress_pct, status.bytes_remaining
+                    );
+                }
+                Err(e) => {
+                    info!("get drain status failed, error = {}", status_to_string(e))
+                }
+            };
+            Ok(())
+        }
         Commands::ListServers { _manager_address } => todo!(),
         Commands::ListVolumes { manager_address } => {
             let manager_address = match manager_address {
@@ -637,6 +1105,53 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
             };
             Ok(())
         }
+        Commands::LazyUmount {
+            volume_name,
+            socket_path,
+        } => {
+            let socket_path = match socket_path {
+                Some(path) => path,
+                None => LOCAL_PATH.to_owned(),
+            };
+            let local_client = LocalCli::new(socket_path.clone());
+
+            if let Err(e) = local_client.add_connection(&socket_path).await {
+                panic!("add connection failed, error = {}", status_to_string(e))
+            }
+
+            let result = local_client.lazy_umount(&volume_name.unwrap()).await;
+            match result {
+                Ok(_) => info!("lazy umount success"),
+                Err(e) => panic!("lazy umount failed, error = {}", status_to_string(e)),
+            };
+
+            Ok(())
+        }
+        Commands::InvalidateCache {
+            volume_name,
+            ino,
+            socket_path,
+        } => {
+            let socket_path = match socket_path {
+                Some(path) => path,
+                None => LOCAL_PATH.to_owned(),
+            };
+            let local_client = LocalCli::new(socket_path.clone());
+
+            if let Err(e) = local_client.add_connection(&socket_path).await {
+                panic!("add connection failed, error = {}", status_to_string(e))
+            }
+
+            let result = local_client
+                .invalidate_cache(&volume_name.unwrap(), ino)
+                .await;
+            match result {
+                Ok(_) => info!("invalidate cache success"),
+                Err(e) => panic!("invalidate cache failed, error = {}", status_to_string(e)),
+            };
+
+            Ok(())
+        }
         Commands::ListMountpoints { socket_path } => {
             let socket_path = match socket_path {
                 Some(path) => path,
@@ -670,11 +1185,17 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
 
             info!("init client");
             init_network_connections(manager_address, client.clone()).await;
-            let result = client.get_cluster_status().await;
+            let result = client.get_cluster_status_info().await;
             match result {
-                Ok(status) => {
+                Ok(info) => {
                     info!("get cluster status success");
-                    println!("{}", status);
+                    println!("{}", info.status);
+                    if info.status != ClusterStatus::Idle {
+                        println!(
+                            "rebalance: {}%, {} bytes remaining",
+                            info.rebalance_progress_pct, info.rebalance_bytes_remaining
+                        );
+                    }
                 }
                 Err(e) => {
                     info!("get cluster status failed, error = {}", status_to_string(e))
@@ -682,6 +1203,58 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
             };
             Ok(())
         }
+        Commands::MembershipLog { manager_address } => {
+            let manager_address = match manager_address {
+                Some(address) => address,
+                None => "127.0.0.1:8081".to_owned(),
+            };
+
+            info!("init client");
+            init_network_connections(manager_address, client.clone()).await;
+            match client.get_membership_log().await {
+                Ok(entries) => {
+                    for entry in entries {
+                        let timestamp = entry
+                            .timestamp
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default();
+                        println!(
+                            "{}.{:03} {:?} {} epoch {}",
+                            timestamp.as_secs(),
+                            timestamp.subsec_millis(),
+                            entry.event,
+                            entry.server,
+                            entry.epoch
+                        );
+                    }
+                }
+                Err(e) => {
+                    info!("get membership log failed, error = {}", status_to_string(e))
+                }
+            };
+            Ok(())
+        }
+        Commands::RingLayout { manager_address } => {
+            let manager_address = match manager_address {
+                Some(address) => address,
+                None => "127.0.0.1:8081".to_owned(),
+            };
+
+            info!("init client");
+            init_network_connections(manager_address, client.clone()).await;
+            match client.get_ring_layout().await {
+                Ok(layout) => {
+                    println!("epoch {}", layout.epoch);
+                    for (position, server) in layout.vnodes {
+                        println!("{:016x} {}", position, server);
+                    }
+                }
+                Err(e) => {
+                    info!("get ring layout failed, error = {}", status_to_string(e))
+                }
+            };
+            Ok(())
+        }
         Commands::Probe { socket_path } => {
             let socket_path = match socket_path {
                 Some(path) => path,
@@ -706,5 +1279,20 @@ pub async fn run_command() -> Result<(), Box<dyn std::error::Error>> {
 
             Ok(())
         }
+        Commands::Config {
+            command: ConfigCommands::Show { server_address },
+        } => {
+            let server_address = server_address.unwrap();
+            match client.get_server_config(&server_address).await {
+                Ok(config) => {
+                    info!("get server config success");
+                    println!("{:#?}", config);
+                }
+                Err(e) => {
+                    info!("get server config failed, error = {}", status_to_string(e))
+                }
+            };
+            Ok(())
+        }
     }
 }
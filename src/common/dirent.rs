@@ -0,0 +1,107 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// streaming decoder for the packed ReadDir format: a type byte, a varint name
+// length and the name, repeated. entries are read one at a time from the reader,
+// only the name being decoded is buffered.
+
+use std::io::{ErrorKind, Read};
+
+use super::varint;
+
+pub struct DirentDecoder<R: Read> {
+    reader: R,
+    name: Vec<u8>,
+}
+
+impl<R: Read> DirentDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            name: Vec::new(),
+        }
+    }
+
+    // returns None at the end of the stream, a stream ending inside an entry is an error
+    pub fn next_entry(&mut self) -> Result<Option<(u8, String)>, i32> {
+        let mut r#type = [0u8; 1];
+        loop {
+            match self.reader.read(&mut r#type) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return Err(libc::EIO),
+            }
+        }
+        let name_len = varint::read_len(&mut self.reader)?;
+        self.name.clear();
+        self.name.resize(name_len, 0);
+        self.reader
+            .read_exact(&mut self.name)
+            .map_err(|_| libc::EINVAL)?;
+        let name = std::str::from_utf8(&self.name).map_err(|_| libc::EINVAL)?;
+        Ok(Some((r#type[0], name.to_owned())))
+    }
+}
+
+impl<R: Read> Iterator for DirentDecoder<R> {
+    type Item = Result<(u8, String), i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::{DT_DIR, DT_REG};
+
+    use super::DirentDecoder;
+    use crate::common::varint;
+
+    fn pack(entries: &[(u8, String)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (r#type, name) in entries {
+            buf.push(*r#type);
+            varint::encode_len(name.len(), &mut buf);
+            buf.extend_from_slice(name.as_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn test_stream_large_directory() {
+        let count = 100_000;
+        let buf = pack(
+            &(0..count)
+                .map(|i| {
+                    let r#type = if i % 2 == 0 { DT_REG } else { DT_DIR };
+                    (r#type, format!("entry_{}", i))
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let mut decoder = DirentDecoder::new(buf.as_slice());
+        let mut n = 0;
+        while let Some((r#type, name)) = decoder.next_entry().unwrap() {
+            assert_eq!(r#type, if n % 2 == 0 { DT_REG } else { DT_DIR });
+            assert_eq!(name, format!("entry_{}", n));
+            n += 1;
+        }
+        assert_eq!(n, count);
+        // only ever held one name, never a copy of the buffer
+        assert!(decoder.name.capacity() < 64);
+    }
+
+    #[test]
+    fn test_truncated_entry() {
+        let buf = pack(&[
+            (DT_REG, "complete".to_string()),
+            (DT_REG, "cut".to_string()),
+        ]);
+        let mut decoder = DirentDecoder::new(&buf[..buf.len() - 1]);
+        assert_eq!(decoder.next(), Some(Ok((DT_REG, "complete".to_string()))));
+        assert_eq!(decoder.next(), Some(Err(libc::EINVAL)));
+    }
+}
@@ -10,6 +10,14 @@ pub const CONNECTION_ERROR: i32 = 10001;
 pub const INVALID_CLUSTER_STATUS: i32 = 10002;
 pub const DATABASE_ERROR: i32 = 10003;
 pub const SERIALIZATION_ERROR: i32 = 10004;
+// retryable, the response flags carry the suggested retry delay in milliseconds
+pub const THROTTLED: i32 = 10005;
+// the path is owned by another server, the response metadata is a MovedRecvMetaData
+pub const MOVED: i32 = 10006;
+// a conditional write found the file modified since the mtime it was made against
+pub const CONFLICT: i32 = 10007;
+// the server lost the manager and rejects what its possibly stale ring cannot vouch for
+pub const MANAGER_UNREACHABLE: i32 = 10008;
 
 pub fn status_to_string(status: i32) -> String {
     match status {
@@ -17,6 +25,10 @@ pub fn status_to_string(status: i32) -> String {
         INVALID_CLUSTER_STATUS => "INVALID_CLUSTER_STATUS".to_string(),
         DATABASE_ERROR => "DATABASE_ERROR".to_string(),
         SERIALIZATION_ERROR => "SERIALIZATION_ERROR".to_string(),
+        THROTTLED => "THROTTLED".to_string(),
+        MOVED => "MOVED".to_string(),
+        CONFLICT => "CONFLICT".to_string(),
+        MANAGER_UNREACHABLE => "MANAGER_UNREACHABLE".to_string(),
         _ => unsafe { CStr::from_ptr(strerror(status)) }
             .to_str()
             .unwrap()
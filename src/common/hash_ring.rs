@@ -2,73 +2,132 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
-
-use conhash::{ConsistentHash, Node};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Clone)]
 pub struct ServerNode {
     pub address: String,
 }
 
-impl Node for ServerNode {
-    fn name(&self) -> String {
-        self.address.clone()
-    }
+type HashFn = fn(&[u8]) -> [u8; 16];
+
+fn md5_hash(input: &[u8]) -> [u8; 16] {
+    md5::compute(input).0
 }
 
-pub struct HashRing {
-    pub ring: ConsistentHash<ServerNode>,
-    pub servers: HashMap<String, usize>,
+// the place of a virtual node on the ring. virtual nodes with equal hashes are
+// ordered by server address and then by index, so every peer building the ring
+// resolves a collision to the same server whatever order the servers were added in
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    hash: [u8; 16],
+    address: String,
+    vnode: usize,
 }
 
-impl Clone for HashRing {
-    fn clone(&self) -> Self {
-        let servers = self.servers.clone();
-        let mut ring = ConsistentHash::<ServerNode>::new();
-        for (server, weight) in servers.iter() {
-            ring.add(
-                &ServerNode {
-                    address: server.clone(),
-                },
-                *weight,
-            );
-        }
-        HashRing { ring, servers }
-    }
+#[derive(Clone)]
+pub struct HashRing {
+    hash_fn: HashFn,
+    ring: BTreeMap<Position, ServerNode>,
+    pub servers: HashMap<String, usize>,
 }
 
 impl HashRing {
     pub fn new(servers: Vec<(String, usize)>) -> Self {
-        let mut ring = ConsistentHash::<ServerNode>::new();
-        let mut servers_map = HashMap::new();
+        Self::with_hash(servers, md5_hash)
+    }
+
+    fn with_hash(servers: Vec<(String, usize)>, hash_fn: HashFn) -> Self {
+        let mut hash_ring = HashRing {
+            hash_fn,
+            ring: BTreeMap::new(),
+            servers: HashMap::new(),
+        };
         for (server, weight) in servers {
-            ring.add(
-                &ServerNode {
-                    address: server.clone(),
-                },
-                weight,
-            );
-            servers_map.insert(server, weight);
-        }
-        HashRing {
-            ring,
-            servers: servers_map,
+            hash_ring.add(ServerNode { address: server }, weight);
         }
+        hash_ring
+    }
+
+    fn positions<'a>(
+        &'a self,
+        address: &'a str,
+        weight: usize,
+    ) -> impl Iterator<Item = Position> + 'a {
+        (0..weight).map(move |vnode| Position {
+            hash: (self.hash_fn)(format!("{}:{}", address, vnode).as_bytes()),
+            address: address.to_owned(),
+            vnode,
+        })
     }
 
     pub fn get(&self, key: &str) -> Option<&ServerNode> {
-        self.ring.get_str(key)
+        let start = Position {
+            hash: (self.hash_fn)(key.as_bytes()),
+            address: String::new(),
+            vnode: 0,
+        };
+        self.ring
+            .range(start..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, server)| server)
+    }
+
+    // the first replicas distinct servers met walking clockwise from the key, the
+    // first of them is the one get returns. fewer when the ring has fewer servers
+    pub fn locate(&self, key: &str, replicas: usize) -> Vec<String> {
+        let start = Position {
+            hash: (self.hash_fn)(key.as_bytes()),
+            address: String::new(),
+            vnode: 0,
+        };
+        let wanted = replicas.min(self.servers.len());
+        let mut servers: Vec<String> = Vec::with_capacity(wanted);
+        for (_, server) in self
+            .ring
+            .range(start.clone()..)
+            .chain(self.ring.range(..start))
+        {
+            if servers.len() == wanted {
+                break;
+            }
+            if !servers.contains(&server.address) {
+                servers.push(server.address.clone());
+            }
+        }
+        servers
     }
 
     pub fn add(&mut self, server: ServerNode, weight: usize) {
-        self.ring.add(&server, weight);
+        self.remove(&server);
+        let positions: Vec<Position> = self.positions(&server.address, weight).collect();
+        for position in positions {
+            self.ring.insert(position, server.clone());
+        }
         self.servers.insert(server.address, weight);
     }
 
     pub fn remove(&mut self, server: &ServerNode) {
-        self.ring.remove(server);
-        self.servers.remove(&server.address);
+        if let Some(weight) = self.servers.remove(&server.address) {
+            let positions: Vec<Position> = self.positions(&server.address, weight).collect();
+            for position in positions {
+                self.ring.remove(&position);
+            }
+        }
+    }
+
+    // (position, server) of every virtual node in ring order. a position is the first
+    // 8 bytes of the hash, so it sorts the same way the ring does
+    pub fn export_layout(&self) -> Vec<(u64, String)> {
+        self.ring
+            .iter()
+            .map(|(position, server)| {
+                let mut prefix = [0u8; 8];
+                prefix.copy_from_slice(&position.hash[..8]);
+                (u64::from_be_bytes(prefix), server.address.clone())
+            })
+            .collect()
     }
 
     pub fn contains(&self, server: &str) -> bool {
@@ -79,3 +138,128 @@ impl HashRing {
         self.servers.keys().cloned().collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{HashRing, ServerNode};
+
+    // every virtual node and key lands on the same spot of the ring
+    fn colliding_hash(_: &[u8]) -> [u8; 16] {
+        [7; 16]
+    }
+
+    #[test]
+    fn test_collision_tie_break() {
+        let servers = [
+            ("127.0.0.1:8087".to_owned(), 3),
+            ("127.0.0.1:8085".to_owned(), 2),
+            ("127.0.0.1:8086".to_owned(), 4),
+        ];
+        let mut rings = vec![
+            HashRing::with_hash(servers.to_vec(), colliding_hash),
+            HashRing::with_hash(servers.iter().rev().cloned().collect(), colliding_hash),
+        ];
+        let mut grown = HashRing::with_hash(vec![servers[1].clone()], colliding_hash);
+        grown.add(
+            ServerNode {
+                address: servers[0].0.clone(),
+            },
+            servers[0].1,
+        );
+        grown.add(
+            ServerNode {
+                address: servers[2].0.clone(),
+            },
+            servers[2].1,
+        );
+        rings.push(grown.clone());
+        rings.push(grown);
+        for ring in rings.iter() {
+            assert_eq!(ring.get("volume/file").unwrap().address, "127.0.0.1:8085");
+        }
+
+        // removing the winner hands the spot to the next address, not to whoever was added last
+        let mut ring = rings.pop().unwrap();
+        ring.remove(&ServerNode {
+            address: "127.0.0.1:8085".to_owned(),
+        });
+        assert_eq!(ring.get("volume/file").unwrap().address, "127.0.0.1:8086");
+    }
+
+    #[test]
+    fn test_locate() {
+        let servers = [
+            ("127.0.0.1:8085".to_owned(), 100),
+            ("127.0.0.1:8086".to_owned(), 100),
+            ("127.0.0.1:8087".to_owned(), 100),
+        ];
+        let ring = HashRing::new(servers.to_vec());
+        let again = HashRing::new(servers.iter().rev().cloned().collect());
+        for i in 0..100 {
+            let key = format!("volume/file_{}", i);
+            let located = ring.locate(&key, 2);
+            assert_eq!(located.len(), 2);
+            assert_ne!(located[0], located[1]);
+            assert_eq!(located[0], ring.get(&key).unwrap().address);
+            assert_eq!(located, again.locate(&key, 2));
+            // asking for more keeps the ones already picked first
+            let all = ring.locate(&key, 5);
+            assert_eq!(all.len(), 3);
+            assert_eq!(all[..2], located[..]);
+            assert!(servers.iter().all(|(server, _)| all.contains(server)));
+        }
+        assert!(ring.locate("volume/file", 0).is_empty());
+        assert!(HashRing::new(vec![]).locate("volume/file", 3).is_empty());
+    }
+
+    #[test]
+    fn test_export_layout() {
+        let servers = [
+            ("127.0.0.1:8085".to_owned(), 100),
+            ("127.0.0.1:8086".to_owned(), 50),
+            ("127.0.0.1:8087".to_owned(), 7),
+        ];
+        let ring = HashRing::new(servers.to_vec());
+        let layout = ring.export_layout();
+        assert_eq!(layout.len(), 157);
+        assert!(layout.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        for (server, weight) in servers.iter() {
+            assert_eq!(
+                layout.iter().filter(|(_, owner)| owner == server).count(),
+                *weight
+            );
+        }
+        // a key belongs to the first virtual node at or after its position
+        for i in 0..100 {
+            let key = format!("volume/file_{}", i);
+            let hash = md5::compute(key.as_bytes()).0;
+            let position = u64::from_be_bytes(hash[..8].try_into().unwrap());
+            let owner = layout
+                .iter()
+                .find(|(vnode, _)| *vnode >= position)
+                .unwrap_or(&layout[0]);
+            assert_eq!(owner.1, ring.get(&key).unwrap().address);
+        }
+        assert!(HashRing::new(vec![]).export_layout().is_empty());
+    }
+
+    #[test]
+    fn test_md5_placement() {
+        let ring = HashRing::new(vec![
+            ("127.0.0.1:8085".to_owned(), 100),
+            ("127.0.0.1:8086".to_owned(), 100),
+        ]);
+        let again = HashRing::new(vec![
+            ("127.0.0.1:8086".to_owned(), 100),
+            ("127.0.0.1:8085".to_owned(), 100),
+        ]);
+        for i in 0..100 {
+            let key = format!("volume/file_{}", i);
+            assert_eq!(
+                ring.get(&key).unwrap().address,
+                again.get(&key).unwrap().address
+            );
+        }
+        assert_eq!(ring.ring.len(), 200);
+    }
+}
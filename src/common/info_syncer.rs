@@ -13,21 +13,28 @@ use tokio::time::sleep;
 
 use crate::common::errors::{self, status_to_string, CONNECTION_ERROR};
 
-use super::{hash_ring::HashRing, sender::Sender, serialization::ClusterStatus};
+use super::{
+    hash_ring::HashRing,
+    sender::Sender,
+    serialization::{ClusterStatus, GetClusterStatusRecvMetaData},
+};
 
 #[async_trait]
 pub trait InfoSyncer {
-    async fn get_cluster_status(&self) -> Result<ClusterStatus, i32>;
+    async fn get_cluster_status_info(&self) -> Result<GetClusterStatusRecvMetaData, i32>;
     fn cluster_status(&self) -> &AtomicI32;
 }
 
+// polls the manager as often as it allows, once a second while it can not be reached
 async fn sync_cluster_infos<I: InfoSyncer>(client: Arc<I>) {
     loop {
+        let mut interval = Duration::from_secs(1);
         {
-            let result = client.get_cluster_status().await;
+            let result = client.get_cluster_status_info().await;
             match result {
-                Ok(status) => {
-                    let status = status.into();
+                Ok(info) => {
+                    interval = info.poll_interval();
+                    let status = info.status.into();
                     if client.cluster_status().load(Ordering::Relaxed) != status {
                         client.cluster_status().store(status, Ordering::Relaxed);
                     }
@@ -37,7 +44,7 @@ async fn sync_cluster_infos<I: InfoSyncer>(client: Arc<I>) {
                 }
             }
         }
-        sleep(Duration::from_secs(1)).await;
+        sleep(interval).await;
     }
 }
 
@@ -112,7 +119,7 @@ pub trait ClientStatusMonitor: InfoSyncer {
         })
     }
 
-    async fn add_new_servers(&self, new_servers_info: Vec<(String, usize)>) -> Result<(), i32> {
+    async fn add_new_servers(&self, new_servers_info: Vec<(String, u32)>) -> Result<(), i32> {
         self.sender()
             .add_new_servers(&self.manager_address().lock().await, new_servers_info)
             .await
@@ -4,9 +4,11 @@
 
 pub mod byte;
 pub mod cache;
+pub mod dirent;
 pub mod errors;
 pub mod hash_ring;
 pub mod info_syncer;
 pub mod sender;
 pub mod serialization;
 pub mod util;
+pub mod varint;
@@ -10,45 +10,42 @@ use log::error;
 
 use crate::{
     common::errors::CONNECTION_ERROR,
-    rpc::client::{RpcClient, TcpStreamCreator},
+    manager::manager_service::{ReRegisterRequest, SendHeartRequest},
+    rpc::{
+        client::{RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf},
+        protocol::MAX_DATA_LENGTH,
+    },
 };
 
 use super::serialization::{
-    AddNodesSendMetaData, ClusterStatus, CreateVolumeSendMetaData, DeleteNodesSendMetaData,
-    GetClusterStatusRecvMetaData, GetHashRingInfoRecvMetaData, ManagerOperationType, OperationType,
-    Volume,
+    AddNodesSendMetaData, AtimePolicy, BatchOp, BatchRecvData, BatchSendData, Capabilities,
+    ChargeVolumeSendMetaData, ClientBandwidthInfo, ClusterStatus, CopyFileRangeSendMetaData,
+    CreateVolumeSendMetaData, DeleteNodesSendMetaData, FileAttrSimple,
+    GetClusterStatusRecvMetaData, GetDrainStatusRecvMetaData, GetHashRingInfoRecvMetaData,
+    GetMembershipLogRecvMetaData, GetParentRecvMetaData, GetRingLayoutRecvData, HealthStatus,
+    InitVolumeRecvMetaData, KillOpSendMetaData, ManagerOperationType, MigrateVolumeSendMetaData,
+    OperationType, ReadDirSendMetaData, ReadDirShardRecvMetaData, ReadFileRecvMetaData,
+    ReadFileSendMetaData, RebalanceVolumeSendMetaData, ResizeVolumeSendMetaData, RunningOpInfo,
+    ServerConfig, SetTransferRateSendMetaData, StatAndChecksumRecvData,
+    StatAndChecksumSendMetaData, Volume, WriteFileSendMetaData,
 };
 
 pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 pub const CONTROLL_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub struct Sender {
-    pub client: Arc<
-        RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    >,
+    pub client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>>,
 }
 
 impl Sender {
-    pub fn new(
-        client: Arc<
-            RpcClient<
-                tokio::net::tcp::OwnedReadHalf,
-                tokio::net::tcp::OwnedWriteHalf,
-                TcpStreamCreator,
-            >,
-        >,
-    ) -> Self {
+    pub fn new(client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>>) -> Self {
         Sender { client }
     }
 
     pub async fn add_new_servers(
         &self,
         manager_address: &str,
-        new_servers_info: Vec<(String, usize)>,
+        new_servers_info: Vec<(String, u32)>,
     ) -> Result<(), i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
@@ -142,23 +139,1105 @@ impl Sender {
     }
 
     pub async fn get_cluster_status(&self, manager_address: &str) -> Result<ClusterStatus, i32> {
+        self.get_cluster_status_info(manager_address)
+            .await
+            .map(|md| md.status)
+    }
+
+    pub async fn get_cluster_status_info(
+        &self,
+        manager_address: &str,
+    ) -> Result<GetClusterStatusRecvMetaData, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 64];
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::GetClusterStatus.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    let cluster_status_meta_data: GetClusterStatusRecvMetaData =
+                        bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                    Ok(cluster_status_meta_data)
+                }
+            }
+            Err(e) => {
+                error!("get cluster status failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn send_heart(
+        &self,
+        manager_address: &str,
+        request: &SendHeartRequest,
+    ) -> Result<(), i32> {
+        let send_meta_data = bincode::serialize(request).unwrap();
+
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::SendHeart.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("send heart failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn reregister(
+        &self,
+        manager_address: &str,
+        request: &ReRegisterRequest,
+    ) -> Result<(), i32> {
+        let send_meta_data = bincode::serialize(request).unwrap();
+
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::ReRegister.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("re-register failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn health_check(&self, address: &str) -> Result<HealthStatus, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 1024];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::HealthCheck.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+                }
+            }
+            Err(e) => {
+                error!("health check failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_server_config(&self, address: &str) -> Result<ServerConfig, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 4096];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::GetServerConfig.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+                }
+            }
+            Err(e) => {
+                error!("get server config failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_capabilities(&self, address: &str) -> Result<Capabilities, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 4096];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::GetCapabilities.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+                }
+            }
+            Err(e) => {
+                error!("get capabilities failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn list_running_ops(&self, address: &str) -> Result<Vec<RunningOpInfo>, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 65536];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::ListRunningOps.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+                }
+            }
+            Err(e) => {
+                error!("list running ops failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    // bytes read and written by each client of the server at address
+    pub async fn get_client_bandwidth(
+        &self,
+        address: &str,
+    ) -> Result<Vec<ClientBandwidthInfo>, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 65536];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::GetClientBandwidth.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+                }
+            }
+            Err(e) => {
+                error!("get client bandwidth failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn kill_op(&self, address: &str, request_id: u64) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data = bincode::serialize(&KillOpSendMetaData { request_id }).unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::KillOp.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("kill op failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    // asks the server at address to move the files of volume it should not hold
+    pub async fn rebalance_volume(&self, address: &str, volume: &str) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data = bincode::serialize(&RebalanceVolumeSendMetaData {
+            volume: volume.to_string(),
+        })
+        .unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::RebalanceVolume.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("rebalance volume failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    // changes how fast the server at address moves files, also the transfer running now
+    pub async fn set_transfer_rate(&self, address: &str, bytes_per_sec: u64) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data =
+            bincode::serialize(&SetTransferRateSendMetaData { bytes_per_sec }).unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::SetTransferRate.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("set transfer rate failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    // asks the server at address to pin the files of volume to target_server and move
+    // the ones it holds there
+    pub async fn migrate_volume(
+        &self,
+        address: &str,
+        volume: &str,
+        target_server: &str,
+    ) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data = bincode::serialize(&MigrateVolumeSendMetaData {
+            volume: volume.to_string(),
+            target_server: target_server.to_string(),
+        })
+        .unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                ManagerOperationType::MigrateVolume.into(),
+                0,
+                "",
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                CONTROLL_REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("migrate volume failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_hash_ring_info(
+        &self,
+        manager_address: &str,
+    ) -> Result<Vec<(String, usize)>, i32> {
+        self.get_hash_ring(manager_address)
+            .await
+            .map(|hash_ring| hash_ring.hash_ring_info)
+    }
+
+    pub async fn get_new_hash_ring_info(
+        &self,
+        manager_address: &str,
+    ) -> Result<Vec<(String, usize)>, i32> {
+        self.get_new_hash_ring(manager_address)
+            .await
+            .map(|hash_ring| hash_ring.hash_ring_info)
+    }
+
+    pub async fn get_hash_ring(
+        &self,
+        manager_address: &str,
+    ) -> Result<GetHashRingInfoRecvMetaData, i32> {
+        self.call_hash_ring(manager_address, ManagerOperationType::GetHashRing)
+            .await
+    }
+
+    pub async fn get_new_hash_ring(
+        &self,
+        manager_address: &str,
+    ) -> Result<GetHashRingInfoRecvMetaData, i32> {
+        self.call_hash_ring(manager_address, ManagerOperationType::GetNewHashRing)
+            .await
+    }
+
+    async fn call_hash_ring(
+        &self,
+        manager_address: &str,
+        operation_type: ManagerOperationType,
+    ) -> Result<GetHashRingInfoRecvMetaData, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 65535];
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                operation_type.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                let hash_ring_meta_data: GetHashRingInfoRecvMetaData =
+                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                Ok(hash_ring_meta_data)
+            }
+            Err(e) => {
+                error!("get hash ring info failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn drain_server(&self, manager_address: &str, server: &str) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::DrainNode.into(),
+                0,
+                server,
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    Err(status)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => {
+                error!("drain server failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_drain_status(
+        &self,
+        manager_address: &str,
+        server: &str,
+    ) -> Result<GetDrainStatusRecvMetaData, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 64];
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::GetDrainStatus.into(),
+                0,
+                server,
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+            }
+            Err(e) => {
+                error!("get drain status failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_membership_log(
+        &self,
+        manager_address: &str,
+    ) -> Result<GetMembershipLogRecvMetaData, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 65535];
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::GetMembershipLog.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap())
+            }
+            Err(e) => {
+                error!("get membership log failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn get_ring_layout(
+        &self,
+        manager_address: &str,
+    ) -> Result<GetRingLayoutRecvData, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_data = vec![0u8; MAX_DATA_LENGTH];
+
+        let result = self
+            .client
+            .call_remote(
+                manager_address,
+                ManagerOperationType::GetRingLayout.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut recv_data,
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(bincode::deserialize(&recv_data[..recv_data_length]).unwrap())
+            }
+            Err(e) => {
+                error!("get ring layout failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn list_volumes(&self, address: &str) -> Result<Vec<Volume>, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 65535];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::ListVolumes.into(),
+                0,
+                "",
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                CONTROLL_REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                let volumes: Vec<Volume> =
+                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                Ok(volumes)
+            }
+            Err(e) => {
+                error!("list volumes failed: {}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn create_volume(
+        &self,
+        address: &str,
+        name: &str,
+        size: u64,
+        atime_policy: AtimePolicy,
+        max_file_size: Option<u64>,
+        allowed_ops: Option<Vec<u32>>,
+    ) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data = bincode::serialize(&CreateVolumeSendMetaData {
+            size,
+            atime_policy,
+            max_file_size,
+            allowed_ops,
+        })
+        .unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::CreateVolume.into(),
+                0,
+                name,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("create volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn resize_volume(&self, address: &str, name: &str, new_size: u64) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let send_meta_data = bincode::serialize(&ResizeVolumeSendMetaData {
+            volume_name: name.to_owned(),
+            new_size,
+        })
+        .unwrap();
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::ResizeVolume.into(),
+                0,
+                name,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("resize volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn delete_volume(&self, address: &str, name: &str) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::DeleteVolume.into(),
+                0,
+                name,
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("delete volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn clean_volume(&self, address: &str, name: &str) -> Result<(), i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::CleanVolume.into(),
+                0,
+                name,
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut [],
+                &mut [],
+                CONTROLL_REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("clean volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    // returns the max write size advertised by the server
+    pub async fn init_volume(&self, address: &str, name: &str) -> Result<u32, i32> {
+        let mut status = 0i32;
+        let mut rsp_flags = 0u32;
+
+        let mut recv_meta_data_length = 0usize;
+        let mut recv_data_length = 0usize;
+
+        let mut recv_meta_data = vec![0u8; 64];
+
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::InitVolume.into(),
+                0,
+                name,
+                &[],
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut [],
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                let init_volume_meta_data: InitVolumeRecvMetaData =
+                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                Ok(init_volume_meta_data.max_write_size)
+            }
+            Err(e) => {
+                error!("init volume failed: {:?}", e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn create_no_parent(
+        &self,
+        address: &str,
+        operation_type: OperationType,
+        parent: &str,
+        send_meta_data: &[u8],
+    ) -> Result<Vec<u8>, i32> {
+        self.create_no_parent_with_data(address, operation_type, parent, send_meta_data, &[])
+            .await
+    }
+
+    // the file is created with data already in it
+    pub async fn create_no_parent_with_data(
+        &self,
+        address: &str,
+        operation_type: OperationType,
+        parent: &str,
+        send_meta_data: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 4];
+        let mut recv_meta_data = vec![0u8; 1024];
 
         let result = self
             .client
             .call_remote(
-                manager_address,
-                ManagerOperationType::GetClusterStatus.into(),
+                address,
+                operation_type.into(),
                 0,
-                "",
-                &[],
-                &[],
+                parent,
+                send_meta_data,
+                data,
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
@@ -173,38 +1252,66 @@ impl Sender {
                 if status != 0 {
                     Err(status)
                 } else {
-                    let cluster_status_meta_data: GetClusterStatusRecvMetaData =
-                        bincode::deserialize(&recv_meta_data).unwrap();
-                    Ok(cluster_status_meta_data.status)
+                    Ok(recv_meta_data[..recv_meta_data_length].to_vec())
                 }
             }
             Err(e) => {
-                error!("get cluster status failed: {}", e);
+                error!("create file failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn get_hash_ring_info(
+    // moves the file at path over the one named in send_meta_data on the same server
+    pub async fn rename_no_parent(
         &self,
-        manager_address: &str,
-    ) -> Result<Vec<(String, usize)>, i32> {
+        address: &str,
+        path: &str,
+        send_meta_data: &[u8],
+    ) -> Result<(), i32> {
+        self.delete_no_parent(address, OperationType::RenameNoParent, path, send_meta_data)
+            .await
+    }
+
+    // renames an entry of the directory at path on the server holding it
+    pub async fn directory_rename_entry(
+        &self,
+        address: &str,
+        path: &str,
+        send_meta_data: &[u8],
+    ) -> Result<(), i32> {
+        self.delete_no_parent(
+            address,
+            OperationType::DirectoryRenameEntry,
+            path,
+            send_meta_data,
+        )
+        .await
+    }
+
+    pub async fn delete_no_parent(
+        &self,
+        address: &str,
+        operation_type: OperationType,
+        parent: &str,
+        send_meta_data: &[u8],
+    ) -> Result<(), i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 65535];
+        let mut recv_meta_data = vec![0u8; 1024];
 
         let result = self
             .client
             .call_remote(
-                manager_address,
-                ManagerOperationType::GetHashRing.into(),
+                address,
+                operation_type.into(),
                 0,
-                "",
-                &[],
+                parent,
+                send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
@@ -218,46 +1325,56 @@ impl Sender {
         match result {
             Ok(_) => {
                 if status != 0 {
-                    return Err(status);
+                    Err(status)
+                } else {
+                    Ok(())
                 }
-                let hash_ring_meta_data: GetHashRingInfoRecvMetaData =
-                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
-                Ok(hash_ring_meta_data.hash_ring_info)
             }
             Err(e) => {
-                error!("get hash ring info failed: {}", e);
+                error!("create file failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn get_new_hash_ring_info(
+    pub async fn read_file(
         &self,
-        manager_address: &str,
-    ) -> Result<Vec<(String, usize)>, i32> {
+        address: &str,
+        path: &str,
+        size: u32,
+        offset: i64,
+    ) -> Result<Vec<u8>, i32> {
+        let send_meta_data = bincode::serialize(&ReadFileSendMetaData {
+            offset,
+            size,
+            verify_checksum: true,
+        })
+        .unwrap();
+
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 65535];
+        let mut recv_meta_data = [0u8; 64];
+        let mut recv_data = vec![0u8; size as usize];
 
         let result = self
             .client
             .call_remote(
-                manager_address,
-                ManagerOperationType::GetNewHashRing.into(),
+                address,
+                OperationType::ReadFile.into(),
                 0,
-                "",
-                &[],
+                path,
+                &send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut recv_meta_data,
-                &mut [],
+                &mut recv_data,
                 REQUEST_TIMEOUT,
             )
             .await;
@@ -266,42 +1383,58 @@ impl Sender {
                 if status != 0 {
                     return Err(status);
                 }
-                let hash_ring_meta_data: GetHashRingInfoRecvMetaData =
-                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
-                Ok(hash_ring_meta_data.hash_ring_info)
+                recv_data.truncate(recv_data_length);
+                match bincode::deserialize::<ReadFileRecvMetaData>(
+                    &recv_meta_data[..recv_meta_data_length],
+                ) {
+                    Ok(md) if md.verify(&recv_data) => Ok(recv_data),
+                    _ => {
+                        error!("read file checksum mismatch, path: {}", path);
+                        Err(libc::EIO)
+                    }
+                }
             }
             Err(e) => {
-                error!("get new hash ring info failed: {}", e);
+                error!("read file failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn list_volumes(&self, address: &str) -> Result<Vec<Volume>, i32> {
+    // runs ops in order on the server at address in one round trip, path only names
+    // the batch in its logs
+    pub async fn batch(
+        &self,
+        address: &str,
+        path: &str,
+        ops: Vec<BatchOp>,
+    ) -> Result<BatchRecvData, i32> {
+        let send_data = bincode::serialize(&BatchSendData { ops }).unwrap();
+
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 65535];
+        let mut recv_data = vec![0u8; MAX_DATA_LENGTH];
 
         let result = self
             .client
             .call_remote(
                 address,
-                OperationType::ListVolumes.into(),
+                OperationType::Batch.into(),
                 0,
-                "",
-                &[],
+                path,
                 &[],
+                &send_data,
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                &mut recv_meta_data,
                 &mut [],
-                CONTROLL_REQUEST_TIMEOUT,
+                &mut recv_data,
+                REQUEST_TIMEOUT,
             )
             .await;
         match result {
@@ -309,79 +1442,103 @@ impl Sender {
                 if status != 0 {
                     return Err(status);
                 }
-                let volumes: Vec<Volume> =
-                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
-                Ok(volumes)
+                Ok(bincode::deserialize(&recv_data[..recv_data_length]).unwrap())
             }
             Err(e) => {
-                error!("list volumes failed: {}", e);
+                error!("batch failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn create_volume(&self, address: &str, name: &str, size: u64) -> Result<(), i32> {
+    // returns how many bytes the server of dst_path copied
+    pub async fn copy_file_range(
+        &self,
+        address: &str,
+        dst_path: &str,
+        md: &CopyFileRangeSendMetaData,
+    ) -> Result<u64, i32> {
+        let send_meta_data = bincode::serialize(md).unwrap();
+
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
-        let send_meta_data = bincode::serialize(&CreateVolumeSendMetaData { size }).unwrap();
-
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = [0u8; 8];
+
         let result = self
             .client
             .call_remote(
                 address,
-                OperationType::CreateVolume.into(),
+                OperationType::CopyFileRange.into(),
                 0,
-                name,
+                dst_path,
                 &send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
+                &mut recv_meta_data,
                 &mut [],
-                &mut [],
-                REQUEST_TIMEOUT,
+                CONTROLL_REQUEST_TIMEOUT,
             )
             .await;
         match result {
             Ok(_) => {
                 if status != 0 {
-                    return Err(status);
+                    Err(status)
+                } else {
+                    Ok(u64::from_le_bytes(recv_meta_data))
                 }
-                Ok(())
             }
             Err(e) => {
-                error!("create volume failed: {:?}", e);
+                error!("copy file range failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn delete_volume(&self, address: &str, name: &str) -> Result<(), i32> {
+    pub async fn write_file(
+        &self,
+        address: &str,
+        path: &str,
+        data: &[u8],
+        offset: i64,
+        flags: u32,
+    ) -> Result<usize, i32> {
+        let send_meta_data = bincode::serialize(&WriteFileSendMetaData {
+            offset,
+            if_mtime: None,
+            checksum: Some(crc32c::crc32c(data)),
+            append: false,
+        })
+        .unwrap();
+
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_meta_data = [0u8; 4];
+
         let result = self
             .client
             .call_remote(
                 address,
-                OperationType::DeleteVolume.into(),
-                0,
-                name,
-                &[],
-                &[],
+                OperationType::WriteFile.into(),
+                flags,
+                path,
+                &send_meta_data,
+                data,
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                &mut [],
+                &mut recv_meta_data,
                 &mut [],
                 REQUEST_TIMEOUT,
             )
@@ -389,21 +1546,25 @@ impl Sender {
         match result {
             Ok(_) => {
                 if status != 0 {
-                    return Err(status);
+                    Err(status)
+                } else {
+                    Ok(u32::from_le_bytes(recv_meta_data) as usize)
                 }
-                Ok(())
             }
             Err(e) => {
-                error!("delete volume failed: {:?}", e);
+                error!("write file failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn clean_volume(&self, address: &str, name: &str) -> Result<(), i32> {
+    // asks the server at address, which owns the volume root, to add delta to the used size
+    pub async fn charge_volume(&self, address: &str, volume: &str, delta: i64) -> Result<(), i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
+        let send_meta_data = bincode::serialize(&ChargeVolumeSendMetaData { delta }).unwrap();
+
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
@@ -411,10 +1572,10 @@ impl Sender {
             .client
             .call_remote(
                 address,
-                OperationType::CleanVolume.into(),
+                OperationType::ChargeVolume.into(),
                 0,
-                name,
-                &[],
+                volume,
+                &send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
@@ -422,85 +1583,98 @@ impl Sender {
                 &mut recv_data_length,
                 &mut [],
                 &mut [],
-                CONTROLL_REQUEST_TIMEOUT,
+                REQUEST_TIMEOUT,
             )
             .await;
         match result {
             Ok(_) => {
                 if status != 0 {
-                    return Err(status);
+                    Err(status)
+                } else {
+                    Ok(())
                 }
-                Ok(())
             }
             Err(e) => {
-                error!("clean volume failed: {:?}", e);
+                error!("charge volume failed: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn init_volume(&self, address: &str, name: &str) -> Result<(), i32> {
+    pub async fn stat_and_checksum(
+        &self,
+        address: &str,
+        paths: Vec<String>,
+    ) -> Result<Vec<(String, FileAttrSimple, u32)>, i32> {
+        // route by the first path so that a server in the middle of a transfer
+        // forwards the request the same way it would forward a single file
+        let path = match paths.first() {
+            Some(path) => path.clone(),
+            None => return Ok(Vec::new()),
+        };
+        let md = StatAndChecksumSendMetaData { paths };
+        let send_meta_data = bincode::serialize(&md).unwrap();
+
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
+        let mut recv_data = vec![0u8; md.max_response_len()];
+
         let result = self
             .client
             .call_remote(
                 address,
-                OperationType::InitVolume.into(),
+                OperationType::StatAndChecksum.into(),
                 0,
-                name,
-                &[],
+                &path,
+                &send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
                 &mut [],
-                &mut [],
+                &mut recv_data,
                 REQUEST_TIMEOUT,
             )
             .await;
         match result {
             Ok(_) => {
                 if status != 0 {
-                    return Err(status);
+                    Err(status)
+                } else {
+                    let data: StatAndChecksumRecvData =
+                        bincode::deserialize(&recv_data[..recv_data_length]).unwrap();
+                    Ok(data.entries)
                 }
-                Ok(())
             }
             Err(e) => {
-                error!("init volume failed: {:?}", e);
+                error!("stat and checksum failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn create_no_parent(
-        &self,
-        address: &str,
-        operation_type: OperationType,
-        parent: &str,
-        send_meta_data: &[u8],
-    ) -> Result<Vec<u8>, i32> {
+    pub async fn get_parent(&self, address: &str, path: &str) -> Result<(String, String), i32> {
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
 
         let mut recv_meta_data_length = 0usize;
         let mut recv_data_length = 0usize;
 
-        let mut recv_meta_data = vec![0u8; 1024];
+        let mut recv_meta_data = vec![0u8; 4096];
 
         let result = self
             .client
             .call_remote(
                 address,
-                operation_type.into(),
+                OperationType::GetParent.into(),
                 0,
-                parent,
-                send_meta_data,
+                path,
+                &[],
                 &[],
                 &mut status,
                 &mut rsp_flags,
@@ -516,45 +1690,40 @@ impl Sender {
                 if status != 0 {
                     Err(status)
                 } else {
-                    Ok(recv_meta_data[..recv_meta_data_length].to_vec())
+                    let md: GetParentRecvMetaData =
+                        bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                    Ok((md.parent, md.name))
                 }
             }
             Err(e) => {
-                error!("create file failed with error: {}", e);
+                error!("get parent failed with error: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn delete_no_parent(
+    pub async fn directory_add_entry(
         &self,
         address: &str,
-        operation_type: OperationType,
-        parent: &str,
+        path: &str,
         send_meta_data: &[u8],
     ) -> Result<(), i32> {
-        let mut status = 0i32;
-        let mut rsp_flags = 0u32;
-
-        let mut recv_meta_data_length = 0usize;
-        let mut recv_data_length = 0usize;
-
-        let mut recv_meta_data = vec![0u8; 1024];
-
+        let (mut status, mut rsp_flags, mut recv_meta_data_length, mut recv_data_length) =
+            (0, 0, 0, 0);
         let result = self
             .client
             .call_remote(
                 address,
-                operation_type.into(),
+                OperationType::DirectoryAddEntry as u32,
                 0,
-                parent,
+                path,
                 send_meta_data,
                 &[],
                 &mut status,
                 &mut rsp_flags,
                 &mut recv_meta_data_length,
                 &mut recv_data_length,
-                &mut recv_meta_data,
+                &mut [],
                 &mut [],
                 REQUEST_TIMEOUT,
             )
@@ -567,14 +1736,14 @@ impl Sender {
                     Ok(())
                 }
             }
-            Err(e) => {
-                error!("create file failed with error: {}", e);
+            e => {
+                error!("Create file: DirectoryAddEntry failed: {} ,{:?}", path, e);
                 Err(CONNECTION_ERROR)
             }
         }
     }
 
-    pub async fn directory_add_entry(
+    pub async fn directory_delete_entry(
         &self,
         address: &str,
         path: &str,
@@ -586,7 +1755,7 @@ impl Sender {
             .client
             .call_remote(
                 address,
-                OperationType::DirectoryAddEntry as u32,
+                OperationType::DirectoryDeleteEntry as u32,
                 0,
                 path,
                 send_meta_data,
@@ -615,9 +1784,11 @@ impl Sender {
         }
     }
 
-    pub async fn directory_delete_entry(
+    // operation_type is DirectoryAddShardEntry or DirectoryDeleteShardEntry
+    pub async fn directory_shard_entry(
         &self,
         address: &str,
+        operation_type: OperationType,
         path: &str,
         send_meta_data: &[u8],
     ) -> Result<(), i32> {
@@ -627,7 +1798,7 @@ impl Sender {
             .client
             .call_remote(
                 address,
-                OperationType::DirectoryDeleteEntry as u32,
+                operation_type.into(),
                 0,
                 path,
                 send_meta_data,
@@ -650,7 +1821,60 @@ impl Sender {
                 }
             }
             e => {
-                error!("Create file: DirectoryAddEntry failed: {} ,{:?}", path, e);
+                error!("directory shard entry failed: {} ,{:?}", path, e);
+                Err(CONNECTION_ERROR)
+            }
+        }
+    }
+
+    pub async fn read_dir_shard(
+        &self,
+        address: &str,
+        path: &str,
+        size: u32,
+        offset: i64,
+    ) -> Result<(Vec<u8>, ReadDirShardRecvMetaData), i32> {
+        let send_meta_data = bincode::serialize(&ReadDirSendMetaData {
+            offset,
+            size,
+            cursor: None,
+        })
+        .unwrap();
+
+        let (mut status, mut rsp_flags, mut recv_meta_data_length, mut recv_data_length) =
+            (0, 0, 0, 0);
+        let mut recv_meta_data = vec![0u8; 64];
+        let mut recv_data = vec![0u8; size as usize];
+        let result = self
+            .client
+            .call_remote(
+                address,
+                OperationType::ReadDirShard.into(),
+                0,
+                path,
+                &send_meta_data,
+                &[],
+                &mut status,
+                &mut rsp_flags,
+                &mut recv_meta_data_length,
+                &mut recv_data_length,
+                &mut recv_meta_data,
+                &mut recv_data,
+                REQUEST_TIMEOUT,
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                if status != 0 {
+                    return Err(status);
+                }
+                let md: ReadDirShardRecvMetaData =
+                    bincode::deserialize(&recv_meta_data[..recv_meta_data_length]).unwrap();
+                recv_data.truncate(recv_data_length);
+                Ok((recv_data, md))
+            }
+            Err(e) => {
+                error!("read dir shard failed: {}", e);
                 Err(CONNECTION_ERROR)
             }
         }
@@ -4,26 +4,22 @@
 
 use fuser::{FileAttr, FileType};
 use libc::{
-    stat, statx, statx_timestamp, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK,
+    stat, statx, statx_timestamp, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK,
+    S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK,
 };
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Formatter};
 use std::{
     collections::BTreeMap,
     fmt::Display,
-    time::{SystemTime, UNIX_EPOCH},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-#[macro_export]
-macro_rules! offset_of {
-    ($ty:ty, $field:ident) => {
-        //  Undefined Behavior: dereferences a null pointer.
-        //  Undefined Behavior: accesses field outside of valid memory area.
-        unsafe { &(*(0 as *const $ty)).$field as *const _ as usize - 1 }
-    };
-}
-
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum OperationType {
+    // never decoded, 0 is rejected by try_from like any other unknown value so a
+    // zeroed header fails in the handler instead of reaching a dispatch arm
     Unkown = 0,
     Lookup = 1,
     CreateFile = 2,
@@ -49,6 +45,110 @@ pub enum OperationType {
     ListVolumes = 22,
     DeleteVolume = 23,
     CleanVolume = 24,
+    Rename = 25,
+    Fsync = 26,
+    CreateAndWrite = 68,
+    StatAndChecksum = 69,
+    GetParent = 71,
+    VerifyDirCount = 72,
+    ReadFileVectored = 74,
+    AtomicIncrement = 75,
+    DirectoryAddShardEntry = 77,
+    DirectoryDeleteShardEntry = 78,
+    ReadDirShard = 79,
+    Link = 80,
+    WriteFileChunk = 81,
+    CreateSymlink = 82,
+    ReadLink = 83,
+    CreateSymlinkNoParent = 84,
+    SetAttr = 85,
+    ChargeVolume = 86,
+    ResizeVolume = 87,
+    StatFs = 88,
+    CopyFileRange = 89,
+    Fallocate = 90,
+    Batch = 91,
+    RenameNoParent = 92,
+    DirectoryRenameEntry = 93,
+}
+
+impl OperationType {
+    pub fn to_le_bytes(&self) -> [u8; 4] {
+        u32::from(*self).to_le_bytes()
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn from_le_bytes(bytes: &[u8; 4]) -> Result<Self, ()> {
+        Self::try_from(u32::from_le_bytes(*bytes))
+    }
+
+    // whether the operation changes a file, a directory or a volume
+    pub fn is_mutating(&self) -> bool {
+        !matches!(
+            self,
+            OperationType::Unkown
+                | OperationType::Lookup
+                | OperationType::GetFileAttr
+                | OperationType::ReadDir
+                | OperationType::OpenFile
+                | OperationType::ReadFile
+                | OperationType::ReadFileVectored
+                | OperationType::CheckFile
+                | OperationType::CheckDir
+                | OperationType::InitVolume
+                | OperationType::ListVolumes
+                | OperationType::Fsync
+                | OperationType::StatAndChecksum
+                | OperationType::GetParent
+                | OperationType::VerifyDirCount
+                | OperationType::ReadDirShard
+                | OperationType::ReadLink
+                | OperationType::StatFs
+        )
+    }
+
+    // the mutating operations a volume's allowed_ops restricts. the ones servers
+    // send each other to carry out a client's request are left out, and so is
+    // creating the volume, which has no settings before it exists
+    pub fn is_restrictable(&self) -> bool {
+        self.is_mutating()
+            && !matches!(
+                self,
+                OperationType::DirectoryAddEntry
+                    | OperationType::DirectoryDeleteEntry
+                    | OperationType::CreateDirNoParent
+                    | OperationType::CreateFileNoParent
+                    | OperationType::CreateSymlinkNoParent
+                    | OperationType::DeleteDirNoParent
+                    | OperationType::DeleteFileNoParent
+                    | OperationType::CreateVolume
+                    | OperationType::DirectoryAddShardEntry
+                    | OperationType::DirectoryDeleteShardEntry
+                    | OperationType::ChargeVolume
+                    | OperationType::Batch
+                    | OperationType::RenameNoParent
+                    | OperationType::DirectoryRenameEntry
+            )
+    }
+
+    // the metadata operations a Batch carries, they send no data and get back no
+    // more than metadata
+    pub fn is_batchable(&self) -> bool {
+        matches!(
+            self,
+            OperationType::CreateFile
+                | OperationType::CreateDir
+                | OperationType::CreateSymlink
+                | OperationType::Link
+                | OperationType::DeleteFile
+                | OperationType::DeleteDir
+                | OperationType::DirectoryAddEntry
+                | OperationType::DirectoryDeleteEntry
+                | OperationType::GetFileAttr
+                | OperationType::SetAttr
+                | OperationType::TruncateFile
+        )
+    }
 }
 
 impl TryFrom<u32> for OperationType {
@@ -56,7 +156,6 @@ impl TryFrom<u32> for OperationType {
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
         match value {
-            0 => Ok(OperationType::Unkown),
             1 => Ok(OperationType::Lookup),
             2 => Ok(OperationType::CreateFile),
             3 => Ok(OperationType::CreateDir),
@@ -81,7 +180,32 @@ impl TryFrom<u32> for OperationType {
             22 => Ok(OperationType::ListVolumes),
             23 => Ok(OperationType::DeleteVolume),
             24 => Ok(OperationType::CleanVolume),
-            _ => panic!("Unkown value: {}", value),
+            25 => Ok(OperationType::Rename),
+            26 => Ok(OperationType::Fsync),
+            68 => Ok(OperationType::CreateAndWrite),
+            69 => Ok(OperationType::StatAndChecksum),
+            71 => Ok(OperationType::GetParent),
+            72 => Ok(OperationType::VerifyDirCount),
+            74 => Ok(OperationType::ReadFileVectored),
+            75 => Ok(OperationType::AtomicIncrement),
+            77 => Ok(OperationType::DirectoryAddShardEntry),
+            78 => Ok(OperationType::DirectoryDeleteShardEntry),
+            79 => Ok(OperationType::ReadDirShard),
+            80 => Ok(OperationType::Link),
+            81 => Ok(OperationType::WriteFileChunk),
+            82 => Ok(OperationType::CreateSymlink),
+            83 => Ok(OperationType::ReadLink),
+            84 => Ok(OperationType::CreateSymlinkNoParent),
+            85 => Ok(OperationType::SetAttr),
+            86 => Ok(OperationType::ChargeVolume),
+            87 => Ok(OperationType::ResizeVolume),
+            88 => Ok(OperationType::StatFs),
+            89 => Ok(OperationType::CopyFileRange),
+            90 => Ok(OperationType::Fallocate),
+            91 => Ok(OperationType::Batch),
+            92 => Ok(OperationType::RenameNoParent),
+            93 => Ok(OperationType::DirectoryRenameEntry),
+            _ => Err(()),
         }
     }
 }
@@ -114,10 +238,100 @@ impl From<OperationType> for u32 {
             OperationType::ListVolumes => 22,
             OperationType::DeleteVolume => 23,
             OperationType::CleanVolume => 24,
+            OperationType::Rename => 25,
+            OperationType::Fsync => 26,
+            OperationType::CreateAndWrite => 68,
+            OperationType::StatAndChecksum => 69,
+            OperationType::GetParent => 71,
+            OperationType::VerifyDirCount => 72,
+            OperationType::ReadFileVectored => 74,
+            OperationType::AtomicIncrement => 75,
+            OperationType::DirectoryAddShardEntry => 77,
+            OperationType::DirectoryDeleteShardEntry => 78,
+            OperationType::ReadDirShard => 79,
+            OperationType::Link => 80,
+            OperationType::WriteFileChunk => 81,
+            OperationType::CreateSymlink => 82,
+            OperationType::ReadLink => 83,
+            OperationType::CreateSymlinkNoParent => 84,
+            OperationType::SetAttr => 85,
+            OperationType::ChargeVolume => 86,
+            OperationType::ResizeVolume => 87,
+            OperationType::StatFs => 88,
+            OperationType::CopyFileRange => 89,
+            OperationType::Fallocate => 90,
+            OperationType::Batch => 91,
+            OperationType::RenameNoParent => 92,
+            OperationType::DirectoryRenameEntry => 93,
         }
     }
 }
 
+impl OperationType {
+    // the name of the variant, for logs and as the label of metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationType::Unkown => "Unkown",
+            OperationType::Lookup => "Lookup",
+            OperationType::CreateFile => "CreateFile",
+            OperationType::CreateDir => "CreateDir",
+            OperationType::GetFileAttr => "GetFileAttr",
+            OperationType::ReadDir => "ReadDir",
+            OperationType::OpenFile => "OpenFile",
+            OperationType::ReadFile => "ReadFile",
+            OperationType::WriteFile => "WriteFile",
+            OperationType::DeleteFile => "DeleteFile",
+            OperationType::DeleteDir => "DeleteDir",
+            OperationType::DirectoryAddEntry => "DirectoryAddEntry",
+            OperationType::DirectoryDeleteEntry => "DirectoryDeleteEntry",
+            OperationType::TruncateFile => "TruncateFile",
+            OperationType::CheckFile => "CheckFile",
+            OperationType::CheckDir => "CheckDir",
+            OperationType::CreateDirNoParent => "CreateDirNoParent",
+            OperationType::CreateFileNoParent => "CreateFileNoParent",
+            OperationType::DeleteDirNoParent => "DeleteDirNoParent",
+            OperationType::DeleteFileNoParent => "DeleteFileNoParent",
+            OperationType::CreateVolume => "CreateVolume",
+            OperationType::InitVolume => "InitVolume",
+            OperationType::ListVolumes => "ListVolumes",
+            OperationType::DeleteVolume => "DeleteVolume",
+            OperationType::CleanVolume => "CleanVolume",
+            OperationType::Rename => "Rename",
+            OperationType::Fsync => "Fsync",
+            OperationType::CreateAndWrite => "CreateAndWrite",
+            OperationType::StatAndChecksum => "StatAndChecksum",
+            OperationType::GetParent => "GetParent",
+            OperationType::VerifyDirCount => "VerifyDirCount",
+            OperationType::ReadFileVectored => "ReadFileVectored",
+            OperationType::AtomicIncrement => "AtomicIncrement",
+            OperationType::DirectoryAddShardEntry => "DirectoryAddShardEntry",
+            OperationType::DirectoryDeleteShardEntry => "DirectoryDeleteShardEntry",
+            OperationType::ReadDirShard => "ReadDirShard",
+            OperationType::Link => "Link",
+            OperationType::WriteFileChunk => "WriteFileChunk",
+            OperationType::CreateSymlink => "CreateSymlink",
+            OperationType::ReadLink => "ReadLink",
+            OperationType::CreateSymlinkNoParent => "CreateSymlinkNoParent",
+            OperationType::SetAttr => "SetAttr",
+            OperationType::ChargeVolume => "ChargeVolume",
+            OperationType::ResizeVolume => "ResizeVolume",
+            OperationType::StatFs => "StatFs",
+            OperationType::CopyFileRange => "CopyFileRange",
+            OperationType::Fallocate => "Fallocate",
+            OperationType::Batch => "Batch",
+            OperationType::RenameNoParent => "RenameNoParent",
+            OperationType::DirectoryRenameEntry => "DirectoryRenameEntry",
+        }
+    }
+}
+
+impl Display for OperationType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ManagerOperationType {
     SendHeart = 101,
     GetMetadata = 102,
@@ -128,6 +342,21 @@ pub enum ManagerOperationType {
     RemoveNodes = 107,
     UpdateServerStatus = 108,
     FinishServer = 109,
+    HealthCheck = 124,
+    ListRunningOps = 128,
+    KillOp = 129,
+    GetServerConfig = 130,
+    ReRegister = 131,
+    GetCapabilities = 132,
+    InvalidateClientCache = 133,
+    RebalanceVolume = 134,
+    GetMembershipLog = 135,
+    MigrateVolume = 136,
+    GetRingLayout = 139,
+    SetTransferRate = 141,
+    DrainNode = 142,
+    GetDrainStatus = 143,
+    GetClientBandwidth = 144,
 }
 
 impl TryFrom<u32> for ManagerOperationType {
@@ -144,7 +373,22 @@ impl TryFrom<u32> for ManagerOperationType {
             107 => Ok(ManagerOperationType::RemoveNodes),
             108 => Ok(ManagerOperationType::UpdateServerStatus),
             109 => Ok(ManagerOperationType::FinishServer),
-            _ => panic!("Unkown value: {}", value),
+            124 => Ok(ManagerOperationType::HealthCheck),
+            128 => Ok(ManagerOperationType::ListRunningOps),
+            129 => Ok(ManagerOperationType::KillOp),
+            130 => Ok(ManagerOperationType::GetServerConfig),
+            131 => Ok(ManagerOperationType::ReRegister),
+            132 => Ok(ManagerOperationType::GetCapabilities),
+            133 => Ok(ManagerOperationType::InvalidateClientCache),
+            134 => Ok(ManagerOperationType::RebalanceVolume),
+            135 => Ok(ManagerOperationType::GetMembershipLog),
+            136 => Ok(ManagerOperationType::MigrateVolume),
+            139 => Ok(ManagerOperationType::GetRingLayout),
+            141 => Ok(ManagerOperationType::SetTransferRate),
+            142 => Ok(ManagerOperationType::DrainNode),
+            143 => Ok(ManagerOperationType::GetDrainStatus),
+            144 => Ok(ManagerOperationType::GetClientBandwidth),
+            _ => Err(()),
         }
     }
 }
@@ -161,24 +405,64 @@ impl From<ManagerOperationType> for u32 {
             ManagerOperationType::RemoveNodes => 107,
             ManagerOperationType::UpdateServerStatus => 108,
             ManagerOperationType::FinishServer => 109,
+            ManagerOperationType::HealthCheck => 124,
+            ManagerOperationType::ListRunningOps => 128,
+            ManagerOperationType::KillOp => 129,
+            ManagerOperationType::GetServerConfig => 130,
+            ManagerOperationType::ReRegister => 131,
+            ManagerOperationType::GetCapabilities => 132,
+            ManagerOperationType::InvalidateClientCache => 133,
+            ManagerOperationType::RebalanceVolume => 134,
+            ManagerOperationType::GetMembershipLog => 135,
+            ManagerOperationType::MigrateVolume => 136,
+            ManagerOperationType::GetRingLayout => 139,
+            ManagerOperationType::SetTransferRate => 141,
+            ManagerOperationType::DrainNode => 142,
+            ManagerOperationType::GetDrainStatus => 143,
+            ManagerOperationType::GetClientBandwidth => 144,
         }
     }
 }
 
 impl ManagerOperationType {
-    pub fn to_le_bytes(&self) -> [u8; 4] {
+    // the name of the variant, for logs
+    pub fn as_str(&self) -> &'static str {
         match self {
-            ManagerOperationType::SendHeart => 101u32.to_le_bytes(),
-            ManagerOperationType::GetMetadata => 102u32.to_le_bytes(),
-            ManagerOperationType::GetClusterStatus => 103u32.to_le_bytes(),
-            ManagerOperationType::GetHashRing => 104u32.to_le_bytes(),
-            ManagerOperationType::GetNewHashRing => 105u32.to_le_bytes(),
-            ManagerOperationType::AddNodes => 106u32.to_le_bytes(),
-            ManagerOperationType::RemoveNodes => 107u32.to_le_bytes(),
-            ManagerOperationType::UpdateServerStatus => 108u32.to_le_bytes(),
-            ManagerOperationType::FinishServer => 109u32.to_le_bytes(),
+            ManagerOperationType::SendHeart => "SendHeart",
+            ManagerOperationType::GetMetadata => "GetMetadata",
+            ManagerOperationType::GetClusterStatus => "GetClusterStatus",
+            ManagerOperationType::GetHashRing => "GetHashRing",
+            ManagerOperationType::GetNewHashRing => "GetNewHashRing",
+            ManagerOperationType::AddNodes => "AddNodes",
+            ManagerOperationType::RemoveNodes => "RemoveNodes",
+            ManagerOperationType::UpdateServerStatus => "UpdateServerStatus",
+            ManagerOperationType::FinishServer => "FinishServer",
+            ManagerOperationType::HealthCheck => "HealthCheck",
+            ManagerOperationType::ListRunningOps => "ListRunningOps",
+            ManagerOperationType::KillOp => "KillOp",
+            ManagerOperationType::GetServerConfig => "GetServerConfig",
+            ManagerOperationType::ReRegister => "ReRegister",
+            ManagerOperationType::GetCapabilities => "GetCapabilities",
+            ManagerOperationType::InvalidateClientCache => "InvalidateClientCache",
+            ManagerOperationType::RebalanceVolume => "RebalanceVolume",
+            ManagerOperationType::GetMembershipLog => "GetMembershipLog",
+            ManagerOperationType::MigrateVolume => "MigrateVolume",
+            ManagerOperationType::GetRingLayout => "GetRingLayout",
+            ManagerOperationType::SetTransferRate => "SetTransferRate",
+            ManagerOperationType::DrainNode => "DrainNode",
+            ManagerOperationType::GetDrainStatus => "GetDrainStatus",
+            ManagerOperationType::GetClientBandwidth => "GetClientBandwidth",
         }
     }
+
+    pub fn to_le_bytes(&self) -> [u8; 4] {
+        u32::from(*self).to_le_bytes()
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn from_le_bytes(bytes: &[u8; 4]) -> Result<Self, ()> {
+        Self::try_from(u32::from_le_bytes(*bytes))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
@@ -260,6 +544,16 @@ impl From<ServerStatus> for u32 {
     }
 }
 
+impl ServerStatus {
+    pub fn to_le_bytes(&self) -> [u8; 4] {
+        u32::from(*self).to_le_bytes()
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 4]) -> Result<Self, String> {
+        Self::try_from(u32::from_le_bytes(bytes))
+    }
+}
+
 impl Display for ServerStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -356,6 +650,36 @@ impl From<ClusterStatus> for i32 {
     }
 }
 
+impl ClusterStatus {
+    pub fn to_le_bytes(&self) -> [u8; 4] {
+        u32::from(*self).to_le_bytes()
+    }
+
+    // the lifecycle of the cluster: Initializing until every server is up, then Idle,
+    // and a membership change goes from NodesStarting through the transfer back to
+    // Idle. any status may fall into StatusError, which is left only by a restart
+    pub fn can_transition_to(&self, next: ClusterStatus) -> bool {
+        use ClusterStatus::*;
+        *self == next
+            || next == StatusError
+            || matches!(
+                (*self, next),
+                (Initializing, Idle)
+                    | (Idle, NodesStarting)
+                    | (NodesStarting, SyncNewHashRing)
+                    | (SyncNewHashRing, PreTransfer)
+                    | (PreTransfer, Transferring)
+                    | (Transferring, PreFinish)
+                    | (PreFinish, Finishing)
+                    | (Finishing, Idle)
+            )
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 4]) -> Result<Self, String> {
+        Self::try_from(u32::from_le_bytes(bytes))
+    }
+}
+
 impl Display for ClusterStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -473,6 +797,30 @@ impl From<FileTypeSimple> for u8 {
     }
 }
 
+impl FileTypeSimple {
+    // the permissions of a file created without a mode. a symlink's are never checked
+    pub fn default_perm(self) -> u16 {
+        match self {
+            FileTypeSimple::Directory => 0o755,
+            FileTypeSimple::Symlink => 0o777,
+            _ => 0o644,
+        }
+    }
+
+    // the d_type of a dirent, numbered apart from both FileTypeSimple and S_IF*
+    pub fn to_dirent_type(self) -> u8 {
+        match self {
+            FileTypeSimple::RegularFile => DT_REG,
+            FileTypeSimple::NamedPipe => DT_FIFO,
+            FileTypeSimple::CharDevice => DT_CHR,
+            FileTypeSimple::BlockDevice => DT_BLK,
+            FileTypeSimple::Directory => DT_DIR,
+            FileTypeSimple::Symlink => DT_LNK,
+            FileTypeSimple::Socket => DT_SOCK,
+        }
+    }
+}
+
 pub fn file_attr_as_bytes(attr: &FileAttr) -> &[u8] {
     unsafe {
         let ptr = attr as *const FileAttr as *const u8;
@@ -501,6 +849,15 @@ pub fn bytes_as_file_attr_mut(bytes: &mut [u8]) -> &mut FileAttr {
     }
 }
 
+// FileAttrSimple.flags bit, the server holds the attr but the data lives on another server
+pub const DATA_REMOTE: u32 = 1 << 31;
+// FileAttrSimple.flags bit, new entries of the directory live on the server owning each entry
+pub const DIR_SHARDED: u32 = 1 << 30;
+// FileAttrSimple.flags bit, the file was created as a tmpfile and no directory entry names it yet
+pub const UNLINKED: u32 = 1 << 29;
+// FileAttrSimple.flags bit, the data of the small file is kept with its attr
+pub const INLINE_DATA: u32 = 1 << 28;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct FileAttrSimple {
     pub size: u64,
@@ -517,6 +874,12 @@ pub struct FileAttrSimple {
     pub rdev: u32,
     pub flags: u32,
     pub blksize: u32,
+    // bumped on every change to the file, cheaper to compare than the times
+    #[serde(default)]
+    pub version: u64,
+    // the data of a file flagged INLINE_DATA
+    #[serde(default)]
+    pub inline_data: Option<Vec<u8>>,
 }
 
 impl Default for FileAttrSimple {
@@ -526,19 +889,16 @@ impl Default for FileAttrSimple {
 }
 
 impl FileAttrSimple {
+    // length of to_bytes: size, blocks, four times as seconds and nanoseconds,
+    // kind, perm, six u32 fields and version
+    pub const SERIALIZED_LEN: usize = 8 + 8 + 4 * (8 + 4) + 4 + 2 + 6 * 4 + 8;
+
     pub fn new(r#type: FileTypeSimple) -> Self {
-        let kind = match r#type {
-            FileTypeSimple::NamedPipe => 0,
-            FileTypeSimple::CharDevice => 1,
-            FileTypeSimple::BlockDevice => 2,
-            FileTypeSimple::Directory => 3,
-            FileTypeSimple::RegularFile => 4,
-            FileTypeSimple::Symlink => 5,
-            FileTypeSimple::Socket => 6,
-        };
-        let size = match r#type {
-            FileTypeSimple::Directory => 4096,
-            _ => 0,
+        let kind = r#type.into();
+        // a directory is also named by its own "." entry
+        let (size, nlink) = match r#type {
+            FileTypeSimple::Directory => (4096, 2),
+            _ => (0, 1),
         };
         FileAttrSimple {
             size,
@@ -548,25 +908,49 @@ impl FileAttrSimple {
             ctime: SystemTime::now(),
             crtime: SystemTime::now(),
             kind,
-            perm: 0,
-            nlink: 0,
+            perm: r#type.default_perm(),
+            nlink,
             uid: 0,
             gid: 0,
             rdev: 0,
             flags: 0,
             blksize: 0,
+            version: 0,
+            inline_data: None,
         }
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                self as *const FileAttrSimple as *const u8,
-                std::mem::size_of::<FileAttrSimple>(),
-            )
+    // the attr of a file created with the mode and umask of open(2) or mkdir(2). a mode
+    // of 0 asks for the default permissions of the type, the file type bits of a mode
+    // ask for an explicit one, which may be 0
+    pub fn with_mode(r#type: FileTypeSimple, mode: u32, umask: u32) -> Self {
+        let perm = match mode {
+            0 => r#type.default_perm() as u32,
+            mode => mode & 0o7777,
+        };
+        FileAttrSimple {
+            perm: (perm & !umask) as u16,
+            ..Self::new(r#type)
         }
     }
 
+    pub fn builder(r#type: FileTypeSimple) -> FileAttrSimpleBuilder {
+        FileAttrSimpleBuilder {
+            attr: FileAttrSimple::new(r#type),
+            perm: None,
+            uid: None,
+            gid: None,
+        }
+    }
+
+    #[deprecated(note = "the layout depends on the host, use to_bytes")]
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+
+    // a mutable view of the raw memory cannot go through the codec, decode with
+    // from_bytes and encode the changed attr with to_bytes instead
+    #[deprecated(note = "the layout depends on the host, use from_bytes and to_bytes")]
     pub fn as_mut_bytes(&mut self) -> &mut [u8] {
         unsafe {
             std::slice::from_raw_parts_mut(
@@ -576,46 +960,148 @@ impl FileAttrSimple {
         }
     }
 
-    // pub fn to_bytes(attr: &FileAttrSimple) -> Vec<u8> {
-    //     let mut bytes = Vec::with_capacity(8 + 8 + 8 + 8 + 8 + 8 + 4 + 2 + 4 + 4 + 4 + 4 + 4 + 4);
-    //     bytes.extend_from_slice(&attr.size.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.blocks.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.atime.duration_since(UNIX_EPOCH).unwrap().as_secs().to_le_bytes());
-    //     bytes.extend_from_slice(&attr.mtime.duration_since(UNIX_EPOCH).unwrap().as_secs().to_le_bytes());
-    //     bytes.extend_from_slice(&attr.ctime.duration_since(UNIX_EPOCH).unwrap().as_secs().to_le_bytes());
-    //     bytes.extend_from_slice(&attr.crtime.duration_since(UNIX_EPOCH).unwrap().as_secs().to_le_bytes());
-    //     bytes.extend_from_slice(&attr.kind.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.perm.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.nlink.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.uid.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.gid.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.rdev.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.flags.to_le_bytes());
-    //     bytes.extend_from_slice(&attr.blksize.to_le_bytes());
-    //     bytes
-    // }
-
-    // pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
-    //     if bytes.len() != 8 + 8 + 8 + 8 + 8 + 8 + 4 + 2 + 4 + 4 + 4 + 4 + 4 + 4 {
-    //         return Err(format!("Invalid length: {}", bytes.len()));
-    //     }
-    //     let mut attr = FileAttrSimple::default();
-    //     attr.size = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
-    //     attr.blocks = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
-    //     attr.atime = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(bytes[16..24].try_into().unwrap()));
-    //     attr.mtime = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(bytes[24..32].try_into().unwrap()));
-    //     attr.ctime = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(bytes[32..40].try_into().unwrap()));
-    //     attr.crtime = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(bytes[40..48].try_into().unwrap()));
-    //     attr.kind = u32::from_le_bytes(bytes[48..52].try_into().unwrap());
-    //     attr.perm = u16::from_le_bytes(bytes[52..54].try_into().unwrap());
-    //     attr.nlink = u32::from_le_bytes(bytes[54..58].try_into().unwrap());
-    //     attr.uid = u32::from_le_bytes(bytes[58..62].try_into().unwrap());
-    //     attr.gid = u32::from_le_bytes(bytes[62..66].try_into().unwrap());
-    //     attr.rdev = u32::from_le_bytes(bytes[66..70].try_into().unwrap());
-    //     attr.flags = u32::from_le_bytes(bytes[70..74].try_into().unwrap());
-    //     attr.blksize = u32::from_le_bytes(bytes[74..78].try_into().unwrap());
-    //     Ok(attr)
-    // }
+    // every field little-endian in declaration order, the times as seconds and
+    // nanoseconds since the epoch. inline_data is not part of the encoding, it is
+    // stored and sent after the attr
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::SERIALIZED_LEN);
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.blocks.to_le_bytes());
+        for time in [self.atime, self.mtime, self.ctime, self.crtime] {
+            let (secs, nanos) = time_to_parts(time);
+            bytes.extend_from_slice(&secs.to_le_bytes());
+            bytes.extend_from_slice(&nanos.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        bytes.extend_from_slice(&self.perm.to_le_bytes());
+        bytes.extend_from_slice(&self.nlink.to_le_bytes());
+        bytes.extend_from_slice(&self.uid.to_le_bytes());
+        bytes.extend_from_slice(&self.gid.to_le_bytes());
+        bytes.extend_from_slice(&self.rdev.to_le_bytes());
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&self.blksize.to_le_bytes());
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != Self::SERIALIZED_LEN {
+            return Err(format!("Invalid length: {}", bytes.len()));
+        }
+        let mut offset = 0;
+        let mut take = move |len: usize| {
+            offset += len;
+            &bytes[offset - len..offset]
+        };
+        let size = u64::from_le_bytes(take(8).try_into().unwrap());
+        let blocks = u64::from_le_bytes(take(8).try_into().unwrap());
+        let mut times = [UNIX_EPOCH; 4];
+        for time in times.iter_mut() {
+            let secs = i64::from_le_bytes(take(8).try_into().unwrap());
+            let nanos = u32::from_le_bytes(take(4).try_into().unwrap());
+            *time = time_from_parts(secs, nanos)?;
+        }
+        let [atime, mtime, ctime, crtime] = times;
+        Ok(FileAttrSimple {
+            size,
+            blocks,
+            atime,
+            mtime,
+            ctime,
+            crtime,
+            kind: u32::from_le_bytes(take(4).try_into().unwrap()),
+            perm: u16::from_le_bytes(take(2).try_into().unwrap()),
+            nlink: u32::from_le_bytes(take(4).try_into().unwrap()),
+            uid: u32::from_le_bytes(take(4).try_into().unwrap()),
+            gid: u32::from_le_bytes(take(4).try_into().unwrap()),
+            rdev: u32::from_le_bytes(take(4).try_into().unwrap()),
+            flags: u32::from_le_bytes(take(4).try_into().unwrap()),
+            blksize: u32::from_le_bytes(take(4).try_into().unwrap()),
+            version: u64::from_le_bytes(take(8).try_into().unwrap()),
+            inline_data: None,
+        })
+    }
+}
+
+// builds the attr of a new file. perm, uid and gid must be given, nlink defaults
+// to what the type starts with: 2 for a directory, which its "." also names, and 1
+// for anything else
+pub struct FileAttrSimpleBuilder {
+    attr: FileAttrSimple,
+    perm: Option<u16>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl FileAttrSimpleBuilder {
+    pub fn perm(mut self, perm: u16) -> Self {
+        self.perm = Some(perm);
+        self
+    }
+
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    pub fn nlink(mut self, nlink: u32) -> Self {
+        self.attr.nlink = nlink;
+        self
+    }
+
+    pub fn build(self) -> Result<FileAttrSimple, String> {
+        let (Some(perm), Some(uid), Some(gid)) = (self.perm, self.uid, self.gid) else {
+            let missing: Vec<&str> = [
+                ("perm", self.perm.is_none()),
+                ("uid", self.uid.is_none()),
+                ("gid", self.gid.is_none()),
+            ]
+            .into_iter()
+            .filter_map(|(field, missing)| missing.then_some(field))
+            .collect();
+            return Err(format!("file attr without {}", missing.join(", ")));
+        };
+        Ok(FileAttrSimple {
+            perm,
+            uid,
+            gid,
+            ..self.attr
+        })
+    }
+}
+
+// seconds relative to the epoch, negative before it, and the nanoseconds into
+// that second
+fn time_to_parts(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+fn time_from_parts(secs: i64, nanos: u32) -> Result<SystemTime, String> {
+    if nanos >= 1_000_000_000 {
+        return Err(format!("Invalid nanoseconds: {}", nanos));
+    }
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|time| time.checked_add(Duration::from_nanos(nanos as u64)))
+    };
+    time.ok_or_else(|| format!("Invalid time: {}.{:09}", secs, nanos))
 }
 pub fn tostat(attr: &FileAttr, statbuf: &mut [u8]) {
     let kind = match attr.kind {
@@ -638,18 +1124,15 @@ pub fn tostat(attr: &FileAttr, statbuf: &mut [u8]) {
         (*(statbuf.as_mut_ptr() as *mut stat)).st_size = attr.size as i64;
         (*(statbuf.as_mut_ptr() as *mut stat)).st_blksize = attr.blksize as i64;
         (*(statbuf.as_mut_ptr() as *mut stat)).st_blocks = attr.blocks as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_atime =
-            attr.atime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_atime_nsec =
-            attr.atime.duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_mtime =
-            attr.mtime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_mtime_nsec =
-            attr.mtime.duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_ctime =
-            attr.ctime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
-        (*(statbuf.as_mut_ptr() as *mut stat)).st_ctime_nsec =
-            attr.ctime.duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64;
+        let (secs, nanos) = stat_time(attr.atime);
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_atime = secs;
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_atime_nsec = nanos as i64;
+        let (secs, nanos) = stat_time(attr.mtime);
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_mtime = secs;
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_mtime_nsec = nanos as i64;
+        let (secs, nanos) = stat_time(attr.ctime);
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_ctime = secs;
+        (*(statbuf.as_mut_ptr() as *mut stat)).st_ctime_nsec = nanos as i64;
     }
 }
 pub fn tostatx(attr: &FileAttr, statxbuf: &mut [u8]) {
@@ -673,40 +1156,113 @@ pub fn tostatx(attr: &FileAttr, statxbuf: &mut [u8]) {
         (*(statxbuf.as_mut_ptr() as *mut statx)).stx_size = attr.size;
         (*(statxbuf.as_mut_ptr() as *mut statx)).stx_blksize = attr.blksize;
         (*(statxbuf.as_mut_ptr() as *mut statx)).stx_blocks = attr.blocks;
-        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_atime = statx_timestamp {
-            tv_sec: attr.atime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
-            tv_nsec: 0,
-            __statx_timestamp_pad1: [0i32; 1],
-        };
-        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_btime = statx_timestamp {
-            tv_sec: 0,
-            tv_nsec: 0,
-            __statx_timestamp_pad1: [0i32; 1],
-        };
-        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_mtime = statx_timestamp {
-            tv_sec: attr.mtime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
-            tv_nsec: 0,
-            __statx_timestamp_pad1: [0i32; 1],
-        };
-        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_ctime = statx_timestamp {
-            tv_sec: attr.ctime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
-            tv_nsec: 0,
-            __statx_timestamp_pad1: [0i32; 1],
-        };
+        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_atime = statx_timestamp_of(attr.atime);
+        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_btime = statx_timestamp_of(attr.crtime);
+        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_mtime = statx_timestamp_of(attr.mtime);
+        (*(statxbuf.as_mut_ptr() as *mut statx)).stx_ctime = statx_timestamp_of(attr.ctime);
     }
 }
 
+// seconds and nanoseconds since the epoch as stat reports them. a time before the
+// epoch is clamped to it and one past i64::MAX seconds saturates, a bad attr must
+// not bring the process down
+fn stat_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => match i64::try_from(since.as_secs()) {
+            Ok(secs) => (secs, since.subsec_nanos()),
+            Err(_) => (i64::MAX, 999_999_999),
+        },
+        Err(_) => (0, 0),
+    }
+}
+
+fn statx_timestamp_of(time: SystemTime) -> statx_timestamp {
+    let (tv_sec, tv_nsec) = stat_time(time);
+    statx_timestamp {
+        tv_sec,
+        tv_nsec,
+        __statx_timestamp_pad1: [0i32; 1],
+    }
+}
+
+fn time_of_stat(secs: i64, nsec: i64) -> Result<SystemTime, String> {
+    let nanos = u32::try_from(nsec).map_err(|_| format!("Invalid nanoseconds: {}", nsec))?;
+    time_from_parts(secs, nanos)
+}
+
+fn kind_of_mode(mode: u32) -> Result<FileTypeSimple, String> {
+    match mode & S_IFMT {
+        S_IFIFO => Ok(FileTypeSimple::NamedPipe),
+        S_IFCHR => Ok(FileTypeSimple::CharDevice),
+        S_IFBLK => Ok(FileTypeSimple::BlockDevice),
+        S_IFDIR => Ok(FileTypeSimple::Directory),
+        S_IFREG => Ok(FileTypeSimple::RegularFile),
+        S_IFLNK => Ok(FileTypeSimple::Symlink),
+        S_IFSOCK => Ok(FileTypeSimple::Socket),
+        _ => Err(format!("Unkown file type in mode: {:o}", mode)),
+    }
+}
+
+// the attr written by tostat, times keep their nanoseconds. stat has no
+// creation time, crtime is taken from ctime
+pub fn fromstat(statbuf: &[u8]) -> Result<FileAttrSimple, String> {
+    if statbuf.len() < std::mem::size_of::<stat>() {
+        return Err(format!("Invalid length: {}", statbuf.len()));
+    }
+    let st = unsafe { std::ptr::read_unaligned(statbuf.as_ptr() as *const stat) };
+    let ctime = time_of_stat(st.st_ctime, st.st_ctime_nsec)?;
+    Ok(FileAttrSimple {
+        size: st.st_size as u64,
+        blocks: st.st_blocks as u64,
+        atime: time_of_stat(st.st_atime, st.st_atime_nsec)?,
+        mtime: time_of_stat(st.st_mtime, st.st_mtime_nsec)?,
+        ctime,
+        crtime: ctime,
+        kind: kind_of_mode(st.st_mode)?.into(),
+        perm: (st.st_mode & !S_IFMT) as u16,
+        nlink: st.st_nlink as u32,
+        uid: st.st_uid,
+        gid: st.st_gid,
+        rdev: st.st_rdev as u32,
+        flags: 0,
+        blksize: st.st_blksize as u32,
+        version: 0,
+        inline_data: None,
+    })
+}
+
+// the attr written by tostatx, crtime comes from btime
+pub fn fromstatx(statxbuf: &[u8]) -> Result<FileAttrSimple, String> {
+    if statxbuf.len() < std::mem::size_of::<statx>() {
+        return Err(format!("Invalid length: {}", statxbuf.len()));
+    }
+    let stx = unsafe { std::ptr::read_unaligned(statxbuf.as_ptr() as *const statx) };
+    let time = |ts: statx_timestamp| time_of_stat(ts.tv_sec, ts.tv_nsec as i64);
+    Ok(FileAttrSimple {
+        size: stx.stx_size,
+        blocks: stx.stx_blocks,
+        atime: time(stx.stx_atime)?,
+        mtime: time(stx.stx_mtime)?,
+        ctime: time(stx.stx_ctime)?,
+        crtime: time(stx.stx_btime)?,
+        kind: kind_of_mode(stx.stx_mode as u32)?.into(),
+        perm: stx.stx_mode & !(S_IFMT as u16),
+        nlink: stx.stx_nlink,
+        uid: stx.stx_uid,
+        gid: stx.stx_gid,
+        rdev: 0,
+        flags: 0,
+        blksize: stx.stx_blksize,
+        version: 0,
+        inline_data: None,
+    })
+}
+
 impl From<FileAttrSimple> for fuser::FileAttr {
     fn from(attr: FileAttrSimple) -> Self {
-        let kind = match attr.kind {
-            0 => FileType::NamedPipe,
-            1 => FileType::CharDevice,
-            2 => FileType::BlockDevice,
-            3 => FileType::Directory,
-            4 => FileType::RegularFile,
-            5 => FileType::Symlink,
-            6 => FileType::Socket,
-            _ => FileType::RegularFile,
+        let kind = match FileTypeSimple::try_from(attr.kind) {
+            Ok(kind) => kind.into(),
+            Err(_) => FileType::RegularFile,
         };
         fuser::FileAttr {
             ino: 0,
@@ -728,6 +1284,29 @@ impl From<FileAttrSimple> for fuser::FileAttr {
     }
 }
 
+impl From<fuser::FileAttr> for FileAttrSimple {
+    fn from(attr: fuser::FileAttr) -> Self {
+        FileAttrSimple {
+            size: attr.size,
+            blocks: attr.blocks,
+            atime: attr.atime,
+            mtime: attr.mtime,
+            ctime: attr.ctime,
+            crtime: attr.crtime,
+            kind: FileTypeSimple::from(attr.kind).into(),
+            perm: attr.perm,
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            flags: attr.flags,
+            blksize: attr.blksize,
+            version: 0,
+            inline_data: None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct SubDirectory {
     pub sub_dir: BTreeMap<String, String>,
@@ -769,6 +1348,34 @@ impl SubDirectory {
 pub struct ReadFileSendMetaData {
     pub offset: i64,
     pub size: u32,
+    // answer a ReadFileRecvMetaData with the crc32c of the returned bytes
+    pub verify_checksum: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ReadFileRecvMetaData {
+    pub checksum: u32,
+}
+
+impl ReadFileRecvMetaData {
+    pub fn verify(&self, data: &[u8]) -> bool {
+        crc32c::crc32c(data) == self.checksum
+    }
+}
+
+// several ranges of a file read in one round trip, answered with the bytes of
+// every range one after another and a ReadFileVectoredRecvMetaData
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ReadFileVectoredSendMetaData {
+    // the handle of the client, servers find the file by its path
+    pub fh: u64,
+    pub ranges: Vec<(i64, u32)>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ReadFileVectoredRecvMetaData {
+    // bytes read for each range, fewer than asked for a range reaching past the end
+    pub lengths: Vec<u32>,
 }
 
 #[repr(C)]
@@ -782,23 +1389,171 @@ pub struct LinuxDirent {
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct WriteFileSendMetaData {
     pub offset: i64,
+    // write only if the file's mtime is still this one, CONFLICT otherwise
+    pub if_mtime: Option<SystemTime>,
+    // crc32c of the data, which is refused with EIO when it does not match
+    pub checksum: Option<u32>,
+    // O_APPEND, offset is ignored and the data lands where the file ends
+    pub append: bool,
+}
+
+// the answer to an appending write. size comes first, as the u32 every other
+// write answers with, then the offset the server wrote the data at
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AppendFileRecvMetaData {
+    pub size: u32,
+    pub offset: i64,
+}
+
+// copy_file_range(2), sent to the server of the destination, which is the path of
+// the request. servers find files by path, so the source is named by src_path and
+// the inodes are the client's, only there for the logs
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CopyFileRangeSendMetaData {
+    pub src_ino: u64,
+    pub src_path: String,
+    pub src_offset: i64,
+    pub dst_ino: u64,
+    pub dst_offset: i64,
+    pub length: u64,
+}
+
+// one piece of a large upload, the chunk is the data of the request. fh is chosen
+// by the client to tell its uploads apart and comes back in the ack
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct WriteFileChunkSendMetaData {
+    pub fh: u64,
+    pub chunk_offset: u64,
+    // the file is synced before the last chunk is acked
+    pub is_last: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct WriteFileChunkRecvMetaData {
+    pub fh: u64,
+    pub chunk_offset: u64,
+    // bytes of the chunk in the store
+    pub stored: u64,
+}
+
+// adds delta to the 8 byte little endian counter the file starts with, answered
+// with the new value as 8 little endian bytes
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AtomicIncrementSendMetaData {
+    // the inode of the client, servers find the file by its path
+    pub ino: u64,
+    pub delta: i64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct DirectoryEntrySendMetaData {
+    // a FileTypeSimple, turned into a d_type only when the entry is read
     pub file_type: u8,
     pub file_name: String,
 }
 
+// sent to the server holding the Volume by the one a file of the volume grows or
+// shrinks on, a delta taking the volume past its size fails with ENOSPC
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ChargeVolumeSendMetaData {
+    pub delta: i64,
+}
+
+// the space of a volume as statfs reports it. a volume without a quota and the
+// files of any volume are not limited, so their totals are u64::MAX
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct StatFsRecvMetaData {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+    pub block_size: u32,
+}
+
+// a new_size of 0 lifts the quota, any other below the used size fails with ENOSPC
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ResizeVolumeSendMetaData {
+    pub volume_name: String,
+    pub new_size: u64,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct TruncateFileSendMetaData {
     pub length: i64,
 }
 
+// the most operations one Batch carries, so their results fit in MAX_DATA_LENGTH
+pub const MAX_BATCH_OPS: usize = 1024;
+
+// one operation of a Batch, run as if it was sent on its own without data
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BatchOp {
+    pub operation_type: u32,
+    pub path: String,
+    pub meta_data: Vec<u8>,
+}
+
+// sent as data, a batch of many operations does not fit in metadata
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BatchSendData {
+    pub ops: Vec<BatchOp>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BatchOpResult {
+    pub status: i32,
+    pub meta_data: Vec<u8>,
+}
+
+// sent as data. the operations run in order until one fails, which is the last
+// of results, completed counts the ones that succeeded
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BatchRecvData {
+    pub completed: u32,
+    pub results: Vec<BatchOpResult>,
+}
+
+// fallocate(2), mode takes FALLOC_FL_KEEP_SIZE and FALLOC_FL_PUNCH_HOLE, which
+// like on linux only comes with FALLOC_FL_KEEP_SIZE
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct FallocateSendMetaData {
+    pub offset: i64,
+    pub length: i64,
+    pub mode: u32,
+}
+
+// only the fields present are changed, a size truncates or extends the file
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct SetAttrSendMetaData {
+    pub perm: Option<u16>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct ReadDirSendMetaData {
     pub offset: i64,
     pub size: u32,
+    // the cursor of the previous page, continue after that entry instead of
+    // skipping offset entries
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ReadDirRecvMetaData {
+    // where the next page starts, None for an empty page or a sharded directory
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ReadDirShardRecvMetaData {
+    // entries of the directory held by the shard
+    pub total: u32,
+    // entries packed in the response, fewer than the rest of the shard when the size ran out
+    pub returned: u32,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -813,6 +1568,56 @@ pub struct CreateFileSendMetaData {
     pub umask: u32,
     pub flags: i32,
     pub name: String,
+    // create the file without a directory entry, the name only identifies it until Link
+    pub tmpfile: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct LinkSendMetaData {
+    pub name: String,
+}
+
+// sent to the path being renamed. flags takes RENAME_NOREPLACE, RENAME_EXCHANGE
+// is refused with EINVAL
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RenameSendMetaData {
+    pub new_parent: String,
+    pub new_name: String,
+    pub flags: u32,
+}
+
+// sent with RenameNoParent to the file staged for a rename. it takes the place of
+// new_path on the same server, with attr's owner, permissions, times and version
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RenameNoParentSendMetaData {
+    pub new_path: String,
+    pub attr: FileAttrSimple,
+}
+
+// sent with DirectoryRenameEntry to the parent of a file renamed within it. the
+// entry file_name becomes new_name, dropping the one of type replaced held there
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct DirectoryRenameEntrySendMetaData {
+    pub file_type: u8,
+    pub file_name: String,
+    pub new_name: String,
+    pub replaced: Option<u8>,
+}
+
+// sent to the parent with CreateSymlink and to the new path with
+// CreateSymlinkNoParent, the target is kept as the data of the symlink
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CreateSymlinkSendMetaData {
+    pub name: String,
+    pub target: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct CreateAndWriteSendMetaData {
+    pub mode: u32,
+    pub umask: u32,
+    pub name: String,
+    pub data: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -836,19 +1641,59 @@ pub struct UpdateServerStatusSendMetaData {
     pub status: ServerStatus,
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct GetClusterStatusRecvMetaData {
     pub status: ClusterStatus,
+    // 100 and 0 while the cluster is Idle
+    pub rebalance_progress_pct: u8,
+    pub rebalance_bytes_remaining: u64,
+    // how long a client should wait before asking again
+    pub min_poll_interval_ms: u32,
+}
+
+impl GetClusterStatusRecvMetaData {
+    // the hint kept within reason, so a broken manager can neither make its
+    // clients spin nor stop them from ever polling again
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.min_poll_interval_ms.clamp(100, 60_000) as u64)
+    }
+}
+
+// bytes a server has to move during the current rebalance, reported in its heartbeat
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct TransferProgress {
+    pub total_bytes: u64,
+    pub remaining_bytes: u64,
+    // bytes per second moved lately
+    #[serde(default)]
+    pub bytes_per_sec: u64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct GetHashRingInfoRecvMetaData {
     pub hash_ring_info: Vec<(String, usize)>,
+    // bumped by the manager every time a new hash ring takes over
+    pub epoch: u64,
+}
+
+// request flag, the caller wants a MOVED status instead of having the request
+// forwarded when the path is owned by another server
+pub const MOVED_ALLOWED: u32 = 1 << 31;
+// request flag, a write of data moved from another server, the volume was charged
+// for it when a client first wrote it
+pub const MOVED_DATA: u32 = 1 << 30;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct MovedRecvMetaData {
+    pub new_server: String,
+    pub epoch: u64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct AddNodesSendMetaData {
-    pub new_servers_info: Vec<(String, usize)>,
+    // the address and weight of each server, the manager gives a server weight
+    // times its virtual_nodes on the ring
+    pub new_servers_info: Vec<(String, u32)>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -856,6 +1701,50 @@ pub struct DeleteNodesSendMetaData {
     pub deleted_servers_info: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum MembershipEvent {
+    Added,
+    Removed,
+    // left the hash ring with its files moved away, still known to the manager
+    // until it is removed
+    Drained,
+}
+
+// a change to the servers of the cluster, epoch is the one of the hash ring the
+// change goes into
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MembershipLogEntry {
+    pub timestamp: SystemTime,
+    pub event: MembershipEvent,
+    pub server: String,
+    pub epoch: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GetDrainStatusRecvMetaData {
+    // what the server reported last, it goes through PreTransfer and Transferring
+    // while its files move
+    pub status: ServerStatus,
+    // every file moved and the ring without the server took over, it may be removed
+    pub drained: bool,
+    pub progress_pct: u8,
+    pub bytes_remaining: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GetMembershipLogRecvMetaData {
+    // oldest first
+    pub entries: Vec<MembershipLogEntry>,
+}
+
+// sent as data, a ring of many virtual nodes does not fit in metadata
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GetRingLayoutRecvData {
+    pub epoch: u64,
+    // (position, server) of every virtual node in ring order
+    pub vnodes: Vec<(u64, String)>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct CheckFileSendMetaData {
     pub file_attr: FileAttrSimple,
@@ -869,6 +1758,86 @@ pub struct CheckDirSendMetaData {
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct CreateVolumeSendMetaData {
     pub size: u64,
+    #[serde(default)]
+    pub atime_policy: AtimePolicy,
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    #[serde(default)]
+    pub allowed_ops: Option<Vec<u32>>,
+}
+
+// a request being handled by a server, answered by ListRunningOps
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RunningOpInfo {
+    // assigned by the server, the id KillOp takes
+    pub request_id: u64,
+    pub op: u32,
+    pub path: String,
+    // id of the connection the request came in on
+    pub client: u32,
+    pub elapsed_ms: u64,
+    // killed and not yet given up
+    pub cancelled: bool,
+    // whether KillOp may stop it, a request that changes files is left to finish
+    pub killable: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct KillOpSendMetaData {
+    pub request_id: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct RebalanceVolumeSendMetaData {
+    pub volume: String,
+}
+
+// sent to every server, each pins the files of the volume to target_server and
+// moves the ones it holds there
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct MigrateVolumeSendMetaData {
+    pub volume: String,
+    pub target_server: String,
+}
+
+// bytes per second the server may send while moving files, 0 for no limit
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct SetTransferRateSendMetaData {
+    pub bytes_per_sec: u64,
+}
+
+// a volume root is its own parent and has an empty name
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GetParentRecvMetaData {
+    pub parent: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct VerifyDirCountSendMetaData {
+    // store the real count when it differs from the stored one
+    pub repair: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct VerifyDirCountRecvMetaData {
+    // entry count kept for the directory before any repair
+    pub stored: u32,
+    // entries found in the directory, on every shard if it is sharded
+    pub actual: u32,
+    pub repaired: bool,
+}
+
+impl VerifyDirCountRecvMetaData {
+    pub fn discrepancy(&self) -> i64 {
+        self.actual as i64 - self.stored as i64
+    }
+}
+
+// answer to InitVolume, what the server accepts from the mounting client
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct InitVolumeRecvMetaData {
+    pub max_write_size: u32,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -878,19 +1847,865 @@ pub struct MountVolumeSendMetaData {
     pub read_only: bool,
 }
 
+// live: the process answers requests
+// ready: the server has its ring share and a writable store
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct HealthStatus {
+    pub live: bool,
+    pub ready: bool,
+    pub server_status: ServerStatus,
+    pub store_writable: bool,
+    pub details: String,
+}
+
+// the values a server runs with, answered by GetServerConfig. the server keeps no
+// secrets, anything added here that is one must be left out of the answer
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ServerConfig {
+    pub address: String,
+    pub manager_address: String,
+    pub database_path: String,
+    pub storage_path: String,
+    // largest data section of a single WriteFile
+    pub max_write_size: u32,
+    // largest data section of any rpc frame
+    pub max_frame_size: u32,
+    pub chunk_size: u32,
+    // bytes per second for each client, 0 for no limit
+    pub client_bandwidth_limit: u64,
+    // bytes per second sent while moving files, 0 for no limit
+    pub transfer_rate_limit: u64,
+    // batched flush window, 0 when every write goes to the store unbatched
+    pub flush_interval_ms: u64,
+    pub write_combine_size: u64,
+    pub dir_shard_threshold: u32,
+    pub max_in_flight: u64,
+    pub heartbeat: bool,
+    // whether startup checked every file against its data and every directory's count
+    pub thorough_check: bool,
+    // failed manager contacts in a row before the server turns degraded, 0 for never
+    pub manager_failure_limit: u32,
+    // files up to this size keep their data with their attr, 0 when inlining is off
+    pub inline_threshold: u64,
+    // requests taking at least this long are logged, 0 for none
+    pub slow_op_threshold_ms: u64,
+    // whether the listener and the connections to peers use tls
+    pub tls: bool,
+    // where /metrics is served, None when it is not
+    pub metrics_address: Option<String>,
+}
+
+// bumped when a change to the wire format breaks older peers
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// what a server answers, so that a client can tell which operations it may send
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Capabilities {
+    pub supported_ops: Vec<u32>,
+    pub features: Vec<String>,
+    pub max_frame_size: u32,
+    pub protocol_version: u32,
+}
+
+impl Capabilities {
+    pub fn supports(&self, operation_type: u32) -> bool {
+        self.supported_ops.contains(&operation_type)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+// drops what a client caches of an inode, or of the whole volume without one
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct InvalidateClientCacheSendMetaData {
+    pub volume: String,
+    pub ino: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct LazyUnmountSendMetaData {
+    pub volume: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct StatAndChecksumSendMetaData {
+    pub paths: Vec<String>,
+}
+
+impl StatAndChecksumSendMetaData {
+    // upper bound of the serialized StatAndChecksumRecvData for these paths
+    pub fn max_response_len(&self) -> usize {
+        self.paths
+            .iter()
+            .map(|path| path.len() + 256)
+            .sum::<usize>()
+            + 8
+    }
+}
+
+// (path, attr, crc32c of the whole file), missing files are left out
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct StatAndChecksumRecvData {
+    pub entries: Vec<(String, FileAttrSimple, u32)>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ClientBandwidthInfo {
+    pub client_id: u32,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_bytes_per_sec: u64,
+    pub write_bytes_per_sec: u64,
+}
+
+// when a ReadFile updates the access time of the file
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AtimePolicy {
+    Noatime = 0,
+    #[default]
+    Relatime = 1,
+    Strictatime = 2,
+}
+
+// relatime still refreshes an atime which is older than this
+const RELATIME_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl AtimePolicy {
+    pub fn should_update(
+        &self,
+        atime: SystemTime,
+        mtime: SystemTime,
+        ctime: SystemTime,
+        now: SystemTime,
+    ) -> bool {
+        match self {
+            Self::Noatime => false,
+            Self::Relatime => {
+                atime <= mtime
+                    || atime <= ctime
+                    || now
+                        .duration_since(atime)
+                        .is_ok_and(|stale| stale >= RELATIME_INTERVAL)
+            }
+            Self::Strictatime => true,
+        }
+    }
+}
+
+impl FromStr for AtimePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noatime" => Ok(Self::Noatime),
+            "relatime" => Ok(Self::Relatime),
+            "strictatime" => Ok(Self::Strictatime),
+            _ => Err(format!("Unkown atime policy: {}", s)),
+        }
+    }
+}
+
+impl Display for AtimePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Noatime => write!(f, "noatime"),
+            Self::Relatime => write!(f, "relatime"),
+            Self::Strictatime => write!(f, "strictatime"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Volume {
     pub name: String,
+    // the quota of the volume, 0 for none
     pub size: u64,
+    // the bytes of the regular files of the volume on all servers
     pub used_size: u64,
+    #[serde(default)]
+    pub atime_policy: AtimePolicy,
+    // largest size a file of the volume may grow to, None for no limit
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    // the mutating operations clients may run on the volume, None for all of them.
+    // operations that only read are always allowed
+    #[serde(default)]
+    pub allowed_ops: Option<Vec<u32>>,
 }
 
 impl Display for Volume {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Volume {{ name: {}, size: {}, used_size: {} }}",
-            self.name, self.size, self.used_size
+            "Volume {{ name: {}, size: {}, used_size: {}, atime_policy: {}, max_file_size: {}, allowed_ops: {} }}",
+            self.name,
+            self.size,
+            self.used_size,
+            self.atime_policy,
+            match self.max_file_size {
+                Some(max_file_size) => max_file_size.to_string(),
+                None => "unlimited".to_owned(),
+            },
+            match &self.allowed_ops {
+                Some(allowed_ops) => format!("{:?}", allowed_ops),
+                None => "all".to_owned(),
+            }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // d_ino and d_off take 16 bytes and d_reclen 2, d_type adds one more
+    // byte ahead of the name in dirent64
+    const _: () = assert!(std::mem::offset_of!(LinuxDirent, d_reclen) == 16);
+    const _: () = assert!(std::mem::offset_of!(LinuxDirent, d_name) == 18);
+    const _: () = assert!(std::mem::offset_of!(libc::dirent64, d_name) == 19);
+
+    const ALL_FILE_TYPES: [FileType; 7] = [
+        FileType::NamedPipe,
+        FileType::CharDevice,
+        FileType::BlockDevice,
+        FileType::Directory,
+        FileType::RegularFile,
+        FileType::Symlink,
+        FileType::Socket,
+    ];
+
+    #[test]
+    fn test_file_attr_kind_round_trip() {
+        for kind in ALL_FILE_TYPES {
+            let mut attr = crate::common::util::empty_file();
+            attr.kind = kind;
+            let simple = FileAttrSimple::from(attr);
+            assert_eq!(simple.kind, u32::from(FileTypeSimple::from(kind)));
+            let back: FileAttr = simple.into();
+            assert_eq!(back.kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_file_attr_simple_new_kind() {
+        for kind in ALL_FILE_TYPES {
+            let attr: FileAttr = FileAttrSimple::new(kind.into()).into();
+            assert_eq!(attr.kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_to_dirent_type() {
+        assert_eq!(FileTypeSimple::Directory.to_dirent_type(), 4);
+        assert_eq!(FileTypeSimple::RegularFile.to_dirent_type(), 8);
+        assert_eq!(FileTypeSimple::Symlink.to_dirent_type(), 10);
+        assert_eq!(FileTypeSimple::NamedPipe.to_dirent_type(), 1);
+        assert_eq!(FileTypeSimple::CharDevice.to_dirent_type(), 2);
+        assert_eq!(FileTypeSimple::BlockDevice.to_dirent_type(), 6);
+        assert_eq!(FileTypeSimple::Socket.to_dirent_type(), 12);
+    }
+
+    #[test]
+    fn test_server_status_le_bytes() {
+        for status in [
+            ServerStatus::Initializing,
+            ServerStatus::PreTransfer,
+            ServerStatus::Transferring,
+            ServerStatus::PreFinish,
+            ServerStatus::Finishing,
+            ServerStatus::Finished,
+        ] {
+            let bytes = status.to_le_bytes();
+            assert_eq!(bytes, (status as u32).to_le_bytes());
+            assert_eq!(ServerStatus::from_le_bytes(bytes), Ok(status));
+        }
+        assert!(ServerStatus::from_le_bytes(0u32.to_le_bytes()).is_err());
+        assert!(ServerStatus::from_le_bytes(301u32.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_cluster_status_le_bytes() {
+        for status in [
+            ClusterStatus::Initializing,
+            ClusterStatus::Idle,
+            ClusterStatus::NodesStarting,
+            ClusterStatus::SyncNewHashRing,
+            ClusterStatus::PreTransfer,
+            ClusterStatus::Transferring,
+            ClusterStatus::PreFinish,
+            ClusterStatus::Finishing,
+            ClusterStatus::StatusError,
+        ] {
+            let bytes = status.to_le_bytes();
+            assert_eq!(bytes, (status as u32).to_le_bytes());
+            assert_eq!(ClusterStatus::from_le_bytes(bytes), Ok(status));
+        }
+        assert!(ClusterStatus::from_le_bytes(201u32.to_le_bytes()).is_err());
+        assert!(ClusterStatus::from_le_bytes(u32::MAX.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_cluster_status_transitions() {
+        use ClusterStatus::*;
+        for (from, to) in [
+            (Initializing, Idle),
+            (Idle, NodesStarting),
+            (NodesStarting, SyncNewHashRing),
+            (SyncNewHashRing, PreTransfer),
+            (PreTransfer, Transferring),
+            (Transferring, PreFinish),
+            (PreFinish, Finishing),
+            (Finishing, Idle),
+            (Transferring, StatusError),
+            (Idle, Idle),
+        ] {
+            assert!(from.can_transition_to(to), "{:?} -> {:?}", from, to);
+        }
+        for (from, to) in [
+            (Idle, Finishing),
+            (Idle, Transferring),
+            (Initializing, NodesStarting),
+            (NodesStarting, Idle),
+            (Transferring, PreTransfer),
+            (Finishing, NodesStarting),
+            (StatusError, Idle),
+        ] {
+            assert!(!from.can_transition_to(to), "{:?} -> {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn test_health_status_serde() {
+        let health = HealthStatus {
+            live: true,
+            ready: false,
+            server_status: ServerStatus::Initializing,
+            store_writable: true,
+            details: "server is initializing".to_string(),
+        };
+        let bytes = bincode::serialize(&health).unwrap();
+        assert_eq!(
+            bincode::deserialize::<HealthStatus>(&bytes).unwrap(),
+            health
+        );
+    }
+
+    #[test]
+    fn test_server_config_serde() {
+        let config = ServerConfig {
+            address: "127.0.0.1:8085".to_string(),
+            manager_address: "127.0.0.1:8081".to_string(),
+            database_path: "/tmp/sealfs/db".to_string(),
+            storage_path: "/tmp/sealfs/storage".to_string(),
+            max_write_size: 1 << 20,
+            max_frame_size: 1 << 23,
+            chunk_size: 65536,
+            client_bandwidth_limit: 0,
+            transfer_rate_limit: 1 << 20,
+            flush_interval_ms: 100,
+            write_combine_size: 4096,
+            dir_shard_threshold: 0,
+            max_in_flight: 64,
+            heartbeat: true,
+            thorough_check: false,
+            manager_failure_limit: 3,
+            inline_threshold: 4096,
+            slow_op_threshold_ms: 500,
+            tls: true,
+            metrics_address: Some("127.0.0.1:9100".to_string()),
+        };
+        let bytes = bincode::serialize(&config).unwrap();
+        assert_eq!(
+            bincode::deserialize::<ServerConfig>(&bytes).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities {
+            supported_ops: vec![
+                OperationType::ReadFile.into(),
+                OperationType::WriteFile.into(),
+                ManagerOperationType::HealthCheck.into(),
+            ],
+            features: vec!["tmpfile".to_string()],
+            max_frame_size: 1 << 23,
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let bytes = bincode::serialize(&capabilities).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Capabilities>(&bytes).unwrap(),
+            capabilities
+        );
+
+        assert!(capabilities.supports(OperationType::WriteFile.into()));
+        assert!(capabilities.supports(ManagerOperationType::HealthCheck.into()));
+        // an op the server does not list is unavailable, even one the client knows
+        assert!(!capabilities.supports(OperationType::Link.into()));
+        assert!(!Capabilities::default().supports(OperationType::ReadFile.into()));
+        assert!(capabilities.has_feature("tmpfile"));
+        assert!(!capabilities.has_feature("dir_sharding"));
+    }
+
+    #[test]
+    fn test_cluster_status_meta_data_serde() {
+        let md = GetClusterStatusRecvMetaData {
+            status: ClusterStatus::Transferring,
+            rebalance_progress_pct: 42,
+            rebalance_bytes_remaining: 1 << 40,
+            min_poll_interval_ms: 1000,
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        assert_eq!(
+            bincode::deserialize::<GetClusterStatusRecvMetaData>(&bytes).unwrap(),
+            md
+        );
+        assert_eq!(md.poll_interval(), Duration::from_secs(1));
+        for (hint, interval) in [(0, 100), (u32::MAX, 60_000)] {
+            let md = GetClusterStatusRecvMetaData {
+                min_poll_interval_ms: hint,
+                ..md.clone()
+            };
+            assert_eq!(md.poll_interval(), Duration::from_millis(interval));
+        }
+    }
+
+    #[test]
+    fn test_relatime() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+        let policy = AtimePolicy::Relatime;
+        // read after a write
+        assert!(policy.should_update(now - hour, now, now - hour, now));
+        // read again, atime is already newer than mtime and ctime
+        assert!(!policy.should_update(now - hour, now - 2 * hour, now - 2 * hour, now));
+        // a day without refresh
+        assert!(policy.should_update(now - 25 * hour, now - 30 * hour, now - 30 * hour, now));
+        assert_eq!(AtimePolicy::default(), AtimePolicy::Relatime);
+    }
+
+    #[test]
+    fn test_stat_and_checksum_meta_data() {
+        let md = StatAndChecksumSendMetaData {
+            paths: vec!["volume/a".to_string(), "volume/dir/b".to_string()],
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        assert_eq!(
+            bincode::deserialize::<StatAndChecksumSendMetaData>(&bytes).unwrap(),
+            md
+        );
+
+        let data = StatAndChecksumRecvData {
+            entries: md
+                .paths
+                .iter()
+                .map(|path| (path.clone(), FileAttrSimple::default(), 0x1234_5678))
+                .collect(),
+        };
+        let bytes = bincode::serialize(&data).unwrap();
+        assert!(bytes.len() <= md.max_response_len());
+        assert_eq!(
+            bincode::deserialize::<StatAndChecksumRecvData>(&bytes).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_create_and_write_send_meta_data() {
+        let md = CreateAndWriteSendMetaData {
+            mode: 0o644,
+            umask: 0o022,
+            name: "small_file".to_string(),
+            data: vec![1u8; 100],
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        let decoded: CreateAndWriteSendMetaData = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(md, decoded);
+        assert_eq!(u32::from(OperationType::CreateAndWrite), 68);
+        assert!(matches!(
+            OperationType::try_from(68),
+            Ok(OperationType::CreateAndWrite)
+        ));
+    }
+
+    #[test]
+    fn test_read_file_vectored_meta_data() {
+        let md = ReadFileVectoredSendMetaData {
+            fh: 3,
+            ranges: vec![(0, 4), (100, 8), (-1, 0)],
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        let decoded: ReadFileVectoredSendMetaData = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(md, decoded);
+        let md = ReadFileVectoredRecvMetaData {
+            lengths: vec![4, 0, 8],
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        let decoded: ReadFileVectoredRecvMetaData = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(md, decoded);
+        assert!(matches!(
+            OperationType::try_from(74),
+            Ok(OperationType::ReadFileVectored)
+        ));
+    }
+
+    #[test]
+    fn test_membership_log_serde() {
+        let md = GetMembershipLogRecvMetaData {
+            entries: vec![MembershipLogEntry {
+                timestamp: UNIX_EPOCH + Duration::new(1_700_000_000, 5),
+                event: MembershipEvent::Removed,
+                server: "127.0.0.1:8086".to_string(),
+                epoch: 3,
+            }],
+        };
+        let bytes = bincode::serialize(&md).unwrap();
+        let decoded: GetMembershipLogRecvMetaData = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(md, decoded);
+        assert!(matches!(
+            ManagerOperationType::try_from(135),
+            Ok(ManagerOperationType::GetMembershipLog)
+        ));
+    }
+
+    #[test]
+    fn test_ring_layout_serde() {
+        let data = GetRingLayoutRecvData {
+            epoch: 2,
+            vnodes: vec![
+                (0x12, "127.0.0.1:8085".to_string()),
+                (u64::MAX, "127.0.0.1:8086".to_string()),
+            ],
+        };
+        let bytes = bincode::serialize(&data).unwrap();
+        assert_eq!(
+            bincode::deserialize::<GetRingLayoutRecvData>(&bytes).unwrap(),
+            data
+        );
+        assert!(matches!(
+            ManagerOperationType::try_from(139),
+            Ok(ManagerOperationType::GetRingLayout)
+        ));
+    }
+
+    #[test]
+    fn test_operation_type_le_bytes() {
+        let (mut operations, mut manager_operations) = (0, 0);
+        for value in 0..=200u32 {
+            if let Ok(operation) = OperationType::try_from(value) {
+                let bytes = operation.to_le_bytes();
+                assert_eq!(bytes, value.to_le_bytes());
+                assert_eq!(OperationType::from_le_bytes(&bytes), Ok(operation));
+                operations += 1;
+            }
+            if let Ok(operation) = ManagerOperationType::try_from(value) {
+                let bytes = operation.to_le_bytes();
+                assert_eq!(bytes, value.to_le_bytes());
+                assert_eq!(ManagerOperationType::from_le_bytes(&bytes), Ok(operation));
+                manager_operations += 1;
+            }
+        }
+        assert_eq!(operations, 49);
+        assert_eq!(manager_operations, 24);
+        for value in [0, 70, 1000, u32::MAX] {
+            assert!(OperationType::from_le_bytes(&value.to_le_bytes()).is_err());
+            assert!(ManagerOperationType::from_le_bytes(&value.to_le_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_operation_type_names() {
+        let mut names = std::collections::HashSet::new();
+        assert!(names.insert(OperationType::Unkown.as_str()));
+        for value in 0..=200u32 {
+            if let Ok(operation) = OperationType::try_from(value) {
+                assert!(!operation.as_str().is_empty());
+                assert!(names.insert(operation.as_str()), "{}", operation);
+                assert_eq!(operation.to_string(), operation.as_str());
+            }
+        }
+        assert_eq!(names.len(), 50);
+
+        let mut names = std::collections::HashSet::new();
+        for value in 0..=200u32 {
+            if let Ok(operation) = ManagerOperationType::try_from(value) {
+                assert!(!operation.as_str().is_empty());
+                assert!(names.insert(operation.as_str()), "{:?}", operation);
+                assert_eq!(format!("{:?}", operation), operation.as_str());
+            }
+        }
+        assert_eq!(names.len(), 24);
+        assert_eq!(OperationType::WriteFile.as_str(), "WriteFile");
+        assert_eq!(ManagerOperationType::GetHashRing.as_str(), "GetHashRing");
+    }
+
+    #[test]
+    fn test_unknown_operation_type() {
+        for value in [0, 70, 1000, u32::MAX] {
+            assert!(OperationType::try_from(value).is_err());
+            assert!(ManagerOperationType::try_from(value).is_err());
+        }
+    }
+
+    #[test]
+    fn test_file_attr_simple_bytes() {
+        let mut attr = FileAttrSimple::new(FileTypeSimple::Directory);
+        attr.blocks = 8;
+        attr.atime = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        attr.mtime = UNIX_EPOCH - Duration::new(10, 250_000_000);
+        attr.ctime = UNIX_EPOCH;
+        attr.perm = 0o755;
+        attr.nlink = 2;
+        attr.uid = 1000;
+        attr.gid = 1000;
+        attr.flags = DIR_SHARDED;
+        attr.blksize = 4096;
+        attr.version = u64::MAX;
+        let bytes = attr.to_bytes();
+        assert_eq!(bytes.len(), FileAttrSimple::SERIALIZED_LEN);
+        // little-endian whatever the host
+        assert_eq!(bytes[0..8], 4096u64.to_le_bytes());
+        assert_eq!(FileAttrSimple::from_bytes(&bytes), Ok(attr));
+
+        assert!(FileAttrSimple::from_bytes(&bytes[..FileAttrSimple::SERIALIZED_LEN - 1]).is_err());
+        let mut bad_nanos = bytes.clone();
+        bad_nanos[24..28].copy_from_slice(&1_000_000_000u32.to_le_bytes());
+        assert!(FileAttrSimple::from_bytes(&bad_nanos).is_err());
+    }
+
+    #[test]
+    fn test_stat_nanos() {
+        let attr: FileAttr = FileAttrSimple {
+            atime: UNIX_EPOCH + Duration::new(1_700_000_001, 1),
+            mtime: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            ctime: UNIX_EPOCH + Duration::new(1_700_000_002, 999_999_999),
+            crtime: UNIX_EPOCH + Duration::new(1_600_000_000, 500_000_000),
+            ..Default::default()
+        }
+        .into();
+
+        let mut st: stat = unsafe { std::mem::zeroed() };
+        tostat(&attr, unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut st as *mut stat as *mut u8,
+                std::mem::size_of::<stat>(),
+            )
+        });
+        assert_eq!((st.st_atime, st.st_atime_nsec), (1_700_000_001, 1));
+        assert_eq!(
+            (st.st_mtime, st.st_mtime_nsec),
+            (1_700_000_000, 123_456_789)
+        );
+        assert_eq!(
+            (st.st_ctime, st.st_ctime_nsec),
+            (1_700_000_002, 999_999_999)
+        );
+
+        let mut stx: statx = unsafe { std::mem::zeroed() };
+        tostatx(&attr, unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut stx as *mut statx as *mut u8,
+                std::mem::size_of::<statx>(),
+            )
+        });
+        assert_eq!(
+            (stx.stx_mtime.tv_sec, stx.stx_mtime.tv_nsec),
+            (1_700_000_000, 123_456_789)
+        );
+        assert_eq!(
+            (stx.stx_atime.tv_sec, stx.stx_atime.tv_nsec),
+            (1_700_000_001, 1)
+        );
+        assert_eq!(
+            (stx.stx_ctime.tv_sec, stx.stx_ctime.tv_nsec),
+            (1_700_000_002, 999_999_999)
+        );
+        assert_eq!(
+            (stx.stx_btime.tv_sec, stx.stx_btime.tv_nsec),
+            (1_600_000_000, 500_000_000)
+        );
+    }
+
+    #[test]
+    fn test_stat_round_trip() {
+        let new_attr = || FileAttrSimple {
+            size: 4097,
+            blocks: 9,
+            atime: UNIX_EPOCH + Duration::new(1_700_000_001, 1),
+            mtime: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            ctime: UNIX_EPOCH + Duration::new(1_700_000_002, 999_999_999),
+            crtime: UNIX_EPOCH + Duration::new(1_700_000_002, 999_999_999),
+            kind: FileTypeSimple::Symlink.into(),
+            perm: 0o4755,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            blksize: 4096,
+            ..Default::default()
+        };
+        let attr: FileAttr = new_attr().into();
+
+        let mut st: stat = unsafe { std::mem::zeroed() };
+        let statbuf = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut st as *mut stat as *mut u8,
+                std::mem::size_of::<stat>(),
+            )
+        };
+        tostat(&attr, statbuf);
+        let back = fromstat(statbuf).unwrap();
+        assert_eq!(
+            back.mtime,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)
+        );
+        assert_eq!(back, new_attr());
+
+        let mut stx: statx = unsafe { std::mem::zeroed() };
+        let statxbuf = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut stx as *mut statx as *mut u8,
+                std::mem::size_of::<statx>(),
+            )
+        };
+        let mut expected = new_attr();
+        expected.crtime = UNIX_EPOCH + Duration::new(1_600_000_000, 500_000_000);
+        let attr: FileAttr = FileAttrSimple {
+            crtime: expected.crtime,
+            ..new_attr()
+        }
+        .into();
+        tostatx(&attr, statxbuf);
+        assert_eq!(fromstatx(statxbuf).unwrap(), expected);
+
+        assert!(fromstat(&statbuf[..8]).is_err());
+    }
+
+    #[test]
+    fn test_new_perm() {
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::RegularFile).perm, 0o644);
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::Directory).perm, 0o755);
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::Symlink).perm, 0o777);
+        for (r#type, mode, umask, perm) in [
+            (FileTypeSimple::RegularFile, 0, 0, 0o644),
+            (FileTypeSimple::Directory, 0, 0, 0o755),
+            (FileTypeSimple::RegularFile, 0, 0o077, 0o600),
+            (
+                FileTypeSimple::RegularFile,
+                libc::S_IFREG | 0o666,
+                0o022,
+                0o644,
+            ),
+            (FileTypeSimple::Directory, 0o777, 0o027, 0o750),
+            (FileTypeSimple::RegularFile, 0o4755, 0, 0o4755),
+            (FileTypeSimple::RegularFile, libc::S_IFREG, 0, 0),
+        ] {
+            assert_eq!(FileAttrSimple::with_mode(r#type, mode, umask).perm, perm);
+        }
+    }
+
+    #[test]
+    fn test_new_nlink() {
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::RegularFile).nlink, 1);
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::Symlink).nlink, 1);
+        assert_eq!(FileAttrSimple::new(FileTypeSimple::Directory).nlink, 2);
+    }
+
+    #[test]
+    fn test_builder() {
+        let file = FileAttrSimple::builder(FileTypeSimple::RegularFile)
+            .perm(0o644)
+            .uid(1000)
+            .gid(100)
+            .build()
+            .unwrap();
+        assert_eq!(file.nlink, 1);
+        assert_eq!((file.perm, file.uid, file.gid), (0o644, 1000, 100));
+        assert_eq!(file.kind, u32::from(FileTypeSimple::RegularFile));
+        let dir = FileAttrSimple::builder(FileTypeSimple::Directory)
+            .perm(0o755)
+            .uid(0)
+            .gid(0)
+            .build()
+            .unwrap();
+        assert_eq!(dir.nlink, 2);
+        assert_eq!(dir.size, 4096);
+        let linked = FileAttrSimple::builder(FileTypeSimple::RegularFile)
+            .perm(0o600)
+            .uid(0)
+            .gid(0)
+            .nlink(3)
+            .build()
+            .unwrap();
+        assert_eq!(linked.nlink, 3);
+
+        assert_eq!(
+            FileAttrSimple::builder(FileTypeSimple::RegularFile)
+                .perm(0o644)
+                .build(),
+            Err("file attr without uid, gid".to_string())
+        );
+    }
+
+    #[test]
+    fn test_serialized_len() {
+        const _: () = assert!(FileAttrSimple::SERIALIZED_LEN == 102);
+        assert_eq!(
+            FileAttrSimple::default().to_bytes().len(),
+            FileAttrSimple::SERIALIZED_LEN
+        );
+        let full = FileAttrSimple {
+            size: u64::MAX,
+            blocks: u64::MAX,
+            atime: UNIX_EPOCH + Duration::new(u32::MAX as u64, 999_999_999),
+            mtime: UNIX_EPOCH - Duration::new(u32::MAX as u64, 1),
+            kind: u32::MAX,
+            perm: u16::MAX,
+            nlink: u32::MAX,
+            uid: u32::MAX,
+            gid: u32::MAX,
+            rdev: u32::MAX,
+            flags: u32::MAX,
+            blksize: u32::MAX,
+            version: u64::MAX,
+            inline_data: Some(vec![1u8; 100]),
+            ..Default::default()
+        };
+        assert_eq!(full.to_bytes().len(), FileAttrSimple::SERIALIZED_LEN);
+    }
+
+    #[test]
+    fn test_stat_time_out_of_range() {
+        assert_eq!(stat_time(UNIX_EPOCH - Duration::from_secs(1)), (0, 0));
+        assert_eq!(stat_time(UNIX_EPOCH), (0, 0));
+        let far_future = UNIX_EPOCH + Duration::new(i64::MAX as u64, 999_999_999);
+        assert_eq!(stat_time(far_future), (i64::MAX, 999_999_999));
+
+        let attr: FileAttr = FileAttrSimple {
+            atime: UNIX_EPOCH - Duration::from_secs(1),
+            mtime: far_future,
+            ..Default::default()
+        }
+        .into();
+        let mut st: stat = unsafe { std::mem::zeroed() };
+        tostat(&attr, unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut st as *mut stat as *mut u8,
+                std::mem::size_of::<stat>(),
+            )
+        });
+        assert_eq!((st.st_atime, st.st_atime_nsec), (0, 0));
+        assert_eq!((st.st_mtime, st.st_mtime_nsec), (i64::MAX, 999_999_999));
+        let mut stx: statx = unsafe { std::mem::zeroed() };
+        tostatx(&attr, unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut stx as *mut statx as *mut u8,
+                std::mem::size_of::<statx>(),
+            )
+        });
+        assert_eq!((stx.stx_atime.tv_sec, stx.stx_atime.tv_nsec), (0, 0));
+        assert_eq!(stx.stx_mtime.tv_sec, i64::MAX);
+    }
+}
@@ -7,6 +7,8 @@ use std::time::SystemTime;
 use fuser::{FileAttr, FileType};
 use log::error;
 
+use super::serialization::{FileAttrSimple, FileTypeSimple};
+
 pub fn get_full_path(parent: &str, name: &str) -> String {
     if parent == "/" {
         return format!("/{}", name);
@@ -42,6 +44,20 @@ pub fn path_split(path: &str) -> Result<(String, String), i32> {
     }
 }
 
+// the errno for an operation on a file of another type than it works on
+pub fn check_type(attr: &FileAttrSimple, expected: FileTypeSimple) -> Result<(), i32> {
+    let actual = FileTypeSimple::try_from(attr.kind).map_err(|e| {
+        error!("check type error: {}", e);
+        libc::EIO
+    })?;
+    match (actual, expected) {
+        (actual, expected) if actual == expected => Ok(()),
+        (_, FileTypeSimple::Directory) => Err(libc::ENOTDIR),
+        (FileTypeSimple::Directory, _) => Err(libc::EISDIR),
+        _ => Err(libc::EINVAL),
+    }
+}
+
 pub fn empty_file() -> FileAttr {
     FileAttr {
         ino: 0,
@@ -53,7 +69,7 @@ pub fn empty_file() -> FileAttr {
         crtime: SystemTime::now(),
         kind: FileType::RegularFile,
         perm: 0,
-        nlink: 0,
+        nlink: 1,
         uid: 0,
         gid: 0,
         rdev: 0,
@@ -73,7 +89,7 @@ pub fn empty_dir() -> FileAttr {
         crtime: SystemTime::now(),
         kind: FileType::Directory,
         perm: 0,
-        nlink: 0,
+        nlink: 2,
         uid: 0,
         gid: 0,
         rdev: 0,
@@ -0,0 +1,85 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// LEB128 style length prefix: 7 bits per byte, high bit set while more bytes follow.
+// Names shorter than 128 bytes cost a single byte.
+
+use std::io::Read;
+
+const CONTINUATION_BIT: u8 = 0x80;
+const MAX_ENCODED_LEN: usize = 10;
+
+pub fn encoded_len(mut len: usize) -> usize {
+    let mut n = 1;
+    while len >= CONTINUATION_BIT as usize {
+        len >>= 7;
+        n += 1;
+    }
+    n
+}
+
+pub fn encode_len(mut len: usize, buf: &mut Vec<u8>) {
+    while len >= CONTINUATION_BIT as usize {
+        buf.push((len as u8) | CONTINUATION_BIT);
+        len >>= 7;
+    }
+    buf.push(len as u8);
+}
+
+// returns the decoded length and the number of bytes consumed by the prefix
+pub fn decode_len(buf: &[u8]) -> Result<(usize, usize), i32> {
+    let mut len = 0usize;
+    for (i, byte) in buf.iter().take(MAX_ENCODED_LEN).enumerate() {
+        len |= ((byte & !CONTINUATION_BIT) as usize) << (7 * i);
+        if byte & CONTINUATION_BIT == 0 {
+            return Ok((len, i + 1));
+        }
+    }
+    Err(libc::EINVAL)
+}
+
+// reads a length prefix from a stream, byte by byte
+pub fn read_len<R: Read>(reader: &mut R) -> Result<usize, i32> {
+    let mut len = 0usize;
+    let mut byte = [0u8; 1];
+    for i in 0..MAX_ENCODED_LEN {
+        reader.read_exact(&mut byte).map_err(|_| libc::EINVAL)?;
+        len |= ((byte[0] & !CONTINUATION_BIT) as usize) << (7 * i);
+        if byte[0] & CONTINUATION_BIT == 0 {
+            return Ok(len);
+        }
+    }
+    Err(libc::EINVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for (len, prefix_len) in [(0, 1), (5, 1), (127, 1), (128, 2), (16383, 2), (16384, 3)] {
+            let mut buf = Vec::new();
+            encode_len(len, &mut buf);
+            assert_eq!(buf.len(), prefix_len);
+            assert_eq!(encoded_len(len), prefix_len);
+            assert_eq!(decode_len(&buf), Ok((len, prefix_len)));
+            assert_eq!(read_len(&mut buf.as_slice()), Ok(len));
+        }
+    }
+
+    #[test]
+    fn test_decode_with_trailing_data() {
+        let mut buf = Vec::new();
+        encode_len(300, &mut buf);
+        buf.extend_from_slice(b"name");
+        assert_eq!(decode_len(&buf), Ok((300, 2)));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        assert_eq!(decode_len(&[]), Err(libc::EINVAL));
+        assert_eq!(decode_len(&[0x80]), Err(libc::EINVAL));
+    }
+}
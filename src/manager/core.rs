@@ -2,37 +2,78 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
 use anyhow::Error;
 use dashmap::DashMap;
-use log::{debug, info};
+use log::{debug, error, info};
 
 use crate::common::hash_ring::{HashRing, ServerNode};
-use crate::common::serialization::{ClusterStatus, ServerStatus, ServerType};
+use crate::common::serialization::{
+    ClusterStatus, MembershipEvent, MembershipLogEntry, ServerStatus, ServerType,
+};
+
+// membership changes kept for operators, the oldest are dropped past this many
+const MEMBERSHIP_LOG_CAPACITY: usize = 256;
+
 pub struct Manager {
     pub hashring: Arc<RwLock<Option<HashRing>>>,
     pub new_hashring: Arc<RwLock<Option<HashRing>>>,
     pub servers: Arc<Mutex<HashMap<String, Server>>>,
     pub cluster_status: Arc<Mutex<ClusterStatus>>,
+    // number of hash rings that have taken over since the manager started
+    pub ring_epoch: AtomicU64,
+    // virtual nodes a server of weight 1 gets on the ring
+    pub base_vnodes: usize,
+    pub membership_log: Mutex<VecDeque<MembershipLogEntry>>,
+    // the server whose files the change under way moves away before it is removed
+    pub draining: Mutex<Option<String>>,
+    // servers out of the ring with nothing left on them, waiting to be removed
+    pub drained: Mutex<HashSet<String>>,
     _clients: DashMap<String, String>,
 }
 
+// moves the cluster to next, or to StatusError when the lifecycle has no such step
+pub fn transition(status: &mut ClusterStatus, next: ClusterStatus) {
+    if status.can_transition_to(next) {
+        *status = next;
+        return;
+    }
+    error!(
+        "illegal cluster status transition {:?} -> {:?}",
+        status, next
+    );
+    *status = ClusterStatus::StatusError;
+}
+
 pub struct Server {
     pub status: ServerStatus,
-    r#_type: ServerType,
+    pub r#type: ServerType,
     _replicas: usize,
 }
 
 impl Manager {
-    pub fn new(servers: Vec<(String, usize)>) -> Self {
+    // the servers the cluster starts with all have weight 1
+    pub fn new(servers: Vec<String>, base_vnodes: usize) -> Self {
+        let servers: Vec<(String, usize)> = servers
+            .into_iter()
+            .map(|server| (server, base_vnodes))
+            .collect();
         let hashring = Arc::new(RwLock::new(Some(HashRing::new(servers.clone()))));
         let manager = Manager {
             hashring,
             new_hashring: Arc::new(RwLock::new(None)),
             servers: Arc::new(Mutex::new(HashMap::new())),
             cluster_status: Arc::new(Mutex::new(ClusterStatus::Initializing)),
+            ring_epoch: AtomicU64::new(0),
+            base_vnodes,
+            membership_log: Mutex::new(VecDeque::new()),
+            draining: Mutex::new(None),
+            drained: Mutex::new(HashSet::new()),
             _clients: DashMap::new(),
         };
 
@@ -41,7 +82,7 @@ impl Manager {
                 server,
                 Server {
                     status: ServerStatus::Initializing,
-                    r#_type: ServerType::Running,
+                    r#type: ServerType::Running,
                     _replicas: weight,
                 },
             );
@@ -68,6 +109,24 @@ impl Manager {
             .collect()
     }
 
+    pub fn get_ring_layout(&self) -> Vec<(u64, String)> {
+        self.hashring
+            .read()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .export_layout()
+    }
+
+    pub fn get_ring_epoch(&self) -> u64 {
+        self.ring_epoch.load(Ordering::Acquire)
+    }
+
+    // the new hash ring gets the next epoch once it takes over
+    pub fn get_new_ring_epoch(&self) -> u64 {
+        self.get_ring_epoch() + 1
+    }
+
     pub fn get_new_hash_ring_info(&self) -> Result<Vec<(String, usize)>, Error> {
         if let Some(new_hashring) = self.new_hashring.read().unwrap().as_ref() {
             Ok(new_hashring
@@ -80,38 +139,73 @@ impl Manager {
         }
     }
 
-    pub fn add_nodes(&self, nodes: Vec<(String, usize)>) -> Option<Error> {
+    fn log_membership(&self, event: MembershipEvent, server: &str) {
+        let mut log = self.membership_log.lock().unwrap();
+        if log.len() == MEMBERSHIP_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(MembershipLogEntry {
+            timestamp: SystemTime::now(),
+            event,
+            server: server.to_owned(),
+            epoch: self.get_new_ring_epoch(),
+        });
+    }
+
+    pub fn get_membership_log(&self) -> Vec<MembershipLogEntry> {
+        self.membership_log
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn add_nodes(&self, nodes: Vec<(String, u32)>) -> Option<Error> {
         info!("add_nodes: {:?}", nodes);
         let mut cluster_status = self.cluster_status.lock().unwrap();
         if *cluster_status != ClusterStatus::Idle {
             return Some(anyhow::anyhow!("cluster is not idle"));
         }
+        if let Some((node, _)) = nodes.iter().find(|(_, weight)| *weight == 0) {
+            return Some(anyhow::anyhow!("server {} has weight 0", node));
+        }
         let mut new_hashring = self.hashring.read().unwrap().clone().unwrap();
         let mut servers = self.servers.lock().unwrap();
         for (node, weight) in nodes {
+            self.log_membership(MembershipEvent::Added, &node);
+            let vnodes = self.base_vnodes * weight as usize;
             new_hashring.add(
                 ServerNode {
                     address: node.clone(),
                 },
-                weight,
+                vnodes,
             );
             servers.insert(
                 node,
                 Server {
                     status: ServerStatus::Initializing,
-                    r#_type: ServerType::Running,
-                    _replicas: weight,
+                    r#type: ServerType::Running,
+                    _replicas: vnodes,
                 },
             );
         }
 
         self.new_hashring.write().unwrap().replace(new_hashring);
-        *cluster_status = ClusterStatus::NodesStarting;
+        transition(&mut cluster_status, ClusterStatus::NodesStarting);
 
         None
     }
 
     pub fn delete_nodes(&self, nodes: Vec<String>) -> Option<Error> {
+        // a drained server already left the ring, removing it only forgets it
+        if self.drained.lock().unwrap().remove(&nodes[0]) {
+            self.log_membership(MembershipEvent::Removed, &nodes[0]);
+            return None;
+        }
+        if self.draining.lock().unwrap().as_ref() == Some(&nodes[0]) {
+            return Some(anyhow::anyhow!("server {} is still draining", nodes[0]));
+        }
         let mut cluster_status = self.cluster_status.lock().unwrap();
         if *cluster_status != ClusterStatus::Idle {
             return Some(anyhow::anyhow!("cluster is not idle"));
@@ -120,13 +214,87 @@ impl Manager {
         new_hashring.remove(&ServerNode {
             address: nodes[0].clone(),
         });
+        self.log_membership(MembershipEvent::Removed, &nodes[0]);
+
+        self.new_hashring.write().unwrap().replace(new_hashring);
+
+        transition(&mut cluster_status, ClusterStatus::NodesStarting);
+        None
+    }
+
+    // takes the server out of the ring like delete_nodes, the change moves its files
+    // to their new owners and the server is kept as drained until it is removed
+    pub fn drain_node(&self, server: &str) -> Option<Error> {
+        let mut cluster_status = self.cluster_status.lock().unwrap();
+        if *cluster_status != ClusterStatus::Idle {
+            return Some(anyhow::anyhow!("cluster is not idle"));
+        }
+        let mut new_hashring = self.hashring.read().unwrap().clone().unwrap();
+        if !new_hashring.contains(server) {
+            return Some(anyhow::anyhow!("server {} is not in the hash ring", server));
+        }
+        if new_hashring.servers.len() == 1 {
+            return Some(anyhow::anyhow!("server {} is the last one left", server));
+        }
+        new_hashring.remove(&ServerNode {
+            address: server.to_owned(),
+        });
+        self.log_membership(MembershipEvent::Drained, server);
 
         self.new_hashring.write().unwrap().replace(new_hashring);
+        self.draining.lock().unwrap().replace(server.to_owned());
 
-        *cluster_status = ClusterStatus::NodesStarting;
+        transition(&mut cluster_status, ClusterStatus::NodesStarting);
         None
     }
 
+    // called once the ring the drain made took over
+    pub fn finish_drain(&self) {
+        if let Some(server) = self.draining.lock().unwrap().take() {
+            info!("server {} drained", server);
+            self.drained.lock().unwrap().insert(server);
+        }
+    }
+
+    // the status of a draining or drained server and whether the drain finished,
+    // None for any other server
+    pub fn get_drain_status(&self, server: &str) -> Option<(ServerStatus, bool)> {
+        if self.drained.lock().unwrap().contains(server) {
+            return Some((ServerStatus::Finished, true));
+        }
+        if self.draining.lock().unwrap().as_deref() != Some(server) {
+            return None;
+        }
+        self.servers
+            .lock()
+            .unwrap()
+            .get(server)
+            .map(|server| (server.status, false))
+    }
+
+    // the server knows its own state best after a partition, so the reported status is
+    // taken without checking it against the transition the cluster is in
+    pub fn reregister_server(
+        &self,
+        server_id: &str,
+        r#type: ServerType,
+        status: ServerStatus,
+    ) -> Option<Error> {
+        let mut servers = self.servers.lock().unwrap();
+        match servers.get_mut(server_id) {
+            Some(server) => {
+                info!(
+                    "re-register server: {} {:?} -> {:?}",
+                    server_id, server.status, status
+                );
+                server.r#type = r#type;
+                server.status = status;
+                None
+            }
+            None => Some(anyhow::anyhow!("unknown server: {}", server_id)),
+        }
+    }
+
     pub fn set_server_status(&self, server_id: String, status: ServerStatus) -> Option<Error> {
         // debug : logs all server_name in self.servers
         debug!(
@@ -275,3 +443,171 @@ impl Manager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::atomic::Ordering};
+
+    use super::{transition, Manager};
+    use crate::common::serialization::{ClusterStatus, MembershipEvent, ServerStatus};
+
+    // what update_server_status does once every server finished the change
+    fn take_over(manager: &Manager) {
+        let new_hashring = manager.new_hashring.write().unwrap().take();
+        *manager.hashring.write().unwrap() = new_hashring;
+        manager.ring_epoch.fetch_add(1, Ordering::AcqRel);
+        *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+    }
+
+    #[test]
+    fn test_membership_log() {
+        let manager = Manager::new(vec!["127.0.0.1:8085".to_string()], 100);
+        *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        assert!(manager.get_membership_log().is_empty());
+
+        assert!(manager
+            .add_nodes(vec![("127.0.0.1:8086".to_string(), 1)])
+            .is_none());
+        take_over(&manager);
+        assert!(manager
+            .delete_nodes(vec!["127.0.0.1:8086".to_string()])
+            .is_none());
+
+        let log = manager.get_membership_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].event, MembershipEvent::Added);
+        assert_eq!(log[1].event, MembershipEvent::Removed);
+        assert!(log.iter().all(|entry| entry.server == "127.0.0.1:8086"));
+        assert!(log[0].epoch < log[1].epoch);
+        assert!(log[0].timestamp <= log[1].timestamp);
+
+        // a change refused while another is under way is not logged
+        assert!(manager
+            .add_nodes(vec![("127.0.0.1:8087".to_string(), 1)])
+            .is_some());
+        assert_eq!(manager.get_membership_log().len(), 2);
+    }
+
+    #[test]
+    fn test_drain_node() {
+        let servers: Vec<String> = (8085..8088)
+            .map(|port| format!("127.0.0.1:{}", port))
+            .collect();
+        let manager = Manager::new(servers.clone(), 100);
+        *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        assert!(manager.drain_node("127.0.0.1:9999").is_some());
+
+        let drained = &servers[2];
+        assert!(manager.drain_node(drained).is_none());
+        assert_eq!(manager.get_cluster_status(), ClusterStatus::NodesStarting);
+        let mut info = manager.get_new_hash_ring_info().unwrap();
+        info.sort();
+        assert_eq!(
+            info,
+            vec![(servers[0].clone(), 100), (servers[1].clone(), 100)]
+        );
+        assert_eq!(
+            manager.get_drain_status(drained),
+            Some((ServerStatus::Initializing, false))
+        );
+        assert_eq!(manager.get_drain_status(&servers[0]), None);
+        // it may not be removed before its files moved
+        assert!(manager.delete_nodes(vec![drained.clone()]).is_some());
+
+        take_over(&manager);
+        manager.finish_drain();
+        assert!(!manager
+            .hashring
+            .read()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .contains(drained));
+        assert_eq!(
+            manager.get_drain_status(drained),
+            Some((ServerStatus::Finished, true))
+        );
+
+        // removing a drained server changes nothing but the servers the manager knows
+        assert!(manager.delete_nodes(vec![drained.clone()]).is_none());
+        assert_eq!(manager.get_cluster_status(), ClusterStatus::Idle);
+        assert_eq!(manager.get_drain_status(drained), None);
+        let events: Vec<MembershipEvent> = manager
+            .get_membership_log()
+            .iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![MembershipEvent::Drained, MembershipEvent::Removed]
+        );
+
+        // the last server can not be drained
+        let manager = Manager::new(vec![servers[0].clone()], 100);
+        *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        assert!(manager.drain_node(&servers[0]).is_some());
+        assert_eq!(manager.get_cluster_status(), ClusterStatus::Idle);
+    }
+
+    #[test]
+    fn test_weighted_nodes() {
+        let manager = Manager::new(vec![], 100);
+        *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        let weights = [
+            ("127.0.0.1:8085".to_string(), 1),
+            ("127.0.0.1:8086".to_string(), 2),
+            ("127.0.0.1:8087".to_string(), 4),
+        ];
+        assert!(manager
+            .add_nodes(vec![("127.0.0.1:8088".to_string(), 0)])
+            .is_some());
+        assert!(manager.add_nodes(weights.to_vec()).is_none());
+
+        let mut info = manager.get_new_hash_ring_info().unwrap();
+        info.sort();
+        assert_eq!(
+            info,
+            vec![
+                ("127.0.0.1:8085".to_string(), 100),
+                ("127.0.0.1:8086".to_string(), 200),
+                ("127.0.0.1:8087".to_string(), 400),
+            ]
+        );
+
+        let ring = manager.new_hashring.read().unwrap().clone().unwrap();
+        let samples = 70000;
+        let mut counts = HashMap::new();
+        for i in 0..samples {
+            let server = &ring.get(&format!("volume/file_{}", i)).unwrap().address;
+            *counts.entry(server.clone()).or_insert(0) += 1;
+        }
+        // each server's share is its weight over the total weight of 7, give or take
+        for (server, weight) in weights {
+            let expected = samples / 7 * weight;
+            let count = counts[&server];
+            assert!(
+                count * 10 > expected * 8 && count * 10 < expected * 12,
+                "{} got {} keys, expected about {}",
+                server,
+                count,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_illegal_transition() {
+        let manager = Manager::new(vec!["127.0.0.1:8085".to_string()], 100);
+        let mut status = manager.cluster_status.lock().unwrap();
+        transition(&mut status, ClusterStatus::Idle);
+        assert_eq!(*status, ClusterStatus::Idle);
+        transition(&mut status, ClusterStatus::NodesStarting);
+        assert_eq!(*status, ClusterStatus::NodesStarting);
+
+        // a step the lifecycle does not have leaves the cluster in StatusError
+        transition(&mut status, ClusterStatus::Finishing);
+        assert_eq!(*status, ClusterStatus::StatusError);
+        transition(&mut status, ClusterStatus::Idle);
+        assert_eq!(*status, ClusterStatus::StatusError);
+    }
+}
@@ -4,6 +4,9 @@
 
 use dashmap;
 use dashmap::DashMap;
+
+use crate::common::serialization::TransferProgress;
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::time;
 use tokio::time::MissedTickBehavior;
@@ -13,6 +16,19 @@ const UNHEALTHY_TIME: u64 = 15;
 #[derive(Default)]
 pub struct Heart {
     pub instances: DashMap<String, u64>,
+    // the last transfer progress each server reported
+    pub transfer_progress: DashMap<String, TransferProgress>,
+    // the (name, used bytes, files) of each volume as last reported by each server
+    pub volume_usage: DashMap<String, Vec<(String, u64, u64)>>,
+    // the volumes each server held when it last re-registered
+    pub held_volumes: DashMap<String, Vec<String>>,
+}
+
+fn percentage(total: u64, remaining: u64) -> (u8, u64) {
+    if total == 0 {
+        return (100, 0);
+    }
+    (((total - remaining) * 100 / total) as u8, remaining)
 }
 
 impl Heart {
@@ -26,6 +42,56 @@ impl Heart {
         );
     }
 
+    pub fn report_progress(&self, address: String, progress: TransferProgress) {
+        self.transfer_progress.insert(address, progress);
+    }
+
+    pub fn report_volumes(&self, address: String, per_volume: Vec<(String, u64, u64)>) {
+        self.volume_usage.insert(address, per_volume);
+    }
+
+    pub fn report_held_volumes(&self, address: String, volumes: Vec<String>) {
+        self.held_volumes.insert(address, volumes);
+    }
+
+    // used bytes and files of each volume summed over the servers holding its files
+    pub fn volume_usage(&self) -> BTreeMap<String, (u64, u64)> {
+        let mut usage = BTreeMap::new();
+        for per_volume in self.volume_usage.iter() {
+            for (name, used, files) in per_volume.iter() {
+                let entry = usage.entry(name.clone()).or_insert((0, 0));
+                entry.0 += used;
+                entry.1 += files;
+            }
+        }
+        usage
+    }
+
+    // overall percentage and bytes left of the rebalance across every reporting server
+    pub fn rebalance_progress(&self) -> (u8, u64) {
+        let (total, remaining) =
+            self.transfer_progress
+                .iter()
+                .fold((0u64, 0u64), |(total, remaining), progress| {
+                    (
+                        total + progress.total_bytes,
+                        remaining + progress.remaining_bytes.min(progress.total_bytes),
+                    )
+                });
+        percentage(total, remaining)
+    }
+
+    // percentage and bytes left of what one server has to move
+    pub fn server_progress(&self, address: &str) -> (u8, u64) {
+        match self.transfer_progress.get(address) {
+            Some(progress) => percentage(
+                progress.total_bytes,
+                progress.remaining_bytes.min(progress.total_bytes),
+            ),
+            None => (0, 0),
+        }
+    }
+
     pub async fn healthy_check(&self) {
         let instances = self.instances.clone();
         tokio::spawn(async move {
@@ -47,3 +113,71 @@ impl Heart {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Heart;
+    use crate::common::serialization::TransferProgress;
+
+    #[test]
+    fn test_rebalance_progress() {
+        let heart = Heart::default();
+        assert_eq!(heart.rebalance_progress(), (100, 0));
+
+        heart.report_progress(
+            "127.0.0.1:8085".to_string(),
+            TransferProgress {
+                total_bytes: 100,
+                remaining_bytes: 50,
+                bytes_per_sec: 0,
+            },
+        );
+        heart.report_progress(
+            "127.0.0.1:8086".to_string(),
+            TransferProgress {
+                total_bytes: 300,
+                remaining_bytes: 150,
+                bytes_per_sec: 0,
+            },
+        );
+        assert_eq!(heart.rebalance_progress(), (50, 200));
+
+        heart.report_progress(
+            "127.0.0.1:8086".to_string(),
+            TransferProgress {
+                total_bytes: 300,
+                remaining_bytes: 0,
+                bytes_per_sec: 0,
+            },
+        );
+        assert_eq!(heart.rebalance_progress(), (87, 50));
+    }
+
+    #[test]
+    fn test_volume_usage() {
+        let heart = Heart::default();
+        heart.report_volumes(
+            "127.0.0.1:8086".to_string(),
+            vec![("volume_a".to_string(), 100, 2)],
+        );
+        // one heartbeat carries every volume of the server
+        heart.report_volumes(
+            "127.0.0.1:8085".to_string(),
+            vec![
+                ("volume_a".to_string(), 4096, 3),
+                ("volume_b".to_string(), 10, 1),
+            ],
+        );
+        let usage = heart.volume_usage();
+        assert_eq!(usage.get("volume_a"), Some(&(4196, 5)));
+        assert_eq!(usage.get("volume_b"), Some(&(10, 1)));
+
+        heart.report_volumes(
+            "127.0.0.1:8085".to_string(),
+            vec![("volume_a".to_string(), 0, 0)],
+        );
+        let usage = heart.volume_usage();
+        assert_eq!(usage.get("volume_a"), Some(&(100, 2)));
+        assert_eq!(usage.get("volume_b"), None);
+    }
+}
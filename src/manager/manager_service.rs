@@ -2,25 +2,45 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
     common::serialization::{
         AddNodesSendMetaData, ClusterStatus, DeleteNodesSendMetaData, GetClusterStatusRecvMetaData,
-        GetHashRingInfoRecvMetaData, ManagerOperationType, ServerStatus,
+        GetDrainStatusRecvMetaData, GetHashRingInfoRecvMetaData, GetMembershipLogRecvMetaData,
+        GetRingLayoutRecvData, ManagerOperationType, ServerStatus, ServerType, TransferProgress,
+    },
+    rpc::{
+        protocol::MAX_DATA_LENGTH,
+        server::{Handler, ProtocolError},
     },
-    rpc::server::Handler,
 };
 
-use super::{core::Manager, heart::Heart};
+use super::{
+    core::{transition, Manager},
+    heart::Heart,
+};
 
 use async_trait::async_trait;
 use log::{debug, error};
 use serde::{Deserialize, Serialize};
 
+// how long a status answer is reused, and how often clients are told they may ask
+const STATUS_CACHE_TTL: Duration = Duration::from_millis(500);
+const MIN_POLL_INTERVAL_MS: u32 = 1000;
+
 pub struct ManagerService {
     pub heart: Heart,
     manager: Arc<Manager>,
+    // the last status answer and when it was made, every client polls it
+    status_cache: Mutex<Option<(Instant, GetClusterStatusRecvMetaData)>>,
+    status_computations: AtomicU64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,6 +48,20 @@ pub struct SendHeartRequest {
     pub address: String,
     pub flags: u32,
     pub lifetime: String,
+    pub transfer_progress: TransferProgress,
+    // (name, used bytes, files) of every volume with files on the server
+    #[serde(default)]
+    pub per_volume: Vec<(String, u64, u64)>,
+}
+
+// sent by a server that lost the manager for a while, so the manager takes the
+// server's own view at once instead of waiting for heartbeats and status updates
+#[derive(Serialize, Deserialize)]
+pub struct ReRegisterRequest {
+    pub heart: SendHeartRequest,
+    pub server_type: ServerType,
+    pub server_status: ServerStatus,
+    pub volumes: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -56,7 +90,10 @@ async fn update_server_status(manager: Arc<Manager>) {
                     .all(|kv| kv.1.status == ServerStatus::Finished);
                 if flag {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::SyncNewHashRing;
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::SyncNewHashRing,
+                    );
                 };
             }
             ClusterStatus::SyncNewHashRing => {
@@ -69,7 +106,10 @@ async fn update_server_status(manager: Arc<Manager>) {
                     .all(|kv| kv.1.status == ServerStatus::PreTransfer);
                 if flag {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::PreTransfer;
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::PreTransfer,
+                    );
                 }
             }
             ClusterStatus::PreTransfer => {
@@ -82,7 +122,10 @@ async fn update_server_status(manager: Arc<Manager>) {
                     .all(|kv| kv.1.status == ServerStatus::Transferring);
                 if flag {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::Transferring;
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::Transferring,
+                    );
                 }
             }
             ClusterStatus::Transferring => {
@@ -95,7 +138,10 @@ async fn update_server_status(manager: Arc<Manager>) {
                     .all(|kv| kv.1.status == ServerStatus::PreFinish);
                 if flag {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::PreFinish;
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::PreFinish,
+                    );
                 }
             }
             ClusterStatus::PreFinish => {
@@ -113,7 +159,11 @@ async fn update_server_status(manager: Arc<Manager>) {
                         .write()
                         .unwrap()
                         .replace(manager.new_hashring.read().unwrap().clone().unwrap());
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::Finishing;
+                    manager.ring_epoch.fetch_add(1, Ordering::AcqRel);
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::Finishing,
+                    );
                 }
             }
             ClusterStatus::Finishing => {
@@ -134,7 +184,11 @@ async fn update_server_status(manager: Arc<Manager>) {
                         .retain(|k, _| new_hashring.as_ref().unwrap().contains(k));
                     // move new_hashring to hashring
                     let _ = new_hashring.take().unwrap();
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+                    manager.finish_drain();
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::Idle,
+                    );
                 }
             }
             ClusterStatus::Initializing => {
@@ -147,20 +201,85 @@ async fn update_server_status(manager: Arc<Manager>) {
                     .all(|kv| kv.1.status == ServerStatus::Finished);
                 if flag {
                     tokio::time::sleep(Duration::from_secs(1)).await;
-                    *manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+                    transition(
+                        &mut manager.cluster_status.lock().unwrap(),
+                        ClusterStatus::Idle,
+                    );
                 }
             }
-            s => panic!("get forward address failed, invalid cluster status: {}", s),
+            // left to an operator, the manager has to be restarted
+            ClusterStatus::StatusError => {}
         }
     }
 }
 
 impl ManagerService {
-    pub fn new(servers: Vec<(String, usize)>) -> Self {
+    pub fn new(servers: Vec<String>, base_vnodes: usize) -> Self {
         let heart = Heart::default();
-        let manager = Arc::new(Manager::new(servers));
+        let manager = Arc::new(Manager::new(servers, base_vnodes));
         tokio::spawn(update_server_status(manager.clone()));
-        ManagerService { heart, manager }
+        ManagerService {
+            heart,
+            manager,
+            status_cache: Mutex::new(None),
+            status_computations: AtomicU64::new(0),
+        }
+    }
+
+    fn cluster_status(&self) -> GetClusterStatusRecvMetaData {
+        let mut cache = self.status_cache.lock().unwrap();
+        // a transition drops the answer at once, only the progress is reused stale
+        let status = self.manager.get_cluster_status();
+        if let Some((made, cached)) = cache.as_ref() {
+            if made.elapsed() < STATUS_CACHE_TTL && cached.status == status {
+                return cached.clone();
+            }
+        }
+        self.status_computations.fetch_add(1, Ordering::Relaxed);
+        let (rebalance_progress_pct, rebalance_bytes_remaining) = match status {
+            ClusterStatus::Idle => (100, 0),
+            _ => self.heart.rebalance_progress(),
+        };
+        let status = GetClusterStatusRecvMetaData {
+            status,
+            rebalance_progress_pct,
+            rebalance_bytes_remaining,
+            min_poll_interval_ms: MIN_POLL_INTERVAL_MS,
+        };
+        cache.replace((Instant::now(), status.clone()));
+        status
+    }
+
+    fn drain_status(&self, server: &str) -> Option<GetDrainStatusRecvMetaData> {
+        let (status, drained) = self.manager.get_drain_status(server)?;
+        let (progress_pct, bytes_remaining) = match status {
+            _ if drained => (100, 0),
+            ServerStatus::Transferring => self.heart.server_progress(server),
+            ServerStatus::PreFinish | ServerStatus::Finishing => (100, 0),
+            ServerStatus::Finished
+                if self.manager.get_cluster_status() == ClusterStatus::Finishing =>
+            {
+                (100, 0)
+            }
+            // nothing is known of the files to move before they start to
+            _ => (0, 0),
+        };
+        Some(GetDrainStatusRecvMetaData {
+            status,
+            drained,
+            progress_pct,
+            bytes_remaining,
+        })
+    }
+
+    async fn report_heart(&self, request: SendHeartRequest) {
+        self.heart
+            .report_progress(request.address.clone(), request.transfer_progress);
+        self.heart
+            .report_volumes(request.address.clone(), request.per_volume);
+        self.heart
+            .register_server(request.address, request.lifetime)
+            .await;
     }
 }
 
@@ -175,14 +294,38 @@ impl Handler for ManagerService {
         _data: Vec<u8>,
         metadata: Vec<u8>,
     ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
-        let r#type = ManagerOperationType::try_from(operation_type).unwrap();
+        let r#type = match ManagerOperationType::try_from(operation_type) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(ProtocolError(format!(
+                    "unknown manager operation type {}",
+                    operation_type
+                ))
+                .into());
+            }
+        };
+        debug!("manager request: {}", r#type.as_str());
         match r#type {
             ManagerOperationType::SendHeart => {
                 let request: SendHeartRequest = bincode::deserialize(&metadata).unwrap();
                 debug!("{}", request.lifetime);
-                self.heart
-                    .register_server(request.address, request.lifetime)
-                    .await;
+                self.report_heart(request).await;
+
+                Ok((0, 0, 0, 0, Vec::new(), Vec::new()))
+            }
+            ManagerOperationType::ReRegister => {
+                let request: ReRegisterRequest = bincode::deserialize(&metadata).unwrap();
+                let address = request.heart.address.clone();
+                if let Some(e) = self.manager.reregister_server(
+                    &address,
+                    request.server_type,
+                    request.server_status,
+                ) {
+                    error!("re-register server error: {}", e);
+                    return Ok((libc::ENOENT, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                self.heart.report_held_volumes(address, request.volumes);
+                self.report_heart(request.heart).await;
 
                 Ok((0, 0, 0, 0, Vec::new(), Vec::new()))
             }
@@ -204,9 +347,7 @@ impl Handler for ManagerService {
                 ))
             }
             ManagerOperationType::GetClusterStatus => {
-                let status = self.manager.get_cluster_status();
-                let response_meta_data =
-                    bincode::serialize(&GetClusterStatusRecvMetaData { status }).unwrap();
+                let response_meta_data = bincode::serialize(&self.cluster_status()).unwrap();
                 Ok((
                     0,
                     0,
@@ -218,8 +359,11 @@ impl Handler for ManagerService {
             }
             ManagerOperationType::GetHashRing => {
                 let hash_ring_info = self.manager.get_hash_ring_info();
-                let response_meta_data =
-                    bincode::serialize(&GetHashRingInfoRecvMetaData { hash_ring_info }).unwrap();
+                let response_meta_data = bincode::serialize(&GetHashRingInfoRecvMetaData {
+                    hash_ring_info,
+                    epoch: self.manager.get_ring_epoch(),
+                })
+                .unwrap();
                 Ok((
                     0,
                     0,
@@ -229,11 +373,25 @@ impl Handler for ManagerService {
                     Vec::new(),
                 ))
             }
+            ManagerOperationType::GetRingLayout => {
+                let response_data = bincode::serialize(&GetRingLayoutRecvData {
+                    epoch: self.manager.get_ring_epoch(),
+                    vnodes: self.manager.get_ring_layout(),
+                })
+                .unwrap();
+                if response_data.len() > MAX_DATA_LENGTH {
+                    error!("ring layout of {} bytes is too long", response_data.len());
+                    return Ok((libc::EOVERFLOW, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                Ok((0, 0, 0, response_data.len(), Vec::new(), response_data))
+            }
             ManagerOperationType::GetNewHashRing => match self.manager.get_new_hash_ring_info() {
                 Ok(hash_ring_info) => {
-                    let response_meta_data =
-                        bincode::serialize(&GetHashRingInfoRecvMetaData { hash_ring_info })
-                            .unwrap();
+                    let response_meta_data = bincode::serialize(&GetHashRingInfoRecvMetaData {
+                        hash_ring_info,
+                        epoch: self.manager.get_new_ring_epoch(),
+                    })
+                    .unwrap();
                     Ok((
                         0,
                         0,
@@ -273,6 +431,45 @@ impl Handler for ManagerService {
                     }
                 }
             }
+            ManagerOperationType::DrainNode => {
+                match self.manager.drain_node(&String::from_utf8(path).unwrap()) {
+                    None => Ok((0, 0, 0, 0, Vec::new(), Vec::new())),
+                    Some(e) => {
+                        error!("drain node error: {}", e);
+                        Ok((libc::EIO, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            ManagerOperationType::GetDrainStatus => {
+                match self.drain_status(&String::from_utf8(path).unwrap()) {
+                    Some(status) => {
+                        let response_meta_data = bincode::serialize(&status).unwrap();
+                        Ok((
+                            0,
+                            0,
+                            response_meta_data.len(),
+                            0,
+                            response_meta_data,
+                            Vec::new(),
+                        ))
+                    }
+                    None => Ok((libc::ENOENT, 0, 0, 0, Vec::new(), Vec::new())),
+                }
+            }
+            ManagerOperationType::GetMembershipLog => {
+                let response_meta_data = bincode::serialize(&GetMembershipLogRecvMetaData {
+                    entries: self.manager.get_membership_log(),
+                })
+                .unwrap();
+                Ok((
+                    0,
+                    0,
+                    response_meta_data.len(),
+                    0,
+                    response_meta_data,
+                    Vec::new(),
+                ))
+            }
             ManagerOperationType::UpdateServerStatus => {
                 match self.manager.set_server_status(
                     String::from_utf8(path).unwrap(),
@@ -285,7 +482,254 @@ impl Handler for ManagerService {
                     }
                 }
             }
-            _ => todo!(),
+            _ => {
+                error!("{} is not handled by the manager", r#type.as_str());
+                Ok((libc::EOPNOTSUPP, 0, 0, 0, Vec::new(), Vec::new()))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::{
+        ManagerService, ReRegisterRequest, SendHeartRequest, MIN_POLL_INTERVAL_MS, STATUS_CACHE_TTL,
+    };
+    use crate::common::serialization::{
+        ClusterStatus, GetClusterStatusRecvMetaData, GetDrainStatusRecvMetaData,
+        ManagerOperationType, ServerStatus, ServerType, TransferProgress,
+    };
+    use crate::rpc::server::Handler;
+
+    #[test]
+    fn test_send_heart_request_serde() {
+        let request = SendHeartRequest {
+            address: "127.0.0.1:8085".to_string(),
+            flags: 0,
+            lifetime: "".to_string(),
+            transfer_progress: TransferProgress {
+                total_bytes: 10,
+                remaining_bytes: 5,
+                bytes_per_sec: 1 << 20,
+            },
+            per_volume: vec![
+                ("volume_a".to_string(), 4096, 3),
+                ("volume_b".to_string(), 0, 1),
+            ],
+        };
+        let bytes = bincode::serialize(&request).unwrap();
+        let decoded: SendHeartRequest = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.address, request.address);
+        assert_eq!(decoded.transfer_progress, request.transfer_progress);
+        assert_eq!(decoded.per_volume, request.per_volume);
+    }
+
+    #[tokio::test]
+    async fn test_reregister_after_partition() {
+        let address = "127.0.0.1:8085".to_string();
+        let service = ManagerService::new(vec![address.clone()], 100);
+        let heart = |per_volume| SendHeartRequest {
+            address: address.clone(),
+            flags: 0,
+            lifetime: "".to_string(),
+            transfer_progress: TransferProgress::default(),
+            per_volume,
+        };
+        let dispatch = |operation_type: ManagerOperationType, metadata| {
+            service.dispatch(0, operation_type.into(), 0, vec![], vec![], metadata)
+        };
+
+        let request = heart(vec![("volume_a".to_string(), 10, 1)]);
+        let (status, ..) = dispatch(
+            ManagerOperationType::SendHeart,
+            bincode::serialize(&request).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, 0);
+
+        // the manager stopped hearing from the server and dropped it
+        service.heart.instances.remove(&address);
+        service.heart.volume_usage.remove(&address);
+        assert!(service.heart.volume_usage().is_empty());
+
+        let request = ReRegisterRequest {
+            heart: heart(vec![
+                ("volume_a".to_string(), 20, 2),
+                ("volume_b".to_string(), 0, 1),
+            ]),
+            server_type: ServerType::Running,
+            server_status: ServerStatus::Finished,
+            volumes: vec![
+                "volume_a".to_string(),
+                "volume_b".to_string(),
+                "volume_c".to_string(),
+            ],
+        };
+        let (status, ..) = dispatch(
+            ManagerOperationType::ReRegister,
+            bincode::serialize(&request).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, 0);
+        assert!(service.heart.instances.contains_key(&address));
+        let usage = service.heart.volume_usage();
+        assert_eq!(usage.get("volume_a"), Some(&(20, 2)));
+        assert_eq!(usage.get("volume_b"), Some(&(0, 1)));
+        assert_eq!(
+            *service.heart.held_volumes.get(&address).unwrap(),
+            request.volumes
+        );
+        assert_eq!(
+            service.manager.servers.lock().unwrap()[&address].status,
+            ServerStatus::Finished
+        );
+
+        // a server the manager never knew is refused
+        let mut request = request;
+        request.heart.address = "127.0.0.1:9999".to_string();
+        let (status, ..) = dispatch(
+            ManagerOperationType::ReRegister,
+            bincode::serialize(&request).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, libc::ENOENT);
+        assert!(!service.heart.instances.contains_key("127.0.0.1:9999"));
+    }
+
+    #[tokio::test]
+    async fn test_drain_status() {
+        let (kept, drained) = ("127.0.0.1:8085", "127.0.0.1:8086");
+        let service = ManagerService::new(vec![kept.to_string(), drained.to_string()], 100);
+        *service.manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        let dispatch = |operation_type: ManagerOperationType, server: &str| {
+            service.dispatch(
+                0,
+                operation_type.into(),
+                0,
+                server.as_bytes().to_vec(),
+                vec![],
+                vec![],
+            )
+        };
+        let drain_status = || async {
+            let (status, _, meta_data_length, _, meta_data, _) =
+                dispatch(ManagerOperationType::GetDrainStatus, drained)
+                    .await
+                    .unwrap();
+            assert_eq!(status, 0);
+            bincode::deserialize::<GetDrainStatusRecvMetaData>(&meta_data[..meta_data_length])
+                .unwrap()
+        };
+
+        let (status, ..) = dispatch(ManagerOperationType::GetDrainStatus, drained)
+            .await
+            .unwrap();
+        assert_eq!(status, libc::ENOENT);
+        let (status, ..) = dispatch(ManagerOperationType::DrainNode, drained)
+            .await
+            .unwrap();
+        assert_eq!(status, 0);
+        // a second drain waits for the first
+        let (status, ..) = dispatch(ManagerOperationType::DrainNode, kept)
+            .await
+            .unwrap();
+        assert_eq!(status, libc::EIO);
+        assert_eq!(drain_status().await.progress_pct, 0);
+
+        service
+            .manager
+            .servers
+            .lock()
+            .unwrap()
+            .get_mut(drained)
+            .unwrap()
+            .status = ServerStatus::Transferring;
+        service.heart.report_progress(
+            drained.to_string(),
+            TransferProgress {
+                total_bytes: 400,
+                remaining_bytes: 100,
+                bytes_per_sec: 0,
+            },
+        );
+        assert_eq!(
+            drain_status().await,
+            GetDrainStatusRecvMetaData {
+                status: ServerStatus::Transferring,
+                drained: false,
+                progress_pct: 75,
+                bytes_remaining: 100,
+            }
+        );
+
+        service.manager.finish_drain();
+        let status = drain_status().await;
+        assert!(status.drained);
+        assert_eq!((status.progress_pct, status.bytes_remaining), (100, 0));
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_operation() {
+        let service = ManagerService::new(vec!["127.0.0.1:8085".to_string()], 100);
+        // answered by servers only
+        let (status, ..) = service
+            .dispatch(
+                0,
+                ManagerOperationType::HealthCheck.into(),
+                0,
+                vec![],
+                vec![],
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(status, libc::EOPNOTSUPP);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_status_cache() {
+        let service = ManagerService::new(vec!["127.0.0.1:8085".to_string()], 100);
+        let get_status = || async {
+            let (status, _, meta_data_length, _, meta_data, _) = service
+                .dispatch(
+                    0,
+                    ManagerOperationType::GetClusterStatus.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            bincode::deserialize::<GetClusterStatusRecvMetaData>(&meta_data[..meta_data_length])
+                .unwrap()
+        };
+
+        let first = get_status().await;
+        let second = get_status().await;
+        assert_eq!(first, second);
+        assert_eq!(service.status_computations.load(Ordering::Relaxed), 1);
+        assert_eq!(first.min_poll_interval_ms, MIN_POLL_INTERVAL_MS);
+        assert!(first.poll_interval() >= STATUS_CACHE_TTL);
+
+        tokio::time::sleep(STATUS_CACHE_TTL).await;
+        get_status().await;
+        assert_eq!(service.status_computations.load(Ordering::Relaxed), 2);
+
+        // a status change is seen by the next poll
+        *service.manager.cluster_status.lock().unwrap() = ClusterStatus::Idle;
+        assert_eq!(get_status().await.status, ClusterStatus::Idle);
+        assert!(service
+            .manager
+            .add_nodes(vec![("127.0.0.1:8086".to_string(), 1)])
+            .is_none());
+        assert_eq!(get_status().await.status, ClusterStatus::NodesStarting);
+        assert_eq!(service.status_computations.load(Ordering::Relaxed), 4);
+    }
+}
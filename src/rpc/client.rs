@@ -4,14 +4,31 @@
 
 use super::{
     callback::CallbackPool,
+    compression::Compression,
     connection::ClientConnection,
     protocol::{CONNECTION_RETRY_TIMES, SEND_RETRY_TIMES},
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
 use log::{error, info, warn};
-use std::{marker::PhantomData, sync::Arc, time::Duration};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use parking_lot::RwLock;
+use rand::Rng;
+use std::{
+    io::IoSlice,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicU8, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, rustls::ServerName, TlsConnector};
 
 #[async_trait]
 pub trait StreamCreator<
@@ -19,31 +36,127 @@ pub trait StreamCreator<
     W: AsyncWriteExt + Unpin + std::marker::Sync + std::marker::Send + 'static,
 >
 {
-    async fn create_stream(server_address: &str) -> Result<(R, W), String>;
+    // tls is the connector of the client, if it has one
+    async fn create_stream(
+        server_address: &str,
+        tls: Option<TlsConnector>,
+    ) -> Result<(R, W), String>;
+}
+
+// the halves of a tcp connection, which is encrypted when the client has tls
+pub enum TcpReadHalf {
+    Plain(tokio::net::tcp::OwnedReadHalf),
+    Tls(ReadHalf<TlsStream<TcpStream>>),
+}
+
+pub enum TcpWriteHalf {
+    Plain(tokio::net::tcp::OwnedWriteHalf),
+    Tls(WriteHalf<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for TcpReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TcpReadHalf::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            TcpReadHalf::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for TcpWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            TcpWriteHalf::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            TcpWriteHalf::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            TcpWriteHalf::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            TcpWriteHalf::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            TcpWriteHalf::Plain(stream) => stream.is_write_vectored(),
+            TcpWriteHalf::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TcpWriteHalf::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            TcpWriteHalf::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TcpWriteHalf::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            TcpWriteHalf::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
 }
 
 pub struct TcpStreamCreator;
 
 #[async_trait]
-impl StreamCreator<tokio::net::tcp::OwnedReadHalf, tokio::net::tcp::OwnedWriteHalf>
-    for TcpStreamCreator
-{
+impl StreamCreator<TcpReadHalf, TcpWriteHalf> for TcpStreamCreator {
     async fn create_stream(
         server_address: &str,
-    ) -> Result<
-        (
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-        ),
-        String,
-    > {
-        let stream = match tokio::net::TcpStream::connect(server_address).await {
+        tls: Option<TlsConnector>,
+    ) -> Result<(TcpReadHalf, TcpWriteHalf), String> {
+        let stream = match TcpStream::connect(server_address).await {
             Ok(stream) => stream,
             Err(e) => {
                 return Err(format!("connect to {} error: {}", server_address, e));
             }
         };
-        Ok(stream.into_split())
+        let connector = match tls {
+            Some(connector) => connector,
+            None => {
+                let (read_stream, write_stream) = stream.into_split();
+                return Ok((
+                    TcpReadHalf::Plain(read_stream),
+                    TcpWriteHalf::Plain(write_stream),
+                ));
+            }
+        };
+        // the certificate of the server is checked against the host it is dialed by
+        let host = server_address
+            .rsplit_once(':')
+            .map_or(server_address, |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let server_name =
+            ServerName::try_from(host).map_err(|e| format!("tls server name {}: {}", host, e))?;
+        match connector.connect(server_name, stream).await {
+            Ok(stream) => {
+                let (read_stream, write_stream) = tokio::io::split(stream);
+                Ok((
+                    TcpReadHalf::Tls(read_stream),
+                    TcpWriteHalf::Tls(write_stream),
+                ))
+            }
+            Err(e) => Err(format!(
+                "tls handshake with {} error: {}",
+                server_address, e
+            )),
+        }
     }
 }
 
@@ -55,6 +168,7 @@ impl StreamCreator<tokio::net::unix::OwnedReadHalf, tokio::net::unix::OwnedWrite
 {
     async fn create_stream(
         server_address: &str,
+        _tls: Option<TlsConnector>,
     ) -> Result<
         (
             tokio::net::unix::OwnedReadHalf,
@@ -72,6 +186,44 @@ impl StreamCreator<tokio::net::unix::OwnedReadHalf, tokio::net::unix::OwnedWrite
     }
 }
 
+// how a call that may be repeated is retried when it fails to reach the server.
+// the wait doubles from base_delay up to max_delay, and jitter is the share of
+// each wait that is randomly taken off so clients do not retry in step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    // a single attempt
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // the wait after the attempt-th failed attempt, counted from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        match jitter > 0.0 {
+            true => delay.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..jitter)),
+            false => delay,
+        }
+    }
+}
+
 pub struct RpcClient<
     R: AsyncReadExt + Unpin + std::marker::Sync + std::marker::Send + 'static,
     W: AsyncWriteExt + Unpin + std::marker::Sync + std::marker::Send + 'static,
@@ -79,6 +231,14 @@ pub struct RpcClient<
 > {
     connections: DashMap<String, Arc<ClientConnection<W, R>>>,
     pool: Arc<CallbackPool>,
+    // the codec request and response data is sent with
+    compression: AtomicU8,
+    // added to the flags of every request
+    request_flags: AtomicU32,
+    // used by call_remote_idempotent
+    retry_policy: RwLock<RetryPolicy>,
+    // connections are made over tls when it is set
+    tls: RwLock<Option<TlsConnector>>,
     stream_creator: PhantomData<S>,
 }
 
@@ -106,17 +266,49 @@ impl<
         Self {
             connections: DashMap::new(),
             pool,
+            compression: AtomicU8::new(Compression::None.into()),
+            request_flags: AtomicU32::new(0),
+            retry_policy: RwLock::new(RetryPolicy::default()),
+            tls: RwLock::new(None),
             stream_creator: PhantomData,
         }
     }
 
+    // a codec that is not built in is replaced by none
+    pub fn set_compression(&self, compression: Compression) {
+        self.compression
+            .store(compression.or_none().into(), Ordering::Relaxed);
+    }
+
+    pub fn compression(&self) -> Compression {
+        Compression::try_from(self.compression.load(Ordering::Relaxed)).unwrap()
+    }
+
+    pub fn set_request_flags(&self, flags: u32) {
+        self.request_flags.store(flags, Ordering::Relaxed);
+    }
+
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.retry_policy.write() = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        *self.retry_policy.read()
+    }
+
+    // for the connections added after it
+    pub fn set_tls(&self, connector: Option<TlsConnector>) {
+        *self.tls.write() = connector;
+    }
+
     pub fn close(&self) {
         self.pool.free();
     }
 
     pub async fn add_connection(&self, server_address: &str) -> Result<(), String> {
         for _ in 0..CONNECTION_RETRY_TIMES {
-            match S::create_stream(server_address).await {
+            let tls = self.tls.read().clone();
+            match S::create_stream(server_address, tls).await {
                 Ok((read_stream, write_stream)) => {
                     if self.connections.contains_key(server_address) {
                         warn!("connection already exists: {}", server_address);
@@ -152,7 +344,8 @@ impl<
         match self.connections.get(server_address) {
             Some(connection) => {
                 if connection.value().reconnect() {
-                    match S::create_stream(server_address).await {
+                    let tls = self.tls.read().clone();
+                    match S::create_stream(server_address, tls).await {
                         Ok((read_stream, write_stream)) => {
                             tokio::spawn(parse_response(
                                 read_stream,
@@ -181,6 +374,10 @@ impl<
         }
     }
 
+    pub fn has_connection(&self, server_address: &str) -> bool {
+        self.connections.contains_key(server_address)
+    }
+
     pub fn remove_connection(&self, server_address: &str) {
         self.connections.remove(server_address);
     }
@@ -220,10 +417,11 @@ impl<
                     batch,
                     id,
                     operation_type,
-                    req_flags,
+                    req_flags | self.request_flags.load(Ordering::Relaxed),
                     path,
                     send_meta_data,
                     send_data,
+                    self.compression(),
                 )
                 .await
             {
@@ -246,6 +444,62 @@ impl<
             server_address
         ))
     }
+
+    // call_remote for a request that does the same when it is sent again, such as a
+    // read. it is retried by the retry policy while the server can not be reached
+    // and the error of the last attempt is returned. a request that changes
+    // anything must not be sent through here
+    #[allow(clippy::too_many_arguments)]
+    pub async fn call_remote_idempotent(
+        &self,
+        server_address: &str,
+        operation_type: u32,
+        req_flags: u32,
+        path: &str,
+        send_meta_data: &[u8],
+        send_data: &[u8],
+        status: &mut i32,
+        rsp_flags: &mut u32,
+        recv_meta_data_length: &mut usize,
+        recv_data_length: &mut usize,
+        recv_meta_data: &mut [u8],
+        recv_data: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let policy = self.retry_policy();
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .call_remote(
+                    server_address,
+                    operation_type,
+                    req_flags,
+                    path,
+                    send_meta_data,
+                    send_data,
+                    status,
+                    rsp_flags,
+                    recv_meta_data_length,
+                    recv_data_length,
+                    recv_meta_data,
+                    recv_data,
+                    timeout,
+                )
+                .await;
+            match result {
+                Err(e) if attempt < policy.max_attempts => {
+                    let delay = policy.delay(attempt);
+                    warn!(
+                        "call {} on {} failed at attempt {}: {}, retry in {:?}",
+                        operation_type, server_address, attempt, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 }
 
 // parse_response
@@ -300,24 +554,53 @@ pub async fn parse_response<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin>(
             }
         }
 
-        if let Err(e) = connection
-            .receive_response(
-                &mut read_stream,
-                pool.get_meta_data_ref(id, header.meta_data_length as usize),
-                pool.get_data_ref(id, header.data_length as usize),
-            )
-            .await
-        {
-            error!("Error receiving response: {}", e);
-            break;
-        };
+        let mut status = header.status;
+        let mut data_length = header.data_length as usize;
+        if header.compression == 0 {
+            if let Err(e) = connection
+                .receive_response(
+                    &mut read_stream,
+                    pool.get_meta_data_ref(id, header.meta_data_length as usize),
+                    pool.get_data_ref(id, data_length),
+                )
+                .await
+            {
+                error!("Error receiving response: {}", e);
+                break;
+            };
+        } else {
+            let mut compressed =
+                vec![0u8; total_length.saturating_sub(header.meta_data_length) as usize];
+            if let Err(e) = connection
+                .receive_response(
+                    &mut read_stream,
+                    pool.get_meta_data_ref(id, header.meta_data_length as usize),
+                    &mut compressed,
+                )
+                .await
+            {
+                error!("Error receiving response: {}", e);
+                break;
+            };
+            match Compression::try_from(header.compression)
+                .map_err(|_| format!("unknown compression {}", header.compression))
+                .and_then(|compression| compression.decompress(&compressed, data_length))
+            {
+                Ok(data) => pool.get_data_ref(id, data_length).copy_from_slice(&data),
+                Err(e) => {
+                    error!("Error decompressing response: {}", e);
+                    status = libc::EIO;
+                    data_length = 0;
+                }
+            }
+        }
         if let Err(e) = pool
             .response(
                 id,
-                header.status,
+                status,
                 header.flags,
                 header.meta_data_length as usize,
-                header.data_length as usize,
+                data_length,
             )
             .await
         {
@@ -326,3 +609,165 @@ pub async fn parse_response<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin>(
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+
+    use super::{RetryPolicy, RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
+    use crate::rpc::server::{Handler, RpcServer};
+
+    // stalls past the timeout of the caller while stalls is not used up, as a
+    // server that is restarting would
+    #[derive(Default)]
+    struct FlakyHandler {
+        stalls: AtomicU32,
+        requests: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Handler for FlakyHandler {
+        async fn dispatch(
+            &self,
+            _id: u32,
+            _operation_type: u32,
+            _flags: u32,
+            _path: Vec<u8>,
+            _data: Vec<u8>,
+            _metadata: Vec<u8>,
+        ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let stalled = self
+                .stalls
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |stalls| {
+                    stalls.checked_sub(1)
+                })
+                .is_ok();
+            if stalled {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok((0, 0, 0, 4, vec![], b"data".to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: 0.0,
+        };
+        let delays: Vec<u64> = (1..7)
+            .map(|attempt| policy.delay(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent() {
+        let address = "127.0.0.1:50070";
+        let handler = Arc::new(FlakyHandler::default());
+        let server = RpcServer::new(handler.clone(), address);
+        tokio::spawn(async move { server.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let client: RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator> = RpcClient::new();
+        client.add_connection(address).await.unwrap();
+        let call = |idempotent: bool| {
+            let client = &client;
+            async move {
+                let (mut status, mut rsp_flags, mut meta_data_length, mut data_length) =
+                    (0, 0, 0, 0);
+                let mut data = [0u8; 4];
+                let timeout = Duration::from_millis(200);
+                let result = match idempotent {
+                    true => {
+                        client
+                            .call_remote_idempotent(
+                                address,
+                                0,
+                                0,
+                                "",
+                                &[],
+                                &[],
+                                &mut status,
+                                &mut rsp_flags,
+                                &mut meta_data_length,
+                                &mut data_length,
+                                &mut [],
+                                &mut data,
+                                timeout,
+                            )
+                            .await
+                    }
+                    false => {
+                        client
+                            .call_remote(
+                                address,
+                                0,
+                                0,
+                                "",
+                                &[],
+                                &[],
+                                &mut status,
+                                &mut rsp_flags,
+                                &mut meta_data_length,
+                                &mut data_length,
+                                &mut [],
+                                &mut data,
+                                timeout,
+                            )
+                            .await
+                    }
+                };
+                result.map(|_| data)
+            }
+        };
+
+        let requests = |stalls| {
+            handler.stalls.store(stalls, Ordering::SeqCst);
+            handler.requests.swap(0, Ordering::SeqCst)
+        };
+
+        // without a policy the first failure is returned
+        requests(1);
+        assert!(call(true).await.is_err());
+
+        client.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_millis(100),
+            jitter: 0.1,
+        });
+        assert_eq!(requests(2), 1);
+        let start = Instant::now();
+        assert_eq!(call(true).await.unwrap(), *b"data");
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        // a call that is not idempotent is never repeated
+        assert_eq!(requests(1), 3);
+        assert!(call(false).await.is_err());
+
+        // nor is one past max_attempts, the last error is returned
+        assert_eq!(requests(3), 1);
+        assert!(call(true).await.is_err());
+        assert_eq!(requests(0), 3);
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// the codec a request asks for in its header. the data of the request and of its
+// response are sent compressed with it, the data_length of the header stays the
+// length of the uncompressed data so the receiver knows what to expect and the
+// compressed length is what total_length has left after the path and metadata
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<Compression> for u8 {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    pub fn is_available(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    // the codec to ask for when this one is wanted, none if it is not built in
+    pub fn or_none(self) -> Self {
+        match self.is_available() {
+            true => self,
+            false => Compression::None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| e.to_string()),
+            #[allow(unreachable_patterns)]
+            _ => Err(format!("compression {:?} is not built in", self)),
+        }
+    }
+
+    // length is the length of the data before it was compressed
+    pub fn decompress(self, data: &[u8], length: usize) -> Result<Vec<u8>, String> {
+        let decompressed = match self {
+            Compression::None => data.to_vec(),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                lz4_flex::block::decompress(data, length).map_err(|e| e.to_string())?
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(data, length).map_err(|e| e.to_string())?,
+            #[allow(unreachable_patterns)]
+            _ => return Err(format!("compression {:?} is not built in", self)),
+        };
+        if decompressed.len() != length {
+            return Err(format!(
+                "{:?} data decompressed to {} bytes, expected {}",
+                self,
+                decompressed.len(),
+                length
+            ));
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+
+    fn payloads() -> Vec<Vec<u8>> {
+        let compressible = b"sealfs ".repeat(64 * 1024);
+        // xorshift, so the bytes have nothing for a codec to find
+        let mut state = 0x2545f4914f6cdd1du64;
+        let incompressible = (0..256 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        vec![compressible, incompressible, Vec::new()]
+    }
+
+    fn round_trip(compression: Compression) {
+        for payload in payloads() {
+            let compressed = compression.compress(&payload).unwrap();
+            let decompressed = compression.decompress(&compressed, payload.len()).unwrap();
+            assert_eq!(decompressed, payload);
+        }
+        let compressible = &payloads()[0];
+        if compression != Compression::None {
+            assert!(compression.compress(compressible).unwrap().len() < compressible.len() / 10);
+        }
+        let compressed = compression.compress(compressible).unwrap();
+        assert!(compression
+            .decompress(&compressed, compressible.len() - 1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_none_round_trip() {
+        round_trip(Compression::None);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4_round_trip() {
+        round_trip(Compression::Lz4);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_round_trip() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[test]
+    fn test_fallback() {
+        for value in 0..3u8 {
+            let compression = Compression::try_from(value).unwrap();
+            assert_eq!(u8::from(compression), value);
+            match compression.is_available() {
+                true => assert_eq!(compression.or_none(), compression),
+                false => {
+                    assert_eq!(compression.or_none(), Compression::None);
+                    assert!(compression.compress(b"data").is_err());
+                }
+            }
+        }
+        assert!(Compression::try_from(3).is_err());
+    }
+}
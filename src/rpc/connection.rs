@@ -2,11 +2,19 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{io::IoSlice, marker::PhantomData, sync::atomic::AtomicU32};
+use std::{
+    borrow::Cow,
+    io::IoSlice,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
 
-use super::protocol::{
-    RequestHeader, ResponseHeader, MAX_DATA_LENGTH, MAX_FILENAME_LENGTH, MAX_METADATA_LENGTH,
-    REQUEST_HEADER_SIZE, RESPONSE_HEADER_SIZE,
+use super::{
+    compression::Compression,
+    protocol::{
+        RequestHeader, ResponseHeader, MAX_DATA_LENGTH, MAX_FILENAME_LENGTH, MAX_METADATA_LENGTH,
+        REQUEST_HEADER_SIZE, RESPONSE_HEADER_SIZE,
+    },
 };
 use log::{error, info};
 use tokio::{
@@ -91,8 +99,8 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ClientConnection<W, R> {
     }
 
     // request
-    // | batch | id | type | flags | total_length | file_path_length | meta_data_length | data_length | filename | meta_data | data |
-    // | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1~4kB | 0~ | 0~ |
+    // | batch | id | type | flags | total_length | file_path_length | meta_data_length | data_length | compression | filename | meta_data | data |
+    // | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1Byte | 1~4kB | 0~ | 0~ |
     #[allow(clippy::too_many_arguments)]
     pub async fn send_request(
         &self,
@@ -103,10 +111,17 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ClientConnection<W, R> {
         filename: &str,
         meta_data: &[u8],
         data: &[u8],
+        compression: Compression,
     ) -> Result<(), String> {
         if !self.is_connected() {
             return Err("connection is not connected".to_string());
         }
+        let uncompressed_length = data.len();
+        let data = match compression {
+            Compression::None => Cow::Borrowed(data),
+            _ => Cow::Owned(compression.compress(data)?),
+        };
+        let data = data.as_ref();
         let filename_length = filename.len();
         let meta_data_length = meta_data.len();
         let data_length = data.len();
@@ -119,7 +134,8 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ClientConnection<W, R> {
         request.extend_from_slice(&(total_length as u32).to_le_bytes());
         request.extend_from_slice(&(filename_length as u32).to_le_bytes());
         request.extend_from_slice(&(meta_data_length as u32).to_le_bytes());
-        request.extend_from_slice(&(data_length as u32).to_le_bytes());
+        request.extend_from_slice(&(uncompressed_length as u32).to_le_bytes());
+        request.push(compression.into());
         request.extend_from_slice(filename.as_bytes());
         let mut stream = self.write_stream.lock().await;
         let mut offset = 0;
@@ -186,6 +202,7 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ClientConnection<W, R> {
             total_length,
             meta_data_length,
             data_length,
+            compression: header[28],
         })
     }
 
@@ -225,6 +242,7 @@ pub struct ServerConnection<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> {
     pub id: u32,
     name_id: String,
     write_stream: Mutex<W>,
+    closed: AtomicBool,
 
     phantom_data: PhantomData<R>,
 }
@@ -235,6 +253,7 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
             id,
             name_id,
             write_stream: Mutex::new(write_stream),
+            closed: AtomicBool::new(false),
 
             phantom_data: PhantomData,
         }
@@ -244,9 +263,22 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
         self.name_id.clone()
     }
 
+    // shuts the write side down, the receive loop stops reading once it sees this
+    pub async fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        if let Err(e) = self.write_stream.lock().await.shutdown().await {
+            error!("close connection {} error: {}", self.name_id, e);
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
     // response
-    // | batch | id | status | flags | total_length | meta_data_lenght | data_length | meta_data | data |
-    // | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 0~ | 0~ |
+    // | batch | id | status | flags | total_length | meta_data_lenght | data_length | compression | meta_data | data |
+    // | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1Byte | 0~ | 0~ |
+    #[allow(clippy::too_many_arguments)]
     pub async fn send_response(
         &self,
         batch: u32,
@@ -255,7 +287,14 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
         flags: u32,
         meta_data: &[u8],
         data: &[u8],
+        compression: Compression,
     ) -> Result<(), String> {
+        let uncompressed_length = data.len();
+        let data = match compression {
+            Compression::None => Cow::Borrowed(data),
+            _ => Cow::Owned(compression.compress(data)?),
+        };
+        let data = data.as_ref();
         let data_length = data.len();
         let meta_data_length = meta_data.len();
         let total_length = data_length + meta_data_length;
@@ -266,7 +305,8 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
         response.extend_from_slice(&flags.to_le_bytes());
         response.extend_from_slice(&(total_length as u32).to_le_bytes());
         response.extend_from_slice(&(meta_data_length as u32).to_le_bytes());
-        response.extend_from_slice(&(data_length as u32).to_le_bytes());
+        response.extend_from_slice(&(uncompressed_length as u32).to_le_bytes());
+        response.push(compression.into());
         let mut stream = self.write_stream.lock().await;
         let mut offset = 0;
         loop {
@@ -328,16 +368,27 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
             file_path_length,
             meta_data_length,
             data_length,
+            compression: header[32],
         })
     }
 
+    // the data is returned as it was sent, still compressed if the header says so
     pub async fn receive_request(
         &self,
         read_stream: &mut R,
         header: &RequestHeader,
     ) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), String> {
+        let sent_data_length = match header.compression {
+            0 => header.data_length,
+            _ => header.total_length.saturating_sub(
+                header
+                    .file_path_length
+                    .saturating_add(header.meta_data_length),
+            ),
+        };
         if header.file_path_length as usize > MAX_FILENAME_LENGTH
             || header.data_length as usize > MAX_DATA_LENGTH
+            || sent_data_length as usize > MAX_DATA_LENGTH
             || header.meta_data_length as usize > MAX_METADATA_LENGTH
         {
             info!(
@@ -347,7 +398,7 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
             return Err("path length or data length or meta data length is too long".into());
         }
         let mut path = vec![0u8; header.file_path_length as usize];
-        let mut data = vec![0u8; header.data_length as usize];
+        let mut data = vec![0u8; sent_data_length as usize];
         let mut meta_data = vec![0u8; header.meta_data_length as usize];
 
         self.receive(read_stream, &mut path[0..header.file_path_length as usize])
@@ -357,8 +408,7 @@ impl<W: AsyncWriteExt + Unpin, R: AsyncReadExt + Unpin> ServerConnection<W, R> {
             &mut meta_data[0..header.meta_data_length as usize],
         )
         .await?;
-        self.receive(read_stream, &mut data[0..header.data_length as usize])
-            .await?;
+        self.receive(read_stream, &mut data).await?;
 
         Ok((path, data, meta_data))
     }
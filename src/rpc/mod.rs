@@ -4,7 +4,9 @@
 
 pub mod callback;
 pub mod client;
+pub mod compression;
 pub mod connection;
 pub mod protocol;
 pub mod rdma;
 pub mod server;
+pub mod tls;
@@ -11,21 +11,26 @@ pub const CONNECTION_RETRY_TIMES: i32 = 100;
 pub const SEND_RETRY_TIMES: i32 = 3;
 
 // request
-// | batch | id | type | flags | total_length | file_path_length | meta_data_length | data_length | filename | meta_data | data |
-// | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1~4kB | 0~ | 0~ |
-pub const REQUEST_HEADER_SIZE: usize = 4 * 8;
+// | batch | id | type | flags | total_length | file_path_length | meta_data_length | data_length | compression | filename | meta_data | data |
+// | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1Byte | 1~4kB | 0~ | 0~ |
+pub const REQUEST_HEADER_SIZE: usize = 4 * 8 + 1;
 pub const REQUEST_FILENAME_LENGTH_SIZE: usize = 4;
 pub const REQUEST_METADATA_LENGTH_SIZE: usize = 4;
 pub const REQUEST_DATA_LENGTH_SIZE: usize = 4;
 
 pub const REQUEST_POOL_SIZE: usize = 65536;
 
+// request flag, set on every request a server sends another server. such requests
+// are not throttled and not limited by max_in_flight, the limits a server puts on
+// its clients are not for its peers
+pub const FROM_SERVER: u32 = 1 << 29;
+
 /* receive operation response and wake up the operation thread using condition variable
     response
-    | batch | id | status | flags | total_length | meta_data_lenght | data_length | meta_data | data |
-    | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 0~ | 0~ |
+    | batch | id | status | flags | total_length | meta_data_lenght | data_length | compression | meta_data | data |
+    | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 4Byte | 1Byte | 0~ | 0~ |
 */
-pub const RESPONSE_HEADER_SIZE: usize = 4 * 7;
+pub const RESPONSE_HEADER_SIZE: usize = 4 * 7 + 1;
 
 // pub const CLIENT_RESPONSE_TIMEOUT: time::Duration = time::Duration::from_micros(300); // timeout for client response loop
 
@@ -39,6 +44,7 @@ pub struct RequestHeader {
     pub file_path_length: u32,
     pub meta_data_length: u32,
     pub data_length: u32,
+    pub compression: u8,
 }
 
 impl RequestHeader {
@@ -52,6 +58,7 @@ impl RequestHeader {
         file_path_length: u32,
         meta_data_length: u32,
         data_length: u32,
+        compression: u8,
     ) -> Self {
         Self {
             batch,
@@ -62,6 +69,7 @@ impl RequestHeader {
             file_path_length,
             meta_data_length,
             data_length,
+            compression,
         }
     }
 }
@@ -74,9 +82,11 @@ pub struct ResponseHeader {
     pub total_length: u32,
     pub meta_data_length: u32,
     pub data_length: u32,
+    pub compression: u8,
 }
 
 impl ResponseHeader {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         batch: u32,
         id: u32,
@@ -85,6 +95,7 @@ impl ResponseHeader {
         total_length: u32,
         meta_data_length: u32,
         data_length: u32,
+        compression: u8,
     ) -> Self {
         Self {
             batch,
@@ -94,6 +105,7 @@ impl ResponseHeader {
             total_length,
             meta_data_length,
             data_length,
+            compression,
         }
     }
 }
@@ -6,6 +6,7 @@ use std::{io::IoSlice, sync::Arc, time::Duration};
 
 use crate::rpc::{
     callback::CallbackPool,
+    compression::Compression,
     protocol::{ResponseHeader, RESPONSE_HEADER_SIZE},
 };
 pub struct Client {
@@ -114,6 +115,8 @@ impl Client {
         request.extend_from_slice(&(path.len() as u32).to_le_bytes());
         request.extend_from_slice(&(send_meta_data.len() as u32).to_le_bytes());
         request.extend_from_slice(&(send_data.len() as u32).to_le_bytes());
+        // data is never compressed over rdma
+        request.push(Compression::None.into());
         request.extend_from_slice(path.as_bytes());
         let request = &[
             IoSlice::new(&request),
@@ -180,6 +183,7 @@ pub fn parse_response_header(response: &[u8]) -> ResponseHeader {
     let total_length = u32::from_le_bytes(header[16..20].try_into().unwrap());
     let meta_data_length = u32::from_le_bytes(header[20..24].try_into().unwrap());
     let data_length = u32::from_le_bytes(header[24..28].try_into().unwrap());
+    let compression = header[28];
     // debug!(
     //     "received response_header batch: {}, id: {}, status: {}, flags: {}, total_length: {}, meta_data_length: {}, data_length: {}",
     //     batch, id, status, flags, total_length, meta_data_length, data_length
@@ -192,6 +196,7 @@ pub fn parse_response_header(response: &[u8]) -> ResponseHeader {
         total_length,
         meta_data_length,
         data_length,
+        compression,
     }
 }
 
@@ -7,6 +7,7 @@ use tokio::sync::mpsc::channel;
 use ibv::connection::conn::run;
 
 use crate::rpc::{
+    compression::Compression,
     protocol::{RequestHeader, REQUEST_HEADER_SIZE, RESPONSE_HEADER_SIZE},
     server::Handler,
 };
@@ -74,6 +75,7 @@ pub fn parse_request_header(request: &[u8]) -> RequestHeader {
     let file_path_length = u32::from_le_bytes(header[20..24].try_into().unwrap());
     let meta_data_length = u32::from_le_bytes(header[24..28].try_into().unwrap());
     let data_length = u32::from_le_bytes(header[28..32].try_into().unwrap());
+    let compression = header[32];
     RequestHeader {
         batch,
         id,
@@ -83,6 +85,7 @@ pub fn parse_request_header(request: &[u8]) -> RequestHeader {
         file_path_length,
         meta_data_length,
         data_length,
+        compression,
     }
 }
 
@@ -168,6 +171,7 @@ pub async fn send_response(
     response.extend_from_slice(&(total_length as u32).to_le_bytes());
     response.extend_from_slice(&(meta_data_length as u32).to_le_bytes());
     response.extend_from_slice(&(data_length as u32).to_le_bytes());
+    response.push(Compression::None.into());
     let response = &[
         IoSlice::new(&response),
         IoSlice::new(meta_data),
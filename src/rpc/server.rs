@@ -2,16 +2,34 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::sync::Arc;
+use std::{
+    any::Any,
+    future::Future,
+    net::SocketAddr,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 use async_trait::async_trait;
 use log::{error, info, warn};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, UnixListener},
+    sync::Semaphore,
 };
 
-use super::{connection::ServerConnection, protocol::RequestHeader};
+use tokio_rustls::TlsAcceptor;
+
+use super::{compression::Compression, connection::ServerConnection, protocol::RequestHeader};
+
+// returned by a handler for a request it cannot make sense of, such as an unknown
+// operation type. the request is not answered and its connection is dropped, as
+// whatever follows on it cannot be trusted either
+#[derive(Debug, thiserror::Error)]
+#[error("protocol error: {0}")]
+pub struct ProtocolError(pub String);
 
 #[async_trait]
 pub trait Handler {
@@ -24,6 +42,45 @@ pub trait Handler {
         data: Vec<u8>,
         metadata: Vec<u8>,
     ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)>;
+
+    // called before the first request of the connection with this id is read, peer
+    // is the address it came from, None for a unix stream
+    fn connection_opened(&self, _id: u32, _peer: Option<SocketAddr>) {}
+
+    // called once the connection with this id stops being read from
+    fn connection_closed(&self, _id: u32) {}
+
+    // whether the connection with this id comes from another server, told from the
+    // address it was accepted from rather than from anything the request says
+    fn is_server_peer(&self, _id: u32) -> bool {
+        false
+    }
+}
+
+// resolves to the panic of the future instead of unwinding through the caller, so
+// a handler bug fails only the request that hit it
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(&mut self.0).poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("unknown panic"),
+    }
 }
 
 pub async fn handle<
@@ -38,16 +95,48 @@ pub async fn handle<
     data: Vec<u8>,
     metadata: Vec<u8>,
 ) {
-    let response = handler
-        .dispatch(
-            connection.id,
-            header.r#type,
-            header.flags,
-            path,
-            data,
-            metadata,
-        )
-        .await;
+    // the response data is compressed the way the request data was
+    let (compression, data) = match decompress_request(&header, data) {
+        Ok(request) => request,
+        Err((status, e)) => {
+            error!("handle, decompress request error: {}", e);
+            if let Err(e) = connection
+                .send_response(
+                    header.batch,
+                    header.id,
+                    status,
+                    0,
+                    &[],
+                    &[],
+                    Compression::None,
+                )
+                .await
+            {
+                error!("handle, send response error: {}", e);
+            }
+            return;
+        }
+    };
+    let response = match CatchUnwind(handler.dispatch(
+        connection.id,
+        header.r#type,
+        header.flags,
+        path,
+        data,
+        metadata,
+    ))
+    .await
+    {
+        Ok(response) => response,
+        Err(panic) => {
+            error!(
+                "handle, operation {} panicked: {}",
+                header.r#type,
+                panic_message(panic.as_ref())
+            );
+            Ok((libc::EIO, 0, 0, 0, Vec::new(), Vec::new()))
+        }
+    };
     match response {
         Ok(response) => {
             if let Err(e) = connection
@@ -58,18 +147,49 @@ pub async fn handle<
                     response.1,
                     &response.4[0..response.2],
                     &response.5[0..response.3],
+                    compression,
                 )
                 .await
             {
                 error!("handle, send response error: {}", e);
             }
         }
+        Err(e) if e.is::<ProtocolError>() => {
+            error!(
+                "handle, {}, dropping connection {}",
+                e,
+                connection.name_id()
+            );
+            connection.close().await;
+        }
         Err(e) => {
             error!("handle, dispatch error: {}", e);
         }
     }
 }
 
+fn decompress_request(
+    header: &RequestHeader,
+    data: Vec<u8>,
+) -> Result<(Compression, Vec<u8>), (i32, String)> {
+    let compression = match Compression::try_from(header.compression) {
+        Ok(compression) if compression.is_available() => compression,
+        _ => {
+            return Err((
+                libc::EPROTONOSUPPORT,
+                format!("compression {} is not supported", header.compression),
+            ))
+        }
+    };
+    match compression {
+        Compression::None => Ok((compression, data)),
+        _ => compression
+            .decompress(&data, header.data_length as usize)
+            .map(|data| (compression, data))
+            .map_err(|e| (libc::EIO, e)),
+    }
+}
+
 pub async fn receive<
     H: Handler + std::marker::Sync + std::marker::Send + 'static,
     W: AsyncWriteExt + Unpin + std::marker::Sync + std::marker::Send + 'static,
@@ -78,12 +198,25 @@ pub async fn receive<
     handler: Arc<H>,
     connection: Arc<ServerConnection<W, R>>,
     mut read_stream: R,
+    max_in_flight: usize,
 ) {
+    // once max_in_flight requests are being handled the body of the next one is not
+    // read until one of them completes, the client is slowed down by tcp flow control.
+    // requests from other servers are not counted, a server waiting on a peer must
+    // not be held up behind requests that wait on it in turn
+    let in_flight = match max_in_flight {
+        0 => None,
+        n => Some(Arc::new(Semaphore::new(n))),
+    };
     loop {
         {
+            if connection.is_closed() {
+                break;
+            }
             let id = connection.name_id();
             let header = match connection.receive_request_header(&mut read_stream).await {
                 Ok(header) => header,
+                Err(_) if connection.is_closed() => break,
                 Err(e) => {
                     if e == "early eof" || e == "Connection reset by peer (os error 104)" {
                         warn!("{:?} receive, connection closed", id);
@@ -92,6 +225,12 @@ pub async fn receive<
                     panic!("{:?} parse_request, header error: {}", id, e);
                 }
             };
+            let permit = match &in_flight {
+                Some(in_flight) if !handler.is_server_peer(connection.id) => {
+                    Some(in_flight.clone().acquire_owned().await.unwrap())
+                }
+                _ => None,
+            };
             let data_result = connection.receive_request(&mut read_stream, &header).await;
             let (path, data, metadata) = match data_result {
                 Ok(data) => data,
@@ -101,15 +240,23 @@ pub async fn receive<
             };
             let handler = handler.clone();
             let connection = connection.clone();
-            tokio::spawn(handle(handler, connection, header, path, data, metadata));
+            tokio::spawn(async move {
+                handle(handler, connection, header, path, data, metadata).await;
+                drop(permit);
+            });
         }
     }
+    handler.connection_closed(connection.id);
 }
 
 pub struct RpcServer<H: Handler + std::marker::Sync + std::marker::Send + 'static> {
     // listener: TcpListener,
     bind_address: String,
     handler: Arc<H>,
+    // requests handled at the same time on one connection, 0 means unlimited
+    max_in_flight: usize,
+    // connections must complete a tls handshake when it is set
+    tls: Option<TlsAcceptor>,
 }
 
 impl<H: Handler + std::marker::Sync + std::marker::Send> RpcServer<H> {
@@ -117,24 +264,61 @@ impl<H: Handler + std::marker::Sync + std::marker::Send> RpcServer<H> {
         Self {
             handler,
             bind_address: String::from(bind_address),
+            max_in_flight: 0,
+            tls: None,
         }
     }
 
+    pub fn set_tls(&mut self, acceptor: Option<TlsAcceptor>) {
+        self.tls = acceptor;
+    }
+
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.max_in_flight = max_in_flight;
+    }
+
     pub async fn run(&self) -> anyhow::Result<()> {
         info!("Listening on {:?}", self.bind_address);
         let listener = TcpListener::bind(&self.bind_address).await?;
         let mut id = 1u32;
         loop {
             match listener.accept().await {
-                Ok((stream, _)) => {
-                    let (read_stream, write_stream) = stream.into_split();
+                Ok((stream, peer)) => {
                     info!("Connection {id} accepted");
                     let handler = Arc::clone(&self.handler);
                     let name_id = format!("{},{}", self.bind_address, id);
-                    let connection = Arc::new(ServerConnection::new(write_stream, name_id, id));
-                    tokio::spawn(async move {
-                        receive(handler, connection, read_stream).await;
-                    });
+                    let max_in_flight = self.max_in_flight;
+                    match self.tls.clone() {
+                        Some(acceptor) => {
+                            // the handshake is done before the first header is read, a
+                            // peer that fails it is dropped without being answered
+                            tokio::spawn(async move {
+                                let stream = match acceptor.accept(stream).await {
+                                    Ok(stream) => stream,
+                                    Err(e) => {
+                                        warn!(
+                                            "Connection {id} from {peer} tls handshake error: {e}"
+                                        );
+                                        return;
+                                    }
+                                };
+                                handler.connection_opened(id, Some(peer));
+                                let (read_stream, write_stream) = tokio::io::split(stream);
+                                let connection =
+                                    Arc::new(ServerConnection::new(write_stream, name_id, id));
+                                receive(handler, connection, read_stream, max_in_flight).await;
+                            });
+                        }
+                        None => {
+                            handler.connection_opened(id, Some(peer));
+                            let (read_stream, write_stream) = stream.into_split();
+                            let connection =
+                                Arc::new(ServerConnection::new(write_stream, name_id, id));
+                            tokio::spawn(async move {
+                                receive(handler, connection, read_stream, max_in_flight).await;
+                            });
+                        }
+                    }
                     id += 1;
                 }
                 Err(e) => {
@@ -164,9 +348,11 @@ impl<H: Handler + std::marker::Sync + std::marker::Send> RpcServer<H> {
                     info!("Connection {id} accepted");
                     let handler = Arc::clone(&self.handler);
                     let name_id = format!("{},{}", self.bind_address, id);
+                    handler.connection_opened(id, None);
                     let connection = Arc::new(ServerConnection::new(write_stream, name_id, id));
+                    let max_in_flight = self.max_in_flight;
                     tokio::spawn(async move {
-                        receive(handler, connection, read_stream).await;
+                        receive(handler, connection, read_stream, max_in_flight).await;
                     });
                     id += 1;
                 }
@@ -177,3 +363,222 @@ impl<H: Handler + std::marker::Sync + std::marker::Send> RpcServer<H> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use super::{Handler, RpcServer};
+    use crate::rpc::{
+        client::{RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf},
+        compression::Compression,
+        protocol::FROM_SERVER,
+    };
+
+    #[derive(Default)]
+    struct SlowHandler {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        server_peer: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Handler for SlowHandler {
+        async fn dispatch(
+            &self,
+            _id: u32,
+            _operation_type: u32,
+            _flags: u32,
+            _path: Vec<u8>,
+            _data: Vec<u8>,
+            _metadata: Vec<u8>,
+        ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok((0, 0, 0, 0, vec![], vec![]))
+        }
+
+        fn is_server_peer(&self, _id: u32) -> bool {
+            self.server_peer.load(Ordering::SeqCst)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_in_flight() {
+        let address = "127.0.0.1:50061";
+        let handler = Arc::new(SlowHandler::default());
+        let mut server = RpcServer::new(handler.clone(), address);
+        server.set_max_in_flight(2);
+        tokio::spawn(async move { server.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>> =
+            Arc::new(RpcClient::new());
+        client.add_connection(address).await.unwrap();
+
+        // requests from other servers are not limited, whatever the header claims
+        client.set_request_flags(FROM_SERVER);
+        for server_peer in [false, true] {
+            handler.server_peer.store(server_peer, Ordering::SeqCst);
+            handler.max_running.store(0, Ordering::SeqCst);
+            let mut requests = Vec::new();
+            for _ in 0..8 {
+                let client = client.clone();
+                requests.push(tokio::spawn(async move {
+                    let (mut status, mut rsp_flags, mut meta_data_length, mut data_length) =
+                        (0, 0, 0, 0);
+                    client
+                        .call_remote(
+                            address,
+                            0,
+                            0,
+                            "",
+                            &[],
+                            &[],
+                            &mut status,
+                            &mut rsp_flags,
+                            &mut meta_data_length,
+                            &mut data_length,
+                            &mut [],
+                            &mut [],
+                            Duration::from_secs(5),
+                        )
+                        .await
+                        .map(|_| status)
+                }));
+            }
+            for request in requests {
+                assert_eq!(request.await.unwrap(), Ok(0));
+            }
+            let max_running = handler.max_running.load(Ordering::SeqCst);
+            match server_peer {
+                false => assert_eq!(max_running, 2),
+                true => assert!(max_running > 2, "{} running", max_running),
+            }
+        }
+    }
+
+    struct EchoHandler;
+
+    #[async_trait]
+    impl Handler for EchoHandler {
+        async fn dispatch(
+            &self,
+            _id: u32,
+            _operation_type: u32,
+            _flags: u32,
+            _path: Vec<u8>,
+            data: Vec<u8>,
+            _metadata: Vec<u8>,
+        ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+            Ok((0, 0, 0, data.len(), vec![], data))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_echo() {
+        let address = "127.0.0.1:50085";
+        let server = RpcServer::new(Arc::new(EchoHandler), address);
+        tokio::spawn(async move { server.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let client: RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator> = RpcClient::new();
+        client.add_connection(address).await.unwrap();
+        let sent = b"sealfs ".repeat(4096);
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            client.set_compression(compression);
+            assert_eq!(client.compression(), compression.or_none());
+            let (mut status, mut rsp_flags, mut meta_data_length, mut data_length) = (0, 0, 0, 0);
+            let mut received = vec![0u8; sent.len()];
+            client
+                .call_remote(
+                    address,
+                    0,
+                    0,
+                    "",
+                    &[],
+                    &sent,
+                    &mut status,
+                    &mut rsp_flags,
+                    &mut meta_data_length,
+                    &mut data_length,
+                    &mut [],
+                    &mut received,
+                    Duration::from_secs(5),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(data_length, sent.len());
+            assert_eq!(received, sent);
+        }
+    }
+
+    struct PanicHandler;
+
+    #[async_trait]
+    impl Handler for PanicHandler {
+        async fn dispatch(
+            &self,
+            _id: u32,
+            operation_type: u32,
+            _flags: u32,
+            path: Vec<u8>,
+            _data: Vec<u8>,
+            _metadata: Vec<u8>,
+        ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+            tokio::task::yield_now().await;
+            if path == b"panic" {
+                panic!("crafted request of operation {}", operation_type);
+            }
+            Ok((0, 0, 0, 0, vec![], vec![]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic() {
+        let address = "127.0.0.1:50086";
+        let server = RpcServer::new(Arc::new(PanicHandler), address);
+        tokio::spawn(async move { server.run().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let call = |path: &'static str| async move {
+            let client: RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator> = RpcClient::new();
+            client.add_connection(address).await.unwrap();
+            let (mut status, mut rsp_flags, mut meta_data_length, mut data_length) = (0, 0, 0, 0);
+            client
+                .call_remote(
+                    address,
+                    1,
+                    0,
+                    path,
+                    &[],
+                    &[],
+                    &mut status,
+                    &mut rsp_flags,
+                    &mut meta_data_length,
+                    &mut data_length,
+                    &mut [],
+                    &mut [],
+                    Duration::from_secs(5),
+                )
+                .await
+                .map(|_| status)
+        };
+        assert_eq!(call("panic").await, Ok(libc::EIO));
+        for _ in 0..4 {
+            assert_eq!(call("file").await, Ok(0));
+        }
+        assert_eq!(call("panic").await, Ok(libc::EIO));
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// optional tls for the tcp transport. a listener given a certificate and key only
+// keeps the connections that complete a handshake, and when it is given a ca too
+// it asks for a client certificate signed by it. a client given a ca only talks to
+// servers whose certificate the ca signed, and shows its own certificate when it
+// has one. nothing configured means plaintext, tls that fails to load is an error
+
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use tokio_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey, RootCertStore,
+        ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+// paths of pem files
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub ca: Option<String>,
+}
+
+impl TlsOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.cert.is_some() || self.key.is_some() || self.ca.is_some()
+    }
+
+    // the acceptor of a listener, none for plaintext
+    pub fn acceptor(&self) -> Result<Option<TlsAcceptor>, String> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let (certs, key) = self
+            .identity()?
+            .ok_or("a tls listener needs a cert and a key")?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let config = match &self.ca {
+            Some(ca) => builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots(ca)?).boxed()),
+            None => builder.with_no_client_auth(),
+        }
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("tls cert and key: {}", e))?;
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    // the connector of a client, none for plaintext
+    pub fn connector(&self) -> Result<Option<TlsConnector>, String> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let ca = self.ca.as_ref().ok_or("a tls client needs a ca")?;
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots(ca)?);
+        let config = match self.identity()? {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| format!("tls cert and key: {}", e))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Some(TlsConnector::from(Arc::new(config))))
+    }
+
+    fn identity(&self) -> Result<Option<(Vec<Certificate>, PrivateKey)>, String> {
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Ok(Some((certs(cert)?, private_key(key)?))),
+            (None, None) => Ok(None),
+            _ => Err("tls cert and key go together".to_owned()),
+        }
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<rustls_pemfile::Item>, String> {
+    let file = File::open(path).map_err(|e| format!("open {}: {}", path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|e| format!("read {}: {}", path, e))
+}
+
+fn certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let certs: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect();
+    match certs.is_empty() {
+        true => Err(format!("no certificate in {}", path)),
+        false => Ok(certs),
+    }
+}
+
+fn private_key(path: &str) -> Result<PrivateKey, String> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(format!("no private key in {}", path))
+}
+
+fn roots(path: &str) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots
+            .add(&cert)
+            .map_err(|e| format!("ca {}: {}", path, e))?;
+    }
+    Ok(roots)
+}
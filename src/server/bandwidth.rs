@@ -0,0 +1,308 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// per client byte counters and an optional token bucket limiter,
+// clients are identified by the id of the connection they send requests on.
+// the data this server sends others while moving files has a limiter of its own
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+
+use crate::common::serialization::ClientBandwidthInfo;
+
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn drained(rate: u64) -> Self {
+        Self {
+            tokens: 0.0,
+            ..Self::new(rate)
+        }
+    }
+
+    // a request larger than the bucket is let through when the bucket is full,
+    // the debt is paid back before the next request is accepted
+    fn consume(&mut self, bytes: u64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+
+        let bytes = bytes as f64;
+        if self.tokens >= bytes || self.tokens >= self.rate {
+            self.tokens -= bytes;
+            Ok(())
+        } else {
+            let missing = bytes.min(self.rate) - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.rate))
+        }
+    }
+}
+
+struct ClientBandwidth {
+    read_bytes: AtomicU64,
+    write_bytes: AtomicU64,
+    first_seen: Instant,
+    bucket: Mutex<Option<TokenBucket>>,
+}
+
+pub struct BandwidthManager {
+    // bytes per second for each client, 0 means unlimited
+    limit: AtomicU64,
+    clients: DashMap<u32, ClientBandwidth>,
+}
+
+impl Default for BandwidthManager {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl BandwidthManager {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            clients: DashMap::new(),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+        for client in self.clients.iter() {
+            *client.bucket.lock() = None;
+        }
+    }
+
+    // returns how long the client should wait before retrying when it is over its rate
+    pub fn acquire(&self, client_id: u32, bytes: u64) -> Result<(), Duration> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return Ok(());
+        }
+        let client = self.client(client_id);
+        let mut bucket = client.bucket.lock();
+        bucket
+            .get_or_insert_with(|| TokenBucket::new(limit))
+            .consume(bytes, Instant::now())
+    }
+
+    pub fn record_read(&self, client_id: u32, bytes: u64) {
+        self.client(client_id)
+            .read_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, client_id: u32, bytes: u64) {
+        self.client(client_id)
+            .write_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn remove_client(&self, client_id: u32) {
+        self.clients.remove(&client_id);
+    }
+
+    pub fn client_infos(&self) -> Vec<ClientBandwidthInfo> {
+        let mut infos: Vec<ClientBandwidthInfo> = self
+            .clients
+            .iter()
+            .map(|kv| {
+                let read_bytes = kv.read_bytes.load(Ordering::Relaxed);
+                let write_bytes = kv.write_bytes.load(Ordering::Relaxed);
+                let secs = kv.first_seen.elapsed().as_secs_f64().max(1.0);
+                ClientBandwidthInfo {
+                    client_id: *kv.key(),
+                    read_bytes,
+                    write_bytes,
+                    read_bytes_per_sec: (read_bytes as f64 / secs) as u64,
+                    write_bytes_per_sec: (write_bytes as f64 / secs) as u64,
+                }
+            })
+            .collect();
+        infos.sort_by_key(|info| info.client_id);
+        infos
+    }
+
+    fn client(&self, client_id: u32) -> dashmap::mapref::one::Ref<'_, u32, ClientBandwidth> {
+        if let Some(client) = self.clients.get(&client_id) {
+            return client;
+        }
+        self.clients
+            .entry(client_id)
+            .or_insert_with(|| ClientBandwidth {
+                read_bytes: AtomicU64::new(0),
+                write_bytes: AtomicU64::new(0),
+                first_seen: Instant::now(),
+                bucket: Mutex::new(None),
+            })
+            .downgrade()
+    }
+}
+
+// how often the measured transfer rate is taken, and the longest a throttled
+// transfer sleeps before looking at the limit again
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_THROTTLE_SLEEP: Duration = Duration::from_millis(100);
+
+struct RateWindow {
+    start: Instant,
+    bytes: u64,
+    // bytes per second of the last full window
+    rate: u64,
+}
+
+pub struct TransferThrottle {
+    // bytes per second sent while moving files, 0 means unlimited
+    limit: AtomicU64,
+    bucket: Mutex<Option<TokenBucket>>,
+    window: Mutex<RateWindow>,
+}
+
+impl Default for TransferThrottle {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TransferThrottle {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            bucket: Mutex::new(None),
+            window: Mutex::new(RateWindow {
+                start: Instant::now(),
+                bytes: 0,
+                rate: 0,
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    // takes effect for the transfers already running
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+        *self.bucket.lock() = None;
+    }
+
+    // waits until bytes may be sent. the bucket starts empty, a transfer does not
+    // get a burst of a whole second up front
+    pub async fn acquire(&self, bytes: u64) {
+        loop {
+            let limit = self.limit();
+            if limit == 0 {
+                break;
+            }
+            let result = self
+                .bucket
+                .lock()
+                .get_or_insert_with(|| TokenBucket::drained(limit))
+                .consume(bytes, Instant::now());
+            match result {
+                Ok(()) => break,
+                Err(wait) => tokio::time::sleep(wait.min(MAX_THROTTLE_SLEEP)).await,
+            }
+        }
+        let mut window = self.window.lock();
+        let elapsed = window.start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            window.rate = (window.bytes as f64 / elapsed.as_secs_f64()) as u64;
+            window.start = Instant::now();
+            window.bytes = 0;
+        }
+        window.bytes += bytes;
+    }
+
+    // bytes per second sent lately, 0 once nothing was sent for a while
+    pub fn rate(&self) -> u64 {
+        let window = self.window.lock();
+        let elapsed = window.start.elapsed();
+        if elapsed >= RATE_WINDOW * 2 {
+            0
+        } else if elapsed >= RATE_WINDOW {
+            (window.bytes as f64 / elapsed.as_secs_f64()) as u64
+        } else {
+            window.rate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{BandwidthManager, TransferThrottle};
+
+    #[test]
+    fn test_throttle_one_client() {
+        let manager = BandwidthManager::new(1000);
+        assert!(manager.acquire(1, 1000).is_ok());
+        let retry_after = manager.acquire(1, 1000).unwrap_err();
+        assert!(retry_after.as_millis() > 0);
+        assert!(manager.acquire(2, 1000).is_ok());
+
+        manager.record_write(1, 1000);
+        manager.record_read(2, 500);
+        let infos = manager.client_infos();
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].write_bytes, 1000);
+        assert_eq!(infos[1].read_bytes, 500);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let manager = BandwidthManager::new(0);
+        for _ in 0..100 {
+            assert!(manager.acquire(1, 1 << 20).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_throttle() {
+        let limit = 256 * 1024;
+        let chunk = 64 * 1024;
+        let throttle = TransferThrottle::new(limit);
+        let start = Instant::now();
+        let mut sent = 0;
+        while start.elapsed() < Duration::from_millis(2500) {
+            throttle.acquire(chunk).await;
+            sent += chunk;
+        }
+        let throughput = sent as f64 / start.elapsed().as_secs_f64();
+        assert!(throughput <= limit as f64, "{} over {}", throughput, limit);
+        assert!(throughput >= limit as f64 / 2.0);
+        assert!(throttle.rate() <= limit + chunk);
+        assert!(throttle.rate() > 0);
+
+        // lifting the limit lets the next transfer through at once
+        throttle.set_limit(0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            throttle.acquire(chunk).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -1,53 +1,118 @@
+use super::bandwidth::{BandwidthManager, TransferThrottle};
+use super::flush::FlushBatcher;
+use super::metrics::Metrics;
+use super::running_ops::RunningOps;
 use super::storage_engine::meta_engine::MetaEngine;
 use super::storage_engine::StorageEngine;
 use super::transfer_manager::TransferManager;
+use super::write_combine::WriteCombiner;
 use crate::common::byte::CHUNK_SIZE;
-use crate::common::errors::CONNECTION_ERROR;
+use crate::common::dirent::DirentDecoder;
+use crate::common::errors::{status_to_string, CONFLICT, CONNECTION_ERROR};
 use crate::common::hash_ring::HashRing;
 use crate::common::sender::{Sender, REQUEST_TIMEOUT};
 use crate::common::serialization::{
-    file_attr_as_bytes, ClusterStatus, CreateDirSendMetaData, CreateFileSendMetaData,
-    FileTypeSimple, ManagerOperationType, ReadFileSendMetaData, ServerStatus,
-    WriteFileSendMetaData,
+    bytes_as_file_attr, file_attr_as_bytes, AtimePolicy, Capabilities, ClusterStatus,
+    CopyFileRangeSendMetaData, CreateDirSendMetaData, CreateFileSendMetaData,
+    CreateSymlinkSendMetaData, DirectoryRenameEntrySendMetaData, FallocateSendMetaData,
+    FileAttrSimple, FileTypeSimple, GetClusterStatusRecvMetaData, GetHashRingInfoRecvMetaData,
+    HealthStatus, ManagerOperationType, MovedRecvMetaData, ReadDirSendMetaData,
+    ReadFileSendMetaData, ReadFileVectoredSendMetaData, RenameNoParentSendMetaData, ServerConfig,
+    ServerStatus, SetAttrSendMetaData, StatAndChecksumSendMetaData, StatFsRecvMetaData,
+    VerifyDirCountRecvMetaData, Volume, WriteFileChunkRecvMetaData, WriteFileChunkSendMetaData,
+    WriteFileSendMetaData, MOVED_DATA, PROTOCOL_VERSION, UNLINKED,
 };
 use crate::common::serialization::{DirectoryEntrySendMetaData, OperationType};
 
-use crate::common::util::{empty_file, get_full_path};
-use crate::rpc::client::{RpcClient, TcpStreamCreator};
+use crate::common::util::{check_type, empty_file, get_full_path, path_split};
+use crate::rpc::client::{RpcClient, TcpReadHalf, TcpStreamCreator, TcpWriteHalf};
+use crate::rpc::protocol::{FROM_SERVER, MAX_DATA_LENGTH};
+use dashmap::mapref::entry::Entry;
 use dashmap::mapref::one::Ref;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use fuser::{FileAttr, FileType};
-use libc::{O_CREAT, O_DIRECTORY, O_EXCL};
-use log::{debug, error, info};
+use libc::{O_CREAT, O_DIRECTORY, O_EXCL, O_RDWR, O_WRONLY};
+use log::{debug, error, info, warn};
 use nix::fcntl::OFlag;
 use rocksdb::IteratorMode;
 use spin::RwLock;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use std::{sync::Arc, vec};
 use tokio::sync::Mutex;
 
+// the block size statfs reports, the store itself has no blocks
+const STATFS_BLOCK_SIZE: u32 = 4096;
+
+// bytes read from the source and written to the destination at a time by CopyFileRange
+const COPY_CHUNK_SIZE: u32 = 1 << 20;
+
+// how long the server owning a volume root is not asked for the volume again after
+// it could not be found there
+const VOLUME_LOOKUP_RETRY: Duration = Duration::from_secs(1);
+
+// how often a write waiting for a rename looks whether it is done
+const MOVING_POLL: Duration = Duration::from_millis(10);
+
 pub struct DistributedEngine<Storage: StorageEngine> {
     pub address: String,
     pub storage_engine: Arc<Storage>,
     pub meta_engine: Arc<MetaEngine>,
-    pub client: Arc<
-        RpcClient<
-            tokio::net::tcp::OwnedReadHalf,
-            tokio::net::tcp::OwnedWriteHalf,
-            TcpStreamCreator,
-        >,
-    >,
+    pub client: Arc<RpcClient<TcpReadHalf, TcpWriteHalf, TcpStreamCreator>>,
     pub sender: Sender,
 
     pub cluster_status: AtomicI32,
+    // the last status of this server accepted by the manager
+    pub server_status: AtomicU32,
 
     pub hash_ring: Arc<RwLock<Option<HashRing>>>,
     pub new_hash_ring: Arc<RwLock<Option<HashRing>>>,
+    // epochs the manager assigned to hash_ring and new_hash_ring
+    pub ring_epoch: AtomicU64,
+    pub new_ring_epoch: AtomicU64,
 
     pub manager_address: Arc<Mutex<String>>,
 
     pub file_locks: DashMap<String, DashMap<String, u32>>,
     pub transfer_manager: TransferManager,
+    // held while the files of a single volume are moved, see rebalance_volume
+    pub volume_rebalance: Mutex<()>,
+    pub client_bandwidth: BandwidthManager,
+    pub transfer_throttle: TransferThrottle,
+    pub flush_batcher: FlushBatcher,
+    pub write_combiner: WriteCombiner,
+    pub running_ops: RunningOps,
+    pub metrics: Arc<Metrics>,
+    // values given at startup, the ones that can change are read from where they live
+    pub config: RwLock<ServerConfig>,
+    // largest data section accepted by a single WriteFile, advertised on InitVolume
+    pub max_write_size: AtomicU32,
+
+    // entries a directory holds before new ones are spread across servers, 0 disables sharding
+    pub dir_shard_threshold: AtomicU32,
+
+    // failed manager contacts in a row, and how many of them make the server degraded
+    pub manager_failures: AtomicU32,
+    pub manager_failure_limit: AtomicU32,
+
+    // volumes whose root lives on another server
+    pub remote_volumes: DashMap<String, Volume>,
+    // when looking up a volume missing from remote_volumes last failed
+    pub remote_volume_failures: DashMap<String, Instant>,
+    // the server MigrateVolume moved the files of a volume to, whatever the ring
+    // says. the volume root stays where the ring puts it
+    pub volume_placement: DashMap<String, String>,
+
+    // crc32c of whole files, computed on demand and dropped when the file changes
+    pub checksums: DashMap<String, u32>,
+
+    // hosts the admin operations are taken from, the manager's and the configured ones
+    pub admin_hosts: DashSet<IpAddr>,
+
+    // files a rename copied and has not deleted yet, changes to them wait until it did
+    pub moving: DashSet<String>,
 }
 
 impl<Storage> DistributedEngine<Storage>
@@ -64,6 +129,7 @@ where
             file_locks.insert(kv.key().to_owned(), DashMap::new());
         }
         let client = Arc::new(RpcClient::new());
+        client.set_request_flags(FROM_SERVER);
         Self {
             address,
             storage_engine,
@@ -71,11 +137,32 @@ where
             client: client.clone(),
             sender: Sender::new(client),
             cluster_status: AtomicI32::new(ClusterStatus::Initializing.into()),
+            server_status: AtomicU32::new(ServerStatus::Initializing.into()),
             hash_ring: Arc::new(RwLock::new(None)),
             new_hash_ring: Arc::new(RwLock::new(None)),
+            ring_epoch: AtomicU64::new(0),
+            new_ring_epoch: AtomicU64::new(0),
             manager_address: Arc::new(Mutex::new("".to_string())),
             file_locks,
             transfer_manager: TransferManager::new(),
+            volume_rebalance: Mutex::new(()),
+            client_bandwidth: BandwidthManager::default(),
+            transfer_throttle: TransferThrottle::default(),
+            flush_batcher: FlushBatcher::default(),
+            write_combiner: WriteCombiner::default(),
+            running_ops: RunningOps::default(),
+            metrics: Arc::default(),
+            config: RwLock::new(ServerConfig::default()),
+            max_write_size: AtomicU32::new(MAX_DATA_LENGTH as u32),
+            dir_shard_threshold: AtomicU32::new(0),
+            manager_failures: AtomicU32::new(0),
+            manager_failure_limit: AtomicU32::new(0),
+            remote_volumes: DashMap::new(),
+            remote_volume_failures: DashMap::new(),
+            volume_placement: DashMap::new(),
+            checksums: DashMap::new(),
+            admin_hosts: DashSet::new(),
+            moving: DashSet::new(),
         }
     }
 
@@ -136,6 +223,7 @@ where
             umask: 0,
             flags: OFlag::O_CREAT.bits() | OFlag::O_RDWR.bits(),
             name: "".to_string(),
+            tmpfile: false,
         })
         .unwrap();
 
@@ -164,14 +252,20 @@ where
             // let file_path = format!("{}_{}", pathname, idx);
             // println!("write: {} {}", file_path, address);
 
-            let send_meta_data =
-                bincode::serialize(&WriteFileSendMetaData { offset: chunk_left }).unwrap();
             let mut status = 0i32;
             let mut rsp_flags = 0u32;
             let chunk_buf = self
                 .storage_engine
                 .read_file(path, CHUNK_SIZE as u32, chunk_left)
                 .unwrap();
+            let send_meta_data = bincode::serialize(&WriteFileSendMetaData {
+                offset: chunk_left,
+                if_mtime: None,
+                checksum: Some(crc32c::crc32c(&chunk_buf)),
+                append: false,
+            })
+            .unwrap();
+            self.transfer_throttle.acquire(chunk_buf.len() as u64).await;
             let mut recv_meta_data_length = 0usize;
             let mut recv_data_length = 0usize;
 
@@ -181,7 +275,7 @@ where
                 .call_remote(
                     &address,
                     OperationType::WriteFile.into(),
-                    0,
+                    MOVED_DATA,
                     path,
                     &send_meta_data,
                     &chunk_buf,
@@ -304,7 +398,12 @@ where
         let server_address = self.get_new_address(path);
         // println!("check: {} {}", file_path, server_address);
 
-        let send_meta_data = self.meta_engine.get_file_attr_raw(path).unwrap();
+        let mut send_meta_data = self.meta_engine.get_file_attr_raw(path).unwrap();
+        // the entries held by other shards are not shipped, only their count
+        if self.meta_engine.is_sharded_directory(path) {
+            let entries = self.meta_engine.directory_entry_count(path)?;
+            send_meta_data.extend_from_slice(&entries.to_le_bytes());
+        }
         let mut status = 0i32;
         let mut rsp_flags = 0u32;
         let mut recv_meta_data_length = 0usize;
@@ -339,9 +438,59 @@ where
         self.delete_dir_no_parent_force(path)
     }
 
+    // moves the entries this server holds as a shard of a directory owned elsewhere
+    // to the server the entry belongs to now, within volume if one is given
+    pub async fn transfer_shard_entries(&self, volume: Option<&str>) -> Result<(), i32> {
+        let mut entries = Vec::new();
+        for item in self.meta_engine.dir_db.db.iterator(IteratorMode::Start) {
+            let (key, value) = item.unwrap();
+            let file_name = String::from_utf8(value.to_vec()).unwrap();
+            let file_type = *key.last().unwrap();
+            let parent =
+                String::from_utf8(key[..key.len() - file_name.len() - 3].to_vec()).unwrap();
+            if self.meta_engine.file_indexs.contains_key(&parent) {
+                continue;
+            }
+            if let Some(volume) = volume {
+                if parent != volume && !parent.starts_with(&format!("{}/", volume)) {
+                    continue;
+                }
+            }
+            let address = self.get_new_address(&get_full_path(&parent, &file_name));
+            if address != self.address {
+                entries.push((address, parent, file_name, file_type));
+            }
+        }
+        info!("transfer_shard_entries: {} entries", entries.len());
+        for (address, parent, file_name, file_type) in entries {
+            let send_meta_data = bincode::serialize(&DirectoryEntrySendMetaData {
+                file_type,
+                file_name: file_name.clone(),
+            })
+            .unwrap();
+            self.sender
+                .directory_shard_entry(
+                    &address,
+                    OperationType::DirectoryAddShardEntry,
+                    &parent,
+                    &send_meta_data,
+                )
+                .await?;
+            self.meta_engine
+                .shard_delete_entry(&parent, &file_name, file_type)?;
+        }
+        Ok(())
+    }
+
     pub async fn transfer_files(&self, file_map: Vec<String>) -> Result<(), i32> {
         // transfer all files ,and set the flag as true
         info!("transfer_files: {:?}", file_map);
+        let size_of = |path: &str| match self.meta_engine.get_file_attr(path) {
+            Ok(attr) if attr.kind != FileType::Directory => attr.size,
+            _ => 0,
+        };
+        self.transfer_manager
+            .start_progress(file_map.iter().map(|k| size_of(k)).sum());
         for k in file_map {
             let _lock = self.transfer_manager.get_wlock(&k).await;
             if self.transfer_manager.status(&k).unwrap() {
@@ -354,6 +503,7 @@ where
                     self.check_dir_remote(&k).await?;
                 }
                 Ok(false) => {
+                    self.flush_pending_writes(&k)?;
                     self.create_file_remote(&k).await?;
                     self.write_file_remote(&k).await?;
                     self.check_file_remote(&k).await?;
@@ -368,16 +518,99 @@ where
                 }
             }
             info!("transfer_files: {} done", k);
+            self.transfer_manager.advance_progress(size_of(&k));
             self.transfer_manager.set_status(&k, true);
         }
         Ok(())
     }
 
+    // moves the files of one volume that this server holds but the ring places on
+    // another server, other volumes are left where they are and the cluster stays Idle
+    pub async fn rebalance_volume(&self, volume: &str) -> Result<(), i32> {
+        if self.cluster_status.load(Ordering::Acquire) != ClusterStatus::Idle.into() {
+            return Err(libc::EBUSY);
+        }
+        let _guard = self.volume_rebalance.try_lock().map_err(|_| libc::EBUSY)?;
+        self.volume_setting(volume, |_| ())
+            .await
+            .ok_or(libc::ENOENT)?;
+        self.move_volume_files(volume).await
+    }
+
+    // pins the files of a volume to target and moves the ones held here there,
+    // independent of the ring. the cluster stays Idle
+    pub async fn migrate_volume(&self, volume: &str, target: &str) -> Result<(), i32> {
+        if self.cluster_status.load(Ordering::Acquire) != ClusterStatus::Idle.into() {
+            return Err(libc::EBUSY);
+        }
+        if !self.hash_ring.read().as_ref().unwrap().contains(target) {
+            return Err(libc::EINVAL);
+        }
+        let _guard = self.volume_rebalance.try_lock().map_err(|_| libc::EBUSY)?;
+        self.volume_setting(volume, |_| ())
+            .await
+            .ok_or(libc::ENOENT)?;
+        self.volume_placement
+            .insert(volume.to_owned(), target.to_owned());
+        self.move_volume_files(volume).await
+    }
+
+    // moves the files of the volume held here that belong on another server
+    async fn move_volume_files(&self, volume: &str) -> Result<(), i32> {
+        let prefix = format!("{}/", volume);
+        let mut file_map = Vec::new();
+        self.meta_engine
+            .file_attr_db
+            .db
+            .iterator(IteratorMode::Start)
+            .for_each(|result| {
+                let (k, _) = result.unwrap();
+                let k = String::from_utf8(k.to_vec()).unwrap();
+                if (k == volume || k.starts_with(&prefix)) && self.get_address(&k) != self.address {
+                    file_map.push(k);
+                }
+            });
+        info!(
+            "rebalance volume {}: {} files to move",
+            volume,
+            file_map.len()
+        );
+        self.transfer_manager.make_up_files(&file_map);
+        self.transfer_files(file_map).await?;
+        self.transfer_shard_entries(Some(volume)).await
+    }
+
+    // the manager, the admin hosts and the hosts of the servers on either ring
+    pub fn is_server_host(&self, ip: IpAddr) -> bool {
+        if self.admin_hosts.contains(&ip) {
+            return true;
+        }
+        [&self.hash_ring, &self.new_hash_ring].iter().any(|ring| {
+            ring.read().as_ref().is_some_and(|ring| {
+                ring.get_server_lists().iter().any(|address| {
+                    address
+                        .parse::<SocketAddr>()
+                        .is_ok_and(|address| address.ip() == ip)
+                })
+            })
+        })
+    }
+
     pub fn remove_connection(&self, address: String) {
         self.client.remove_connection(&address);
     }
 
+    fn pinned_address(&self, path: &str) -> Option<String> {
+        let (volume, _) = path.split_once('/')?;
+        self.volume_placement
+            .get(volume)
+            .map(|server| server.clone())
+    }
+
     pub fn get_address(&self, path: &str) -> String {
+        if let Some(address) = self.pinned_address(path) {
+            return address;
+        }
         self.hash_ring
             .read()
             .as_ref()
@@ -389,6 +622,9 @@ where
     }
 
     pub fn get_new_address(&self, path: &str) -> String {
+        if let Some(address) = self.pinned_address(path) {
+            return address;
+        }
         match self.new_hash_ring.read().as_ref() {
             Some(ring) => ring.get(path).unwrap().address.clone(),
             None => self.get_address(path),
@@ -529,6 +765,29 @@ where
         }
     }
 
+    // where a caller should retry when the path has moved away from this server,
+    // either after a rebalance or because it was already transferred during one
+    pub fn moved_to(&self, path: &str) -> Option<MovedRecvMetaData> {
+        let (new_server, epoch) = match self.cluster_status.load(Ordering::Acquire).try_into() {
+            Ok(ClusterStatus::Idle) | Ok(ClusterStatus::Finishing) => {
+                (self.get_address(path), &self.ring_epoch)
+            }
+            Ok(ClusterStatus::Transferring) => match self.transfer_manager.status(path) {
+                Some(true) => (self.get_new_address(path), &self.new_ring_epoch),
+                _ => return None,
+            },
+            Ok(ClusterStatus::PreFinish) => (self.get_new_address(path), &self.new_ring_epoch),
+            _ => return None,
+        };
+        if new_server == self.address {
+            return None;
+        }
+        Some(MovedRecvMetaData {
+            new_server,
+            epoch: epoch.load(Ordering::Acquire),
+        })
+    }
+
     pub async fn update_server_status(&self, server_status: ServerStatus) -> Result<(), i32> {
         let send_meta_data = bincode::serialize(&server_status).unwrap();
 
@@ -561,6 +820,8 @@ where
                 if status != 0 {
                     Err(status)
                 } else {
+                    self.server_status
+                        .store(server_status.into(), Ordering::Release);
                     Ok(())
                 }
             }
@@ -571,22 +832,207 @@ where
         }
     }
 
-    pub async fn get_cluster_status(&self) -> Result<ClusterStatus, i32> {
-        self.sender
-            .get_cluster_status(&self.manager_address.lock().await)
-            .await
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig {
+            address: self.address.clone(),
+            max_write_size: self.max_write_size.load(Ordering::Relaxed),
+            max_frame_size: MAX_DATA_LENGTH as u32,
+            chunk_size: CHUNK_SIZE as u32,
+            client_bandwidth_limit: self.client_bandwidth.limit(),
+            transfer_rate_limit: self.transfer_throttle.limit(),
+            flush_interval_ms: self
+                .flush_batcher
+                .interval()
+                .map_or(0, |interval| interval.as_millis() as u64),
+            write_combine_size: self.write_combiner.capacity() as u64,
+            dir_shard_threshold: self.dir_shard_threshold.load(Ordering::Relaxed),
+            manager_failure_limit: self.manager_failure_limit.load(Ordering::Relaxed),
+            slow_op_threshold_ms: self.running_ops.slow_op_threshold(),
+            ..self.config.read().clone()
+        }
     }
 
-    pub async fn get_hash_ring_info(&self) -> Result<Vec<(String, usize)>, i32> {
-        self.sender
-            .get_hash_ring_info(&self.manager_address.lock().await)
-            .await
+    pub fn capabilities(&self) -> Capabilities {
+        let mut supported_ops: Vec<u32> = [
+            OperationType::CreateFile,
+            OperationType::CreateDir,
+            OperationType::GetFileAttr,
+            OperationType::ReadDir,
+            OperationType::OpenFile,
+            OperationType::ReadFile,
+            OperationType::ReadFileVectored,
+            OperationType::WriteFile,
+            OperationType::DeleteFile,
+            OperationType::DeleteDir,
+            OperationType::DirectoryAddEntry,
+            OperationType::DirectoryDeleteEntry,
+            OperationType::TruncateFile,
+            OperationType::CheckFile,
+            OperationType::CheckDir,
+            OperationType::CreateDirNoParent,
+            OperationType::CreateFileNoParent,
+            OperationType::DeleteDirNoParent,
+            OperationType::DeleteFileNoParent,
+            OperationType::CreateVolume,
+            OperationType::InitVolume,
+            OperationType::ListVolumes,
+            OperationType::DeleteVolume,
+            OperationType::CleanVolume,
+            OperationType::Fsync,
+            OperationType::CreateAndWrite,
+            OperationType::StatAndChecksum,
+            OperationType::GetParent,
+            OperationType::VerifyDirCount,
+            OperationType::DirectoryAddShardEntry,
+            OperationType::DirectoryDeleteShardEntry,
+            OperationType::ReadDirShard,
+            OperationType::Link,
+            OperationType::WriteFileChunk,
+            OperationType::Rename,
+            OperationType::CreateSymlink,
+            OperationType::ReadLink,
+            OperationType::CreateSymlinkNoParent,
+            OperationType::SetAttr,
+            OperationType::AtomicIncrement,
+            OperationType::ChargeVolume,
+            OperationType::ResizeVolume,
+            OperationType::StatFs,
+            OperationType::CopyFileRange,
+            OperationType::Fallocate,
+            OperationType::Batch,
+            OperationType::RenameNoParent,
+            OperationType::DirectoryRenameEntry,
+        ]
+        .into_iter()
+        .map(u32::from)
+        .collect();
+        supported_ops.extend(
+            [
+                ManagerOperationType::HealthCheck,
+                ManagerOperationType::ListRunningOps,
+                ManagerOperationType::KillOp,
+                ManagerOperationType::GetServerConfig,
+                ManagerOperationType::GetCapabilities,
+                ManagerOperationType::RebalanceVolume,
+                ManagerOperationType::MigrateVolume,
+                ManagerOperationType::SetTransferRate,
+                ManagerOperationType::GetClientBandwidth,
+            ]
+            .into_iter()
+            .map(u32::from),
+        );
+
+        // what every server of this version does, then what depends on its config
+        let mut features = vec!["moved", "if_mtime", "tmpfile", "file_version"];
+        if self.dir_shard_threshold.load(Ordering::Relaxed) > 0 {
+            features.push("dir_sharding");
+        }
+        if self.write_combiner.capacity() > 0 {
+            features.push("write_combine");
+        }
+        if self.flush_batcher.interval().is_some() {
+            features.push("batched_flush");
+        }
+        if self.manager_failure_limit.load(Ordering::Relaxed) > 0 {
+            features.push("degraded_mode");
+        }
+        Capabilities {
+            supported_ops,
+            features: features.into_iter().map(str::to_owned).collect(),
+            max_frame_size: MAX_DATA_LENGTH as u32,
+            protocol_version: PROTOCOL_VERSION,
+        }
     }
 
-    pub async fn get_new_hash_ring_info(&self) -> Result<Vec<(String, usize)>, i32> {
-        self.sender
-            .get_new_hash_ring_info(&self.manager_address.lock().await)
-            .await
+    pub fn health_status(&self) -> HealthStatus {
+        let server_status =
+            ServerStatus::try_from(self.server_status.load(Ordering::Acquire)).unwrap();
+        let store_writable = self.storage_engine.is_writable();
+        let mut details = vec![];
+        if server_status == ServerStatus::Initializing {
+            details.push("server is initializing");
+        }
+        if self.hash_ring.read().is_none() {
+            details.push("hash ring is not loaded");
+        }
+        if !store_writable {
+            details.push("store is not writable");
+        }
+        if self.is_degraded() {
+            details.push("manager is unreachable");
+        }
+        HealthStatus {
+            live: true,
+            ready: details.is_empty(),
+            server_status,
+            store_writable,
+            details: details.join(", "),
+        }
+    }
+
+    // counts the result of a contact with the manager
+    pub fn track_manager<T>(&self, result: Result<T, i32>) -> Result<T, i32> {
+        let limit = self.manager_failure_limit.load(Ordering::Relaxed);
+        match result {
+            Ok(_) => {
+                let failures = self.manager_failures.swap(0, Ordering::Relaxed);
+                if limit > 0 && failures >= limit {
+                    info!(
+                        "{} manager is reachable again, degraded mode left",
+                        self.address
+                    );
+                }
+            }
+            Err(e) => {
+                let failures = self.manager_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures == limit {
+                    warn!(
+                        "{} manager unreachable after {} contacts, error = {}, degraded mode entered",
+                        self.address,
+                        failures,
+                        status_to_string(e)
+                    );
+                }
+            }
+        }
+        result
+    }
+
+    // without the manager the ring may be stale, a degraded server only serves reads of
+    // paths it owns while no transfer was going on
+    pub fn is_degraded(&self) -> bool {
+        let limit = self.manager_failure_limit.load(Ordering::Relaxed);
+        limit > 0 && self.manager_failures.load(Ordering::Relaxed) >= limit
+    }
+
+    pub fn serves_degraded(&self, r#type: &OperationType, forwarded: bool) -> bool {
+        !forwarded
+            && !r#type.is_mutating()
+            && self.cluster_status.load(Ordering::Relaxed) == i32::from(ClusterStatus::Idle)
+    }
+
+    pub async fn get_cluster_status_info(&self) -> Result<GetClusterStatusRecvMetaData, i32> {
+        let result = self
+            .sender
+            .get_cluster_status_info(&self.manager_address.lock().await)
+            .await;
+        self.track_manager(result)
+    }
+
+    pub async fn get_hash_ring_info(&self) -> Result<GetHashRingInfoRecvMetaData, i32> {
+        let result = self
+            .sender
+            .get_hash_ring(&self.manager_address.lock().await)
+            .await;
+        self.track_manager(result)
+    }
+
+    pub async fn get_new_hash_ring_info(&self) -> Result<GetHashRingInfoRecvMetaData, i32> {
+        let result = self
+            .sender
+            .get_new_hash_ring(&self.manager_address.lock().await)
+            .await;
+        self.track_manager(result)
     }
 
     #[inline]
@@ -612,7 +1058,18 @@ where
             OperationType::CreateFile => (0, 0, 0, 0, vec![0; 1024], vec![]),
             OperationType::CreateDir => (0, 0, 0, 0, vec![0; 1024], vec![]),
             OperationType::GetFileAttr => (0, 0, 0, 0, vec![0; 1024], vec![]),
-            OperationType::ReadDir => (0, 0, 0, 0, vec![], vec![0; 2048]),
+            OperationType::ReadDir => {
+                let unwraped_meta_data =
+                    bincode::deserialize::<ReadDirSendMetaData>(&metadata).unwrap();
+                (
+                    0,
+                    0,
+                    0,
+                    0,
+                    vec![0; 1024],
+                    vec![0; unwraped_meta_data.size as usize],
+                )
+            }
             OperationType::OpenFile => (0, 0, 0, 0, vec![], vec![]),
             OperationType::ReadFile => {
                 let unwraped_meta_data =
@@ -622,11 +1079,29 @@ where
                     0,
                     0,
                     0,
-                    vec![],
+                    vec![0; 64],
                     vec![0; unwraped_meta_data.size as usize],
                 )
             }
+            OperationType::ReadFileVectored => {
+                let unwraped_meta_data =
+                    bincode::deserialize::<ReadFileVectoredSendMetaData>(&metadata).unwrap();
+                let size: usize = unwraped_meta_data
+                    .ranges
+                    .iter()
+                    .map(|(_, size)| *size as usize)
+                    .sum();
+                (
+                    0,
+                    0,
+                    0,
+                    0,
+                    vec![0; 16 + 4 * unwraped_meta_data.ranges.len()],
+                    vec![0; size],
+                )
+            }
             OperationType::WriteFile => (0, 0, 0, 0, vec![0; 4], vec![]),
+            OperationType::AtomicIncrement => (0, 0, 0, 0, vec![0; 8], vec![]),
             OperationType::DeleteFile => (0, 0, 0, 0, vec![], vec![]),
             OperationType::DeleteDir => (0, 0, 0, 0, vec![], vec![]),
             OperationType::DirectoryAddEntry => (0, 0, 0, 0, vec![], vec![]),
@@ -639,10 +1114,55 @@ where
             OperationType::DeleteDirNoParent => (0, 0, 0, 0, vec![], vec![]),
             OperationType::DeleteFileNoParent => (0, 0, 0, 0, vec![], vec![]),
             OperationType::CreateVolume => (0, 0, 0, 0, vec![], vec![]),
-            OperationType::InitVolume => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::InitVolume => (0, 0, 0, 0, vec![0; 64], vec![]),
             OperationType::ListVolumes => (0, 0, 0, 0, vec![], vec![]),
             OperationType::DeleteVolume => (0, 0, 0, 0, vec![], vec![]),
             OperationType::CleanVolume => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::Fsync => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::CreateAndWrite => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::GetParent => (0, 0, 0, 0, vec![0; 4096], vec![]),
+            OperationType::VerifyDirCount => (0, 0, 0, 0, vec![0; 64], vec![]),
+            OperationType::DirectoryAddShardEntry => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::DirectoryDeleteShardEntry => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::Link => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::Rename => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::WriteFileChunk => (0, 0, 0, 0, vec![0; 64], vec![]),
+            OperationType::CreateSymlink => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::CreateSymlinkNoParent => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::ReadLink => (0, 0, 0, 0, vec![], vec![0; libc::PATH_MAX as usize]),
+            OperationType::SetAttr => (0, 0, 0, 0, vec![0; 1024], vec![]),
+            OperationType::ChargeVolume => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::ResizeVolume => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::StatFs => (0, 0, 0, 0, vec![0; 64], vec![]),
+            OperationType::CopyFileRange => (0, 0, 0, 0, vec![0; 8], vec![]),
+            OperationType::Fallocate => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::Batch => (0, 0, 0, 0, vec![], vec![0; MAX_DATA_LENGTH]),
+            OperationType::RenameNoParent => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::DirectoryRenameEntry => (0, 0, 0, 0, vec![], vec![]),
+            OperationType::ReadDirShard => {
+                let unwraped_meta_data =
+                    bincode::deserialize::<ReadDirSendMetaData>(&metadata).unwrap();
+                (
+                    0,
+                    0,
+                    0,
+                    0,
+                    vec![0; 64],
+                    vec![0; unwraped_meta_data.size as usize],
+                )
+            }
+            OperationType::StatAndChecksum => {
+                let unwraped_meta_data =
+                    bincode::deserialize::<StatAndChecksumSendMetaData>(&metadata).unwrap();
+                (
+                    0,
+                    0,
+                    0,
+                    0,
+                    vec![],
+                    vec![0; unwraped_meta_data.max_response_len()],
+                )
+            }
         };
         let result = self
             .client
@@ -706,9 +1226,9 @@ where
             return Err(libc::EEXIST);
         }
 
-        let result =
-            self.meta_engine
-                .directory_add_entry(parent, name, FileTypeSimple::Directory.into());
+        let result = self
+            .add_directory_entry(parent, name, FileTypeSimple::Directory.into())
+            .await;
 
         let result = match result {
             Ok(_) => {
@@ -800,11 +1320,8 @@ where
         };
 
         if result.is_ok() {
-            self.meta_engine.directory_delete_entry(
-                parent,
-                name,
-                FileTypeSimple::Directory.into(),
-            )?;
+            self.delete_directory_entry(parent, name, FileTypeSimple::Directory.into())
+                .await?;
         }
 
         self.lock_file(parent)?.remove(name);
@@ -816,8 +1333,202 @@ where
     }
 
     pub async fn read_dir(&self, path: &str, size: u32, offset: i64) -> Result<Vec<u8>, i32> {
+        self.check_type(path, FileTypeSimple::Directory)?;
+        if !self.meta_engine.is_sharded_directory(path) {
+            let _file_lock = self.lock_file(path)?;
+            return self.meta_engine.read_directory(path, size, offset);
+        }
+
+        // the shard on this server comes first, then the others in address order
+        let mut shards = self.hash_ring.read().as_ref().unwrap().get_server_lists();
+        shards.retain(|address| address != &self.address);
+        shards.sort();
+        shards.insert(0, self.address.clone());
+
+        let mut result = Vec::new();
+        let mut offset = offset.max(0);
+        for shard in shards {
+            let size = size - result.len() as u32;
+            let (data, md) = if shard == self.address {
+                self.meta_engine.read_directory_shard(path, size, offset)?
+            } else {
+                self.sender
+                    .read_dir_shard(&shard, path, size, offset)
+                    .await?
+            };
+            result.extend_from_slice(&data);
+            if offset + (md.returned as i64) < md.total as i64 {
+                break;
+            }
+            offset = (offset - md.total as i64).max(0);
+        }
+        Ok(result)
+    }
+
+    // a page of the directory with where the next one starts, the name of its last
+    // entry. given a cursor the page starts right after that entry, which stays
+    // correct however the directory changed since. sharded directories page by
+    // offset only and give no cursor
+    pub async fn read_dir_page(
+        &self,
+        path: &str,
+        size: u32,
+        offset: i64,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<u8>, Option<String>), i32> {
+        if self.meta_engine.is_sharded_directory(path) {
+            return Ok((self.read_dir(path, size, offset).await?, None));
+        }
+        let data = match cursor {
+            Some(cursor) => {
+                self.check_type(path, FileTypeSimple::Directory)?;
+                let _file_lock = self.lock_file(path)?;
+                self.meta_engine.read_directory_after(path, size, cursor)?
+            }
+            None => self.read_dir(path, size, offset).await?,
+        };
+        let cursor = DirentDecoder::new(data.as_slice())
+            .map_while(Result::ok)
+            .last()
+            .map(|(_, name)| name);
+        Ok((data, cursor))
+    }
+
+    // compares the entry count kept for a directory with the entries actually stored,
+    // a sharded directory is counted on every shard
+    pub async fn verify_dir_count(
+        &self,
+        path: &str,
+        repair: bool,
+    ) -> Result<VerifyDirCountRecvMetaData, i32> {
+        if self.meta_engine.get_file_attr(path)?.kind != FileType::Directory {
+            return Err(libc::ENOTDIR);
+        }
+        let mut remote = 0;
+        if self.meta_engine.is_sharded_directory(path) {
+            let shards = self.hash_ring.read().as_ref().unwrap().get_server_lists();
+            for shard in shards.iter().filter(|address| *address != &self.address) {
+                let (_, md) = self.sender.read_dir_shard(shard, path, 0, 0).await?;
+                remote += md.total;
+            }
+        }
+
         let _file_lock = self.lock_file(path)?;
-        self.meta_engine.read_directory(path, size, offset)
+        let stored = self.meta_engine.directory_entry_count(path)?;
+        let actual = self.meta_engine.count_directory_entries(path) + remote;
+        let repaired = repair && stored != actual;
+        if repaired {
+            info!(
+                "verify dir count: {} repaired from {} to {}",
+                path, stored, actual
+            );
+            self.meta_engine.set_directory_entry_count(path, actual)?;
+        }
+        Ok(VerifyDirCountRecvMetaData {
+            stored,
+            actual,
+            repaired,
+        })
+    }
+
+    // entries of a sharded directory go to the server owning the entry itself,
+    // the owner of the directory keeps counting them
+    pub async fn add_directory_entry(
+        &self,
+        parent: &str,
+        name: &str,
+        file_type: u8,
+    ) -> Result<(), i32> {
+        if self.meta_engine.is_sharded_directory(parent) {
+            let shard = self.get_address(&get_full_path(parent, name));
+            if shard != self.address {
+                let send_meta_data = bincode::serialize(&DirectoryEntrySendMetaData {
+                    file_type,
+                    file_name: name.to_owned(),
+                })
+                .unwrap();
+                self.sender
+                    .directory_shard_entry(
+                        &shard,
+                        OperationType::DirectoryAddShardEntry,
+                        parent,
+                        &send_meta_data,
+                    )
+                    .await?;
+                return self.meta_engine.count_shard_entry(parent, true);
+            }
+            return self
+                .meta_engine
+                .directory_add_entry(parent, name, file_type);
+        }
+
+        self.meta_engine
+            .directory_add_entry(parent, name, file_type)?;
+        let threshold = self.dir_shard_threshold.load(Ordering::Relaxed);
+        if threshold > 0 && self.meta_engine.directory_entry_count(parent)? >= threshold {
+            info!("shard directory: {}", parent);
+            self.meta_engine.shard_directory(parent)?;
+        }
+        Ok(())
+    }
+
+    // the entries of a sharded directory may live on other servers, there the file
+    // itself is looked up instead
+    async fn name_taken(&self, parent: &str, name: &str) -> Result<bool, i32> {
+        if self
+            .meta_engine
+            .lookup_directory_entry(parent, name)
+            .is_some()
+        {
+            return Ok(true);
+        }
+        if !self.meta_engine.is_sharded_directory(parent) {
+            return Ok(false);
+        }
+        match self
+            .call_get_attr_remote_or_local(&get_full_path(parent, name))
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(libc::ENOENT) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // entries added before the directory was sharded stay on its owner
+    pub async fn delete_directory_entry(
+        &self,
+        parent: &str,
+        name: &str,
+        file_type: u8,
+    ) -> Result<(), i32> {
+        if !self.meta_engine.is_sharded_directory(parent)
+            || self
+                .meta_engine
+                .has_directory_entry(parent, name, file_type)
+        {
+            return self
+                .meta_engine
+                .directory_delete_entry(parent, name, file_type);
+        }
+        let shard = self.get_address(&get_full_path(parent, name));
+        if shard == self.address {
+            return Err(libc::ENOENT);
+        }
+        let send_meta_data = bincode::serialize(&DirectoryEntrySendMetaData {
+            file_type,
+            file_name: name.to_owned(),
+        })
+        .unwrap();
+        self.sender
+            .directory_shard_entry(
+                &shard,
+                OperationType::DirectoryDeleteShardEntry,
+                parent,
+                &send_meta_data,
+            )
+            .await?;
+        self.meta_engine.count_shard_entry(parent, false)
     }
 
     pub fn create_file_no_parent(
@@ -908,9 +1619,27 @@ where
             }
         }
 
-        let result =
-            self.meta_engine
-                .directory_add_entry(parent, name, FileTypeSimple::RegularFile.into());
+        // the name is held until this create returns, so no other create can add
+        // it between the check and the insert
+        match self.name_taken(parent, name).await {
+            Ok(false) => {}
+            Ok(true) => {
+                self.file_locks.get(parent).unwrap().remove(name);
+                if (oflag & O_EXCL) != 0 {
+                    info!("create file failed, file exists, path: {}", path);
+                    return Err(libc::EEXIST);
+                }
+                return self.call_get_attr_remote_or_local(&path).await;
+            }
+            Err(e) => {
+                self.file_locks.get(parent).unwrap().remove(name);
+                return Err(e);
+            }
+        }
+
+        let result = self
+            .add_directory_entry(parent, name, FileTypeSimple::RegularFile.into())
+            .await;
 
         let result = match result {
             Ok(_) => {
@@ -957,30 +1686,810 @@ where
         }
     }
 
-    pub fn delete_file_no_parent(&self, path: &str) -> Result<(), i32> {
-        match self.file_locks.get_mut(path) {
-            Some(value) => {
-                self.storage_engine.delete_file(path)?;
-                drop(value);
-                self.file_locks.remove(path);
-                Ok(())
-            }
-            None => Err(libc::ENOENT),
-        }
-    }
-
-    pub async fn delete_file(
+    // create a small file and write its whole content in one request. like a
+    // symlink the file is made with its data on its owner before its entry is
+    // added, so it is never found without the data, and a failure only removes
+    // what this request made
+    pub async fn create_and_write(
         &self,
-        send_meta_data: Vec<u8>,
         parent: &str,
         name: &str,
-    ) -> Result<(), i32> {
+        umask: u32,
+        mode: u32,
+        data: &[u8],
+    ) -> Result<Vec<u8>, i32> {
+        let path = get_full_path(parent, name);
+        self.check_file_size(&path, data.len() as u64).await?;
         if self.lock_file(parent)?.insert(name.to_owned(), 0).is_some() {
-            info!("delete file failed, file exists, path: {}/{}", parent, name);
+            return Err(libc::EEXIST);
+        }
+
+        let result = match self.name_taken(parent, name).await {
+            Ok(true) => Err(libc::EEXIST),
+            Ok(false) => match self
+                .create_with_data_remote_or_local(&path, name, umask, mode, data)
+                .await
+            {
+                Ok(attr) => match self
+                    .add_directory_entry(parent, name, FileTypeSimple::RegularFile.into())
+                    .await
+                {
+                    Ok(()) => Ok(attr),
+                    Err(e) => {
+                        error!(
+                            "create and write: DirectoryAddEntry failed: {}, {:?}",
+                            path, e
+                        );
+                        let (address, _lock) = self.get_server_address(&path);
+                        let rollback = if self.address == address {
+                            self.delete_file_no_parent(&path)
+                        } else {
+                            self.sender
+                                .delete_no_parent(
+                                    &address,
+                                    OperationType::DeleteFileNoParent,
+                                    &path,
+                                    &[],
+                                )
+                                .await
+                        };
+                        if let Err(e) = rollback {
+                            error!("create and write: rollback failed: {}, {:?}", path, e);
+                        }
+                        Err(e)
+                    }
+                },
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        self.file_locks.get(parent).unwrap().remove(name);
+        result
+    }
+
+    async fn create_with_data_remote_or_local(
+        &self,
+        path: &str,
+        name: &str,
+        umask: u32,
+        mode: u32,
+        data: &[u8],
+    ) -> Result<Vec<u8>, i32> {
+        let oflag = O_CREAT | O_EXCL | O_WRONLY;
+        let (address, _lock) = self.get_server_address(path);
+        if self.address == address {
+            return self
+                .create_file_with_data_no_parent(path, oflag, umask, mode, data)
+                .await;
+        }
+        let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+            mode,
+            umask,
+            flags: oflag,
+            name: name.to_owned(),
+            tmpfile: false,
+        })
+        .unwrap();
+        self.sender
+            .create_no_parent_with_data(
+                &address,
+                OperationType::CreateFileNoParent,
+                path,
+                &send_meta_data,
+                data,
+            )
+            .await
+    }
+
+    // creates the file with data in it, the lock of the file is held from its insert
+    // until the data is written so no request sees it before. the data is charged to
+    // the volume first, and given back if the file can not be made
+    pub async fn create_file_with_data_no_parent(
+        &self,
+        path: &str,
+        oflag: i32,
+        umask: u32,
+        mode: u32,
+        data: &[u8],
+    ) -> Result<Vec<u8>, i32> {
+        let volume = path.split('/').next().unwrap();
+        self.charge_volume(volume, data.len() as i64).await?;
+        let (created, result) = match self.file_locks.entry(path.to_owned()) {
+            Entry::Occupied(_) => (false, Err(libc::EEXIST)),
+            Entry::Vacant(entry) => {
+                let _file_lock = entry.insert(DashMap::new());
+                info!("local create file with data, path: {}", path);
+                let result = self
+                    .storage_engine
+                    .create_file(path, oflag, umask, mode)
+                    .map(|_| ())
+                    .and_then(|_| match data.is_empty() {
+                        true => Ok(()),
+                        false => self.storage_engine.write_file(path, data, 0).map(|_| ()),
+                    })
+                    .and_then(|_| self.meta_engine.get_file_attr_raw(path));
+                (true, result)
+            }
+        };
+        if let Err(e) = result {
+            if created {
+                if let Err(e) = self.delete_file_no_parent(path) {
+                    error!("create file with data: rollback failed: {}, {:?}", path, e);
+                }
+            }
+            self.release_volume(path, data.len() as u64).await;
+            return Err(e);
+        }
+        if !data.is_empty() {
+            self.flush_batcher.mark_dirty(path);
+        }
+        result
+    }
+
+    // the symlink is made on its owner before its entry is added, so a name already
+    // taken by any kind of file fails with EEXIST
+    pub async fn create_symlink(
+        &self,
+        parent: &str,
+        name: &str,
+        target: &str,
+    ) -> Result<Vec<u8>, i32> {
+        if target.is_empty() {
+            return Err(libc::ENOENT);
+        }
+        if target.len() >= libc::PATH_MAX as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+        if self.lock_file(parent)?.insert(name.to_owned(), 0).is_some() {
+            return Err(libc::EEXIST);
+        }
+
+        let path = get_full_path(parent, name);
+        let result = match self
+            .create_symlink_remote_or_local(&path, name, target)
+            .await
+        {
+            Ok(attr) => match self
+                .add_directory_entry(parent, name, FileTypeSimple::Symlink.into())
+                .await
+            {
+                Ok(()) => Ok(attr),
+                Err(e) => {
+                    error!(
+                        "create symlink: DirectoryAddEntry failed: {}, {:?}",
+                        path, e
+                    );
+                    let (address, _lock) = self.get_server_address(&path);
+                    let rollback = if self.address == address {
+                        self.delete_file_no_parent(&path)
+                    } else {
+                        self.sender
+                            .delete_no_parent(
+                                &address,
+                                OperationType::DeleteFileNoParent,
+                                &path,
+                                &[],
+                            )
+                            .await
+                    };
+                    if let Err(e) = rollback {
+                        error!("create symlink: rollback failed: {}, {:?}", path, e);
+                    }
+                    Err(e)
+                }
+            },
+            Err(e) => Err(e),
+        };
+
+        self.file_locks.get(parent).unwrap().remove(name);
+        result
+    }
+
+    async fn create_symlink_remote_or_local(
+        &self,
+        path: &str,
+        name: &str,
+        target: &str,
+    ) -> Result<Vec<u8>, i32> {
+        let (address, _lock) = self.get_server_address(path);
+        if self.address == address {
+            return self.create_symlink_no_parent(path, target);
+        }
+        let send_meta_data = bincode::serialize(&CreateSymlinkSendMetaData {
+            name: name.to_owned(),
+            target: target.to_owned(),
+        })
+        .unwrap();
+        self.sender
+            .create_no_parent(
+                &address,
+                OperationType::CreateSymlinkNoParent,
+                path,
+                &send_meta_data,
+            )
+            .await
+    }
+
+    pub fn create_symlink_no_parent(&self, path: &str, target: &str) -> Result<Vec<u8>, i32> {
+        self.create_file_no_parent(path, O_CREAT | O_RDWR, 0, 0o777)?;
+        let result = self
+            .storage_engine
+            .write_file(path, target.as_bytes(), 0)
+            .and_then(|_| self.meta_engine.set_symlink(path));
+        if result.is_err() {
+            if let Err(e) = self.delete_file_no_parent(path) {
+                error!("create symlink: rollback failed: {}, {:?}", path, e);
+            }
+        }
+        result
+    }
+
+    pub fn read_link(&self, path: &str) -> Result<Vec<u8>, i32> {
+        let _file_lock = self.lock_file(path)?;
+        let attr = self.meta_engine.get_file_attr(path)?;
+        if attr.kind != FileType::Symlink {
+            return Err(libc::EINVAL);
+        }
+        self.storage_engine.read_file(path, attr.size as u32, 0)
+    }
+
+    // O_TMPFILE: the file is created on its owner but not added to the parent, so it
+    // stays invisible until Link gives it a name
+    pub async fn create_tmpfile(
+        &self,
+        send_meta_data: Vec<u8>,
+        parent: &str,
+        name: &str,
+        oflag: i32,
+        umask: u32,
+        mode: u32,
+    ) -> Result<Vec<u8>, i32> {
+        self.check_type(parent, FileTypeSimple::Directory)?;
+        let path = get_full_path(parent, name);
+        let (address, _lock) = self.get_server_address(&path);
+        if self.address == address {
+            self.create_tmpfile_no_parent(&path, oflag, umask, mode)
+        } else {
+            self.sender
+                .create_no_parent(
+                    &address,
+                    OperationType::CreateFileNoParent,
+                    &path,
+                    &send_meta_data,
+                )
+                .await
+        }
+    }
+
+    pub fn create_tmpfile_no_parent(
+        &self,
+        path: &str,
+        oflag: i32,
+        umask: u32,
+        mode: u32,
+    ) -> Result<Vec<u8>, i32> {
+        self.create_file_no_parent(path, oflag, umask, mode)?;
+        self.meta_engine.set_unlinked(path, true)
+    }
+
+    // gives the tmpfile at tmp_path a name in the same directory. the data is keyed by
+    // path, so it is copied to the owner of the new path and the tmpfile removed
+    pub async fn link(&self, tmp_path: &str, name: &str) -> Result<Vec<u8>, i32> {
+        let attr = self.meta_engine.get_file_attr(tmp_path)?;
+        if attr.flags & UNLINKED == 0 {
+            return Err(libc::EINVAL);
+        }
+        let (parent, _) = path_split(tmp_path)?;
+        let path = get_full_path(&parent, name);
+        let oflag = O_CREAT | O_RDWR;
+        // with the type bits, so that a file without permissions stays without
+        let mode = libc::S_IFREG | attr.perm as u32;
+
+        let (address, _lock) = self.get_server_address(&path);
+        if self.address == address {
+            self.create_file_no_parent(&path, oflag, 0, mode)?;
+        } else {
+            let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                mode,
+                umask: 0,
+                flags: oflag,
+                name: name.to_owned(),
+                tmpfile: false,
+            })
+            .unwrap();
+            self.sender
+                .create_no_parent(
+                    &address,
+                    OperationType::CreateFileNoParent,
+                    &path,
+                    &send_meta_data,
+                )
+                .await?;
+        }
+
+        // nothing is written to the tmpfile while it is copied
+        let result = match self.file_locks.get_mut(tmp_path) {
+            Some(_file_lock) => match self
+                .flush_pending_writes(tmp_path)
+                .and_then(|_| self.meta_engine.get_file_attr(tmp_path))
+            {
+                Ok(attr) => self.link_data(tmp_path, &path, &address, attr.size).await,
+                Err(e) => Err(e),
+            },
+            None => Err(libc::ENOENT),
+        };
+        let result = match result {
+            Ok(()) => {
+                let (parent_address, _lock) = self.get_server_address(&parent);
+                if self.address == parent_address {
+                    self.add_directory_entry(&parent, name, FileTypeSimple::RegularFile.into())
+                        .await
+                } else {
+                    let send_meta_data = bincode::serialize(&DirectoryEntrySendMetaData {
+                        file_type: FileTypeSimple::RegularFile.into(),
+                        file_name: name.to_owned(),
+                    })
+                    .unwrap();
+                    self.sender
+                        .directory_add_entry(&parent_address, &parent, &send_meta_data)
+                        .await
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("link: failed: {}, {:?}", path, e);
+            let rollback = if self.address == address {
+                self.delete_file_no_parent(&path)
+            } else {
+                self.sender
+                    .delete_no_parent(&address, OperationType::DeleteFileNoParent, &path, &[])
+                    .await
+            };
+            if let Err(e) = rollback {
+                error!("link: rollback failed: {}, {:?}", path, e);
+            }
+            return Err(e);
+        }
+
+        self.delete_file_no_parent(tmp_path)?;
+        self.call_get_attr_remote_or_local(&path).await
+    }
+
+    // moves a regular file held by this server. the data and the attr are copied to
+    // the server owning the new path, a file replaced there is only gone once the copy
+    // and the entries are done. writes to the file wait from the copy until it is
+    // deleted, and then find it gone. directories are refused with EXDEV, moving their
+    // whole subtree is left to the client. RENAME_EXCHANGE is refused with EINVAL,
+    // two files on different servers can not be swapped at once
+    pub async fn rename(
+        &self,
+        path: &str,
+        new_parent: &str,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<Vec<u8>, i32> {
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            return Err(libc::EINVAL);
+        }
+        let attr = self.meta_engine.get_file_attr(path)?;
+        if attr.flags & UNLINKED != 0 {
+            return Err(libc::ENOENT);
+        }
+        if attr.kind == FileType::Directory {
+            return Err(libc::EXDEV);
+        }
+        let (parent, name) = path_split(path)?;
+        let new_path = get_full_path(new_parent, new_name);
+        if new_path == path {
+            return self.meta_engine.get_file_attr_raw(path);
+        }
+        let file_type: u8 = FileTypeSimple::from(attr.kind).into();
+        let (replaced, replaced_size): (Option<u8>, u64) =
+            match self.regular_file_size(&new_path).await {
+                Ok((kind, size)) => {
+                    if flags & libc::RENAME_NOREPLACE != 0 {
+                        return Err(libc::EEXIST);
+                    }
+                    if kind == FileType::Directory {
+                        return Err(libc::EISDIR);
+                    }
+                    (Some(FileTypeSimple::from(kind).into()), size)
+                }
+                Err(libc::ENOENT) => (None, 0),
+                Err(e) => return Err(e),
+            };
+
+        // the copy is staged as a tmpfile on the server holding the new path and moved
+        // over it there
+        let (address, _lock) = self.get_server_address(&new_path);
+        let staging = self.staging_path(path, new_parent, &address)?;
+        let oflag = O_CREAT | O_RDWR;
+        // with the type bits, so that a file without permissions stays without
+        let mode = libc::S_IFREG | attr.perm as u32;
+        if self.address == address {
+            self.create_tmpfile_no_parent(&staging, oflag, 0, mode)?;
+        } else {
+            let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                mode,
+                umask: 0,
+                flags: oflag,
+                name: staging.rsplit('/').next().unwrap().to_owned(),
+                tmpfile: true,
+            })
+            .unwrap();
+            self.sender
+                .create_no_parent(
+                    &address,
+                    OperationType::CreateFileNoParent,
+                    &staging,
+                    &send_meta_data,
+                )
+                .await?;
+        }
+
+        // nothing is written to the file while it is copied, the attr moved with it
+        // is the one of the data copied. marked moving, the writes after wait for
+        // the rename to delete it or fail
+        let mut marked = false;
+        let copied = match self.file_locks.get_mut(path) {
+            Some(_) if self.moving.contains(path) => Err(libc::EBUSY),
+            Some(_file_lock) => {
+                self.moving.insert(path.to_owned());
+                marked = true;
+                match self
+                    .flush_pending_writes(path)
+                    .and_then(|_| self.meta_engine.get_file_attr(path))
+                {
+                    Ok(attr) => self
+                        .link_data(path, &staging, &address, attr.size)
+                        .await
+                        .and_then(|_| {
+                            let mut attr: FileAttrSimple = attr.into();
+                            attr.version = self.meta_engine.version(path)?;
+                            Ok(attr)
+                        }),
+                    Err(e) => Err(e),
+                }
+            }
+            None => Err(libc::ENOENT),
+        };
+
+        let mut moved_attr = None;
+        let mut result = copied.map(|attr| moved_attr = Some(attr));
+        // without a replaced file the staged one is moved first, so that the file is
+        // found under one of its names all along
+        let move_first = replaced.is_none();
+        let (mut moved, mut new_entry, mut old_entry) = (false, false, false);
+        if result.is_ok() && move_first {
+            let attr = moved_attr.take().unwrap();
+            result = self.move_staged(&address, &staging, &new_path, attr).await;
+            moved = result.is_ok();
+        }
+        // within one directory the entry is renamed in place, in a single write
+        if result.is_ok() && parent == new_parent {
+            result = self
+                .rename_entry_remote_or_local(&parent, &name, new_name, file_type, replaced)
+                .await;
+            (new_entry, old_entry) = (result.is_ok(), result.is_ok());
+        }
+        if result.is_ok() && !new_entry {
+            result = match replaced {
+                Some(replaced_type) if replaced_type != file_type => {
+                    self.swap_entry_type(new_parent, new_name, replaced_type, file_type)
+                        .await
+                }
+                Some(_) => Ok(()),
+                None => {
+                    self.directory_entry_remote_or_local(new_parent, new_name, file_type, true)
+                        .await
+                }
+            };
+            new_entry = result.is_ok();
+        }
+        if result.is_ok() && !old_entry {
+            result = self
+                .directory_entry_remote_or_local(&parent, &name, file_type, false)
+                .await;
+            old_entry = result.is_ok();
+        }
+        if result.is_ok() && !move_first {
+            let attr = moved_attr.take().unwrap();
+            result = self.move_staged(&address, &staging, &new_path, attr).await;
+            moved = result.is_ok();
+        }
+
+        if let Err(e) = result {
+            error!("rename: failed: {} -> {}, {:?}", path, new_path, e);
+            if old_entry {
+                if let Err(e) = self
+                    .directory_entry_remote_or_local(&parent, &name, file_type, true)
+                    .await
+                {
+                    error!("rename: rollback entry failed: {}, {:?}", path, e);
+                }
+            }
+            if new_entry {
+                let rollback = match replaced {
+                    Some(replaced_type) if replaced_type != file_type => {
+                        self.swap_entry_type(new_parent, new_name, file_type, replaced_type)
+                            .await
+                    }
+                    Some(_) => Ok(()),
+                    None => {
+                        self.directory_entry_remote_or_local(new_parent, new_name, file_type, false)
+                            .await
+                    }
+                };
+                if let Err(e) = rollback {
+                    error!("rename: rollback entry failed: {}, {:?}", new_path, e);
+                }
+            }
+            // only a new path nothing was replaced at is moved to before it fails
+            let staged = match moved {
+                true => &new_path,
+                false => &staging,
+            };
+            let rollback = if self.address == address {
+                self.delete_file_no_parent(staged)
+            } else {
+                self.sender
+                    .delete_no_parent(&address, OperationType::DeleteFileNoParent, staged, &[])
+                    .await
+            };
+            if let Err(e) = rollback {
+                error!("rename: rollback failed: {}, {:?}", staged, e);
+            }
+            if marked {
+                self.moving.remove(path);
+            }
+            return Err(e);
+        }
+
+        self.release_volume(&new_path, replaced_size).await;
+        let deleted = self.delete_file_no_parent(path);
+        self.moving.remove(path);
+        deleted?;
+        self.call_get_attr_remote_or_local(&new_path).await
+    }
+
+    // a name in parent for the copy of path a rename stages, held by the server at
+    // address like the new path, so that every request for it goes there
+    fn staging_path(&self, path: &str, parent: &str, address: &str) -> Result<String, i32> {
+        let id = crc32c::crc32c(path.as_bytes());
+        (0..u16::MAX)
+            .map(|i| get_full_path(parent, &format!(".{:08x}.{}.rename", id, i)))
+            .find(|staging| self.get_server_address(staging).0 == address)
+            .ok_or(libc::EAGAIN)
+    }
+
+    // moves the file staged at staging over new_path on the server at address
+    async fn move_staged(
+        &self,
+        address: &str,
+        staging: &str,
+        new_path: &str,
+        attr: FileAttrSimple,
+    ) -> Result<(), i32> {
+        if self.address == address {
+            return self.rename_no_parent(staging, new_path, attr);
+        }
+        let send_meta_data = bincode::serialize(&RenameNoParentSendMetaData {
+            new_path: new_path.to_owned(),
+            attr,
+        })
+        .unwrap();
+        self.sender
+            .rename_no_parent(address, staging, &send_meta_data)
+            .await
+    }
+
+    // changes the type the entry name of parent is added with
+    async fn swap_entry_type(
+        &self,
+        parent: &str,
+        name: &str,
+        old_type: u8,
+        new_type: u8,
+    ) -> Result<(), i32> {
+        self.directory_entry_remote_or_local(parent, name, old_type, false)
+            .await?;
+        let result = self
+            .directory_entry_remote_or_local(parent, name, new_type, true)
+            .await;
+        if result.is_err() {
+            if let Err(e) = self
+                .directory_entry_remote_or_local(parent, name, old_type, true)
+                .await
+            {
+                error!(
+                    "rename: rollback entry failed: {}/{}, {:?}",
+                    parent, name, e
+                );
+            }
+        }
+        result
+    }
+
+    // moves the file at path over new_path, both held here, with the type, owner,
+    // permissions, times and version of attr. a file at new_path is replaced at once
+    pub fn rename_no_parent(
+        &self,
+        path: &str,
+        new_path: &str,
+        attr: FileAttrSimple,
+    ) -> Result<(), i32> {
+        self.flush_pending_writes(path)?;
+        self.write_combiner.discard(new_path);
+        match self.file_locks.get_mut(path) {
+            Some(_file_lock) => self.storage_engine.rename_file(path, new_path)?,
+            None => return Err(libc::ENOENT),
+        }
+        self.file_locks.remove(path);
+        self.file_locks.entry(new_path.to_owned()).or_default();
+        self.checksums.remove(path);
+        self.checksums.remove(new_path);
+        self.meta_engine.set_moved_attr(new_path, attr).map(|_| ())
+    }
+
+    // adds or removes the entry name of parent on the server holding parent
+    async fn directory_entry_remote_or_local(
+        &self,
+        parent: &str,
+        name: &str,
+        file_type: u8,
+        add: bool,
+    ) -> Result<(), i32> {
+        let (address, _lock) = self.get_server_address(parent);
+        if self.address == address {
+            return match add {
+                true => self.add_directory_entry(parent, name, file_type).await,
+                false => self.delete_directory_entry(parent, name, file_type).await,
+            };
+        }
+        let send_meta_data = bincode::serialize(&DirectoryEntrySendMetaData {
+            file_type,
+            file_name: name.to_owned(),
+        })
+        .unwrap();
+        match add {
+            true => {
+                self.sender
+                    .directory_add_entry(&address, parent, &send_meta_data)
+                    .await
+            }
+            false => {
+                self.sender
+                    .directory_delete_entry(&address, parent, &send_meta_data)
+                    .await
+            }
+        }
+    }
+
+    async fn rename_entry_remote_or_local(
+        &self,
+        parent: &str,
+        name: &str,
+        new_name: &str,
+        file_type: u8,
+        replaced: Option<u8>,
+    ) -> Result<(), i32> {
+        let (address, _lock) = self.get_server_address(parent);
+        if self.address == address {
+            return self
+                .rename_directory_entry(parent, name, new_name, file_type, replaced)
+                .await;
+        }
+        let send_meta_data = bincode::serialize(&DirectoryRenameEntrySendMetaData {
+            file_type,
+            file_name: name.to_owned(),
+            new_name: new_name.to_owned(),
+            replaced,
+        })
+        .unwrap();
+        self.sender
+            .directory_rename_entry(&address, parent, &send_meta_data)
+            .await
+    }
+
+    // renames the entry name of parent to new_name, an entry of type replaced held by
+    // new_name goes away with it. the entries of a sharded directory may live on
+    // other servers, there they are deleted and added one after the other
+    async fn rename_directory_entry(
+        &self,
+        parent: &str,
+        name: &str,
+        new_name: &str,
+        file_type: u8,
+        replaced: Option<u8>,
+    ) -> Result<(), i32> {
+        if !self.meta_engine.is_sharded_directory(parent) {
+            return self
+                .meta_engine
+                .directory_rename_entry(parent, name, new_name, file_type, replaced);
+        }
+        if let Some(replaced) = replaced {
+            self.delete_directory_entry(parent, new_name, replaced)
+                .await?;
+        }
+        self.delete_directory_entry(parent, name, file_type).await?;
+        self.add_directory_entry(parent, new_name, file_type).await
+    }
+
+    async fn link_data(
+        &self,
+        tmp_path: &str,
+        path: &str,
+        address: &str,
+        size: u64,
+    ) -> Result<(), i32> {
+        let mut offset = 0;
+        while offset < size as i64 {
+            let data = self
+                .storage_engine
+                .read_file(tmp_path, CHUNK_SIZE as u32, offset)?;
+            if data.is_empty() {
+                break;
+            }
+            if self.address == address {
+                self.storage_engine.write_file(path, &data, offset)?;
+            } else {
+                self.sender
+                    .write_file(address, path, &data, offset, MOVED_DATA)
+                    .await?;
+            }
+            offset += data.len() as i64;
+        }
+        Ok(())
+    }
+
+    // the kind of the file at path and its size if it is a regular file, with the
+    // writes still buffered for it written out first
+    async fn regular_file_size(&self, path: &str) -> Result<(FileType, u64), i32> {
+        if let Ok(_file_lock) = self.lock_file(path) {
+            self.flush_pending_writes(path)?;
+        }
+        let attr = self.call_get_attr_remote_or_local(path).await?;
+        let attr = bytes_as_file_attr(&attr);
+        match attr.kind {
+            FileType::RegularFile => Ok((attr.kind, attr.size)),
+            kind => Ok((kind, 0)),
+        }
+    }
+
+    pub fn delete_file_no_parent(&self, path: &str) -> Result<(), i32> {
+        match self.file_locks.get_mut(path) {
+            Some(value) => {
+                self.storage_engine.delete_file(path)?;
+                self.checksums.remove(path);
+                self.write_combiner.discard(path);
+                drop(value);
+                self.file_locks.remove(path);
+                Ok(())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub async fn delete_file(
+        &self,
+        send_meta_data: Vec<u8>,
+        parent: &str,
+        name: &str,
+    ) -> Result<(), i32> {
+        if self.lock_file(parent)?.insert(name.to_owned(), 0).is_some() {
+            info!("delete file failed, file exists, path: {}/{}", parent, name);
             return Err(libc::ENOENT); // this may indicate that the file is being created or deleted
         }
 
         let path = get_full_path(parent, name);
+        // symlinks are listed with their own type, the size of a regular file is
+        // given back to the volume once it is deleted
+        let (file_type, size) = match self.regular_file_size(&path).await {
+            Ok((kind, size)) => (FileTypeSimple::from(kind).into(), size),
+            Err(_) => (FileTypeSimple::RegularFile.into(), 0),
+        };
         let (address, _lock) = self.get_server_address(&path);
         let result = if self.address == address {
             debug!(
@@ -1003,11 +2512,9 @@ where
         };
 
         if result.is_ok() {
-            self.meta_engine.directory_delete_entry(
-                parent,
-                name,
-                FileTypeSimple::RegularFile.into(),
-            )?;
+            self.metrics.add_deleted_bytes(size);
+            self.release_volume(&path, size).await;
+            self.delete_directory_entry(parent, name, file_type).await?;
         }
         self.file_locks.get(parent).unwrap().remove(name);
 
@@ -1018,24 +2525,508 @@ where
     }
 
     pub async fn truncate_file(&self, path: &str, length: i64) -> Result<(), i32> {
-        // a temporary implementation
-        let _file_lock = self.lock_file(path)?;
-        self.storage_engine.truncate_file(path, length)
+        let length = length.max(0);
+        self.check_file_size(path, length as u64).await?;
+        self.charged(path, length as u64, true, true, libc::ENOENT, || {
+            self.flush_pending_writes(path)?;
+            let result = self.storage_engine.truncate_file(path, length);
+            self.checksums.remove(path);
+            result?;
+            self.meta_engine.set_size(path, length as u64)?;
+            self.meta_engine.touch_mtime(path, SystemTime::now())
+        })
+        .await
+    }
+
+    // fallocate(2), preallocates md.length bytes at md.offset and grows the file to
+    // them unless FALLOC_FL_KEEP_SIZE is set, or with FALLOC_FL_PUNCH_HOLE zeroes them
+    // and keeps the size. blocks of the attr is what the data takes after. only the
+    // size is charged to the volume, not space kept past it
+    pub async fn fallocate(&self, path: &str, md: &FallocateSendMetaData) -> Result<(), i32> {
+        let mode = md.mode as i32;
+        let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+        if mode & !(libc::FALLOC_FL_KEEP_SIZE | libc::FALLOC_FL_PUNCH_HOLE) != 0
+            || (mode & libc::FALLOC_FL_PUNCH_HOLE != 0 && !keep_size)
+        {
+            return Err(libc::EOPNOTSUPP);
+        }
+        if md.offset < 0 || md.length <= 0 {
+            return Err(libc::EINVAL);
+        }
+        let end = md.offset.checked_add(md.length).ok_or(libc::EFBIG)? as u64;
+        let end = match keep_size {
+            true => 0,
+            false => {
+                self.check_file_size(path, end).await?;
+                end
+            }
+        };
+        self.charged(path, end, false, true, libc::ENOENT, || {
+            self.flush_pending_writes(path)?;
+            let result = self
+                .storage_engine
+                .fallocate(path, mode, md.offset, md.length);
+            self.checksums.remove(path);
+            self.meta_engine.set_blocks(path, result?)?;
+            if end > self.meta_engine.get_file_attr(path)?.size {
+                self.meta_engine.set_size(path, end)?;
+            }
+            self.meta_engine.touch_mtime(path, SystemTime::now())
+        })
+        .await
+    }
+
+    // chmod, chown, truncate and utimes in one, returns the attr after the change
+    pub async fn set_attr(&self, path: &str, md: SetAttrSendMetaData) -> Result<Vec<u8>, i32> {
+        if let Some(size) = md.size {
+            self.truncate_file(path, size as i64).await?;
+        }
+        loop {
+            self.wait_moved(path).await;
+            let _file_lock = self.lock_file(path)?;
+            if self.moving.contains(path) {
+                continue;
+            }
+            return self.meta_engine.set_attr(
+                path,
+                md.perm,
+                md.uid,
+                md.gid,
+                md.atime,
+                md.mtime,
+                SystemTime::now(),
+            );
+        }
+    }
+
+    // waits for a rename copying the file to be done with it. the mark is set under
+    // the exclusive lock, so a change that finds none with the lock held makes it
+    // into the copy
+    async fn wait_moved(&self, path: &str) {
+        while self.moving.contains(path) {
+            tokio::time::sleep(MOVING_POLL).await;
+        }
     }
 
     pub async fn read_file(&self, path: &str, size: u32, offset: i64) -> Result<Vec<u8>, i32> {
+        self.read_file_vectored(path, &[(offset, size)])
+            .await
+            .map(|(data, _)| data)
+    }
+
+    // reads the ranges one after another under a single lock, returns their bytes
+    // together and how many were read for each range
+    pub async fn read_file_vectored(
+        &self,
+        path: &str,
+        ranges: &[(i64, u32)],
+    ) -> Result<(Vec<u8>, Vec<u32>), i32> {
+        let volume = path.split('/').next().unwrap();
+        let atime_policy = self.atime_policy(volume).await;
         let _file_lock = self.lock_file(path)?;
-        self.storage_engine.read_file(path, size, offset)
+        self.check_type(path, FileTypeSimple::RegularFile)?;
+        self.flush_pending_writes(path)?;
+        let mut data = Vec::new();
+        let mut lengths = Vec::with_capacity(ranges.len());
+        for (offset, size) in ranges {
+            let range = self.storage_engine.read_file(path, *size, *offset)?;
+            lengths.push(range.len() as u32);
+            data.extend_from_slice(&range);
+        }
+        if atime_policy != AtimePolicy::Noatime {
+            self.meta_engine
+                .touch_atime(path, atime_policy, SystemTime::now())?;
+        }
+        Ok((data, lengths))
+    }
+
+    // copies up to md.length bytes of the source from md.src_offset to dst_path, which
+    // this server holds, at md.dst_offset. a source held here is read from the store
+    // and any other from its server, a chunk at a time. stops at the end of the
+    // source and returns how many bytes were copied, an error past the first chunk
+    // ends the copy short like copy_file_range(2) does
+    pub async fn copy_file_range(
+        &self,
+        dst_path: &str,
+        md: &CopyFileRangeSendMetaData,
+    ) -> Result<u64, i32> {
+        if md.src_offset < 0 || md.dst_offset < 0 {
+            return Err(libc::EINVAL);
+        }
+        let length = md
+            .length
+            .min((i64::MAX - md.src_offset.max(md.dst_offset)) as u64);
+        if md.src_path == dst_path
+            && md.src_offset < md.dst_offset.saturating_add(length as i64)
+            && md.dst_offset < md.src_offset.saturating_add(length as i64)
+        {
+            return Err(libc::EINVAL);
+        }
+        let source = match self.moved_to(&md.src_path) {
+            Some(moved) => moved.new_server,
+            None => self.get_address(&md.src_path),
+        };
+        // a file held here may have its data on another server
+        let source = match source == self.address {
+            true => self.meta_engine.data_owner(&md.src_path)?.unwrap_or(source),
+            false => source,
+        };
+        let mut copied = 0u64;
+        while copied < length {
+            let size = (length - copied).min(COPY_CHUNK_SIZE as u64) as u32;
+            let offset = copied as i64;
+            let result = match source == self.address {
+                true => {
+                    self.read_file(&md.src_path, size, md.src_offset + offset)
+                        .await
+                }
+                false => {
+                    self.sender
+                        .read_file(&source, &md.src_path, size, md.src_offset + offset)
+                        .await
+                }
+            };
+            let result = match result {
+                Ok(data) if data.is_empty() => break,
+                Ok(data) => self
+                    .write_file(dst_path, &data, md.dst_offset + offset, None)
+                    .await
+                    .map(|written| (written, data.len() < size as usize)),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok((written, end)) => {
+                    copied += written as u64;
+                    if end {
+                        break;
+                    }
+                }
+                Err(_) if copied > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(copied)
+    }
+
+    pub async fn write_file(
+        &self,
+        path: &str,
+        data: &[u8],
+        offset: i64,
+        if_mtime: Option<SystemTime>,
+    ) -> Result<usize, i32> {
+        let end = offset.max(0) as u64 + data.len() as u64;
+        self.check_file_size(path, end).await?;
+        // a writer holds a handle to a file it opened, a file gone since then was
+        // deleted under it. nothing tracks open handles here, so one opened before
+        // the unlink fails the same way, only a tmpfile is written while unlinked.
+        // with if_mtime the lock is held exclusively, so no other write lands
+        // between the check and this one
+        self.charged(path, end, false, if_mtime.is_some(), libc::ESTALE, || {
+            self.write_file_locked(path, data, offset, if_mtime)
+        })
+        .await
+    }
+
+    // O_APPEND, the data lands where the file ends once the lock is held
+    // exclusively, so appenders never overlap. returns the size and the offset
+    // it was written at
+    pub async fn append_file(&self, path: &str, data: &[u8]) -> Result<(usize, i64), i32> {
+        loop {
+            if !self.file_locks.contains_key(path) {
+                return Err(libc::ESTALE);
+            }
+            let offset = self.effective_size(path)?;
+            let end = offset + data.len() as u64;
+            self.check_file_size(path, end).await?;
+            // another append got in before the lock, charge again for its new end
+            let written = self
+                .charged(path, end, false, true, libc::ESTALE, || {
+                    if self.effective_size(path)? != offset {
+                        return Ok(None);
+                    }
+                    self.write_file_locked(path, data, offset as i64, None)
+                        .map(|size| Some((size, offset as i64)))
+                })
+                .await?;
+            if let Some(written) = written {
+                return Ok(written);
+            }
+        }
+    }
+
+    // the data of a file moved here from another server, its bytes are already
+    // charged to the volume
+    pub fn write_moved_data(&self, path: &str, data: &[u8], offset: i64) -> Result<usize, i32> {
+        let _file_lock = self.lock_file(path).map_err(|_| libc::ESTALE)?;
+        self.write_file_locked(path, data, offset, None)
+    }
+
+    // where the file ends, counting the writes still buffered for it
+    fn effective_size(&self, path: &str) -> Result<u64, i32> {
+        self.check_type(path, FileTypeSimple::RegularFile)?;
+        let size = self.meta_engine.get_file_attr(path)?.size;
+        Ok(match self.write_combiner.pending_end(path) {
+            Some(end) => size.max(end as u64),
+            None => size,
+        })
+    }
+
+    // runs op on the file at path locked, once no rename is moving it, with the bytes
+    // it grows the file by to end charged to the volume first and what it did not use
+    // given back after. a truncate also gives back the bytes it cuts off. the lock is
+    // exclusive when op grows the file, so the size it was charged for can not change
+    // under it
+    async fn charged<T>(
+        &self,
+        path: &str,
+        end: u64,
+        truncate: bool,
+        exclusive: bool,
+        missing: i32,
+        op: impl Fn() -> Result<T, i32>,
+    ) -> Result<T, i32> {
+        let volume = path.split('/').next().unwrap();
+        let mut charged = 0i64;
+        let result = loop {
+            self.wait_moved(path).await;
+            if !self.file_locks.contains_key(path) {
+                break Err(missing);
+            }
+            let growth = match self.effective_size(path) {
+                Ok(size) => end as i64 - size as i64,
+                Err(e) => break Err(e),
+            };
+            if growth > charged {
+                if let Err(e) = self.charge_volume(volume, growth - charged).await {
+                    break Err(e);
+                }
+                charged = growth;
+            }
+            if exclusive || truncate || charged > 0 {
+                let Some(_file_lock) = self.file_locks.get_mut(path) else {
+                    break Err(missing);
+                };
+                if self.moving.contains(path) {
+                    continue;
+                }
+                let growth = match self.effective_size(path) {
+                    Ok(size) => end as i64 - size as i64,
+                    Err(e) => break Err(e),
+                };
+                // grown past what was charged while unlocked
+                if growth > charged {
+                    continue;
+                }
+                let change = match truncate {
+                    true => growth,
+                    false => growth.max(0),
+                };
+                break op().map(|value| (value, change));
+            }
+            let Ok(_file_lock) = self.lock_file(path) else {
+                break Err(missing);
+            };
+            if self.moving.contains(path) {
+                continue;
+            }
+            match self.effective_size(path) {
+                Ok(size) if end > size => continue,
+                Ok(_) => break op().map(|value| (value, 0)),
+                Err(e) => break Err(e),
+            }
+        };
+        let change = result.as_ref().map_or(0, |(_, change)| *change);
+        if change != charged {
+            if let Err(e) = self.charge_volume(volume, change - charged).await {
+                error!(
+                    "charge {} bytes to {} failed: {}",
+                    change - charged,
+                    path,
+                    e
+                );
+            }
+        }
+        result.map(|(value, _)| value)
+    }
+
+    fn write_file_locked(
+        &self,
+        path: &str,
+        data: &[u8],
+        offset: i64,
+        if_mtime: Option<SystemTime>,
+    ) -> Result<usize, i32> {
+        self.check_type(path, FileTypeSimple::RegularFile)?;
+        if let Some(if_mtime) = if_mtime {
+            if self.meta_engine.get_file_attr(path)?.mtime != if_mtime {
+                return Err(CONFLICT);
+            }
+        }
+        let result = self
+            .write_combiner
+            .write(path, data, offset, |data, offset| {
+                self.storage_engine.write_file(path, data, offset)
+            });
+        self.checksums.remove(path);
+        if result.is_ok() {
+            self.flush_batcher.mark_dirty(path);
+            self.meta_engine.touch_mtime(path, SystemTime::now())?;
+        }
+        result
+    }
+
+    // held exclusively from reading the counter to writing it back, so no other
+    // increment or write lands in between. bytes past the end of the file read as 0
+    pub async fn atomic_increment(&self, path: &str, delta: i64) -> Result<i64, i32> {
+        self.check_file_size(path, 8).await?;
+        self.charged(path, 8, false, true, libc::ENOENT, || {
+            self.flush_pending_writes(path)?;
+            let current = self.storage_engine.read_file(path, 8, 0)?;
+            let mut counter = [0u8; 8];
+            counter[..current.len()].copy_from_slice(&current);
+            let value = i64::from_le_bytes(counter)
+                .checked_add(delta)
+                .ok_or(libc::EOVERFLOW)?;
+            self.write_file_locked(path, &value.to_le_bytes(), 0, None)?;
+            Ok(value)
+        })
+        .await
+    }
+
+    pub async fn write_file_chunk(
+        &self,
+        path: &str,
+        md: WriteFileChunkSendMetaData,
+        data: &[u8],
+    ) -> Result<WriteFileChunkRecvMetaData, i32> {
+        let mut stored = 0;
+        while stored < data.len() {
+            match self
+                .write_file(
+                    path,
+                    &data[stored..],
+                    (md.chunk_offset + stored as u64) as i64,
+                    None,
+                )
+                .await?
+            {
+                0 => return Err(libc::EIO),
+                n => stored += n,
+            }
+        }
+        if md.is_last {
+            self.fsync(path).await?;
+        }
+        Ok(WriteFileChunkRecvMetaData {
+            fh: md.fh,
+            chunk_offset: md.chunk_offset,
+            stored: stored as u64,
+        })
     }
 
-    pub async fn write_file(&self, path: &str, data: &[u8], offset: i64) -> Result<usize, i32> {
+    pub async fn fsync(&self, path: &str) -> Result<(), i32> {
         let _file_lock = self.lock_file(path)?;
-        self.storage_engine.write_file(path, data, offset)
+        self.flush_pending_writes(path)?;
+        self.flush_batcher.remove(path);
+        self.storage_engine.sync_file(path)
+    }
+
+    // one sync of the store for every file written since the last window, combined
+    // writes are put in the store first so the window covers them too
+    pub fn flush_dirty_files(&self) -> Result<usize, i32> {
+        self.write_combiner
+            .flush_all(|path, data, offset| self.storage_engine.write_file(path, data, offset))?;
+        self.flush_batcher
+            .flush_with(|| self.storage_engine.sync_all())
+    }
+
+    fn check_type(&self, path: &str, expected: FileTypeSimple) -> Result<(), i32> {
+        let attr = self.meta_engine.get_file_attr(path)?;
+        check_type(&attr.into(), expected)
+    }
+
+    pub fn flush_pending_writes(&self, path: &str) -> Result<(), i32> {
+        self.write_combiner.flush(path, |data, offset| {
+            self.storage_engine.write_file(path, data, offset)
+        })
+    }
+
+    // must be called with the file locked exclusively. writes run under the shared
+    // lock, one dropping the checksum while it is computed would be undone by the insert
+    fn file_checksum(&self, path: &str, size: u64) -> Result<u32, i32> {
+        if let Some(checksum) = self.checksums.get(path) {
+            return Ok(*checksum);
+        }
+        let mut checksum = 0u32;
+        let mut offset = 0i64;
+        while offset < size as i64 {
+            let chunk = self
+                .storage_engine
+                .read_file(path, CHUNK_SIZE as u32, offset)?;
+            if chunk.is_empty() {
+                break;
+            }
+            checksum = crc32c::crc32c_append(checksum, &chunk);
+            offset += chunk.len() as i64;
+        }
+        self.checksums.insert(path.to_owned(), checksum);
+        Ok(checksum)
+    }
+
+    pub fn local_stat_and_checksum(&self, path: &str) -> Result<(FileAttrSimple, u32), i32> {
+        let Some(_file_lock) = self.file_locks.get_mut(path) else {
+            return Err(libc::ENOENT);
+        };
+        self.flush_pending_writes(path)?;
+        let attr = self.meta_engine.get_file_attr(path)?;
+        let checksum = match attr.kind {
+            FileType::RegularFile => self.file_checksum(path, attr.size)?,
+            _ => 0,
+        };
+        let mut attr: FileAttrSimple = attr.into();
+        attr.version = self.meta_engine.version(path)?;
+        attr.inline_data = self.meta_engine.inline_data(path)?;
+        Ok((attr, checksum))
+    }
+
+    pub async fn stat_and_checksum(
+        &self,
+        paths: Vec<String>,
+    ) -> Result<Vec<(String, FileAttrSimple, u32)>, i32> {
+        let mut entries = Vec::with_capacity(paths.len());
+        let mut remote_paths: HashMap<String, Vec<String>> = HashMap::new();
+        for path in paths {
+            let (address, _lock) = self.get_server_address(&path);
+            if self.address == address {
+                match self.local_stat_and_checksum(&path) {
+                    Ok((attr, checksum)) => entries.push((path, attr, checksum)),
+                    Err(libc::ENOENT) => {}
+                    Err(e) => return Err(e),
+                }
+            } else {
+                remote_paths.entry(address).or_default().push(path);
+            }
+        }
+        for (address, paths) in remote_paths {
+            entries.extend(self.sender.stat_and_checksum(&address, paths).await?);
+        }
+        Ok(entries)
+    }
+
+    // paths are unique, so the parent is the path itself minus its last component
+    pub fn get_parent(&self, path: &str) -> Result<(String, String), i32> {
+        self.meta_engine.get_file_attr(path)?;
+        match path.rfind('/') {
+            Some(index) => Ok((path[..index].to_owned(), path[index + 1..].to_owned())),
+            None => Ok((path.to_owned(), "".to_owned())),
+        }
     }
 
     pub async fn get_file_attr(&self, path: &str) -> Result<Vec<u8>, i32> {
         let _file_lock = self.lock_file(path)?;
-        self.meta_engine.get_file_attr_raw(path)
+        self.flush_pending_writes(path)?;
+        self.meta_engine.get_file_attr_versioned(path)
     }
 
     pub async fn open_file(&self, path: &str, flag: i32, mode: u32) -> Result<(), i32> {
@@ -1050,17 +3041,11 @@ where
     }
 
     pub async fn directory_add_entry(&self, path: &str, file_name: String, file_type: u8) -> i32 {
-        let _lock = match self.lock_file(path) {
-            Ok(lock) => lock,
-            Err(e) => {
-                error!("directory add entry, lock file failed: {:?}", e);
-                return e;
-            }
-        };
-        match self
-            .meta_engine
-            .directory_add_entry(path, &file_name, file_type)
-        {
+        if let Err(e) = self.lock_file(path) {
+            error!("directory add entry, lock file failed: {:?}", e);
+            return e;
+        }
+        match self.add_directory_entry(path, &file_name, file_type).await {
             Ok(()) => {
                 debug!("{} Directory Add Entry success", self.address);
                 0
@@ -1072,11 +3057,41 @@ where
         }
     }
 
+    pub async fn directory_rename_entry(
+        &self,
+        path: &str,
+        md: DirectoryRenameEntrySendMetaData,
+    ) -> i32 {
+        if let Err(e) = self.lock_file(path) {
+            error!("directory rename entry, lock file failed: {:?}", e);
+            return e;
+        }
+        match self
+            .rename_directory_entry(path, &md.file_name, &md.new_name, md.file_type, md.replaced)
+            .await
+        {
+            Ok(()) => 0,
+            Err(value) => {
+                debug!("{} Directory Rename Entry error: {:?}", self.address, value);
+                value
+            }
+        }
+    }
+
     pub async fn check_file(&self, path: &str, file_attr: &FileAttr) -> Result<(), i32> {
         self.meta_engine.complete_transfer_file(path, file_attr)
     }
 
-    pub async fn check_dir(&self, path: &str, file_attr: &FileAttr) -> Result<(), i32> {
+    // a sharded directory comes with the count of all its entries
+    pub async fn check_dir(
+        &self,
+        path: &str,
+        file_attr: &FileAttr,
+        entries: Option<u32>,
+    ) -> Result<(), i32> {
+        if let Some(entries) = entries {
+            self.meta_engine.set_directory_entry_count(path, entries)?;
+        }
         self.meta_engine.complete_transfer_file(path, file_attr)
     }
 
@@ -1086,16 +3101,13 @@ where
         file_name: String,
         file_type: u8,
     ) -> i32 {
-        let _lock = match self.lock_file(path) {
-            Ok(lock) => lock,
-            Err(e) => {
-                error!("directory delete entry, lock file failed: {:?}", e);
-                return e;
-            }
-        };
+        if let Err(e) = self.lock_file(path) {
+            error!("directory delete entry, lock file failed: {:?}", e);
+            return e;
+        }
         match self
-            .meta_engine
-            .directory_delete_entry(path, &file_name, file_type)
+            .delete_directory_entry(path, &file_name, file_type)
+            .await
         {
             Ok(()) => 0,
             Err(value) => {
@@ -1105,10 +3117,140 @@ where
         }
     }
 
-    pub fn create_volume(&self, name: &str, _size: u64) -> Result<(), i32> {
+    pub fn create_volume(
+        &self,
+        name: &str,
+        size: u64,
+        atime_policy: AtimePolicy,
+        max_file_size: Option<u64>,
+        allowed_ops: Option<Vec<u32>>,
+    ) -> Result<(), i32> {
         match self.file_locks.insert(name.to_owned(), DashMap::new()) {
             Some(_) => Err(libc::EEXIST),
-            None => self.meta_engine.create_volume(name),
+            None => {
+                self.meta_engine
+                    .create_volume(name, size, atime_policy, max_file_size, allowed_ops)
+            }
+        }
+    }
+
+    // the used size of a volume is kept by the server owning the volume root, the
+    // others charge their writes to it. ENOSPC when delta would take it past the quota
+    pub async fn charge_volume(&self, volume: &str, delta: i64) -> Result<(), i32> {
+        if self.meta_engine.volumes.contains_key(volume) {
+            return self.meta_engine.charge_volume(volume, delta);
+        }
+        self.sender
+            .charge_volume(&self.get_address(volume), volume, delta)
+            .await
+    }
+
+    // answered by the server owning the volume root, the files counted are the
+    // ones of the volume it holds
+    pub fn stat_fs(&self, name: &str) -> Result<StatFsRecvMetaData, i32> {
+        let (size, used_size) = match self.meta_engine.volumes.get(name) {
+            Some(volume) => (volume.size, volume.used_size),
+            None => return Err(libc::ENOENT),
+        };
+        let files = self
+            .meta_engine
+            .volume_usage()
+            .into_iter()
+            .find(|(volume, _, _)| volume == name)
+            .map_or(0, |(_, _, files)| files);
+        let total_bytes = match size {
+            0 => u64::MAX,
+            size => size,
+        };
+        Ok(StatFsRecvMetaData {
+            total_bytes,
+            free_bytes: total_bytes.saturating_sub(used_size),
+            total_inodes: u64::MAX,
+            free_inodes: u64::MAX - files,
+            block_size: STATFS_BLOCK_SIZE,
+        })
+    }
+
+    // gives freed bytes back to the volume, a failure leaves them charged until
+    // the used size is recomputed
+    async fn release_volume(&self, path: &str, size: u64) {
+        if size == 0 {
+            return;
+        }
+        let volume = path.split('/').next().unwrap();
+        if let Err(e) = self.charge_volume(volume, -(size as i64)).await {
+            error!("release {} bytes of {} failed: {}", size, path, e);
+        }
+    }
+
+    // the Volume is kept by the server owning the volume root,
+    // the other servers ask it once and cache the answer. a failed lookup is not
+    // tried again for VOLUME_LOOKUP_RETRY, requests go on with the defaults meanwhile
+    async fn volume_setting<T>(&self, volume: &str, setting: impl Fn(&Volume) -> T) -> Option<T> {
+        if let Some(volume) = self.meta_engine.volumes.get(volume) {
+            return Some(setting(&volume));
+        }
+        if let Some(volume) = self.remote_volumes.get(volume) {
+            return Some(setting(&volume));
+        }
+        if self
+            .remote_volume_failures
+            .get(volume)
+            .is_some_and(|failed| failed.elapsed() < VOLUME_LOOKUP_RETRY)
+        {
+            return None;
+        }
+        match self.sender.list_volumes(&self.get_address(volume)).await {
+            Ok(volumes) => {
+                for v in volumes {
+                    self.remote_volumes.insert(v.name.clone(), v);
+                }
+            }
+            Err(e) => error!("get volume {} failed: {}", volume, e),
+        }
+        match self.remote_volumes.get(volume) {
+            Some(volume) => Some(setting(&volume)),
+            None => {
+                self.remote_volume_failures
+                    .insert(volume.to_owned(), Instant::now());
+                None
+            }
+        }
+    }
+
+    async fn atime_policy(&self, volume: &str) -> AtimePolicy {
+        self.volume_setting(volume, |volume| volume.atime_policy)
+            .await
+            .unwrap_or_default()
+    }
+
+    // EFBIG when a file of the volume of path would grow past its max file size
+    async fn check_file_size(&self, path: &str, size: u64) -> Result<(), i32> {
+        let volume = path.split('/').next().unwrap();
+        match self
+            .volume_setting(volume, |volume| volume.max_file_size)
+            .await
+            .flatten()
+        {
+            Some(max_file_size) if size > max_file_size => Err(libc::EFBIG),
+            _ => Ok(()),
+        }
+    }
+
+    // EPERM when the volume of path does not let clients run the operation
+    pub async fn check_allowed_op(&self, path: &str, operation_type: u32) -> Result<(), i32> {
+        match OperationType::try_from(operation_type) {
+            Ok(r#type) if r#type.is_restrictable() => {}
+            _ => return Ok(()),
+        }
+        let volume = path.split('/').next().unwrap();
+        match self
+            .volume_setting(volume, |volume| volume.allowed_ops.clone())
+            .await
+            .flatten()
+        {
+            Some(allowed_ops) if !allowed_ops.contains(&operation_type) => Err(libc::EPERM),
+            _ => Ok(()),
         }
     }
 
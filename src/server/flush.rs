@@ -0,0 +1,112 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// files written since the last flush are collected so that a single sync of the
+// store covers all of them, an explicit fsync still syncs its own file at once
+
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+pub struct FlushBatcher {
+    // milliseconds between batched flushes, 0 disables batching
+    interval_ms: AtomicU64,
+    dirty: Mutex<HashSet<String>>,
+    batched_flushes: AtomicU64,
+}
+
+impl Default for FlushBatcher {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl FlushBatcher {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval_ms: AtomicU64::new(interval_ms),
+            dirty: Mutex::new(HashSet::new()),
+            batched_flushes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_interval(&self, interval_ms: u64) {
+        self.interval_ms.store(interval_ms, Ordering::Relaxed);
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        match self.interval_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn mark_dirty(&self, path: &str) {
+        if self.interval().is_some() {
+            self.dirty.lock().insert(path.to_owned());
+        }
+    }
+
+    // returns whether the file was waiting for the next batch
+    pub fn remove(&self, path: &str) -> bool {
+        self.dirty.lock().remove(path)
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.lock().len()
+    }
+
+    pub fn batched_flushes(&self) -> u64 {
+        self.batched_flushes.load(Ordering::Relaxed)
+    }
+
+    // calls flush once if any file is dirty and returns how many files it covered,
+    // on failure the files stay dirty for the next window
+    pub fn flush_with<F>(&self, flush: F) -> Result<usize, i32>
+    where
+        F: FnOnce() -> Result<(), i32>,
+    {
+        let dirty = std::mem::take(&mut *self.dirty.lock());
+        if dirty.is_empty() {
+            return Ok(0);
+        }
+        if let Err(e) = flush() {
+            self.dirty.lock().extend(dirty);
+            return Err(e);
+        }
+        self.batched_flushes.fetch_add(1, Ordering::Relaxed);
+        Ok(dirty.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlushBatcher;
+
+    #[test]
+    fn test_flush_failure_keeps_files_dirty() {
+        let batcher = FlushBatcher::new(100);
+        batcher.mark_dirty("volume/a");
+        batcher.mark_dirty("volume/a");
+        batcher.mark_dirty("volume/b");
+        assert_eq!(batcher.flush_with(|| Err(libc::EIO)), Err(libc::EIO));
+        assert_eq!(batcher.dirty_count(), 2);
+        assert_eq!(batcher.batched_flushes(), 0);
+
+        assert_eq!(batcher.flush_with(|| Ok(())), Ok(2));
+        assert_eq!(batcher.flush_with(|| Ok(())), Ok(0));
+        assert_eq!(batcher.batched_flushes(), 1);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let batcher = FlushBatcher::default();
+        batcher.mark_dirty("volume/a");
+        assert_eq!(batcher.dirty_count(), 0);
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// counters and latency histograms of the requests this server handles, scraped by
+// prometheus from /metrics. dashboards depend on the names and labels, keep them
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use log::{error, info};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::common::serialization::OperationType;
+
+// upper bounds of the latency buckets in seconds, +Inf follows the last
+const LATENCY_BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+// a scrape is a request line and a few headers
+const MAX_REQUEST_SIZE: usize = 8192;
+
+#[derive(Default)]
+struct OpMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    // requests in each bucket alone, the last one for those slower than every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_ns: AtomicU64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    ops: DashMap<u32, OpMetrics>,
+    written_bytes: AtomicU64,
+    deleted_bytes: AtomicU64,
+}
+
+impl Metrics {
+    // failed is any answer with a non-zero status
+    pub fn record(&self, r#type: OperationType, latency: Duration, failed: bool) {
+        let op = self.ops.entry(r#type.into()).or_default();
+        op.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            op.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        op.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        op.latency_ns
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_written_bytes(&self, bytes: u64) {
+        self.written_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_deleted_bytes(&self, bytes: u64) {
+        self.deleted_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // the prometheus text format, operations in name order
+    pub fn render(&self) -> String {
+        let mut ops: Vec<(&'static str, u64, u64, Vec<u64>, u64)> = self
+            .ops
+            .iter()
+            .filter_map(|kv| {
+                let name = OperationType::try_from(*kv.key()).ok()?.as_str();
+                let buckets = kv
+                    .buckets
+                    .iter()
+                    .map(|bucket| bucket.load(Ordering::Relaxed))
+                    .collect();
+                Some((
+                    name,
+                    kv.requests.load(Ordering::Relaxed),
+                    kv.errors.load(Ordering::Relaxed),
+                    buckets,
+                    kv.latency_ns.load(Ordering::Relaxed),
+                ))
+            })
+            .collect();
+        ops.sort_by_key(|op| op.0);
+
+        let mut text = String::new();
+        text.push_str("# HELP sealfs_requests_total Requests handled, by operation.\n");
+        text.push_str("# TYPE sealfs_requests_total counter\n");
+        for (name, requests, ..) in &ops {
+            let _ = writeln!(
+                text,
+                "sealfs_requests_total{{op=\"{}\"}} {}",
+                name, requests
+            );
+        }
+        text.push_str(
+            "# HELP sealfs_request_errors_total Requests answered with an error, by operation.\n",
+        );
+        text.push_str("# TYPE sealfs_request_errors_total counter\n");
+        for (name, _, errors, ..) in &ops {
+            let _ = writeln!(
+                text,
+                "sealfs_request_errors_total{{op=\"{}\"}} {}",
+                name, errors
+            );
+        }
+        text.push_str(
+            "# HELP sealfs_request_duration_seconds Time spent handling requests, by operation.\n",
+        );
+        text.push_str("# TYPE sealfs_request_duration_seconds histogram\n");
+        for (name, _, _, buckets, latency_ns) in &ops {
+            let mut count = 0;
+            for (bound, requests) in LATENCY_BUCKETS.iter().zip(buckets) {
+                count += requests;
+                let _ = writeln!(
+                    text,
+                    "sealfs_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    name, bound, count
+                );
+            }
+            count += buckets[LATENCY_BUCKETS.len()];
+            let _ = writeln!(
+                text,
+                "sealfs_request_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}",
+                name, count
+            );
+            let _ = writeln!(
+                text,
+                "sealfs_request_duration_seconds_sum{{op=\"{}\"}} {}",
+                name,
+                *latency_ns as f64 / 1e9
+            );
+            let _ = writeln!(
+                text,
+                "sealfs_request_duration_seconds_count{{op=\"{}\"}} {}",
+                name, count
+            );
+        }
+        text.push_str("# HELP sealfs_written_bytes_total Bytes written by WriteFile.\n");
+        text.push_str("# TYPE sealfs_written_bytes_total counter\n");
+        let _ = writeln!(
+            text,
+            "sealfs_written_bytes_total {}",
+            self.written_bytes.load(Ordering::Relaxed)
+        );
+        text.push_str("# HELP sealfs_deleted_bytes_total Bytes of the files DeleteFile removed.\n");
+        text.push_str("# TYPE sealfs_deleted_bytes_total counter\n");
+        let _ = writeln!(
+            text,
+            "sealfs_deleted_bytes_total {}",
+            self.deleted_bytes.load(Ordering::Relaxed)
+        );
+        text
+    }
+}
+
+// answers GET /metrics on address, each connection gets one answer and is closed
+pub async fn serve(metrics: Arc<Metrics>, address: String) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&address).await?;
+    info!("metrics on http://{}/metrics", address);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(&metrics, stream).await {
+                error!("metrics request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn answer(metrics: &Metrics, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "").await;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next();
+    let path = request_line
+        .next()
+        .map(|target| target.split('?').next().unwrap());
+    match (method, path) {
+        (Some("GET"), Some("/metrics")) => respond(&mut stream, "200 OK", &metrics.render()).await,
+        (Some("GET"), _) => respond(&mut stream, "404 Not Found", "").await,
+        _ => respond(&mut stream, "405 Method Not Allowed", "").await,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Metrics;
+    use crate::common::serialization::OperationType;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record(OperationType::WriteFile, Duration::from_micros(50), false);
+        metrics.record(OperationType::WriteFile, Duration::from_millis(3), true);
+        metrics.record(OperationType::WriteFile, Duration::from_secs(10), false);
+        metrics.record(OperationType::CreateFile, Duration::from_millis(20), false);
+        metrics.add_written_bytes(100);
+
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "sealfs_requests_total{op=\"WriteFile\"} 3",
+            "sealfs_request_errors_total{op=\"WriteFile\"} 1",
+            "sealfs_request_errors_total{op=\"CreateFile\"} 0",
+            "sealfs_request_duration_seconds_bucket{op=\"WriteFile\",le=\"0.0001\"} 1",
+            "sealfs_request_duration_seconds_bucket{op=\"WriteFile\",le=\"0.005\"} 2",
+            "sealfs_request_duration_seconds_bucket{op=\"WriteFile\",le=\"5\"} 2",
+            "sealfs_request_duration_seconds_bucket{op=\"WriteFile\",le=\"+Inf\"} 3",
+            "sealfs_request_duration_seconds_count{op=\"WriteFile\"} 3",
+            "sealfs_request_duration_seconds_sum{op=\"CreateFile\"} 0.02",
+            "sealfs_written_bytes_total 100",
+            "sealfs_deleted_bytes_total 0",
+        ] {
+            assert!(lines.contains(&line), "{} missing from\n{}", line, text);
+        }
+        // operations come in name order
+        let create = text.find("sealfs_requests_total{op=\"CreateFile\"}");
+        let write = text.find("sealfs_requests_total{op=\"WriteFile\"}");
+        assert!(create.unwrap() < write.unwrap());
+    }
+}
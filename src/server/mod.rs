@@ -2,32 +2,54 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+mod bandwidth;
 pub mod distributed_engine;
+mod flush;
+mod metrics;
+mod running_ops;
 pub mod storage_engine;
 mod transfer_manager;
+mod write_combine;
 use std::{
+    net::{IpAddr, SocketAddr},
     sync::{atomic::Ordering, Arc},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
-use log::{debug, error, info};
+use dashmap::DashMap;
+use log::{debug, error, info, warn};
 use storage_engine::StorageEngine;
 use tokio::time::sleep;
 
 use crate::{
     common::{
-        errors::status_to_string,
+        errors::{status_to_string, MANAGER_UNREACHABLE, MOVED, THROTTLED},
         hash_ring::HashRing,
         serialization::{
-            bytes_as_file_attr, ClusterStatus, CreateDirSendMetaData, CreateFileSendMetaData,
-            CreateVolumeSendMetaData, DeleteDirSendMetaData, DeleteFileSendMetaData,
-            DirectoryEntrySendMetaData, OpenFileSendMetaData, OperationType, ReadDirSendMetaData,
-            ServerStatus, TruncateFileSendMetaData,
+            bytes_as_file_attr, AppendFileRecvMetaData, AtomicIncrementSendMetaData, BatchOpResult,
+            BatchRecvData, BatchSendData, ChargeVolumeSendMetaData, ClusterStatus,
+            CopyFileRangeSendMetaData, CreateAndWriteSendMetaData, CreateDirSendMetaData,
+            CreateFileSendMetaData, CreateSymlinkSendMetaData, CreateVolumeSendMetaData,
+            DeleteDirSendMetaData, DeleteFileSendMetaData, DirectoryEntrySendMetaData,
+            DirectoryRenameEntrySendMetaData, FallocateSendMetaData, GetParentRecvMetaData,
+            InitVolumeRecvMetaData, KillOpSendMetaData, LinkSendMetaData, ManagerOperationType,
+            MigrateVolumeSendMetaData, MovedRecvMetaData, OpenFileSendMetaData, OperationType,
+            ReadDirRecvMetaData, ReadDirSendMetaData, ReadFileVectoredRecvMetaData,
+            ReadFileVectoredSendMetaData, RebalanceVolumeSendMetaData, RenameNoParentSendMetaData,
+            RenameSendMetaData, ResizeVolumeSendMetaData, ServerConfig, ServerStatus, ServerType,
+            SetAttrSendMetaData, SetTransferRateSendMetaData, StatAndChecksumRecvData,
+            StatAndChecksumSendMetaData, TransferProgress, TruncateFileSendMetaData,
+            VerifyDirCountSendMetaData, WriteFileChunkSendMetaData, MAX_BATCH_OPS, MOVED_ALLOWED,
+            MOVED_DATA,
         },
-        serialization::{ReadFileSendMetaData, WriteFileSendMetaData},
+        serialization::{ReadFileRecvMetaData, ReadFileSendMetaData, WriteFileSendMetaData},
+    },
+    manager::manager_service::{ReRegisterRequest, SendHeartRequest},
+    rpc::{
+        server::{Handler, ProtocolError, RpcServer},
+        tls::TlsOptions,
     },
-    rpc::server::{Handler, RpcServer},
     server::storage_engine::meta_engine::MetaEngine,
 };
 use distributed_engine::DistributedEngine;
@@ -39,23 +61,88 @@ pub enum ServerError {
     ParseHeaderError,
 }
 
+// polls the manager as often as it allows, once a second while it can not be reached
 pub async fn sync_cluster_infos(engine: Arc<DistributedEngine<FileEngine>>) {
     loop {
+        let mut interval = Duration::from_secs(1);
         {
-            let result = engine.get_cluster_status().await;
+            let result = engine.get_cluster_status_info().await;
             match result {
-                Ok(status) => {
-                    let status: i32 = status.into();
+                Ok(info) => {
+                    interval = info.poll_interval();
+                    let status: i32 = info.status.into();
                     if engine.cluster_status.load(Ordering::Relaxed) != status {
                         engine.cluster_status.store(status, Ordering::Relaxed);
                     }
                 }
+                // counted by the engine, enough of them make the server degraded
                 Err(e) => {
-                    panic!("sync server infos failed, error = {}", e);
+                    error!("sync server infos failed, error = {}", status_to_string(e));
                 }
             }
         }
-        sleep(Duration::from_secs(1)).await;
+        sleep(interval).await;
+    }
+}
+
+// keeps the manager informed that this server is alive and how far its transfer has got
+fn heart_request(engine: &DistributedEngine<FileEngine>) -> SendHeartRequest {
+    SendHeartRequest {
+        address: engine.address.clone(),
+        flags: 0,
+        lifetime: "".to_owned(),
+        transfer_progress: TransferProgress {
+            bytes_per_sec: engine.transfer_throttle.rate(),
+            ..engine.transfer_manager.progress()
+        },
+        per_volume: engine.meta_engine.volume_usage(),
+    }
+}
+
+fn reregister_request(engine: &DistributedEngine<FileEngine>) -> ReRegisterRequest {
+    ReRegisterRequest {
+        heart: heart_request(engine),
+        server_type: ServerType::Running,
+        server_status: ServerStatus::try_from(engine.server_status.load(Ordering::Acquire))
+            .unwrap(),
+        volumes: engine.meta_engine.volume_names(),
+    }
+}
+
+pub async fn send_heartbeat(engine: Arc<DistributedEngine<FileEngine>>) {
+    // once the manager is reachable again after failed heartbeats it may have dropped
+    // this server, so the first one through is a full re-registration
+    let mut reachable = true;
+    loop {
+        let manager_address = engine.manager_address.lock().await.clone();
+        let result = if reachable {
+            engine
+                .sender
+                .send_heart(&manager_address, &heart_request(&engine))
+                .await
+        } else {
+            engine
+                .sender
+                .reregister(&manager_address, &reregister_request(&engine))
+                .await
+        };
+        match engine.track_manager(result) {
+            Ok(()) => reachable = true,
+            Err(e) => {
+                error!("send heartbeat failed, error = {}", status_to_string(e));
+                reachable = false;
+            }
+        }
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+pub async fn flush_dirty_files(engine: Arc<DistributedEngine<FileEngine>>) {
+    while let Some(interval) = engine.flush_batcher.interval() {
+        sleep(interval).await;
+        if let Err(e) = engine.flush_dirty_files() {
+            error!("flush dirty files failed, error = {}", status_to_string(e));
+        }
     }
 }
 
@@ -69,12 +156,16 @@ pub async fn watch_status(engine: Arc<DistributedEngine<FileEngine>>) {
         {
             ClusterStatus::SyncNewHashRing => {
                 info!("Transfer: start to sync new hash ring");
-                let all_servers_address = match engine.get_new_hash_ring_info().await {
+                let new_hash_ring = match engine.get_new_hash_ring_info().await {
                     Ok(value) => value,
                     Err(e) => {
                         panic!("Get Hash Ring Info Failed. Error = {}", e);
                     }
                 };
+                engine
+                    .new_ring_epoch
+                    .store(new_hash_ring.epoch, Ordering::Release);
+                let all_servers_address = new_hash_ring.hash_ring_info;
                 info!("Transfer: get new hash ring info");
                 for value in all_servers_address.iter() {
                     if engine.address == value.0
@@ -146,6 +237,9 @@ pub async fn watch_status(engine: Arc<DistributedEngine<FileEngine>>) {
                 if let Err(e) = engine.transfer_files(file_map).await {
                     panic!("transfer files failed, error = {}", e);
                 }
+                if let Err(e) = engine.transfer_shard_entries(None).await {
+                    panic!("transfer shard entries failed, error = {}", e);
+                }
 
                 info!("Transfer: transfer files finished");
                 match engine.update_server_status(ServerStatus::PreFinish).await {
@@ -175,6 +269,10 @@ pub async fn watch_status(engine: Arc<DistributedEngine<FileEngine>>) {
                     .hash_ring
                     .write()
                     .replace(engine.new_hash_ring.read().clone().unwrap()); // TODO: _old_hash_ring should be used to rollback the transfer process
+                engine.ring_epoch.store(
+                    engine.new_ring_epoch.load(Ordering::Acquire),
+                    Ordering::Release,
+                );
 
                 info!("Transfer: start to finishing");
                 match engine.update_server_status(ServerStatus::Finishing).await {
@@ -245,15 +343,64 @@ pub async fn watch_status(engine: Arc<DistributedEngine<FileEngine>>) {
     }
 }
 
-pub async fn run(
-    database_path: String,
-    storage_path: String,
-    server_address: String,
-    manager_address: String,
-    #[cfg(feature = "disk-db")] cache_capacity: usize,
-    #[cfg(feature = "disk-db")] write_buffer_size: usize,
-) -> anyhow::Result<()> {
+// what a server is started with, the flags of the server binary
+pub struct ServerOptions {
+    pub database_path: String,
+    pub storage_path: String,
+    pub server_address: String,
+    pub manager_address: String,
+    #[cfg(feature = "disk-db")]
+    pub cache_capacity: usize,
+    #[cfg(feature = "disk-db")]
+    pub write_buffer_size: usize,
+    pub client_bandwidth_limit: u64,
+    pub transfer_rate_limit: u64,
+    pub max_write_size: u32,
+    pub flush_interval_ms: u64,
+    pub max_in_flight: usize,
+    pub dir_shard_threshold: u32,
+    pub heartbeat: bool,
+    pub write_combine_size: usize,
+    pub thorough_check: bool,
+    pub manager_failure_limit: u32,
+    pub inline_threshold: usize,
+    pub slow_op_threshold_ms: u64,
+    pub metrics_address: Option<String>,
+    pub tls: TlsOptions,
+    // besides the manager, the hosts allowed to send the admin operations
+    pub admin_hosts: Vec<IpAddr>,
+}
+
+pub async fn run(options: ServerOptions) -> anyhow::Result<()> {
     debug!("run server");
+    let ServerOptions {
+        database_path,
+        storage_path,
+        server_address,
+        manager_address,
+        #[cfg(feature = "disk-db")]
+        cache_capacity,
+        #[cfg(feature = "disk-db")]
+        write_buffer_size,
+        client_bandwidth_limit,
+        transfer_rate_limit,
+        max_write_size,
+        flush_interval_ms,
+        max_in_flight,
+        dir_shard_threshold,
+        heartbeat,
+        write_combine_size,
+        thorough_check,
+        manager_failure_limit,
+        inline_threshold,
+        slow_op_threshold_ms,
+        metrics_address,
+        tls,
+        admin_hosts,
+    } = options;
+    // the listener and the connections to the manager and the other servers
+    let acceptor = tls.acceptor().map_err(anyhow::Error::msg)?;
+    let connector = tls.connector().map_err(anyhow::Error::msg)?;
     let meta_engine = Arc::new(MetaEngine::new(
         &database_path,
         #[cfg(feature = "disk-db")]
@@ -262,34 +409,97 @@ pub async fn run(
         write_buffer_size,
     ));
     let storage_engine = Arc::new(FileEngine::new(&storage_path, Arc::clone(&meta_engine)));
+    storage_engine.set_inline_threshold(inline_threshold);
     storage_engine.init();
+    storage_engine.check_consistency(thorough_check);
 
     let engine = Arc::new(DistributedEngine::new(
         server_address.clone(),
         storage_engine,
         meta_engine,
     ));
+    engine.client_bandwidth.set_limit(client_bandwidth_limit);
+    engine.transfer_throttle.set_limit(transfer_rate_limit);
+    engine
+        .max_write_size
+        .store(max_write_size, Ordering::Relaxed);
+    engine
+        .dir_shard_threshold
+        .store(dir_shard_threshold, Ordering::Relaxed);
+    engine
+        .manager_failure_limit
+        .store(manager_failure_limit, Ordering::Relaxed);
+    engine
+        .running_ops
+        .set_slow_op_threshold(slow_op_threshold_ms);
+    *engine.config.write() = ServerConfig {
+        manager_address: manager_address.clone(),
+        database_path,
+        storage_path,
+        max_in_flight: max_in_flight as u64,
+        heartbeat,
+        thorough_check,
+        inline_threshold: inline_threshold as u64,
+        tls: tls.is_enabled(),
+        metrics_address: metrics_address.clone(),
+        ..Default::default()
+    };
+    engine.client.set_tls(connector);
 
     info!("Init: Connect To Manager: {}", manager_address);
     if let Err(e) = engine.client.add_connection(&manager_address).await {
         panic!("Connect To Manager Failed, Error = {}", e);
     }
+    for host in admin_hosts {
+        engine.admin_hosts.insert(host);
+    }
+    match tokio::net::lookup_host(&manager_address).await {
+        Ok(addresses) => addresses.for_each(|address| {
+            engine.admin_hosts.insert(address.ip());
+        }),
+        Err(e) => warn!("Resolve Manager Address Failed, Error = {}", e),
+    }
     *engine.manager_address.lock().await = manager_address;
 
+    engine.flush_batcher.set_interval(flush_interval_ms);
+    // combined writes reach the store no later than the batched sync that would make
+    // them durable, without batching every write goes to the store at once
+    if flush_interval_ms > 0 {
+        engine.write_combiner.set_capacity(write_combine_size);
+    } else if write_combine_size > 0 {
+        warn!("write combining needs a flush interval, disabled");
+    }
+
     tokio::spawn(sync_cluster_infos(Arc::clone(&engine)));
+    tokio::spawn(flush_dirty_files(Arc::clone(&engine)));
+    if heartbeat {
+        tokio::spawn(send_heartbeat(Arc::clone(&engine)));
+    }
+    if let Some(address) = metrics_address {
+        let metrics = engine.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics, address).await {
+                error!("metrics stopped, error = {}", e);
+            }
+        });
+    }
 
     let handler = Arc::new(FileRequestHandler::new(engine.clone()));
-    let server = RpcServer::new(handler, &server_address);
+    let mut server = RpcServer::new(handler, &server_address);
+    server.set_max_in_flight(max_in_flight);
+    server.set_tls(acceptor);
 
     info!("Init: Add connections and update Server Status");
 
     tokio::spawn(async move {
-        let all_servers_address = match engine.get_hash_ring_info().await {
+        let hash_ring = match engine.get_hash_ring_info().await {
             Ok(value) => value,
             Err(_) => {
                 panic!("Get Hash Ring Info Failed.");
             }
         };
+        engine.ring_epoch.store(hash_ring.epoch, Ordering::Release);
+        let all_servers_address = hash_ring.hash_ring_info;
         info!("Init: Hash Ring Info: {:?}", all_servers_address);
         for value in all_servers_address.iter() {
             if server_address == value.0 {
@@ -321,8 +531,19 @@ pub async fn run(
     Ok(())
 }
 
+const ADMIN_OPERATIONS: [ManagerOperationType; 6] = [
+    ManagerOperationType::ListRunningOps,
+    ManagerOperationType::GetClientBandwidth,
+    ManagerOperationType::KillOp,
+    ManagerOperationType::RebalanceVolume,
+    ManagerOperationType::SetTransferRate,
+    ManagerOperationType::MigrateVolume,
+];
+
 pub struct FileRequestHandler<S: StorageEngine + std::marker::Send + std::marker::Sync + 'static> {
     engine: Arc<DistributedEngine<S>>,
+    // the host each open tcp connection came from
+    peers: DashMap<u32, IpAddr>,
 }
 
 impl<S: StorageEngine> FileRequestHandler<S>
@@ -330,7 +551,10 @@ where
     S: StorageEngine + std::marker::Send + std::marker::Sync + 'static,
 {
     pub fn new(engine: Arc<DistributedEngine<S>>) -> Self {
-        Self { engine }
+        Self {
+            engine,
+            peers: DashMap::new(),
+        }
     }
 }
 
@@ -353,20 +577,242 @@ where
         path: Vec<u8>,
         data: Vec<u8>,
         metadata: Vec<u8>,
+    ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+        // probes ask every server directly, never forward them
+        if operation_type == u32::from(ManagerOperationType::HealthCheck) {
+            let health = bincode::serialize(&self.engine.health_status()).unwrap();
+            return Ok((0, 0, health.len(), 0, health, Vec::new()));
+        }
+
+        if operation_type == u32::from(ManagerOperationType::GetServerConfig) {
+            let config = bincode::serialize(&self.engine.server_config()).unwrap();
+            return Ok((0, 0, config.len(), 0, config, Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::GetCapabilities) {
+            let capabilities = bincode::serialize(&self.engine.capabilities()).unwrap();
+            return Ok((0, 0, capabilities.len(), 0, capabilities, Vec::new()));
+        }
+        // the operations that manage the server itself are only taken from the manager
+        // and the configured admin hosts
+        if ADMIN_OPERATIONS
+            .iter()
+            .any(|op| operation_type == u32::from(*op))
+            && !self.is_admin(id)
+        {
+            warn!(
+                "{} refused admin op {} from connection {}",
+                self.engine.address, operation_type, id
+            );
+            return Ok((libc::EPERM, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::ListRunningOps) {
+            let ops = bincode::serialize(&self.engine.running_ops.list()).unwrap();
+            return Ok((0, 0, ops.len(), 0, ops, Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::GetClientBandwidth) {
+            let clients = bincode::serialize(&self.engine.client_bandwidth.client_infos()).unwrap();
+            return Ok((0, 0, clients.len(), 0, clients, Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::KillOp) {
+            let md: KillOpSendMetaData = bincode::deserialize(&metadata).unwrap();
+            info!("{} Kill Op: {}", self.engine.address, md.request_id);
+            let status = match self.engine.running_ops.kill(md.request_id) {
+                Ok(()) => 0,
+                Err(e) => e,
+            };
+            return Ok((status, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::RebalanceVolume) {
+            let md: RebalanceVolumeSendMetaData = bincode::deserialize(&metadata).unwrap();
+            info!("{} Rebalance Volume: {}", self.engine.address, md.volume);
+            let status = match self.engine.rebalance_volume(&md.volume).await {
+                Ok(()) => 0,
+                Err(e) => e,
+            };
+            return Ok((status, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::SetTransferRate) {
+            let md: SetTransferRateSendMetaData = bincode::deserialize(&metadata).unwrap();
+            info!(
+                "{} Set Transfer Rate: {} bytes/s",
+                self.engine.address, md.bytes_per_sec
+            );
+            self.engine.transfer_throttle.set_limit(md.bytes_per_sec);
+            return Ok((0, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+        if operation_type == u32::from(ManagerOperationType::MigrateVolume) {
+            let md: MigrateVolumeSendMetaData = bincode::deserialize(&metadata).unwrap();
+            info!(
+                "{} Migrate Volume: {} to {}",
+                self.engine.address, md.volume, md.target_server
+            );
+            let status = match self
+                .engine
+                .migrate_volume(&md.volume, &md.target_server)
+                .await
+            {
+                Ok(()) => 0,
+                Err(e) => e,
+            };
+            return Ok((status, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+
+        let op = self
+            .engine
+            .running_ops
+            .start(operation_type, &String::from_utf8_lossy(&path), id);
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = self.handle_request(id, operation_type, flags, path, data, metadata) => result,
+            _ = op.cancelled() => {
+                info!("{} Op Killed: {}", self.engine.address, op.request_id());
+                Ok((libc::ECANCELED, 0, 0, 0, Vec::new(), Vec::new()))
+            }
+        };
+        if let Ok(r#type) = OperationType::try_from(operation_type) {
+            self.engine
+                .metrics
+                .record(r#type, started.elapsed(), !matches!(result, Ok((0, ..))));
+        }
+        result
+    }
+
+    fn connection_opened(&self, id: u32, peer: Option<SocketAddr>) {
+        if let Some(peer) = peer {
+            self.peers.insert(id, peer.ip());
+        }
+    }
+
+    fn connection_closed(&self, id: u32) {
+        self.peers.remove(&id);
+        self.engine.client_bandwidth.remove_client(id);
+    }
+
+    fn is_server_peer(&self, id: u32) -> bool {
+        self.peers
+            .get(&id)
+            .is_some_and(|peer| self.engine.is_server_host(*peer))
+    }
+}
+
+impl<S: StorageEngine> FileRequestHandler<S>
+where
+    S: StorageEngine + std::marker::Send + std::marker::Sync + 'static,
+{
+    fn is_admin(&self, id: u32) -> bool {
+        self.peers
+            .get(&id)
+            .is_some_and(|peer| self.engine.admin_hosts.contains(&*peer))
+    }
+
+    // requests from other servers are neither throttled nor counted for a client
+    fn acquire_bandwidth(&self, id: u32, bytes: u64) -> Result<(), Duration> {
+        match self.is_server_peer(id) {
+            false => self.engine.client_bandwidth.acquire(id, bytes),
+            true => Ok(()),
+        }
+    }
+
+    fn record_read(&self, id: u32, bytes: u64) {
+        if !self.is_server_peer(id) {
+            self.engine.client_bandwidth.record_read(id, bytes);
+        }
+    }
+
+    fn record_write(&self, id: u32, bytes: u64) {
+        if !self.is_server_peer(id) {
+            self.engine.client_bandwidth.record_write(id, bytes);
+        }
+    }
+
+    // the data of the file was moved to owner, send the client there or read it
+    // from there for it
+    async fn read_from_owner(
+        &self,
+        owner: String,
+        operation_type: u32,
+        flags: u32,
+        file_path: &str,
+        data: Vec<u8>,
+        metadata: Vec<u8>,
+    ) -> (i32, u32, usize, usize, Vec<u8>, Vec<u8>) {
+        if flags & MOVED_ALLOWED != 0 {
+            let moved = bincode::serialize(&MovedRecvMetaData {
+                new_server: owner,
+                epoch: self.engine.ring_epoch.load(Ordering::Acquire),
+            })
+            .unwrap();
+            return (MOVED, 0, moved.len(), 0, moved, Vec::new());
+        }
+        match self
+            .engine
+            .forward_request(owner, operation_type, flags, file_path, data, metadata)
+            .await
+        {
+            Ok(value) => value,
+            Err(e) => (e, 0, 0, 0, Vec::new(), Vec::new()),
+        }
+    }
+
+    async fn handle_request(
+        &self,
+        id: u32,
+        operation_type: u32,
+        flags: u32,
+        path: Vec<u8>,
+        data: Vec<u8>,
+        metadata: Vec<u8>,
     ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
         let r#type = match OperationType::try_from(operation_type) {
             Ok(value) => value,
-            Err(e) => {
-                error!("Operation Type Error: {:?}", e);
-                return Ok((libc::EINVAL, 0, 0, 0, vec![], vec![]));
+            Err(_) => {
+                return Err(
+                    ProtocolError(format!("unknown operation type {}", operation_type)).into(),
+                );
             }
         };
 
         let file_path = unsafe { std::str::from_utf8_unchecked(&path) };
 
+        // shard requests name the server holding the shard and a batch runs where it
+        // is sent, its operations are forwarded one by one. never forward them
+        let addressed = matches!(
+            r#type,
+            OperationType::DirectoryAddShardEntry
+                | OperationType::DirectoryDeleteShardEntry
+                | OperationType::ReadDirShard
+                | OperationType::Batch
+        );
+
+        if flags & MOVED_ALLOWED != 0 && !addressed {
+            if let Some(moved) = self.engine.moved_to(file_path) {
+                let moved = bincode::serialize(&moved).unwrap();
+                return Ok((MOVED, 0, moved.len(), 0, moved, Vec::new()));
+            }
+        }
+
         // this lock is deprecated, and always return false
+        let forward_address = if addressed {
+            (None, false)
+        } else {
+            self.engine.get_forward_address(file_path)
+        };
+
+        if self.engine.is_degraded()
+            && !self
+                .engine
+                .serves_degraded(&r#type, forward_address.0.is_some())
+        {
+            info!(
+                "{} Degraded, rejected: path: {}, operation_type: {}",
+                self.engine.address,
+                file_path,
+                r#type.as_str()
+            );
+            return Ok((MANAGER_UNREACHABLE, 0, 0, 0, Vec::new(), Vec::new()));
+        }
         let _lock =
-            match self.engine.get_forward_address(file_path) {
+            match forward_address {
                 (Some(address), _) => {
                     match self
                         .engine
@@ -379,7 +825,7 @@ where
                         Err(e) => {
                             info!(
                             "Forward Request Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            status_to_string(e), file_path, operation_type, flags
+                            status_to_string(e), file_path, r#type.as_str(), flags
                         );
                             return Ok((e, 0, 0, 0, Vec::new(), Vec::new()));
                         }
@@ -388,11 +834,23 @@ where
                 (None, lock) => lock,
             };
 
+        if let Err(e) = self
+            .engine
+            .check_allowed_op(file_path, operation_type)
+            .await
+        {
+            info!(
+                "{} Not allowed on the volume: path: {}, operation_type: {}",
+                self.engine.address,
+                file_path,
+                r#type.as_str()
+            );
+            return Ok((e, 0, 0, 0, Vec::new(), Vec::new()));
+        }
+
         match r#type {
-            OperationType::Unkown => {
-                error!("Unkown Operation Type: path: {}", file_path);
-                Ok((-1, 0, 0, 0, Vec::new(), Vec::new()))
-            }
+            // try_from(0) is an error, the request was refused before it got here
+            OperationType::Unkown => unreachable!("Unkown operation type dispatched"),
             OperationType::Lookup => {
                 error!("{} Lookup not implemented", self.engine.address);
                 Ok((-1, 0, 0, 0, Vec::new(), Vec::new()))
@@ -401,25 +859,76 @@ where
                 info!("{} Create File: path: {}", self.engine.address, file_path);
                 let meta_data_unwraped: CreateFileSendMetaData =
                     bincode::deserialize(&metadata).unwrap();
+                let result = if meta_data_unwraped.tmpfile {
+                    self.engine
+                        .create_tmpfile(
+                            metadata,
+                            file_path,
+                            &meta_data_unwraped.name,
+                            meta_data_unwraped.flags,
+                            meta_data_unwraped.umask,
+                            meta_data_unwraped.mode,
+                        )
+                        .await
+                } else {
+                    self.engine
+                        .create_file(
+                            metadata,
+                            file_path,
+                            &meta_data_unwraped.name,
+                            meta_data_unwraped.flags,
+                            meta_data_unwraped.umask,
+                            meta_data_unwraped.mode,
+                        )
+                        .await
+                };
+                let (return_meta_data, status) = match result {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Create File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::CreateAndWrite => {
+                info!(
+                    "{} Create And Write: path: {}",
+                    self.engine.address, file_path
+                );
+                let meta_data_unwraped: CreateAndWriteSendMetaData =
+                    bincode::deserialize(&metadata).unwrap();
                 let (return_meta_data, status) = match self
                     .engine
-                    .create_file(
-                        metadata,
+                    .create_and_write(
                         file_path,
                         &meta_data_unwraped.name,
-                        meta_data_unwraped.flags,
                         meta_data_unwraped.umask,
                         meta_data_unwraped.mode,
+                        &meta_data_unwraped.data,
                     )
                     .await
                 {
                     Ok(value) => (value, 0),
                     Err(e) => {
                         info!(
-                            "Create File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            "Create And Write Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         (Vec::new(), e)
@@ -452,7 +961,10 @@ where
                     Err(e) => {
                         info!(
                             "Create Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            e, file_path, operation_type, flags
+                            e,
+                            file_path,
+                            r#type.as_str(),
+                            flags
                         );
                         (Vec::new(), e)
                     }
@@ -474,7 +986,7 @@ where
                         Err(e) => {
                             info!(
                             "Get File Attr Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            status_to_string(e), file_path, operation_type, flags
+                            status_to_string(e), file_path, r#type.as_str(), flags
                         );
                             (Vec::new(), e)
                         }
@@ -503,7 +1015,7 @@ where
                             "Open File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -514,25 +1026,57 @@ where
             OperationType::ReadDir => {
                 info!("{} Read Dir: {}", self.engine.address, file_path);
                 let md: ReadDirSendMetaData = bincode::deserialize(&metadata).unwrap();
-                let (data, status) = match self.engine.read_dir(file_path, md.size, md.offset).await
+                match self
+                    .engine
+                    .read_dir_page(file_path, md.size, md.offset, md.cursor.as_deref())
+                    .await
                 {
-                    Ok(value) => (value, 0),
+                    Ok((data, cursor)) => {
+                        let meta_data =
+                            bincode::serialize(&ReadDirRecvMetaData { cursor }).unwrap();
+                        Ok((0, 0, meta_data.len(), data.len(), meta_data, data))
+                    }
                     Err(e) => {
                         info!(
                             "Read Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
-                        (Vec::new(), e)
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
                     }
-                };
-                Ok((status, 0, 0, data.len(), Vec::new(), data))
+                }
             }
             OperationType::ReadFile => {
                 info!("{} Read File: {}", self.engine.address, file_path);
+                match self.engine.meta_engine.data_owner(file_path) {
+                    Ok(Some(owner)) => {
+                        return Ok(self
+                            .read_from_owner(
+                                owner,
+                                operation_type,
+                                flags,
+                                file_path,
+                                data,
+                                metadata,
+                            )
+                            .await);
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Ok((e, 0, 0, 0, Vec::new(), Vec::new())),
+                }
                 let md: ReadFileSendMetaData = bincode::deserialize(&metadata).unwrap();
+                if let Err(retry_after) = self.acquire_bandwidth(id, md.size as u64) {
+                    return Ok((
+                        THROTTLED,
+                        retry_after.as_millis() as u32,
+                        0,
+                        0,
+                        Vec::new(),
+                        Vec::new(),
+                    ));
+                }
                 let (data, status) =
                     match self.engine.read_file(file_path, md.size, md.offset).await {
                         Ok(value) => (value, 0),
@@ -541,40 +1085,236 @@ where
                                 "Read File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                                 status_to_string(e),
                                 file_path,
-                                operation_type,
+                                r#type.as_str(),
                                 flags
                             );
                             (Vec::new(), e)
                         }
                     };
+                self.record_read(id, data.len() as u64);
+                if md.verify_checksum && status == 0 {
+                    let checksum = bincode::serialize(&ReadFileRecvMetaData {
+                        checksum: crc32c::crc32c(&data),
+                    })
+                    .unwrap();
+                    return Ok((0, 0, checksum.len(), data.len(), checksum, data));
+                }
                 Ok((status, 0, 0, data.len(), Vec::new(), data))
             }
+            OperationType::ReadFileVectored => {
+                info!("{} Read File Vectored: {}", self.engine.address, file_path);
+                match self.engine.meta_engine.data_owner(file_path) {
+                    Ok(Some(owner)) => {
+                        return Ok(self
+                            .read_from_owner(
+                                owner,
+                                operation_type,
+                                flags,
+                                file_path,
+                                data,
+                                metadata,
+                            )
+                            .await);
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Ok((e, 0, 0, 0, Vec::new(), Vec::new())),
+                }
+                let md: ReadFileVectoredSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let size = md.ranges.iter().map(|(_, size)| *size as u64).sum();
+                if let Err(retry_after) = self.acquire_bandwidth(id, size) {
+                    return Ok((
+                        THROTTLED,
+                        retry_after.as_millis() as u32,
+                        0,
+                        0,
+                        Vec::new(),
+                        Vec::new(),
+                    ));
+                }
+                match self.engine.read_file_vectored(file_path, &md.ranges).await {
+                    Ok((data, lengths)) => {
+                        self.record_read(id, data.len() as u64);
+                        let meta_data =
+                            bincode::serialize(&ReadFileVectoredRecvMetaData { lengths }).unwrap();
+                        Ok((0, 0, meta_data.len(), data.len(), meta_data, data))
+                    }
+                    Err(e) => {
+                        info!(
+                            "Read File Vectored Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
             OperationType::WriteFile => {
                 info!("{} Write File: {}", self.engine.address, file_path);
                 let md: WriteFileSendMetaData = bincode::deserialize(&metadata).unwrap();
-                let (status, size) = match self
+                // the fuse client has the kernel send writes no larger than the size it
+                // was told at mount, a larger one is refused and not split
+                if data.len() > self.engine.max_write_size.load(Ordering::Relaxed) as usize {
+                    return Ok((libc::EINVAL, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                if md
+                    .checksum
+                    .is_some_and(|checksum| checksum != crc32c::crc32c(&data))
+                {
+                    error!(
+                        "{} Write File checksum mismatch: {}",
+                        self.engine.address, file_path
+                    );
+                    return Ok((libc::EIO, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                if let Err(retry_after) = self.acquire_bandwidth(id, data.len() as u64) {
+                    return Ok((
+                        THROTTLED,
+                        retry_after.as_millis() as u32,
+                        0,
+                        0,
+                        Vec::new(),
+                        Vec::new(),
+                    ));
+                }
+                // only another server moves data, written by a client it would skip the quota
+                if flags & MOVED_DATA != 0 && !self.is_server_peer(id) {
+                    warn!(
+                        "{} refused moved data from connection {}: {}",
+                        self.engine.address, id, file_path
+                    );
+                    return Ok((libc::EPERM, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                // data moved from another server was charged to the volume when first written.
+                // an append answers with the offset it picked as well
+                let result = match (flags & MOVED_DATA, md.append) {
+                    (0, true) => self
+                        .engine
+                        .append_file(file_path, data.as_slice())
+                        .await
+                        .map(|(size, offset)| (size, Some(offset))),
+                    (0, false) => self
+                        .engine
+                        .write_file(file_path, data.as_slice(), md.offset, md.if_mtime)
+                        .await
+                        .map(|size| (size, None)),
+                    _ => self
+                        .engine
+                        .write_moved_data(file_path, data.as_slice(), md.offset)
+                        .map(|size| (size, None)),
+                };
+                let (status, size, offset) = match result {
+                    Ok((size, offset)) => {
+                        self.record_write(id, size as u64);
+                        self.engine.metrics.add_written_bytes(size as u64);
+                        (0, size as u32, offset)
+                    }
+                    Err(e) => {
+                        info!(
+                            "Write File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (e, 0, None)
+                    }
+                };
+                let recv_meta_data = match offset {
+                    Some(offset) => {
+                        bincode::serialize(&AppendFileRecvMetaData { size, offset }).unwrap()
+                    }
+                    None => size.to_le_bytes().to_vec(),
+                };
+                Ok((
+                    status,
+                    0,
+                    recv_meta_data.len(),
+                    0,
+                    recv_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::CopyFileRange => {
+                let md: CopyFileRangeSendMetaData = bincode::deserialize(&metadata).unwrap();
+                info!(
+                    "{} Copy File Range: {} (ino {}) -> {} (ino {})",
+                    self.engine.address, md.src_path, md.src_ino, file_path, md.dst_ino
+                );
+                match self.engine.copy_file_range(file_path, &md).await {
+                    Ok(copied) => Ok((0, 0, 8, 0, copied.to_le_bytes().to_vec(), Vec::new())),
+                    Err(e) => {
+                        info!(
+                            "Copy File Range Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            OperationType::AtomicIncrement => {
+                info!("{} Atomic Increment: {}", self.engine.address, file_path);
+                let md: AtomicIncrementSendMetaData = bincode::deserialize(&metadata).unwrap();
+                match self.engine.atomic_increment(file_path, md.delta).await {
+                    Ok(value) => Ok((0, 0, 8, 0, value.to_le_bytes().to_vec(), Vec::new())),
+                    Err(e) => {
+                        info!(
+                            "Atomic Increment Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            OperationType::WriteFileChunk => {
+                info!("{} Write File Chunk: {}", self.engine.address, file_path);
+                let md: WriteFileChunkSendMetaData = bincode::deserialize(&metadata).unwrap();
+                if data.len() > self.engine.max_write_size.load(Ordering::Relaxed) as usize {
+                    return Ok((libc::EINVAL, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                if let Err(retry_after) = self.acquire_bandwidth(id, data.len() as u64) {
+                    return Ok((
+                        THROTTLED,
+                        retry_after.as_millis() as u32,
+                        0,
+                        0,
+                        Vec::new(),
+                        Vec::new(),
+                    ));
+                }
+                let (return_meta_data, status) = match self
                     .engine
-                    .write_file(file_path, data.as_slice(), md.offset)
+                    .write_file_chunk(file_path, md, data.as_slice())
                     .await
                 {
-                    Ok(size) => (0, size as u32),
+                    Ok(ack) => {
+                        self.record_write(id, ack.stored);
+                        (bincode::serialize(&ack).unwrap(), 0)
+                    }
                     Err(e) => {
                         info!(
-                            "Write File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            "Write File Chunk Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
-                        (e, 0)
+                        (Vec::new(), e)
                     }
                 };
                 Ok((
                     status,
                     0,
-                    size.to_le_bytes().len(),
+                    return_meta_data.len(),
                     0,
-                    size.to_le_bytes().to_vec(),
+                    return_meta_data,
                     Vec::new(),
                 ))
             }
@@ -593,7 +1333,7 @@ where
                             "Delete File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -616,7 +1356,7 @@ where
                             "Delete Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -655,22 +1395,258 @@ where
                     vec![],
                 ))
             }
-            OperationType::TruncateFile => {
-                info!("{} Truncate File: {}", self.engine.address, file_path);
-                let md: TruncateFileSendMetaData = bincode::deserialize(&metadata).unwrap();
-                let status =
-                    match self.engine.truncate_file(file_path, md.length).await {
-                        Ok(()) => 0,
-                        Err(e) => {
-                            info!(
-                            "Truncate File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            status_to_string(e), file_path, operation_type, flags
-                        );
-                            e
+            OperationType::DirectoryRenameEntry => {
+                info!(
+                    "{} Directory Rename Entry: {}",
+                    self.engine.address, file_path
+                );
+                let md: DirectoryRenameEntrySendMetaData = bincode::deserialize(&metadata).unwrap();
+                Ok((
+                    self.engine.directory_rename_entry(file_path, md).await,
+                    0,
+                    0,
+                    0,
+                    vec![],
+                    vec![],
+                ))
+            }
+            OperationType::DirectoryAddShardEntry => {
+                let md: DirectoryEntrySendMetaData = bincode::deserialize(&metadata).unwrap();
+                let status = match self.engine.meta_engine.shard_add_entry(
+                    file_path,
+                    &md.file_name,
+                    md.file_type,
+                ) {
+                    Ok(()) => 0,
+                    Err(e) => e,
+                };
+                Ok((status, 0, 0, 0, vec![], vec![]))
+            }
+            OperationType::DirectoryDeleteShardEntry => {
+                let md: DirectoryEntrySendMetaData = bincode::deserialize(&metadata).unwrap();
+                let status = match self.engine.meta_engine.shard_delete_entry(
+                    file_path,
+                    &md.file_name,
+                    md.file_type,
+                ) {
+                    Ok(()) => 0,
+                    Err(e) => e,
+                };
+                Ok((status, 0, 0, 0, vec![], vec![]))
+            }
+            OperationType::ReadDirShard => {
+                let md: ReadDirSendMetaData = bincode::deserialize(&metadata).unwrap();
+                match self
+                    .engine
+                    .meta_engine
+                    .read_directory_shard(file_path, md.size, md.offset)
+                {
+                    Ok((data, md)) => {
+                        let meta_data = bincode::serialize(&md).unwrap();
+                        Ok((0, 0, meta_data.len(), data.len(), meta_data, data))
+                    }
+                    Err(e) => Ok((e, 0, 0, 0, vec![], vec![])),
+                }
+            }
+            OperationType::TruncateFile => {
+                info!("{} Truncate File: {}", self.engine.address, file_path);
+                let md: TruncateFileSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let status =
+                    match self.engine.truncate_file(file_path, md.length).await {
+                        Ok(()) => 0,
+                        Err(e) => {
+                            info!(
+                            "Truncate File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e), file_path, r#type.as_str(), flags
+                        );
+                            e
                         }
                     };
                 Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
             }
+            OperationType::Batch => {
+                let md: BatchSendData = bincode::deserialize(&data).unwrap();
+                info!(
+                    "{} Batch: {}, {} ops",
+                    self.engine.address,
+                    file_path,
+                    md.ops.len()
+                );
+                if md.ops.len() > MAX_BATCH_OPS
+                    || !md.ops.iter().all(|op| {
+                        OperationType::try_from(op.operation_type)
+                            .is_ok_and(|r#type| r#type.is_batchable())
+                    })
+                {
+                    return Ok((libc::EINVAL, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                let mut completed = 0;
+                let mut results = Vec::with_capacity(md.ops.len());
+                // the operations are forwarded to where they belong. a MOVED answer
+                // would stop the batch with the operations before it already done
+                let flags = flags & !MOVED_ALLOWED;
+                for op in md.ops {
+                    let (status, _, meta_data_length, _, mut meta_data, _) =
+                        Box::pin(self.handle_request(
+                            id,
+                            op.operation_type,
+                            flags,
+                            op.path.into_bytes(),
+                            Vec::new(),
+                            op.meta_data,
+                        ))
+                        .await?;
+                    meta_data.truncate(meta_data_length);
+                    results.push(BatchOpResult { status, meta_data });
+                    if status != 0 {
+                        info!(
+                            "Batch Stopped: {:?}, path: {}, {} ops completed",
+                            status_to_string(status),
+                            file_path,
+                            completed
+                        );
+                        break;
+                    }
+                    completed += 1;
+                }
+                let data = bincode::serialize(&BatchRecvData { completed, results }).unwrap();
+                Ok((0, 0, 0, data.len(), Vec::new(), data))
+            }
+            OperationType::Fallocate => {
+                info!("{} Fallocate: {}", self.engine.address, file_path);
+                let md: FallocateSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let status = match self.engine.fallocate(file_path, &md).await {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        info!(
+                            "Fallocate Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        e
+                    }
+                };
+                Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
+            }
+            OperationType::SetAttr => {
+                info!("{} Set Attr: {}", self.engine.address, file_path);
+                let md: SetAttrSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (return_meta_data, status) = match self.engine.set_attr(file_path, md).await {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Set Attr Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::ChargeVolume => {
+                info!("{} Charge Volume: {}", self.engine.address, file_path);
+                // the servers holding files of the volume charge it, never a client
+                if !self.is_server_peer(id) {
+                    return Ok((libc::EPERM, 0, 0, 0, Vec::new(), Vec::new()));
+                }
+                let md: ChargeVolumeSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let status = match self.engine.meta_engine.charge_volume(file_path, md.delta) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        info!(
+                            "Charge Volume Failed: {:?}, volume: {}, delta: {}",
+                            status_to_string(e),
+                            file_path,
+                            md.delta
+                        );
+                        e
+                    }
+                };
+                Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
+            }
+            OperationType::GetParent => {
+                info!("{} Get Parent: {}", self.engine.address, file_path);
+                match self.engine.get_parent(file_path) {
+                    Ok((parent, name)) => {
+                        let return_meta_data =
+                            bincode::serialize(&GetParentRecvMetaData { parent, name }).unwrap();
+                        Ok((
+                            0,
+                            0,
+                            return_meta_data.len(),
+                            0,
+                            return_meta_data,
+                            Vec::new(),
+                        ))
+                    }
+                    Err(e) => {
+                        info!(
+                            "Get Parent Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            OperationType::VerifyDirCount => {
+                info!("{} Verify Dir Count: {}", self.engine.address, file_path);
+                let md: VerifyDirCountSendMetaData = bincode::deserialize(&metadata).unwrap();
+                match self.engine.verify_dir_count(file_path, md.repair).await {
+                    Ok(result) => {
+                        let return_meta_data = bincode::serialize(&result).unwrap();
+                        Ok((
+                            0,
+                            0,
+                            return_meta_data.len(),
+                            0,
+                            return_meta_data,
+                            Vec::new(),
+                        ))
+                    }
+                    Err(e) => {
+                        info!(
+                            "Verify Dir Count Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            OperationType::Fsync => {
+                info!("{} Fsync: {}", self.engine.address, file_path);
+                let status = match self.engine.fsync(file_path).await {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        info!(
+                            "Fsync Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        e
+                    }
+                };
+                Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
+            }
             OperationType::CheckFile => {
                 info!("{} Checkout File: {}", self.engine.address, file_path);
                 let file_attr = bytes_as_file_attr(&metadata);
@@ -680,7 +1656,7 @@ where
                         Err(e) => {
                             info!(
                             "Checkout File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            status_to_string(e), file_path, operation_type, flags
+                            status_to_string(e), file_path, r#type.as_str(), flags
                         );
                             e
                         }
@@ -690,13 +1666,17 @@ where
             OperationType::CheckDir => {
                 info!("{} Checkout Dir: {}", self.engine.address, file_path);
                 let file_attr = bytes_as_file_attr(&metadata);
+                let entries = metadata
+                    .get(std::mem::size_of::<fuser::FileAttr>()..)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u32::from_le_bytes);
                 let status =
-                    match self.engine.check_dir(file_path, file_attr).await {
+                    match self.engine.check_dir(file_path, file_attr, entries).await {
                         Ok(()) => 0,
                         Err(e) => {
                             info!(
                             "Checkout Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
-                            status_to_string(e), file_path, operation_type, flags
+                            status_to_string(e), file_path, r#type.as_str(), flags
                         );
                             e
                         }
@@ -720,7 +1700,7 @@ where
                             "Create Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         (Vec::new(), e)
@@ -742,19 +1722,123 @@ where
                 );
                 let meta_data_unwraped: CreateFileSendMetaData =
                     bincode::deserialize(&metadata).unwrap();
-                let (return_meta_data, status) = match self.engine.create_file_no_parent(
-                    file_path,
-                    meta_data_unwraped.flags,
-                    meta_data_unwraped.umask,
-                    meta_data_unwraped.mode,
-                ) {
+                // data comes with the create of CreateAndWrite
+                let result = if meta_data_unwraped.tmpfile {
+                    self.engine.create_tmpfile_no_parent(
+                        file_path,
+                        meta_data_unwraped.flags,
+                        meta_data_unwraped.umask,
+                        meta_data_unwraped.mode,
+                    )
+                } else if !data.is_empty() {
+                    self.engine
+                        .create_file_with_data_no_parent(
+                            file_path,
+                            meta_data_unwraped.flags,
+                            meta_data_unwraped.umask,
+                            meta_data_unwraped.mode,
+                            &data,
+                        )
+                        .await
+                } else {
+                    self.engine.create_file_no_parent(
+                        file_path,
+                        meta_data_unwraped.flags,
+                        meta_data_unwraped.umask,
+                        meta_data_unwraped.mode,
+                    )
+                };
+                let (return_meta_data, status) = match result {
                     Ok(value) => (value, 0),
                     Err(e) => {
                         info!(
                             "Create File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::Link => {
+                info!("{} Link: {}", self.engine.address, file_path);
+                let md: LinkSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (return_meta_data, status) = match self.engine.link(file_path, &md.name).await {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Link Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::Rename => {
+                info!("{} Rename: {}", self.engine.address, file_path);
+                let md: RenameSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (return_meta_data, status) = match self
+                    .engine
+                    .rename(file_path, &md.new_parent, &md.new_name, md.flags)
+                    .await
+                {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Rename Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::CreateSymlink => {
+                info!("{} Create Symlink: {}", self.engine.address, file_path);
+                let md: CreateSymlinkSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (return_meta_data, status) = match self
+                    .engine
+                    .create_symlink(file_path, &md.name, &md.target)
+                    .await
+                {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Create Symlink Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
                             flags
                         );
                         (Vec::new(), e)
@@ -769,6 +1853,52 @@ where
                     Vec::new(),
                 ))
             }
+            OperationType::CreateSymlinkNoParent => {
+                info!(
+                    "{} Create Symlink no Parent: {}",
+                    self.engine.address, file_path
+                );
+                let md: CreateSymlinkSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (return_meta_data, status) =
+                    match self.engine.create_symlink_no_parent(file_path, &md.target) {
+                        Ok(value) => (value, 0),
+                        Err(e) => {
+                            info!(
+                            "Create Symlink Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                            (Vec::new(), e)
+                        }
+                    };
+                Ok((
+                    status,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ))
+            }
+            OperationType::ReadLink => {
+                debug!("{} Read Link: {}", self.engine.address, file_path);
+                let (data, status) = match self.engine.read_link(file_path) {
+                    Ok(value) => (value, 0),
+                    Err(e) => {
+                        info!(
+                            "Read Link Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((status, 0, 0, data.len(), Vec::new(), data))
+            }
             OperationType::DeleteDirNoParent => {
                 info!(
                     "{} Delete Dir no Parent: {}",
@@ -781,7 +1911,7 @@ where
                             "Delete Dir Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -801,7 +1931,7 @@ where
                             "Delete File Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             file_path,
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -809,29 +1939,74 @@ where
                 };
                 Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
             }
-            OperationType::CreateVolume => {
-                info!("{} Create Volume", self.engine.address);
-                let meta_data_unwraped: CreateVolumeSendMetaData =
-                    bincode::deserialize(&metadata).unwrap();
-                info!("Create Volume: {:?}, id: {}", file_path, id);
-                if file_path.is_empty()
-                    || file_path.len() > 255
-                    || file_path.contains('\0')
-                    || file_path.contains('/')
-                {
-                    return Ok((libc::EINVAL, 0, 0, 0, vec![], vec![]));
-                }
+            OperationType::RenameNoParent => {
+                info!("{} Rename no Parent: {}", self.engine.address, file_path);
+                let md: RenameNoParentSendMetaData = bincode::deserialize(&metadata).unwrap();
                 let status = match self
                     .engine
-                    .create_volume(file_path, meta_data_unwraped.size)
+                    .rename_no_parent(file_path, &md.new_path, md.attr)
                 {
                     Ok(()) => 0,
                     Err(e) => {
                         info!(
-                            "Create Volume Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            "Rename no Parent Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
-                            std::str::from_utf8(path.as_slice()).unwrap(),
-                            operation_type,
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        e
+                    }
+                };
+                Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
+            }
+            OperationType::StatAndChecksum => {
+                info!("{} Stat And Checksum: {}", self.engine.address, file_path);
+                let md: StatAndChecksumSendMetaData = bincode::deserialize(&metadata).unwrap();
+                let (data, status) = match self.engine.stat_and_checksum(md.paths).await {
+                    Ok(entries) => (
+                        bincode::serialize(&StatAndChecksumRecvData { entries }).unwrap(),
+                        0,
+                    ),
+                    Err(e) => {
+                        info!(
+                            "Stat And Checksum Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        (Vec::new(), e)
+                    }
+                };
+                Ok((status, 0, 0, data.len(), Vec::new(), data))
+            }
+            OperationType::CreateVolume => {
+                info!("{} Create Volume", self.engine.address);
+                let meta_data_unwraped: CreateVolumeSendMetaData =
+                    bincode::deserialize(&metadata).unwrap();
+                info!("Create Volume: {:?}, id: {}", file_path, id);
+                if file_path.is_empty()
+                    || file_path.len() > 255
+                    || file_path.contains('\0')
+                    || file_path.contains('/')
+                {
+                    return Ok((libc::EINVAL, 0, 0, 0, vec![], vec![]));
+                }
+                let status = match self.engine.create_volume(
+                    file_path,
+                    meta_data_unwraped.size,
+                    meta_data_unwraped.atime_policy,
+                    meta_data_unwraped.max_file_size,
+                    meta_data_unwraped.allowed_ops,
+                ) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        info!(
+                            "Create Volume Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            std::str::from_utf8(path.as_slice()).unwrap(),
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -858,7 +2033,18 @@ where
                     return Ok((libc::ENOENT, 0, 0, 0, vec![], vec![]));
                 }
                 //self.engine.volume_indexes.insert(id, file_path);
-                return Ok((0, 0, 0, 0, Vec::new(), Vec::new()));
+                let return_meta_data = bincode::serialize(&InitVolumeRecvMetaData {
+                    max_write_size: self.engine.max_write_size.load(Ordering::Relaxed),
+                })
+                .unwrap();
+                return Ok((
+                    0,
+                    0,
+                    return_meta_data.len(),
+                    0,
+                    return_meta_data,
+                    Vec::new(),
+                ));
             }
             OperationType::ListVolumes => {
                 info!("{} List Volume", self.engine.address);
@@ -872,6 +2058,62 @@ where
                     Vec::new(),
                 ));
             }
+            OperationType::StatFs => {
+                info!("{} StatFs: {}", self.engine.address, file_path);
+                match self.engine.stat_fs(file_path) {
+                    Ok(stat) => {
+                        let return_meta_data = bincode::serialize(&stat).unwrap();
+                        Ok((
+                            0,
+                            0,
+                            return_meta_data.len(),
+                            0,
+                            return_meta_data,
+                            Vec::new(),
+                        ))
+                    }
+                    Err(e) => {
+                        info!(
+                            "StatFs Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        Ok((e, 0, 0, 0, Vec::new(), Vec::new()))
+                    }
+                }
+            }
+            OperationType::ResizeVolume => {
+                info!("{} Resize Volume", self.engine.address);
+                let md: ResizeVolumeSendMetaData = bincode::deserialize(&metadata).unwrap();
+                info!(
+                    "Resize Volume: {:?} to {}, id: {}",
+                    file_path, md.new_size, id
+                );
+                // the request is routed by its path, which must name the volume too
+                if md.volume_name != file_path {
+                    return Ok((libc::EINVAL, 0, 0, 0, vec![], vec![]));
+                }
+                let status = match self
+                    .engine
+                    .meta_engine
+                    .resize_volume(&md.volume_name, md.new_size)
+                {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        info!(
+                            "Resize Volume Failed: {:?}, path: {}, operation_type: {}, flags: {}",
+                            status_to_string(e),
+                            file_path,
+                            r#type.as_str(),
+                            flags
+                        );
+                        e
+                    }
+                };
+                Ok((status, 0, 0, 0, Vec::new(), Vec::new()))
+            }
             OperationType::DeleteVolume => {
                 info!("{} Delete Volume", self.engine.address);
                 info!("Delete Volume: {:?}, id: {}", file_path, id);
@@ -889,7 +2131,7 @@ where
                             "Delete Volume Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             std::str::from_utf8(path.as_slice()).unwrap(),
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -914,7 +2156,7 @@ where
                             "Clean Volume Failed: {:?}, path: {}, operation_type: {}, flags: {}",
                             status_to_string(e),
                             std::str::from_utf8(path.as_slice()).unwrap(),
-                            operation_type,
+                            r#type.as_str(),
                             flags
                         );
                         e
@@ -925,3 +2167,4227 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::OsStr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use super::{DistributedEngine, FileRequestHandler};
+    use crate::client::{daemon::SealfsFused, fuse_client::Client};
+    use crate::common::dirent::DirentDecoder;
+    use crate::common::errors::{CONFLICT, MANAGER_UNREACHABLE, MOVED};
+    use crate::common::hash_ring::HashRing;
+    use crate::common::serialization::{
+        bytes_as_file_attr, AppendFileRecvMetaData, AtimePolicy, AtomicIncrementSendMetaData,
+        BatchOp, BatchRecvData, BatchSendData, Capabilities, ChargeVolumeSendMetaData,
+        ClientBandwidthInfo, ClusterStatus, CopyFileRangeSendMetaData, CreateAndWriteSendMetaData,
+        CreateDirSendMetaData, CreateFileSendMetaData, CreateSymlinkSendMetaData,
+        DeleteFileSendMetaData, FallocateSendMetaData, GetParentRecvMetaData, HealthStatus,
+        InitVolumeRecvMetaData, InvalidateClientCacheSendMetaData, KillOpSendMetaData,
+        LinkSendMetaData, ManagerOperationType, MigrateVolumeSendMetaData, MovedRecvMetaData,
+        OperationType, ReadDirSendMetaData, ReadFileRecvMetaData, ReadFileSendMetaData,
+        ReadFileVectoredRecvMetaData, ReadFileVectoredSendMetaData, RebalanceVolumeSendMetaData,
+        RenameSendMetaData, ResizeVolumeSendMetaData, RunningOpInfo, ServerConfig, ServerStatus,
+        SetAttrSendMetaData, StatAndChecksumRecvData, StatAndChecksumSendMetaData,
+        StatFsRecvMetaData, TruncateFileSendMetaData, VerifyDirCountRecvMetaData,
+        VerifyDirCountSendMetaData, WriteFileChunkRecvMetaData, WriteFileChunkSendMetaData,
+        WriteFileSendMetaData, DATA_REMOTE, MOVED_ALLOWED, MOVED_DATA, PROTOCOL_VERSION, UNLINKED,
+    };
+    use crate::rpc::{
+        client::{StreamCreator, TcpStreamCreator},
+        protocol::{FROM_SERVER, REQUEST_HEADER_SIZE, RESPONSE_HEADER_SIZE},
+        server::{Handler, ProtocolError, RpcServer},
+        tls::TlsOptions,
+    };
+    use crate::server::storage_engine::{
+        file_engine::FileEngine, meta_engine::MetaEngine, StorageEngine,
+    };
+    use fuser::FileType;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const TEST_ADDRESS: &str = "127.0.0.1:8085";
+
+    // a handler whose connection 0 comes from an admin host
+    fn admin_handler(engine: Arc<DistributedEngine<FileEngine>>) -> FileRequestHandler<FileEngine> {
+        engine.admin_hosts.insert([127, 0, 0, 1].into());
+        let handler = FileRequestHandler::new(engine);
+        handler.connection_opened(0, Some(([127, 0, 0, 1], 40000).into()));
+        handler
+    }
+
+    // a single server cluster which owns every path
+    fn new_engine(root: &str, db_path: &str) -> Arc<DistributedEngine<FileEngine>> {
+        new_cluster_engine(TEST_ADDRESS, &[TEST_ADDRESS], root, db_path)
+    }
+
+    fn new_cluster_engine(
+        address: &str,
+        servers: &[&str],
+        root: &str,
+        db_path: &str,
+    ) -> Arc<DistributedEngine<FileEngine>> {
+        let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+        let storage_engine = Arc::new(FileEngine::new(root, meta_engine.clone()));
+        storage_engine.init();
+        let engine = Arc::new(DistributedEngine::new(
+            address.to_string(),
+            storage_engine,
+            meta_engine,
+        ));
+        engine.hash_ring.write().replace(HashRing::new(
+            servers
+                .iter()
+                .map(|server| (server.to_string(), 100))
+                .collect(),
+        ));
+        engine
+            .cluster_status
+            .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+        engine
+            .create_volume("test_volume", 0, AtimePolicy::default(), None, None)
+            .unwrap();
+        engine
+    }
+
+    fn destroy_db(db_path: &str) {
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
+    async fn create_and_write(
+        handler: &FileRequestHandler<FileEngine>,
+        name: &str,
+        data: Vec<u8>,
+    ) -> (i32, Vec<u8>) {
+        let metadata = bincode::serialize(&CreateAndWriteSendMetaData {
+            mode: 0o644,
+            umask: 0,
+            name: name.to_string(),
+            data,
+        })
+        .unwrap();
+        let (status, _, meta_data_length, _, meta_data, _) = handler
+            .dispatch(
+                0,
+                OperationType::CreateAndWrite.into(),
+                0,
+                "test_volume".as_bytes().to_vec(),
+                vec![],
+                metadata,
+            )
+            .await
+            .unwrap();
+        (status, meta_data[..meta_data_length].to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let address = "127.0.0.1:50081";
+        let db_path = "/tmp/test_metrics_db";
+        {
+            let engine = new_engine("/tmp/test_metrics", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            tokio::spawn(super::metrics::serve(
+                engine.metrics.clone(),
+                address.to_string(),
+            ));
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let dispatch = |operation_type: OperationType, path: &str, data, metadata| {
+                handler.dispatch(
+                    0,
+                    operation_type.into(),
+                    0,
+                    path.as_bytes().to_vec(),
+                    data,
+                    metadata,
+                )
+            };
+
+            let (status, _) = create_and_write(&handler, "file", vec![]).await;
+            assert_eq!(status, 0);
+            for offset in [0, 60] {
+                let (status, ..) = dispatch(
+                    OperationType::WriteFile,
+                    "test_volume/file",
+                    vec![1u8; 60],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(status, 0);
+            }
+            let (status, ..) = dispatch(
+                OperationType::GetFileAttr,
+                "test_volume/missing",
+                vec![],
+                vec![],
+            )
+            .await
+            .unwrap();
+            assert_eq!(status, libc::ENOENT);
+            let (status, ..) = dispatch(
+                OperationType::DeleteFile,
+                "test_volume",
+                vec![],
+                bincode::serialize(&DeleteFileSendMetaData {
+                    name: "file".to_owned(),
+                })
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(status, 0);
+
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            let (header, body) = response.split_once("\r\n\r\n").unwrap();
+            assert!(header.starts_with("HTTP/1.1 200 OK"));
+            assert!(header.contains("Content-Type: text/plain; version=0.0.4"));
+            let lines: Vec<&str> = body.lines().collect();
+            for line in [
+                "sealfs_requests_total{op=\"CreateAndWrite\"} 1",
+                "sealfs_requests_total{op=\"WriteFile\"} 2",
+                "sealfs_request_errors_total{op=\"WriteFile\"} 0",
+                "sealfs_requests_total{op=\"GetFileAttr\"} 1",
+                "sealfs_request_errors_total{op=\"GetFileAttr\"} 1",
+                "sealfs_request_duration_seconds_count{op=\"WriteFile\"} 2",
+                "sealfs_written_bytes_total 120",
+                "sealfs_deleted_bytes_total 120",
+            ] {
+                assert!(lines.contains(&line), "{} missing from\n{}", line, body);
+            }
+
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_create_and_write() {
+        let db_path = "/tmp/test_create_and_write_db";
+        {
+            let engine = new_engine("/tmp/test_create_and_write", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let data = vec![7u8; 100];
+            let (status, meta_data) = create_and_write(&handler, "small_file", data.clone()).await;
+            assert_eq!(status, 0);
+            assert_eq!(bytes_as_file_attr(&meta_data).size, 100);
+            assert_eq!(
+                engine
+                    .read_file("test_volume/small_file", 100, 0)
+                    .await
+                    .unwrap(),
+                data
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_stat_and_checksum_invalidate_on_write() {
+        let db_path = "/tmp/test_stat_and_checksum_db";
+        {
+            let engine = new_engine("/tmp/test_stat_and_checksum", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let path = "test_volume/checksum_file";
+            let (status, _) = create_and_write(&handler, "checksum_file", vec![1u8; 100]).await;
+            assert_eq!(status, 0);
+
+            let stat_and_checksum = || async {
+                let md = StatAndChecksumSendMetaData {
+                    paths: vec![path.to_string(), "test_volume/missing".to_string()],
+                };
+                let (status, _, _, data_length, _, data) = handler
+                    .dispatch(
+                        0,
+                        OperationType::StatAndChecksum.into(),
+                        0,
+                        path.as_bytes().to_vec(),
+                        vec![],
+                        bincode::serialize(&md).unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+                bincode::deserialize::<StatAndChecksumRecvData>(&data[..data_length])
+                    .unwrap()
+                    .entries
+            };
+
+            let entries = stat_and_checksum().await;
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].0, path);
+            assert_eq!(entries[0].1.size, 100);
+            assert_eq!(entries[0].2, crc32c::crc32c(&[1u8; 100]));
+            assert!(engine.checksums.contains_key(path));
+
+            engine.write_file(path, &[2u8; 50], 50, None).await.unwrap();
+            assert!(!engine.checksums.contains_key(path));
+
+            let mut expected = vec![1u8; 50];
+            expected.extend_from_slice(&[2u8; 50]);
+            let entries = stat_and_checksum().await;
+            assert_eq!(entries[0].2, crc32c::crc32c(&expected));
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_initializing() {
+        let db_path = "/tmp/test_health_check_db";
+        {
+            let engine = new_engine("/tmp/test_health_check", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    0,
+                    ManagerOperationType::HealthCheck.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let health: HealthStatus =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert!(health.live);
+            assert!(!health.ready);
+            assert_eq!(health.server_status, ServerStatus::Initializing);
+            assert!(health.store_writable);
+
+            engine
+                .server_status
+                .store(ServerStatus::Finished.into(), Ordering::Relaxed);
+            assert!(engine.health_status().ready);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_get_server_config() {
+        let db_path = "/tmp/test_get_server_config_db";
+        {
+            let engine = new_engine("/tmp/test_get_server_config", db_path);
+            // as set by run() from the command line
+            engine.max_write_size.store(1024, Ordering::Relaxed);
+            engine.config.write().database_path = db_path.to_owned();
+            engine.config.write().max_in_flight = 64;
+            let handler = FileRequestHandler::new(engine.clone());
+
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    0,
+                    ManagerOperationType::GetServerConfig.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let config: ServerConfig =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(config, engine.server_config());
+            assert_eq!(config.address, engine.address);
+            assert_eq!(config.max_write_size, 1024);
+            assert_eq!(config.database_path, db_path);
+            assert_eq!(config.max_in_flight, 64);
+            assert_eq!(config.write_combine_size, 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities() {
+        let db_path = "/tmp/test_get_capabilities_db";
+        {
+            let engine = new_engine("/tmp/test_get_capabilities", db_path);
+            engine.dir_shard_threshold.store(16, Ordering::Relaxed);
+            let handler = FileRequestHandler::new(engine.clone());
+
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    0,
+                    ManagerOperationType::GetCapabilities.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let capabilities: Capabilities =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(capabilities, engine.capabilities());
+            assert_eq!(capabilities.protocol_version, PROTOCOL_VERSION);
+            assert!(capabilities.supports(OperationType::WriteFileChunk.into()));
+            assert!(capabilities.supports(ManagerOperationType::GetCapabilities.into()));
+            // lookup has no handler
+            assert!(!capabilities.supports(OperationType::Lookup.into()));
+            assert!(capabilities.has_feature("dir_sharding"));
+            assert!(!capabilities.has_feature("write_combine"));
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_max_write_size() {
+        let db_path = "/tmp/test_max_write_size_db";
+        {
+            let engine = new_engine("/tmp/test_max_write_size", db_path);
+            engine.max_write_size.store(1024, Ordering::Relaxed);
+            let handler = FileRequestHandler::new(engine.clone());
+
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::InitVolume.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let advertised: InitVolumeRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(advertised.max_write_size, 1024);
+
+            let (status, _) = create_and_write(&handler, "big_write", vec![]).await;
+            assert_eq!(status, 0);
+            let write = |len: usize| {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "test_volume/big_write".as_bytes().to_vec(),
+                    vec![3u8; len],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 0,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _, _, _, _, _) = write(1025).await.unwrap();
+            assert_eq!(status, libc::EINVAL);
+            let (status, _, _, _, _, _) = write(1024).await.unwrap();
+            assert_eq!(status, 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_batched_flush() {
+        let db_path = "/tmp/test_batched_flush_db";
+        {
+            let engine = new_engine("/tmp/test_batched_flush", db_path);
+            engine.flush_batcher.set_interval(60_000);
+            let handler = FileRequestHandler::new(engine.clone());
+            for name in ["flush_a", "flush_b", "flush_c"] {
+                let (status, _) = create_and_write(&handler, name, vec![]).await;
+                assert_eq!(status, 0);
+                engine
+                    .write_file(&format!("test_volume/{}", name), &[1u8; 10], 0, None)
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(engine.flush_batcher.dirty_count(), 3);
+            assert_eq!(engine.flush_dirty_files(), Ok(3));
+            assert_eq!(engine.flush_batcher.batched_flushes(), 1);
+
+            // an explicit fsync does not wait for the window
+            engine
+                .write_file("test_volume/flush_a", &[2u8; 10], 0, None)
+                .await
+                .unwrap();
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::Fsync.into(),
+                    0,
+                    "test_volume/flush_a".as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(engine.flush_batcher.dirty_count(), 0);
+            assert_eq!(engine.flush_dirty_files(), Ok(0));
+            assert_eq!(engine.flush_batcher.batched_flushes(), 1);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_write_combining() {
+        let db_path = "/tmp/test_write_combining_db";
+        {
+            let engine = new_engine("/tmp/test_write_combining", db_path);
+            engine.flush_batcher.set_interval(60_000);
+            engine.write_combiner.set_capacity(4096);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "log", vec![]).await;
+            assert_eq!(status, 0);
+
+            let mut expected = Vec::new();
+            for i in 0..100u8 {
+                let (status, _, _, _, _, _) = handler
+                    .dispatch(
+                        0,
+                        OperationType::WriteFile.into(),
+                        0,
+                        "test_volume/log".as_bytes().to_vec(),
+                        vec![i; 10],
+                        bincode::serialize(&WriteFileSendMetaData {
+                            offset: expected.len() as i64,
+                            if_mtime: None,
+                            checksum: None,
+                            append: false,
+                        })
+                        .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+                expected.extend_from_slice(&[i; 10]);
+            }
+            assert_eq!(engine.write_combiner.store_writes(), 0);
+
+            // reading the file puts the buffered appends in the store as one write
+            assert_eq!(
+                engine.read_file("test_volume/log", 2000, 0).await.unwrap(),
+                expected
+            );
+            assert_eq!(engine.write_combiner.store_writes(), 1);
+            let attr = engine.get_file_attr("test_volume/log").await.unwrap();
+            assert_eq!(bytes_as_file_attr(&attr).size, 1000);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_write_if_mtime() {
+        let db_path = "/tmp/test_write_if_mtime_db";
+        {
+            let engine = new_engine("/tmp/test_write_if_mtime", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+            let mtime = |engine: &DistributedEngine<FileEngine>| {
+                engine
+                    .meta_engine
+                    .get_file_attr("test_volume/file")
+                    .unwrap()
+                    .mtime
+            };
+            let read = mtime(&engine);
+            let write = |if_mtime| {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    vec![2u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 0,
+                        if_mtime: Some(if_mtime),
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            let (status, _, _, _, _, _) = write(read - Duration::from_secs(1)).await.unwrap();
+            assert_eq!(status, CONFLICT);
+            assert_eq!(mtime(&engine), read);
+            assert_eq!(
+                engine.read_file("test_volume/file", 10, 0).await.unwrap(),
+                vec![1u8; 10]
+            );
+
+            std::thread::sleep(Duration::from_millis(10));
+            let (status, _, _, _, _, _) = write(read).await.unwrap();
+            assert_eq!(status, 0);
+            assert!(mtime(&engine) > read);
+            assert_eq!(
+                engine.read_file("test_volume/file", 10, 0).await.unwrap(),
+                vec![2u8; 10]
+            );
+
+            // the mtime read before that write is now stale
+            let (status, _, _, _, _, _) = write(read).await.unwrap();
+            assert_eq!(status, CONFLICT);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let db_path = "/tmp/test_version_db";
+        {
+            let engine = new_engine("/tmp/test_version", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+            // the version getattr answers after the attr
+            let version = || async {
+                let (status, _, meta_len, _, meta, _) = handler
+                    .dispatch(
+                        0,
+                        OperationType::GetFileAttr.into(),
+                        0,
+                        "test_volume/file".as_bytes().to_vec(),
+                        Vec::new(),
+                        Vec::new(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+                let attr_length = std::mem::size_of::<fuser::FileAttr>();
+                assert_eq!(meta_len, attr_length + 8);
+                u64::from_le_bytes(meta[attr_length..].try_into().unwrap())
+            };
+            let created = version().await;
+            assert_eq!(version().await, created);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    vec![2u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 10,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let written = version().await;
+            assert!(written > created);
+            assert_eq!(version().await, written);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::TruncateFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&TruncateFileSendMetaData { length: 5 }).unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let truncated = version().await;
+            assert!(truncated > written);
+            let attr = engine
+                .meta_engine
+                .get_file_attr("test_volume/file")
+                .unwrap();
+            assert_eq!(attr.size, 5);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::SetAttr.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&SetAttrSendMetaData {
+                        perm: Some(0o600),
+                        uid: None,
+                        gid: None,
+                        size: None,
+                        atime: None,
+                        mtime: None,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let changed = version().await;
+            assert!(changed > truncated);
+            assert_eq!(version().await, changed);
+
+            // reads and atime updates leave it alone
+            engine.read_file("test_volume/file", 5, 0).await.unwrap();
+            assert_eq!(version().await, changed);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_read_verify_checksum() {
+        let db_path = "/tmp/test_read_verify_checksum_db";
+        {
+            let engine = new_engine("/tmp/test_read_verify_checksum", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let data: Vec<u8> = (0..100).collect();
+            let (status, _) = create_and_write(&handler, "file", data.clone()).await;
+            assert_eq!(status, 0);
+            let read = |verify_checksum| {
+                handler.dispatch(
+                    0,
+                    OperationType::ReadFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&ReadFileSendMetaData {
+                        offset: 10,
+                        size: 50,
+                        verify_checksum,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            let (status, _, meta_data_length, data_length, _, _) = read(false).await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(meta_data_length, 0);
+            assert_eq!(data_length, 50);
+
+            let (status, _, meta_data_length, data_length, meta_data, mut read_data) =
+                read(true).await.unwrap();
+            assert_eq!(status, 0);
+            let md: ReadFileRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(&read_data[..data_length], &data[10..60]);
+            assert_eq!(md.checksum, crc32c::crc32c(&data[10..60]));
+            assert!(md.verify(&read_data[..data_length]));
+
+            // a block corrupted on its way no longer matches
+            read_data[7] ^= 0xff;
+            assert!(!md.verify(&read_data[..data_length]));
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_read_file_vectored() {
+        let db_path = "/tmp/test_read_file_vectored_db";
+        {
+            let engine = new_engine("/tmp/test_read_file_vectored", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let data: Vec<u8> = (0..100).collect();
+            let (status, _) = create_and_write(&handler, "file", data.clone()).await;
+            assert_eq!(status, 0);
+
+            // the last range reaches past the end of the file
+            let (status, _, meta_data_length, data_length, meta_data, read_data) = handler
+                .dispatch(
+                    0,
+                    OperationType::ReadFileVectored.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&ReadFileVectoredSendMetaData {
+                        fh: 0,
+                        ranges: vec![(0, 4), (50, 8), (96, 10)],
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let md: ReadFileVectoredRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(md.lengths, vec![4, 8, 4]);
+            assert_eq!(data_length, 16);
+            assert_eq!(
+                read_data,
+                [&data[0..4], &data[50..58], &data[96..100]].concat()
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_degraded_without_manager() {
+        let db_path = "/tmp/test_degraded_without_manager_db";
+        {
+            let engine = new_engine("/tmp/test_degraded_without_manager", db_path);
+            engine.manager_failure_limit.store(3, Ordering::Relaxed);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+            let write = || {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    vec![2u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 0,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+            let read = || {
+                handler.dispatch(
+                    0,
+                    OperationType::ReadFile.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&ReadFileSendMetaData {
+                        offset: 0,
+                        size: 10,
+                        verify_checksum: false,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            // the test engine never connected to a manager
+            for _ in 0..2 {
+                assert!(engine.get_cluster_status_info().await.is_err());
+            }
+            assert!(!engine.is_degraded());
+            let (status, _, _, _, _, _) = write().await.unwrap();
+            assert_eq!(status, 0);
+
+            assert!(engine.get_cluster_status_info().await.is_err());
+            assert!(engine.is_degraded());
+            assert!(!engine.health_status().ready);
+            let (status, _, _, _, _, _) = write().await.unwrap();
+            assert_eq!(status, MANAGER_UNREACHABLE);
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::DeleteFile.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&DeleteFileSendMetaData {
+                        name: "file".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, MANAGER_UNREACHABLE);
+            let (status, _, _, data_len, _, data) = read().await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(&data[..data_len], &[2u8; 10]);
+
+            // a contact that goes through leaves degraded mode
+            engine.track_manager(Ok(())).unwrap();
+            assert!(!engine.is_degraded());
+            let (status, _, _, _, _, _) = write().await.unwrap();
+            assert_eq!(status, 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_tmpfile_link() {
+        let db_path = "/tmp/test_tmpfile_link_db";
+        {
+            let engine = new_engine("/tmp/test_tmpfile_link", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let list = || async {
+                let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+                DirentDecoder::new(data.as_slice())
+                    .map(|entry| entry.unwrap().1)
+                    .collect::<Vec<String>>()
+            };
+
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateFile.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateFileSendMetaData {
+                        mode: 0o600,
+                        umask: 0,
+                        flags: libc::O_RDWR,
+                        name: ".tmpfile_1".to_owned(),
+                        tmpfile: true,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_ne!(
+                bytes_as_file_attr(&meta_data[..meta_data_length]).flags & UNLINKED,
+                0
+            );
+            engine
+                .write_file("test_volume/.tmpfile_1", &[3u8; 100], 0, None)
+                .await
+                .unwrap();
+            assert!(list().await.is_empty());
+
+            let link = |name: &str| {
+                handler.dispatch(
+                    0,
+                    OperationType::Link.into(),
+                    0,
+                    "test_volume/.tmpfile_1".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&LinkSendMetaData {
+                        name: name.to_owned(),
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _, meta_data_length, _, meta_data, _) = link("linked").await.unwrap();
+            assert_eq!(status, 0);
+            let attr = bytes_as_file_attr(&meta_data[..meta_data_length]);
+            assert_eq!(attr.size, 100);
+            assert_eq!(attr.flags & UNLINKED, 0);
+            assert_eq!(list().await, vec!["linked".to_owned()]);
+            assert_eq!(
+                engine
+                    .read_file("test_volume/linked", 100, 0)
+                    .await
+                    .unwrap(),
+                vec![3u8; 100]
+            );
+            assert_eq!(
+                engine
+                    .get_file_attr("test_volume/.tmpfile_1")
+                    .await
+                    .unwrap_err(),
+                libc::ENOENT
+            );
+
+            // a linked file is no tmpfile any more
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::Link.into(),
+                    0,
+                    "test_volume/linked".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&LinkSendMetaData {
+                        name: "again".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, libc::EINVAL);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let db_path = "/tmp/test_rename_db";
+        {
+            let engine = new_engine("/tmp/test_rename", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let list = |dir: &'static str| {
+                let engine = engine.clone();
+                async move {
+                    let data = engine.read_dir(dir, 4096, 0).await.unwrap();
+                    let mut names = DirentDecoder::new(data.as_slice())
+                        .map(|entry| entry.unwrap().1)
+                        .collect::<Vec<String>>();
+                    names.sort();
+                    names
+                }
+            };
+            let rename = |path: &str, new_parent: &str, new_name: &str, flags: u32| {
+                handler.dispatch(
+                    0,
+                    OperationType::Rename.into(),
+                    0,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&RenameSendMetaData {
+                        new_parent: new_parent.to_owned(),
+                        new_name: new_name.to_owned(),
+                        flags,
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _) = create_and_write(&handler, "a", vec![1u8; 100]).await;
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateDir.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateDirSendMetaData {
+                        mode: 0o755,
+                        name: "dir".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+            engine
+                .meta_engine
+                .set_attr(
+                    "test_volume/a",
+                    Some(0o640),
+                    Some(1000),
+                    Some(1001),
+                    None,
+                    Some(mtime),
+                    SystemTime::now(),
+                )
+                .unwrap();
+            let attr = engine.meta_engine.get_file_attr("test_volume/a").unwrap();
+            let version = engine.meta_engine.version("test_volume/a").unwrap();
+            let entry_type = |name: &'static str| {
+                let engine = engine.clone();
+                async move {
+                    let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+                    DirentDecoder::new(data.as_slice())
+                        .map(|entry| entry.unwrap())
+                        .find(|entry| entry.1 == name)
+                        .map(|entry| entry.0)
+                }
+            };
+            let kind = entry_type("a").await;
+            assert!(kind.is_some());
+
+            // same directory, the file keeps its attr
+            let (status, _, meta_data_length, _, meta_data, _) =
+                rename("test_volume/a", "test_volume", "b", 0)
+                    .await
+                    .unwrap();
+            assert_eq!(status, 0);
+            let renamed = bytes_as_file_attr(&meta_data[..meta_data_length]);
+            assert_eq!(renamed.size, 100);
+            assert_eq!(
+                (renamed.perm, renamed.uid, renamed.gid, renamed.mtime),
+                (0o640, 1000, 1001, mtime)
+            );
+            assert_eq!((renamed.atime, renamed.crtime), (attr.atime, attr.crtime));
+            assert_eq!(
+                engine.meta_engine.version("test_volume/b").unwrap(),
+                version
+            );
+            assert_eq!(list("test_volume").await, vec!["b", "dir"]);
+            // the entry was renamed in place, with its type and the inode it names
+            assert_eq!(entry_type("b").await, kind);
+            assert_eq!(renamed.ino, attr.ino);
+            assert_eq!(
+                engine.get_file_attr("test_volume/a").await.unwrap_err(),
+                libc::ENOENT
+            );
+
+            // into another directory
+            let (status, _, _, _, _, _) = rename("test_volume/b", "test_volume/dir", "c", 0)
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(list("test_volume").await, vec!["dir"]);
+            assert_eq!(list("test_volume/dir").await, vec!["c"]);
+            assert_eq!(
+                engine.read_file("test_volume/dir/c", 100, 0).await.unwrap(),
+                vec![1u8; 100]
+            );
+
+            // an existing name is kept with RENAME_NOREPLACE and replaced without it
+            let (status, _) = create_and_write(&handler, "d", vec![2u8; 10]).await;
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = rename(
+                "test_volume/d",
+                "test_volume/dir",
+                "c",
+                libc::RENAME_NOREPLACE,
+            )
+            .await
+            .unwrap();
+            assert_eq!(status, libc::EEXIST);
+            assert_eq!(
+                engine.read_file("test_volume/dir/c", 100, 0).await.unwrap(),
+                vec![1u8; 100]
+            );
+            let (status, _, _, _, _, _) = rename("test_volume/d", "test_volume/dir", "c", 0)
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(list("test_volume").await, vec!["dir"]);
+            assert_eq!(list("test_volume/dir").await, vec!["c"]);
+            assert_eq!(
+                engine.read_file("test_volume/dir/c", 100, 0).await.unwrap(),
+                vec![2u8; 10]
+            );
+            // and within one directory, where the replaced entry goes in the same write
+            let count = engine
+                .meta_engine
+                .directory_entry_count("test_volume/dir")
+                .unwrap();
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateFile.into(),
+                    0,
+                    "test_volume/dir".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateFileSendMetaData {
+                        mode: 0o644,
+                        umask: 0,
+                        flags: libc::O_CREAT | libc::O_RDWR,
+                        name: "e".to_owned(),
+                        tmpfile: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = rename("test_volume/dir/e", "test_volume/dir", "c", 0)
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(list("test_volume/dir").await, vec!["c"]);
+            assert_eq!(
+                engine
+                    .meta_engine
+                    .directory_entry_count("test_volume/dir")
+                    .unwrap(),
+                count
+            );
+            assert!(engine
+                .read_file("test_volume/dir/c", 100, 0)
+                .await
+                .unwrap()
+                .is_empty());
+            // no copy staged on the way is left
+            assert!(!engine
+                .meta_engine
+                .file_indexs
+                .iter()
+                .any(|entry| entry.key().ends_with(".rename")));
+
+            let (status, _, _, _, _, _) = rename("test_volume/dir", "test_volume", "moved", 0)
+                .await
+                .unwrap();
+            assert_eq!(status, libc::EXDEV);
+            let (status, _, _, _, _, _) = rename("test_volume/dir/c", "test_volume", "dir", 0)
+                .await
+                .unwrap();
+            assert_eq!(status, libc::EISDIR);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_create_and_write_atomic() {
+        let db_path = "/tmp/test_create_and_write_atomic_db";
+        {
+            let engine = new_engine("/tmp/test_create_and_write_atomic", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let size = 16 << 20;
+            let created = {
+                let handler = handler.clone();
+                tokio::spawn(
+                    async move { create_and_write(&handler, "file", vec![1u8; size]).await },
+                )
+            };
+            // once its entry is listed the file has its data
+            while !created.is_finished() {
+                let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+                if DirentDecoder::new(data.as_slice()).any(|entry| entry.unwrap().1 == "file") {
+                    let attr = engine
+                        .meta_engine
+                        .get_file_attr("test_volume/file")
+                        .unwrap();
+                    assert_eq!(attr.size, size as u64);
+                }
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(created.await.unwrap().0, 0);
+
+            // a failed create leaves the file that was there alone
+            let (status, _) = create_and_write(&handler, "file", vec![2u8; 10]).await;
+            assert_eq!(status, libc::EEXIST);
+            let attr = engine.get_file_attr("test_volume/file").await.unwrap();
+            assert_eq!(bytes_as_file_attr(&attr).size, size as u64);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rename_racing_writes() {
+        let db_path = "/tmp/test_rename_racing_writes_db";
+        {
+            let engine = new_engine("/tmp/test_rename_racing_writes", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            // big enough for the writes to pile up behind the copy
+            let (status, _) = create_and_write(&handler, "a", vec![0u8; 16 << 20]).await;
+            assert_eq!(status, 0);
+
+            let write = |offset: usize| {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let (status, _, _, _, _, _) = handler
+                        .dispatch(
+                            0,
+                            OperationType::WriteFile.into(),
+                            0,
+                            "test_volume/a".as_bytes().to_vec(),
+                            vec![offset as u8 | 1],
+                            bincode::serialize(&WriteFileSendMetaData {
+                                offset: offset as i64,
+                                if_mtime: None,
+                                checksum: None,
+                                append: false,
+                            })
+                            .unwrap(),
+                        )
+                        .await
+                        .unwrap();
+                    (offset, status)
+                })
+            };
+            let renamed = {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    handler
+                        .dispatch(
+                            0,
+                            OperationType::Rename.into(),
+                            0,
+                            "test_volume/a".as_bytes().to_vec(),
+                            vec![],
+                            bincode::serialize(&RenameSendMetaData {
+                                new_parent: "test_volume".to_owned(),
+                                new_name: "b".to_owned(),
+                                flags: 0,
+                            })
+                            .unwrap(),
+                        )
+                        .await
+                        .unwrap()
+                        .0
+                })
+            };
+            let writes: Vec<_> = (0..200).map(write).collect();
+            assert_eq!(renamed.await.unwrap(), 0);
+
+            // every write acknowledged made it into the renamed file, the others
+            // found the file gone
+            let data = engine.read_file("test_volume/b", 200, 0).await.unwrap();
+            for task in writes {
+                let (offset, status) = task.await.unwrap();
+                match status {
+                    0 => assert_eq!(data[offset], offset as u8 | 1),
+                    status => assert_eq!(status, libc::ESTALE),
+                }
+            }
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_symlink() {
+        let db_path = "/tmp/test_symlink_db";
+        {
+            let engine = new_engine("/tmp/test_symlink", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let symlink = |name: &str, target: &str| {
+                handler.dispatch(
+                    0,
+                    OperationType::CreateSymlink.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateSymlinkSendMetaData {
+                        name: name.to_owned(),
+                        target: target.to_owned(),
+                    })
+                    .unwrap(),
+                )
+            };
+            let readlink = |path: &str| {
+                handler.dispatch(
+                    0,
+                    OperationType::ReadLink.into(),
+                    0,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+            };
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            // relative, and pointing outside the volume and the mount
+            let targets = [
+                ("to_file", "file"),
+                ("to_parent", "../other_volume/file"),
+                ("to_root", "/etc/passwd"),
+            ];
+            for (name, target) in targets {
+                let (status, _, meta_data_length, _, meta_data, _) =
+                    symlink(name, target).await.unwrap();
+                assert_eq!(status, 0);
+                let attr = bytes_as_file_attr(&meta_data[..meta_data_length]);
+                assert_eq!(attr.kind, FileType::Symlink);
+                assert_eq!(attr.size, target.len() as u64);
+
+                let path = format!("test_volume/{}", name);
+                let (status, _, _, data_length, _, data) = readlink(&path).await.unwrap();
+                assert_eq!(status, 0);
+                assert_eq!(&data[..data_length], target.as_bytes());
+                let attr = engine.get_file_attr(&path).await.unwrap();
+                assert_eq!(bytes_as_file_attr(&attr).kind, FileType::Symlink);
+            }
+
+            let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+            let mut entries = DirentDecoder::new(data.as_slice())
+                .map(|entry| entry.unwrap())
+                .collect::<Vec<(u8, String)>>();
+            entries.sort_by(|a, b| a.1.cmp(&b.1));
+            assert_eq!(
+                entries,
+                vec![
+                    (libc::DT_REG, "file".to_owned()),
+                    (libc::DT_LNK, "to_file".to_owned()),
+                    (libc::DT_LNK, "to_parent".to_owned()),
+                    (libc::DT_LNK, "to_root".to_owned()),
+                ]
+            );
+
+            let (status, _, _, _, _, _) = symlink("file", "elsewhere").await.unwrap();
+            assert_eq!(status, libc::EEXIST);
+            let (status, _, _, _, _, _) = symlink("empty", "").await.unwrap();
+            assert_eq!(status, libc::ENOENT);
+            let (status, _, _, _, _, _) = readlink("test_volume/file").await.unwrap();
+            assert_eq!(status, libc::EINVAL);
+
+            // renamed and removed under the symlink type
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::Rename.into(),
+                    0,
+                    "test_volume/to_file".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&RenameSendMetaData {
+                        new_parent: "test_volume".to_owned(),
+                        new_name: "renamed".to_owned(),
+                        flags: 0,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let (status, _, _, data_length, _, data) =
+                readlink("test_volume/renamed").await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(&data[..data_length], b"file");
+            for name in ["renamed", "to_parent", "to_root"] {
+                let (status, _, _, _, _, _) = handler
+                    .dispatch(
+                        0,
+                        OperationType::DeleteFile.into(),
+                        0,
+                        "test_volume".as_bytes().to_vec(),
+                        vec![],
+                        bincode::serialize(&DeleteFileSendMetaData {
+                            name: name.to_owned(),
+                        })
+                        .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+            }
+            let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+            assert_eq!(
+                DirentDecoder::new(data.as_slice())
+                    .map(|entry| entry.unwrap())
+                    .collect::<Vec<(u8, String)>>(),
+                vec![(libc::DT_REG, "file".to_owned())]
+            );
+            assert_eq!(
+                engine.meta_engine.directory_entry_count("test_volume"),
+                Ok(1)
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_write_deleted_file() {
+        let db_path = "/tmp/test_write_deleted_file_db";
+        {
+            let engine = new_engine("/tmp/test_write_deleted_file", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "gone", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+            let write = |if_mtime: Option<SystemTime>| {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "test_volume/gone".as_bytes().to_vec(),
+                    vec![2u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 10,
+                        if_mtime,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _, _, _, _, _) = write(None).await.unwrap();
+            assert_eq!(status, 0);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::DeleteFile.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&DeleteFileSendMetaData {
+                        name: "gone".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+
+            // the handle opened before the unlink is stale too, and nothing is recreated
+            for if_mtime in [None, Some(SystemTime::now())] {
+                let (status, _, _, _, _, _) = write(if_mtime).await.unwrap();
+                assert_eq!(status, libc::ESTALE);
+            }
+            assert_eq!(
+                engine.get_file_attr("test_volume/gone").await.unwrap_err(),
+                libc::ENOENT
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_set_attr() {
+        let db_path = "/tmp/test_set_attr_db";
+        {
+            let engine = new_engine("/tmp/test_set_attr", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+            let set_attr = |md: SetAttrSendMetaData| {
+                handler.dispatch(
+                    0,
+                    OperationType::SetAttr.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    Vec::new(),
+                    bincode::serialize(&md).unwrap(),
+                )
+            };
+            let created = engine
+                .meta_engine
+                .get_file_attr("test_volume/file")
+                .unwrap();
+            let version = engine.meta_engine.version("test_volume/file").unwrap();
+
+            let (status, _, _, _, meta, _) = set_attr(SetAttrSendMetaData {
+                perm: Some(0o600),
+                uid: Some(1000),
+                gid: Some(100),
+                size: None,
+                atime: None,
+                mtime: None,
+            })
+            .await
+            .unwrap();
+            assert_eq!(status, 0);
+            let attr = *bytes_as_file_attr(&meta);
+            assert_eq!((attr.perm, attr.uid, attr.gid), (0o600, 1000, 100));
+            assert_eq!(attr.size, 10);
+            assert_eq!(attr.mtime, created.mtime);
+            assert!(attr.ctime > created.ctime);
+            assert!(engine.meta_engine.version("test_volume/file").unwrap() > version);
+
+            // fields left out stay as they were
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+            let (status, _, _, _, _, _) = set_attr(SetAttrSendMetaData {
+                perm: None,
+                uid: None,
+                gid: Some(0),
+                size: Some(4),
+                atime: None,
+                mtime: Some(mtime),
+            })
+            .await
+            .unwrap();
+            assert_eq!(status, 0);
+            let attr = bytes_as_file_attr(&engine.get_file_attr("test_volume/file").await.unwrap())
+                .to_owned();
+            assert_eq!((attr.perm, attr.uid, attr.gid), (0o600, 1000, 0));
+            assert_eq!(attr.size, 4);
+            assert_eq!(attr.mtime, mtime);
+            assert_eq!(
+                engine.read_file("test_volume/file", 10, 0).await.unwrap(),
+                vec![1u8; 4]
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_create_perm() {
+        let db_path = "/tmp/test_create_perm_db";
+        {
+            let engine = new_engine("/tmp/test_create_perm", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let create = |operation_type: OperationType, metadata| {
+                handler.dispatch(
+                    0,
+                    operation_type.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    metadata,
+                )
+            };
+            let create_file = |name: &str, mode, umask| {
+                create(
+                    OperationType::CreateFile,
+                    bincode::serialize(&CreateFileSendMetaData {
+                        mode,
+                        umask,
+                        flags: libc::O_RDWR,
+                        name: name.to_owned(),
+                        tmpfile: false,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            // without a mode, a file gets 0644 and a directory 0755
+            let (status, _, meta_data_length, _, meta_data, _) =
+                create_file("file", 0, 0o022).await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(
+                bytes_as_file_attr(&meta_data[..meta_data_length]).perm,
+                0o644
+            );
+            let (status, _, meta_data_length, _, meta_data, _) = create(
+                OperationType::CreateDir,
+                bincode::serialize(&CreateDirSendMetaData {
+                    mode: 0,
+                    name: "dir".to_owned(),
+                })
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(
+                bytes_as_file_attr(&meta_data[..meta_data_length]).perm,
+                0o755
+            );
+            assert_eq!(
+                engine
+                    .meta_engine
+                    .get_file_attr("test_volume/dir")
+                    .unwrap()
+                    .perm,
+                0o755
+            );
+
+            let (status, _, meta_data_length, _, meta_data, _) =
+                create_file("private", libc::S_IFREG | 0o666, 0o077)
+                    .await
+                    .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(
+                bytes_as_file_attr(&meta_data[..meta_data_length]).perm,
+                0o600
+            );
+            assert_eq!(
+                engine
+                    .meta_engine
+                    .get_file_attr("test_volume/private")
+                    .unwrap()
+                    .perm,
+                0o600
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_atomic_increment() {
+        let db_path = "/tmp/test_atomic_increment_db";
+        {
+            let engine = new_engine("/tmp/test_atomic_increment", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let (status, _) = create_and_write(&handler, "counter", vec![]).await;
+            assert_eq!(status, 0);
+
+            let tasks: Vec<_> = (1..=200i64)
+                .map(|delta| {
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        let (status, _, meta_data_length, _, meta_data, _) = handler
+                            .dispatch(
+                                0,
+                                OperationType::AtomicIncrement.into(),
+                                0,
+                                "test_volume/counter".as_bytes().to_vec(),
+                                Vec::new(),
+                                bincode::serialize(&AtomicIncrementSendMetaData { ino: 0, delta })
+                                    .unwrap(),
+                            )
+                            .await
+                            .unwrap();
+                        assert_eq!(status, 0);
+                        assert_eq!(meta_data_length, 8);
+                        i64::from_le_bytes(meta_data.try_into().unwrap())
+                    })
+                })
+                .collect();
+            let mut values = Vec::new();
+            for task in tasks {
+                values.push(task.await.unwrap());
+            }
+            // no increment was lost, and each saw a sum no other one did
+            let total = (1..=200i64).sum::<i64>();
+            values.sort();
+            values.dedup();
+            assert_eq!(values.len(), 200);
+            assert_eq!(*values.last().unwrap(), total);
+            assert_eq!(
+                engine
+                    .read_file("test_volume/counter", 16, 0)
+                    .await
+                    .unwrap(),
+                total.to_le_bytes()
+            );
+
+            assert_eq!(
+                engine
+                    .atomic_increment("test_volume/counter", i64::MAX)
+                    .await
+                    .unwrap_err(),
+                libc::EOVERFLOW
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_append() {
+        let db_path = "/tmp/test_append_db";
+        {
+            let engine = new_engine("/tmp/test_append", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let (status, _) = create_and_write(&handler, "log", vec![]).await;
+            assert_eq!(status, 0);
+
+            // markers of different lengths, so an overlap can not line up by chance
+            let appenders: Vec<_> = [vec![b'a'; 100], vec![b'b'; 37]]
+                .into_iter()
+                .map(|marker| {
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        let mut offsets = Vec::new();
+                        for _ in 0..50 {
+                            let (status, _, meta_data_length, _, meta_data, _) = handler
+                                .dispatch(
+                                    0,
+                                    OperationType::WriteFile.into(),
+                                    0,
+                                    "test_volume/log".as_bytes().to_vec(),
+                                    marker.clone(),
+                                    bincode::serialize(&WriteFileSendMetaData {
+                                        offset: 0,
+                                        if_mtime: None,
+                                        checksum: None,
+                                        append: true,
+                                    })
+                                    .unwrap(),
+                                )
+                                .await
+                                .unwrap();
+                            assert_eq!(status, 0);
+                            let md: AppendFileRecvMetaData =
+                                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+                            assert_eq!(md.size as usize, marker.len());
+                            offsets.push((md.offset as usize, marker.clone()));
+                        }
+                        offsets
+                    })
+                })
+                .collect();
+            let mut appends = Vec::new();
+            for appender in appenders {
+                appends.extend(appender.await.unwrap());
+            }
+
+            let data = engine
+                .read_file("test_volume/log", 1 << 20, 0)
+                .await
+                .unwrap();
+            assert_eq!(data.len(), 50 * 100 + 50 * 37);
+            // every append is whole where it said it was, and together they tile the file
+            appends.sort();
+            let mut end = 0;
+            for (offset, marker) in appends {
+                assert_eq!(offset, end);
+                assert_eq!(&data[offset..offset + marker.len()], marker.as_slice());
+                end = offset + marker.len();
+            }
+            assert_eq!(end, data.len());
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_create_exclusive() {
+        let db_path = "/tmp/test_create_exclusive_db";
+        {
+            let engine = new_engine("/tmp/test_create_exclusive", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let create = |name: String, flags: i32| {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    handler
+                        .dispatch(
+                            0,
+                            OperationType::CreateFile.into(),
+                            0,
+                            "test_volume".as_bytes().to_vec(),
+                            vec![],
+                            bincode::serialize(&CreateFileSendMetaData {
+                                mode: 0o644,
+                                umask: 0,
+                                flags,
+                                name,
+                                tmpfile: false,
+                            })
+                            .unwrap(),
+                        )
+                        .await
+                        .unwrap()
+                        .0
+                })
+            };
+
+            let exclusive = libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY;
+            for i in 0..20 {
+                let name = format!("file_{}", i);
+                let first = create(name.clone(), exclusive);
+                let second = create(name.clone(), exclusive);
+                let mut statuses = vec![first.await.unwrap(), second.await.unwrap()];
+                statuses.sort();
+                assert_eq!(statuses, vec![0, libc::EEXIST]);
+                // without O_EXCL the existing file is opened
+                assert_eq!(
+                    create(name, libc::O_CREAT | libc::O_WRONLY).await.unwrap(),
+                    0
+                );
+            }
+
+            let data = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+            assert_eq!(DirentDecoder::new(data.as_slice()).count(), 20);
+            assert_eq!(
+                engine
+                    .meta_engine
+                    .directory_entry_count("test_volume")
+                    .unwrap(),
+                20
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_write_file_chunk() {
+        let db_path = "/tmp/test_write_file_chunk_db";
+        {
+            let engine = new_engine("/tmp/test_write_file_chunk", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "upload", vec![]).await;
+            assert_eq!(status, 0);
+
+            let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+            // sent out of order, each lands at its own offset
+            for (chunk_offset, is_last) in [(1024, false), (0, false), (2048, true)] {
+                let chunk = &content[chunk_offset..(chunk_offset + 1024).min(content.len())];
+                let (status, _, meta_data_length, _, meta_data, _) = handler
+                    .dispatch(
+                        0,
+                        OperationType::WriteFileChunk.into(),
+                        0,
+                        "test_volume/upload".as_bytes().to_vec(),
+                        chunk.to_vec(),
+                        bincode::serialize(&WriteFileChunkSendMetaData {
+                            fh: 7,
+                            chunk_offset: chunk_offset as u64,
+                            is_last,
+                        })
+                        .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+                let ack: WriteFileChunkRecvMetaData =
+                    bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+                assert_eq!(
+                    ack,
+                    WriteFileChunkRecvMetaData {
+                        fh: 7,
+                        chunk_offset: chunk_offset as u64,
+                        stored: chunk.len() as u64,
+                    }
+                );
+            }
+            assert_eq!(
+                engine
+                    .read_file("test_volume/upload", 4096, 0)
+                    .await
+                    .unwrap(),
+                content
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_file_type() {
+        let db_path = "/tmp/test_wrong_file_type_db";
+        {
+            let engine = new_engine("/tmp/test_wrong_file_type", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::ReadFile.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ReadFileSendMetaData {
+                        offset: 0,
+                        size: 10,
+                        verify_checksum: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, libc::EISDIR);
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::ReadDir.into(),
+                    0,
+                    "test_volume/file".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ReadDirSendMetaData {
+                        offset: 0,
+                        size: 1024,
+                        cursor: None,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, libc::ENOTDIR);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_max_file_size() {
+        let db_path = "/tmp/test_max_file_size_db";
+        {
+            let engine = new_engine("/tmp/test_max_file_size", db_path);
+            engine
+                .create_volume("limited_volume", 0, AtimePolicy::default(), Some(100), None)
+                .unwrap();
+            let handler = FileRequestHandler::new(engine.clone());
+            engine
+                .create_and_write("limited_volume", "file", 0, 0o644, &[])
+                .await
+                .unwrap();
+
+            let write = |offset: i64| {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "limited_volume/file".as_bytes().to_vec(),
+                    vec![1u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _, _, _, _, _) = write(90).await.unwrap();
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = write(95).await.unwrap();
+            assert_eq!(status, libc::EFBIG);
+            assert_eq!(
+                engine.truncate_file("limited_volume/file", 101).await,
+                Err(libc::EFBIG)
+            );
+            let attr = engine.get_file_attr("limited_volume/file").await.unwrap();
+            assert_eq!(bytes_as_file_attr(&attr).size, 100);
+
+            // other volumes are not limited
+            let (status, _) = create_and_write(&handler, "big", vec![1u8; 200]).await;
+            assert_eq!(status, 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_operation_rejected() {
+        let db_path = "/tmp/test_unknown_operation_rejected_db";
+        {
+            let engine = new_engine("/tmp/test_unknown_operation_rejected", db_path);
+            let handler = FileRequestHandler::new(engine);
+            for operation_type in [OperationType::Unkown.into(), 70] {
+                let error = handler
+                    .dispatch(
+                        0,
+                        operation_type,
+                        0,
+                        "test_volume/file".as_bytes().to_vec(),
+                        vec![],
+                        vec![],
+                    )
+                    .await
+                    .unwrap_err();
+                assert!(error.is::<ProtocolError>());
+            }
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_ops() {
+        let db_path = "/tmp/test_allowed_ops_db";
+        {
+            let engine = new_engine("/tmp/test_allowed_ops", db_path);
+            engine
+                .create_volume(
+                    "log_volume",
+                    0,
+                    AtimePolicy::default(),
+                    None,
+                    Some(vec![
+                        OperationType::CreateFile.into(),
+                        OperationType::WriteFile.into(),
+                    ]),
+                )
+                .unwrap();
+            let handler = FileRequestHandler::new(engine.clone());
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateDir.into(),
+                    0,
+                    "log_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateDirSendMetaData {
+                        mode: 0o755,
+                        name: "dir".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, libc::EPERM);
+            assert!(engine.get_file_attr("log_volume/dir").await.is_err());
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateFile.into(),
+                    0,
+                    "log_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateFileSendMetaData {
+                        mode: 0o644,
+                        umask: 0,
+                        flags: 0,
+                        name: "log".to_owned(),
+                        tmpfile: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    "log_volume/log".as_bytes().to_vec(),
+                    vec![1u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 0,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+
+            // reads are never restricted, other volumes are not restricted
+            let (status, _, _, data_length, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::ReadFile.into(),
+                    0,
+                    "log_volume/log".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ReadFileSendMetaData {
+                        offset: 0,
+                        size: 10,
+                        verify_checksum: false,
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!((status, data_length), (0, 10));
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::CreateDir.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&CreateDirSendMetaData {
+                        mode: 0o755,
+                        name: "dir".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_kill_op() {
+        let db_path = "/tmp/test_kill_op_db";
+        {
+            let engine = new_engine("/tmp/test_kill_op", db_path);
+            let handler = Arc::new(admin_handler(engine.clone()));
+            let (status, _) = create_and_write(&handler, "remote", vec![]).await;
+            assert_eq!(status, 0);
+
+            // the data owner accepts the read and never answers it
+            let owner = "127.0.0.1:50064";
+            let listener = tokio::net::TcpListener::bind(owner).await.unwrap();
+            tokio::spawn(async move {
+                let mut streams = Vec::new();
+                while let Ok((stream, _)) = listener.accept().await {
+                    streams.push(stream);
+                }
+            });
+            engine.add_connection(owner.to_string()).await.unwrap();
+            engine
+                .meta_engine
+                .set_data_owner("test_volume/remote", Some(owner))
+                .unwrap();
+
+            let read = {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    handler
+                        .dispatch(
+                            7,
+                            OperationType::ReadFile.into(),
+                            0,
+                            "test_volume/remote".as_bytes().to_vec(),
+                            vec![],
+                            bincode::serialize(&ReadFileSendMetaData {
+                                offset: 0,
+                                size: 5,
+                                verify_checksum: false,
+                            })
+                            .unwrap(),
+                        )
+                        .await
+                        .unwrap()
+                })
+            };
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let list = || {
+                handler.dispatch(
+                    0,
+                    ManagerOperationType::ListRunningOps.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+            };
+            let kill = |request_id: u64| {
+                handler.dispatch(
+                    0,
+                    ManagerOperationType::KillOp.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    bincode::serialize(&KillOpSendMetaData { request_id }).unwrap(),
+                )
+            };
+            let (status, _, meta_data_length, _, meta_data, _) = list().await.unwrap();
+            assert_eq!(status, 0);
+            let ops: Vec<RunningOpInfo> =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(ops.len(), 1);
+            assert_eq!(ops[0].op, u32::from(OperationType::ReadFile));
+            assert_eq!(ops[0].path, "test_volume/remote");
+            assert_eq!(ops[0].client, 7);
+            assert!(ops[0].elapsed_ms >= 200);
+            assert!(!ops[0].cancelled);
+            assert!(ops[0].killable);
+
+            let (status, _, _, _, _, _) = kill(ops[0].request_id).await.unwrap();
+            assert_eq!(status, 0);
+            let (status, _, _, _, _, _) = read.await.unwrap();
+            assert_eq!(status, libc::ECANCELED);
+
+            let (_, _, meta_data_length, _, meta_data, _) = list().await.unwrap();
+            let ops: Vec<RunningOpInfo> =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert!(ops.is_empty());
+            let (status, _, _, _, _, _) = kill(1000).await.unwrap();
+            assert_eq!(status, libc::ESRCH);
+
+            // a request changing a file could be stopped halfway
+            let write =
+                engine
+                    .running_ops
+                    .start(OperationType::WriteFile.into(), "test_volume/remote", 7);
+            let (_, _, meta_data_length, _, meta_data, _) = list().await.unwrap();
+            let ops: Vec<RunningOpInfo> =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert!(!ops[0].killable);
+            let (status, _, _, _, _, _) = kill(write.request_id()).await.unwrap();
+            assert_eq!(status, libc::EBUSY);
+
+            // only the manager and the admin hosts manage the server
+            handler.connection_opened(1, Some(([10, 0, 0, 1], 40000).into()));
+            for id in [1, 2] {
+                let (status, _, _, _, _, _) = handler
+                    .dispatch(
+                        id,
+                        ManagerOperationType::KillOp.into(),
+                        0,
+                        vec![],
+                        vec![],
+                        bincode::serialize(&KillOpSendMetaData {
+                            request_id: write.request_id(),
+                        })
+                        .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, libc::EPERM);
+            }
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_get_parent() {
+        let db_path = "/tmp/test_get_parent_db";
+        {
+            let engine = new_engine("/tmp/test_get_parent", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "child", vec![]).await;
+            assert_eq!(status, 0);
+
+            let get_parent = |path: &str| {
+                handler.dispatch(
+                    0,
+                    OperationType::GetParent.into(),
+                    0,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+            };
+            let (status, _, meta_data_length, _, meta_data, _) =
+                get_parent("test_volume/child").await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(
+                bincode::deserialize::<GetParentRecvMetaData>(&meta_data[..meta_data_length])
+                    .unwrap(),
+                GetParentRecvMetaData {
+                    parent: "test_volume".to_string(),
+                    name: "child".to_string(),
+                }
+            );
+
+            let (status, _, meta_data_length, _, meta_data, _) =
+                get_parent("test_volume").await.unwrap();
+            assert_eq!(status, 0);
+            let md: GetParentRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(md.parent, "test_volume");
+
+            let (status, _, _, _, _, _) = get_parent("test_volume/missing").await.unwrap();
+            assert_eq!(status, libc::ENOENT);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_verify_dir_count() {
+        let db_path = "/tmp/test_verify_dir_count_db";
+        {
+            let engine = new_engine("/tmp/test_verify_dir_count", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            for name in ["a", "b", "c"] {
+                let (status, _) = create_and_write(&handler, name, vec![]).await;
+                assert_eq!(status, 0);
+            }
+            // two entries counted that were never stored
+            engine
+                .meta_engine
+                .count_shard_entry("test_volume", true)
+                .unwrap();
+            engine
+                .meta_engine
+                .count_shard_entry("test_volume", true)
+                .unwrap();
+
+            let verify = |path: &str, repair: bool| {
+                handler.dispatch(
+                    0,
+                    OperationType::VerifyDirCount.into(),
+                    0,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&VerifyDirCountSendMetaData { repair }).unwrap(),
+                )
+            };
+            let (status, _, meta_data_length, _, meta_data, _) =
+                verify("test_volume", false).await.unwrap();
+            assert_eq!(status, 0);
+            let md: VerifyDirCountRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(
+                md,
+                VerifyDirCountRecvMetaData {
+                    stored: 5,
+                    actual: 3,
+                    repaired: false,
+                }
+            );
+            assert_eq!(md.discrepancy(), -2);
+            assert_eq!(
+                engine.meta_engine.directory_entry_count("test_volume"),
+                Ok(5)
+            );
+
+            let (status, _, meta_data_length, _, meta_data, _) =
+                verify("test_volume", true).await.unwrap();
+            assert_eq!(status, 0);
+            let md: VerifyDirCountRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert!(md.repaired);
+            assert_eq!(
+                engine.meta_engine.directory_entry_count("test_volume"),
+                Ok(3)
+            );
+
+            let (status, _, meta_data_length, _, meta_data, _) =
+                verify("test_volume", true).await.unwrap();
+            assert_eq!(status, 0);
+            let md: VerifyDirCountRecvMetaData =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(md.discrepancy(), 0);
+            assert!(!md.repaired);
+
+            let (status, _, _, _, _, _) = verify("test_volume/a", false).await.unwrap();
+            assert_eq!(status, libc::ENOTDIR);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_moved() {
+        let db_path = "/tmp/test_moved_db";
+        {
+            let engine = new_engine("/tmp/test_moved", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let other = "127.0.0.1:8086";
+            let new_ring = HashRing::new(vec![
+                (TEST_ADDRESS.to_string(), 100),
+                (other.to_string(), 100),
+            ]);
+            let name = (0..)
+                .map(|i| format!("moved_{}", i))
+                .find(|name| {
+                    new_ring
+                        .get(&format!("test_volume/{}", name))
+                        .unwrap()
+                        .address
+                        == other
+                })
+                .unwrap();
+            let path = format!("test_volume/{}", name);
+            let (status, _) = create_and_write(&handler, &name, vec![]).await;
+            assert_eq!(status, 0);
+
+            engine.ring_epoch.store(3, Ordering::Relaxed);
+            engine.new_ring_epoch.store(4, Ordering::Relaxed);
+            engine.new_hash_ring.write().replace(new_ring.clone());
+            engine
+                .cluster_status
+                .store(ClusterStatus::Transferring.into(), Ordering::Relaxed);
+
+            let get_file_attr = || {
+                handler.dispatch(
+                    0,
+                    OperationType::GetFileAttr.into(),
+                    MOVED_ALLOWED,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+            };
+            let moved = |epoch| MovedRecvMetaData {
+                new_server: other.to_string(),
+                epoch,
+            };
+
+            // not transferred yet, still served here
+            engine.transfer_manager.set_status(&path, false);
+            let (status, _, _, _, _, _) = get_file_attr().await.unwrap();
+            assert_eq!(status, 0);
+
+            engine.transfer_manager.set_status(&path, true);
+            let (status, _, meta_data_length, _, meta_data, _) = get_file_attr().await.unwrap();
+            assert_eq!(status, MOVED);
+            assert_eq!(
+                bincode::deserialize::<MovedRecvMetaData>(&meta_data[..meta_data_length]).unwrap(),
+                moved(4)
+            );
+
+            // the rebalance finished and the new ring took over
+            engine.hash_ring.write().replace(new_ring);
+            engine.ring_epoch.store(4, Ordering::Relaxed);
+            engine.new_hash_ring.write().take();
+            engine
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            let (status, _, meta_data_length, _, meta_data, _) = get_file_attr().await.unwrap();
+            assert_eq!(status, MOVED);
+            assert_eq!(
+                bincode::deserialize::<MovedRecvMetaData>(&meta_data[..meta_data_length]).unwrap(),
+                moved(4)
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_atime_policy() {
+        let db_path = "/tmp/test_atime_policy_db";
+        {
+            let engine = new_engine("/tmp/test_atime_policy", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let path = "test_volume/atime_file";
+            let (status, _) = create_and_write(&handler, "atime_file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            // an atime newer than mtime and ctime that relatime would keep
+            let mut attr = engine.meta_engine.get_file_attr(path).unwrap();
+            let old_atime = SystemTime::now() - Duration::from_secs(60);
+            attr.mtime = old_atime - Duration::from_secs(60);
+            attr.ctime = attr.mtime;
+            attr.atime = old_atime;
+            engine
+                .meta_engine
+                .file_indexs
+                .get_mut(path)
+                .unwrap()
+                .file_attr = attr;
+
+            let set_policy = |atime_policy| {
+                engine
+                    .meta_engine
+                    .volumes
+                    .get_mut("test_volume")
+                    .unwrap()
+                    .atime_policy = atime_policy;
+            };
+
+            set_policy(AtimePolicy::Noatime);
+            engine.read_file(path, 10, 0).await.unwrap();
+            assert_eq!(
+                engine.meta_engine.get_file_attr(path).unwrap().atime,
+                old_atime
+            );
+
+            set_policy(AtimePolicy::Relatime);
+            engine.read_file(path, 10, 0).await.unwrap();
+            assert_eq!(
+                engine.meta_engine.get_file_attr(path).unwrap().atime,
+                old_atime
+            );
+
+            set_policy(AtimePolicy::Strictatime);
+            engine.read_file(path, 10, 0).await.unwrap();
+            let atime = engine.meta_engine.get_file_attr(path).unwrap().atime;
+            assert!(atime > old_atime);
+            engine.read_file(path, 10, 0).await.unwrap();
+            assert!(engine.meta_engine.get_file_attr(path).unwrap().atime > atime);
+
+            // a volume that could not be looked up is not asked for on every read
+            assert_eq!(
+                engine.read_file("missing/file", 10, 0).await,
+                Err(libc::ENOENT)
+            );
+            let failed = *engine.remote_volume_failures.get("missing").unwrap();
+            assert_eq!(
+                engine.read_file("missing/file", 10, 0).await,
+                Err(libc::ENOENT)
+            );
+            assert_eq!(
+                *engine.remote_volume_failures.get("missing").unwrap(),
+                failed
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_data_remote() {
+        let db_path = "/tmp/test_data_remote_db";
+        {
+            let engine = new_engine("/tmp/test_data_remote", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "local", b"local".to_vec()).await;
+            assert_eq!(status, 0);
+            let (status, _) = create_and_write(&handler, "remote", b"stale".to_vec()).await;
+            assert_eq!(status, 0);
+            let owner = "127.0.0.1:8086";
+            engine
+                .meta_engine
+                .set_data_owner("test_volume/remote", Some(owner))
+                .unwrap();
+            assert_ne!(
+                engine
+                    .meta_engine
+                    .get_file_attr("test_volume/remote")
+                    .unwrap()
+                    .flags
+                    & DATA_REMOTE,
+                0
+            );
+            engine.ring_epoch.store(2, Ordering::Relaxed);
+
+            let read_file = |path: &str| {
+                handler.dispatch(
+                    0,
+                    OperationType::ReadFile.into(),
+                    MOVED_ALLOWED,
+                    path.as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ReadFileSendMetaData {
+                        offset: 0,
+                        size: 5,
+                        verify_checksum: false,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            let (status, _, _, data_length, _, data) =
+                read_file("test_volume/local").await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(&data[..data_length], b"local");
+
+            let (status, _, meta_data_length, _, meta_data, _) =
+                read_file("test_volume/remote").await.unwrap();
+            assert_eq!(status, MOVED);
+            assert_eq!(
+                bincode::deserialize::<MovedRecvMetaData>(&meta_data[..meta_data_length]).unwrap(),
+                MovedRecvMetaData {
+                    new_server: owner.to_string(),
+                    epoch: 2,
+                }
+            );
+
+            engine
+                .meta_engine
+                .set_data_owner("test_volume/remote", None)
+                .unwrap();
+            let (status, _, _, data_length, _, data) =
+                read_file("test_volume/remote").await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(&data[..data_length], b"stale");
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_client_cache() {
+        let address = "127.0.0.1:50065";
+        let db_path = "/tmp/test_invalidate_client_cache_db";
+        {
+            let engine = new_cluster_engine(
+                address,
+                &[address],
+                "/tmp/test_invalidate_client_cache",
+                db_path,
+            );
+            let server = RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            let client = Arc::new(Client::new());
+            client.client.add_connection(address).await.unwrap();
+            client
+                .hash_ring
+                .write()
+                .replace(HashRing::new(vec![(address.to_owned(), 100)]));
+            client
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            let fused = SealfsFused::new(String::new(), client.clone());
+            let invalidate = |ino| {
+                fused.dispatch(
+                    0,
+                    ManagerOperationType::InvalidateClientCache.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    bincode::serialize(&InvalidateClientCacheSendMetaData {
+                        volume: "test_volume".to_owned(),
+                        ino,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            let attr = client.get_attr("test_volume/file").await.unwrap();
+            assert_eq!(attr.size, 10);
+            engine
+                .write_file("test_volume/file", &[2u8; 10], 10, None)
+                .await
+                .unwrap();
+            // the change is not seen while the cached attr is fresh
+            assert_eq!(client.get_attr("test_volume/file").await.unwrap().size, 10);
+
+            let (status, _, _, _, _, _) = invalidate(Some(attr.ino)).await.unwrap();
+            assert_eq!(status, 0);
+            let fetched = client.get_attr("test_volume/file").await.unwrap();
+            assert_eq!(fetched.size, 20);
+            assert_eq!(fetched.ino, attr.ino);
+
+            engine
+                .write_file("test_volume/file", &[3u8; 10], 20, None)
+                .await
+                .unwrap();
+            assert_eq!(client.get_attr("test_volume/file").await.unwrap().size, 20);
+            let (status, _, _, _, _, _) = invalidate(None).await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(client.get_attr("test_volume/file").await.unwrap().size, 30);
+        }
+        destroy_db(db_path);
+    }
+
+    // passes requests on to the handler, counting the GetFileAttr and ReadFile ones
+    struct CountingHandler {
+        handler: FileRequestHandler<FileEngine>,
+        get_file_attrs: AtomicUsize,
+        read_files: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Handler for CountingHandler {
+        async fn dispatch(
+            &self,
+            id: u32,
+            operation_type: u32,
+            flags: u32,
+            path: Vec<u8>,
+            data: Vec<u8>,
+            metadata: Vec<u8>,
+        ) -> anyhow::Result<(i32, u32, usize, usize, Vec<u8>, Vec<u8>)> {
+            match OperationType::try_from(operation_type) {
+                Ok(OperationType::GetFileAttr) => {
+                    self.get_file_attrs.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(OperationType::ReadFile) => {
+                    self.read_files.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+            self.handler
+                .dispatch(id, operation_type, flags, path, data, metadata)
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dentry_cache() {
+        let address = "127.0.0.1:50082";
+        let db_path = "/tmp/test_dentry_cache_db";
+        {
+            let engine = new_cluster_engine(address, &[address], "/tmp/test_dentry_cache", db_path);
+            let handler = Arc::new(CountingHandler {
+                handler: FileRequestHandler::new(engine.clone()),
+                get_file_attrs: AtomicUsize::new(0),
+                read_files: AtomicUsize::new(0),
+            });
+            let server = RpcServer::new(handler.clone(), address);
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let (status, _) = create_and_write(&handler.handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            let client = Client::new();
+            client.client.add_connection(address).await.unwrap();
+            client
+                .hash_ring
+                .write()
+                .replace(HashRing::new(vec![(address.to_owned(), 100)]));
+            client
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            client.dentry_ttl_ms.store(60_000, Ordering::Relaxed);
+            let (root, _) = client.init_volume("test_volume").await.unwrap();
+            let name = OsStr::new("file");
+            let get_file_attrs = || handler.get_file_attrs.load(Ordering::Relaxed);
+
+            let attr = client.lookup(root, name).await.unwrap();
+            assert_eq!(attr.size, 10);
+            assert_eq!(get_file_attrs(), 1);
+            // past the ttl of the attr, but the dentry still answers
+            client.attr_cache.get_mut(&attr.ino).unwrap().1 =
+                Instant::now() - Duration::from_secs(2);
+            assert_eq!(client.lookup(root, name).await.unwrap().ino, attr.ino);
+            assert_eq!(get_file_attrs(), 1);
+            client.get_attr("test_volume/file").await.unwrap();
+            assert_eq!(get_file_attrs(), 2);
+
+            client.delete_file(root, name).await.unwrap();
+            assert_eq!(client.lookup(root, name).await.unwrap_err(), libc::ENOENT);
+            assert_eq!(get_file_attrs(), 3);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_readahead() {
+        let address = "127.0.0.1:50083";
+        let db_path = "/tmp/test_readahead_db";
+        {
+            let engine = new_cluster_engine(address, &[address], "/tmp/test_readahead", db_path);
+            let handler = Arc::new(CountingHandler {
+                handler: FileRequestHandler::new(engine.clone()),
+                get_file_attrs: AtomicUsize::new(0),
+                read_files: AtomicUsize::new(0),
+            });
+            let server = RpcServer::new(handler.clone(), address);
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let data: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+            let (status, _) = create_and_write(&handler.handler, "file", data.clone()).await;
+            assert_eq!(status, 0);
+
+            let client = Arc::new(Client::new());
+            client.client.add_connection(address).await.unwrap();
+            client
+                .hash_ring
+                .write()
+                .replace(HashRing::new(vec![(address.to_owned(), 100)]));
+            client
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            client.readahead_blocks.store(8, Ordering::Relaxed);
+            let (root, _) = client.init_volume("test_volume").await.unwrap();
+            let ino = client.lookup(root, OsStr::new("file")).await.unwrap().ino;
+            let read_files = || handler.read_files.load(Ordering::Relaxed);
+
+            let mut read = Vec::new();
+            let mut reads = 0;
+            loop {
+                let chunk = client.read(ino, read.len() as i64, 4096).await.unwrap();
+                reads += 1;
+                if chunk.is_empty() {
+                    break;
+                }
+                read.extend(chunk);
+            }
+            assert_eq!(read, data);
+            assert_eq!(reads, 65);
+            // a request for each window of 8 reads, and one for the first read
+            assert!(read_files() <= reads / 8 + 2, "{} requests", read_files());
+
+            // a random read goes to the server alone
+            let before = read_files();
+            let chunk = client.read(ino, 100_000, 4096).await.unwrap();
+            assert_eq!(chunk, data[100_000..104_096]);
+            assert_eq!(read_files(), before + 1);
+
+            client.readahead_blocks.store(0, Ordering::Relaxed);
+            let chunk = client.read(ino, 104_096, 4096).await.unwrap();
+            assert_eq!(chunk, data[104_096..108_192]);
+            assert_eq!(read_files(), before + 2);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_throttled_read() {
+        // a host of its own, the client connects from 127.0.0.1 and is no server
+        let address = "127.0.0.2:50084";
+        let db_path = "/tmp/test_throttled_read_db";
+        {
+            let engine =
+                new_cluster_engine(address, &[address], "/tmp/test_throttled_read", db_path);
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let server = RpcServer::new(handler.clone(), address);
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let data: Vec<u8> = (0..16384).map(|i| (i % 251) as u8).collect();
+            let (status, _) = create_and_write(&handler, "file", data.clone()).await;
+            assert_eq!(status, 0);
+            engine.client_bandwidth.set_limit(8192);
+
+            // requests from other servers are let through, ids past the ones the server
+            // gives its connections
+            let read = bincode::serialize(&ReadFileSendMetaData {
+                offset: 0,
+                size: 16384,
+                verify_checksum: false,
+            })
+            .unwrap();
+            handler.connection_opened(1001, Some(([127, 0, 0, 2], 40000).into()));
+            for _ in 0..2 {
+                let (status, ..) = handler
+                    .dispatch(
+                        1001,
+                        OperationType::ReadFile.into(),
+                        0,
+                        "test_volume/file".as_bytes().to_vec(),
+                        vec![],
+                        read.clone(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+            }
+            assert!(engine.client_bandwidth.client_infos().is_empty());
+
+            // a client claiming to be a server in the header is still counted
+            handler.connection_opened(1002, Some(([127, 0, 0, 1], 40000).into()));
+            handler
+                .dispatch(
+                    1002,
+                    OperationType::ReadFile.into(),
+                    FROM_SERVER,
+                    "test_volume/file".as_bytes().to_vec(),
+                    vec![],
+                    read.clone(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(engine.client_bandwidth.client_infos().len(), 1);
+            handler.connection_closed(1002);
+
+            // the client waits out the throttle and reads again
+            let client = Arc::new(Client::new());
+            client.client.add_connection(address).await.unwrap();
+            client
+                .hash_ring
+                .write()
+                .replace(HashRing::new(vec![(address.to_owned(), 100)]));
+            client
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            client.readahead_blocks.store(0, Ordering::Relaxed);
+            let (root, _) = client.init_volume("test_volume").await.unwrap();
+            let ino = client.lookup(root, OsStr::new("file")).await.unwrap().ino;
+            let start = Instant::now();
+            let first = client.read(ino, 0, 8192).await.unwrap();
+            let second = client.read(ino, 8192, 8192).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(500));
+            assert_eq!([first, second].concat(), data);
+            assert_eq!(engine.client_bandwidth.client_infos().len(), 1);
+
+            // what the manager sees
+            engine.admin_hosts.insert([127, 0, 0, 2].into());
+            let (status, _, meta_data_length, _, meta_data, _) = handler
+                .dispatch(
+                    1001,
+                    ManagerOperationType::GetClientBandwidth.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let clients: Vec<ClientBandwidthInfo> =
+                bincode::deserialize(&meta_data[..meta_data_length]).unwrap();
+            assert_eq!(clients.len(), 1);
+            assert!(clients[0].read_bytes >= 16384);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_sharded_directory() {
+        let (address_a, address_b) = ("127.0.0.1:50062", "127.0.0.1:50063");
+        let (db_path_a, db_path_b) = ("/tmp/test_shard_a_db", "/tmp/test_shard_b_db");
+        {
+            let servers = [address_a, address_b];
+            let engine_a = new_cluster_engine(address_a, &servers, "/tmp/test_shard_a", db_path_a);
+            let engine_b = new_cluster_engine(address_b, &servers, "/tmp/test_shard_b", db_path_b);
+            let server_b = RpcServer::new(
+                Arc::new(FileRequestHandler::new(engine_b.clone())),
+                address_b,
+            );
+            tokio::spawn(async move { server_b.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            engine_a
+                .add_connection(address_b.to_string())
+                .await
+                .unwrap();
+            engine_a.dir_shard_threshold.store(4, Ordering::Relaxed);
+
+            let dir_name = (0..)
+                .map(|i| format!("big_{}", i))
+                .find(|name| engine_a.get_address(&format!("test_volume/{}", name)) == address_a)
+                .unwrap();
+            let dir = format!("test_volume/{}", dir_name);
+            engine_a
+                .create_dir(
+                    bincode::serialize(&CreateDirSendMetaData {
+                        mode: 0o755,
+                        name: dir_name.clone(),
+                    })
+                    .unwrap(),
+                    "test_volume",
+                    &dir_name,
+                    0o755,
+                )
+                .await
+                .unwrap();
+
+            let names: Vec<String> = (0..20).map(|i| format!("file_{}", i)).collect();
+            for name in &names {
+                let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                    mode: 0o644,
+                    umask: 0,
+                    flags: libc::O_CREAT | libc::O_RDWR,
+                    name: name.clone(),
+                    tmpfile: false,
+                })
+                .unwrap();
+                engine_a
+                    .create_file(
+                        send_meta_data,
+                        &dir,
+                        name,
+                        libc::O_CREAT | libc::O_RDWR,
+                        0,
+                        0o644,
+                    )
+                    .await
+                    .unwrap();
+            }
+            assert!(engine_a.meta_engine.is_sharded_directory(&dir));
+            let (_, local) = engine_a
+                .meta_engine
+                .read_directory_shard(&dir, 4096, 0)
+                .unwrap();
+            let (_, remote) = engine_b
+                .meta_engine
+                .read_directory_shard(&dir, 4096, 0)
+                .unwrap();
+            assert!(remote.total > 0);
+            assert_eq!(local.total + remote.total, 20);
+
+            // page through with a small size so reads stop inside a shard
+            let read_all = || async {
+                let mut listed = Vec::new();
+                loop {
+                    let data = engine_a
+                        .read_dir(&dir, 40, listed.len() as i64)
+                        .await
+                        .unwrap();
+                    if data.is_empty() {
+                        break;
+                    }
+                    for entry in DirentDecoder::new(data.as_slice()) {
+                        listed.push(entry.unwrap().1);
+                    }
+                }
+                listed.sort();
+                listed
+            };
+            let mut expected = names.clone();
+            expected.sort();
+            assert_eq!(read_all().await, expected);
+
+            // an entry held by the other server
+            let remote_name = names
+                .iter()
+                .rev()
+                .find(|name| engine_a.get_address(&format!("{}/{}", dir, name)) == address_b)
+                .unwrap()
+                .clone();
+            engine_a
+                .delete_file(
+                    bincode::serialize(&DeleteFileSendMetaData {
+                        name: remote_name.clone(),
+                    })
+                    .unwrap(),
+                    &dir,
+                    &remote_name,
+                )
+                .await
+                .unwrap();
+            expected.retain(|name| name != &remote_name);
+            assert_eq!(read_all().await, expected);
+            assert_eq!(engine_a.meta_engine.directory_entry_count(&dir), Ok(19));
+        }
+        destroy_db(db_path_a);
+        destroy_db(db_path_b);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_volume() {
+        let (address_a, address_b) = ("127.0.0.1:50066", "127.0.0.1:50067");
+        let (db_path_a, db_path_b) = ("/tmp/test_rebalance_a_db", "/tmp/test_rebalance_b_db");
+        {
+            let engine_a =
+                new_cluster_engine(address_a, &[address_a], "/tmp/test_rebalance_a", db_path_a);
+            let engine_b = new_cluster_engine(
+                address_b,
+                &[address_a, address_b],
+                "/tmp/test_rebalance_b",
+                db_path_b,
+            );
+            let server_b = RpcServer::new(
+                Arc::new(FileRequestHandler::new(engine_b.clone())),
+                address_b,
+            );
+            tokio::spawn(async move { server_b.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            engine_a
+                .add_connection(address_b.to_string())
+                .await
+                .unwrap();
+
+            // everything is written while engine_a is the only server of the ring
+            let volumes = ["moving_volume", "staying_volume"];
+            let mut paths = Vec::new();
+            for volume in volumes {
+                engine_a
+                    .create_volume(volume, 0, AtimePolicy::default(), None, None)
+                    .unwrap();
+                for i in 0..20 {
+                    let name = format!("file_{}", i);
+                    let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                        mode: 0o644,
+                        umask: 0,
+                        flags: libc::O_CREAT | libc::O_RDWR,
+                        name: name.clone(),
+                        tmpfile: false,
+                    })
+                    .unwrap();
+                    engine_a
+                        .create_file(
+                            send_meta_data,
+                            volume,
+                            &name,
+                            libc::O_CREAT | libc::O_RDWR,
+                            0,
+                            0o644,
+                        )
+                        .await
+                        .unwrap();
+                    let path = format!("{}/{}", volume, name);
+                    engine_a
+                        .write_file(&path, name.as_bytes(), 0, None)
+                        .await
+                        .unwrap();
+                    paths.push(path);
+                }
+            }
+            engine_a.hash_ring.write().replace(HashRing::new(vec![
+                (address_a.to_string(), 100),
+                (address_b.to_string(), 100),
+            ]));
+
+            let handler = admin_handler(engine_a.clone());
+            let rebalance = |volume: &str| {
+                handler.dispatch(
+                    0,
+                    ManagerOperationType::RebalanceVolume.into(),
+                    0,
+                    vec![],
+                    vec![],
+                    bincode::serialize(&RebalanceVolumeSendMetaData {
+                        volume: volume.to_string(),
+                    })
+                    .unwrap(),
+                )
+            };
+            let (status, _, _, _, _, _) = rebalance("moving_volume").await.unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(
+                engine_a.cluster_status.load(Ordering::Relaxed),
+                i32::from(ClusterStatus::Idle)
+            );
+
+            let mut moved = 0;
+            for path in &paths {
+                let name = path.rsplit('/').next().unwrap();
+                let on_a = engine_a.meta_engine.get_file_attr(path).is_ok();
+                let on_b = engine_b.meta_engine.get_file_attr(path).is_ok();
+                if path.starts_with("moving_volume/") && engine_a.get_address(path) == address_b {
+                    assert!(!on_a && on_b, "{} was not moved", path);
+                    assert_eq!(
+                        engine_b.read_file(path, 4096, 0).await.unwrap(),
+                        name.as_bytes()
+                    );
+                    moved += 1;
+                } else {
+                    // the other volume stays where it was placed, even where the ring disagrees
+                    assert!(on_a && !on_b, "{} was moved", path);
+                }
+            }
+            assert!(moved > 0);
+
+            let (status, _, _, _, _, _) = rebalance("missing_volume").await.unwrap();
+            assert_eq!(status, libc::ENOENT);
+        }
+        destroy_db(db_path_a);
+        destroy_db(db_path_b);
+    }
+
+    #[tokio::test]
+    async fn test_drain_node() {
+        let addresses = ["127.0.0.1:50078", "127.0.0.1:50079", "127.0.0.1:50080"];
+        let db_paths = [
+            "/tmp/test_drain_a_db",
+            "/tmp/test_drain_b_db",
+            "/tmp/test_drain_c_db",
+        ];
+        {
+            let engines: Vec<_> = ["a", "b", "c"]
+                .iter()
+                .zip(addresses.iter().zip(db_paths))
+                .map(|(name, (address, db_path))| {
+                    new_cluster_engine(
+                        address,
+                        &addresses,
+                        &format!("/tmp/test_drain_{}", name),
+                        db_path,
+                    )
+                })
+                .collect();
+            for (engine, address) in engines.iter().zip(addresses) {
+                let server =
+                    RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+                tokio::spawn(async move { server.run().await });
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            for engine in &engines {
+                for address in addresses {
+                    if address != engine.address {
+                        engine.add_connection(address.to_string()).await.unwrap();
+                    }
+                }
+            }
+            let engine_of = |address: &str| {
+                engines
+                    .iter()
+                    .find(|engine| engine.address == address)
+                    .unwrap()
+            };
+
+            // the volume is placed on one server and its files spread over all three
+            let volume = "test_volume";
+            let root_owner = engine_of(engines[0].get_address(volume).as_str());
+            for engine in &engines {
+                if engine.address != root_owner.address {
+                    engine.meta_engine.delete_volume(volume).unwrap();
+                }
+            }
+            let mut paths = Vec::new();
+            for i in 0..30 {
+                let name = format!("file_{}", i);
+                let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                    mode: 0o644,
+                    umask: 0,
+                    flags: libc::O_CREAT | libc::O_RDWR,
+                    name: name.clone(),
+                    tmpfile: false,
+                })
+                .unwrap();
+                root_owner
+                    .create_file(
+                        send_meta_data,
+                        volume,
+                        &name,
+                        libc::O_CREAT | libc::O_RDWR,
+                        0,
+                        0o644,
+                    )
+                    .await
+                    .unwrap();
+                let path = format!("{}/{}", volume, name);
+                engine_of(engines[0].get_address(&path).as_str())
+                    .write_file(&path, name.as_bytes(), 0, None)
+                    .await
+                    .unwrap();
+                paths.push(path);
+            }
+
+            // what every server does once the manager drains a server other than the root's
+            let drained = engines
+                .iter()
+                .find(|engine| engine.address != root_owner.address)
+                .unwrap();
+            let drained_paths: Vec<&String> = paths
+                .iter()
+                .filter(|path| drained.get_address(path) == drained.address)
+                .collect();
+            assert!(!drained_paths.is_empty());
+            let new_ring = HashRing::new(
+                addresses
+                    .iter()
+                    .filter(|address| **address != drained.address)
+                    .map(|address| (address.to_string(), 100))
+                    .collect(),
+            );
+            for engine in &engines {
+                engine.new_hash_ring.write().replace(new_ring.clone());
+                engine
+                    .cluster_status
+                    .store(ClusterStatus::Transferring.into(), Ordering::Relaxed);
+            }
+            for engine in &engines {
+                let file_map = engine.make_up_file_map();
+                if engine.address != drained.address {
+                    assert!(file_map.is_empty());
+                }
+                engine.transfer_files(file_map).await.unwrap();
+            }
+            for engine in &engines {
+                engine.hash_ring.write().replace(new_ring.clone());
+                engine.new_hash_ring.write().take();
+                engine
+                    .cluster_status
+                    .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            }
+
+            // every file the drained server held is served by its new owner
+            let client = Client::new();
+            for address in addresses {
+                client.client.add_connection(address).await.unwrap();
+            }
+            for path in &paths {
+                let owner = &new_ring.get(path).unwrap().address;
+                assert_ne!(owner, &drained.address);
+                let name = path.rsplit('/').next().unwrap();
+                assert_eq!(
+                    client.sender.read_file(owner, path, 4096, 0).await,
+                    Ok(name.as_bytes().to_vec()),
+                    "{} is not reachable",
+                    path
+                );
+            }
+            for path in drained_paths {
+                let attr = engine_of(&new_ring.get(path).unwrap().address)
+                    .meta_engine
+                    .get_file_attr(path)
+                    .unwrap();
+                assert_eq!(attr.size, path.rsplit('/').next().unwrap().len() as u64);
+            }
+        }
+        for db_path in db_paths {
+            destroy_db(db_path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_volume_quota() {
+        let db_path = "/tmp/test_volume_quota_db";
+        {
+            let engine = new_engine("/tmp/test_volume_quota", db_path);
+            engine
+                .meta_engine
+                .volumes
+                .get_mut("test_volume")
+                .unwrap()
+                .size = 100;
+            let handler = FileRequestHandler::new(engine.clone());
+            let used_size = || {
+                engine
+                    .meta_engine
+                    .volumes
+                    .get("test_volume")
+                    .unwrap()
+                    .used_size
+            };
+            let write = |name: &str, data: Vec<u8>, offset: i64| {
+                handler.dispatch(
+                    0,
+                    OperationType::WriteFile.into(),
+                    0,
+                    format!("test_volume/{}", name).into_bytes(),
+                    data,
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+
+            let (status, _) = create_and_write(&handler, "a", vec![1u8; 60]).await;
+            assert_eq!(status, 0);
+            let (status, _) = create_and_write(&handler, "b", vec![]).await;
+            assert_eq!(status, 0);
+            assert_eq!(used_size(), 60);
+            assert_eq!(write("b", vec![2u8; 40], 0).await.unwrap().0, 0);
+            assert_eq!(used_size(), 100);
+
+            // the volume is full, only growing a file is refused
+            assert_eq!(write("b", vec![3u8; 1], 40).await.unwrap().0, libc::ENOSPC);
+            assert_eq!(write("b", vec![3u8; 20], 30).await.unwrap().0, libc::ENOSPC);
+            assert_eq!(write("b", vec![3u8; 10], 30).await.unwrap().0, 0);
+            let truncate = |name: &str, length: i64| {
+                handler.dispatch(
+                    0,
+                    OperationType::TruncateFile.into(),
+                    0,
+                    format!("test_volume/{}", name).into_bytes(),
+                    vec![],
+                    bincode::serialize(&TruncateFileSendMetaData { length }).unwrap(),
+                )
+            };
+            assert_eq!(truncate("a", 61).await.unwrap().0, libc::ENOSPC);
+            assert_eq!(used_size(), 100);
+            assert_eq!(truncate("a", 50).await.unwrap().0, 0);
+            assert_eq!(used_size(), 90);
+            let (status, _) = create_and_write(&handler, "c", vec![4u8; 11]).await;
+            assert_eq!(status, libc::ENOSPC);
+            assert_eq!(used_size(), 90);
+            assert_eq!(
+                engine.get_file_attr("test_volume/c").await,
+                Err(libc::ENOENT)
+            );
+
+            let (status, _, _, _, _, _) = handler
+                .dispatch(
+                    0,
+                    OperationType::DeleteFile.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&DeleteFileSendMetaData {
+                        name: "a".to_owned(),
+                    })
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            assert_eq!(used_size(), 40);
+            assert_eq!(write("b", vec![5u8; 60], 40).await.unwrap().0, 0);
+            assert_eq!(used_size(), 100);
+            assert_eq!(
+                engine
+                    .read_file("test_volume/b", 200, 0)
+                    .await
+                    .unwrap()
+                    .len(),
+                100
+            );
+
+            // moved data was charged on the server it came from, only a server moves it
+            let (status, _) = create_and_write(&handler, "d", vec![]).await;
+            assert_eq!(status, 0);
+            let write_moved = |id: u32| {
+                handler.dispatch(
+                    id,
+                    OperationType::WriteFile.into(),
+                    MOVED_DATA,
+                    "test_volume/d".as_bytes().to_vec(),
+                    vec![6u8; 10],
+                    bincode::serialize(&WriteFileSendMetaData {
+                        offset: 0,
+                        if_mtime: None,
+                        checksum: None,
+                        append: false,
+                    })
+                    .unwrap(),
+                )
+            };
+            assert_eq!(write_moved(0).await.unwrap().0, libc::EPERM);
+            assert_eq!(
+                engine
+                    .meta_engine
+                    .get_file_attr("test_volume/d")
+                    .unwrap()
+                    .size,
+                0
+            );
+            handler.connection_opened(1, Some(([127, 0, 0, 1], 40000).into()));
+            assert_eq!(write_moved(1).await.unwrap().0, 0);
+            assert_eq!(used_size(), 100);
+
+            // and only a server charges the volume
+            let charge = |id: u32| {
+                handler.dispatch(
+                    id,
+                    OperationType::ChargeVolume.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ChargeVolumeSendMetaData { delta: -100 }).unwrap(),
+                )
+            };
+            assert_eq!(charge(0).await.unwrap().0, libc::EPERM);
+            assert_eq!(used_size(), 100);
+            assert_eq!(charge(1).await.unwrap().0, 0);
+            assert_eq!(used_size(), 0);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_resize_volume() {
+        let db_path = "/tmp/test_resize_volume_db";
+        {
+            let engine = new_engine("/tmp/test_resize_volume", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let resize = |volume_name: &str, new_size: u64| {
+                handler.dispatch(
+                    0,
+                    OperationType::ResizeVolume.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&ResizeVolumeSendMetaData {
+                        volume_name: volume_name.to_owned(),
+                        new_size,
+                    })
+                    .unwrap(),
+                )
+            };
+            let size = || engine.meta_engine.volumes.get("test_volume").unwrap().size;
+
+            assert_eq!(resize("test_volume", 100).await.unwrap().0, 0);
+            let (status, _) = create_and_write(&handler, "a", vec![1u8; 80]).await;
+            assert_eq!(status, 0);
+            let (status, _) = create_and_write(&handler, "b", vec![2u8; 40]).await;
+            assert_eq!(status, libc::ENOSPC);
+
+            // growing the volume makes room for the write refused before
+            assert_eq!(resize("test_volume", 200).await.unwrap().0, 0);
+            assert_eq!(size(), 200);
+            let (status, _) = create_and_write(&handler, "b", vec![2u8; 40]).await;
+            assert_eq!(status, 0);
+
+            // shrinking it to what is used is fine, below that is not
+            assert_eq!(resize("test_volume", 120).await.unwrap().0, 0);
+            assert_eq!(size(), 120);
+            let (status, _) = create_and_write(&handler, "c", vec![3u8; 1]).await;
+            assert_eq!(status, libc::ENOSPC);
+            assert_eq!(resize("test_volume", 119).await.unwrap().0, libc::ENOSPC);
+            assert_eq!(size(), 120);
+
+            // 0 lifts the quota
+            assert_eq!(resize("test_volume", 0).await.unwrap().0, 0);
+            let (status, _) = create_and_write(&handler, "c", vec![3u8; 1]).await;
+            assert_eq!(status, 0);
+
+            assert_eq!(resize("other_volume", 100).await.unwrap().0, libc::EINVAL);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_stat_fs() {
+        let db_path = "/tmp/test_stat_fs_db";
+        {
+            let engine = new_engine("/tmp/test_stat_fs", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let stat_fs = || async {
+                let (status, _, meta_data_length, _, meta_data, _) = handler
+                    .dispatch(
+                        0,
+                        OperationType::StatFs.into(),
+                        0,
+                        "test_volume".as_bytes().to_vec(),
+                        vec![],
+                        vec![],
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(status, 0);
+                bincode::deserialize::<StatFsRecvMetaData>(&meta_data[..meta_data_length]).unwrap()
+            };
+
+            // without a quota the volume is only limited by the servers
+            let empty = stat_fs().await;
+            assert_eq!(empty.total_bytes, u64::MAX);
+            assert_eq!(empty.free_bytes, u64::MAX);
+            assert_eq!(empty.block_size, 4096);
+
+            engine
+                .meta_engine
+                .volumes
+                .get_mut("test_volume")
+                .unwrap()
+                .size = 1 << 20;
+            let (status, _) = create_and_write(&handler, "a", vec![1u8; 5000]).await;
+            assert_eq!(status, 0);
+            let (status, _) = create_and_write(&handler, "b", vec![2u8; 3000]).await;
+            assert_eq!(status, 0);
+
+            let stat = stat_fs().await;
+            let volume = engine
+                .meta_engine
+                .volumes
+                .get("test_volume")
+                .unwrap()
+                .clone();
+            assert_eq!(volume.used_size, 8000);
+            assert_eq!(stat.total_bytes, volume.size);
+            assert_eq!(stat.free_bytes, volume.size - volume.used_size);
+            assert_eq!(stat.total_inodes, u64::MAX);
+            assert_eq!(stat.free_inodes, empty.free_inodes - 2);
+
+            let (status, ..) = handler
+                .dispatch(
+                    0,
+                    OperationType::StatFs.into(),
+                    0,
+                    "no_volume".as_bytes().to_vec(),
+                    vec![],
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, libc::ENOENT);
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_volume() {
+        let (address_a, address_b) = ("127.0.0.1:50068", "127.0.0.1:50069");
+        let (db_path_a, db_path_b) = ("/tmp/test_migrate_a_db", "/tmp/test_migrate_b_db");
+        {
+            let engine_a =
+                new_cluster_engine(address_a, &[address_a], "/tmp/test_migrate_a", db_path_a);
+            let engine_b = new_cluster_engine(
+                address_b,
+                &[address_a, address_b],
+                "/tmp/test_migrate_b",
+                db_path_b,
+            );
+            for (engine, address) in [(&engine_a, address_a), (&engine_b, address_b)] {
+                let server =
+                    RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+                tokio::spawn(async move { server.run().await });
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            engine_a
+                .add_connection(address_b.to_string())
+                .await
+                .unwrap();
+            engine_b
+                .add_connection(address_a.to_string())
+                .await
+                .unwrap();
+
+            // everything is written while engine_a is the only server of the ring
+            let volume = "migrating_volume";
+            engine_a
+                .create_volume(volume, 0, AtimePolicy::default(), None, None)
+                .unwrap();
+            let mut paths = Vec::new();
+            for i in 0..20 {
+                let name = format!("file_{}", i);
+                let send_meta_data = bincode::serialize(&CreateFileSendMetaData {
+                    mode: 0o644,
+                    umask: 0,
+                    flags: libc::O_CREAT | libc::O_RDWR,
+                    name: name.clone(),
+                    tmpfile: false,
+                })
+                .unwrap();
+                engine_a
+                    .create_file(
+                        send_meta_data,
+                        volume,
+                        &name,
+                        libc::O_CREAT | libc::O_RDWR,
+                        0,
+                        0o644,
+                    )
+                    .await
+                    .unwrap();
+                let path = format!("{}/{}", volume, name);
+                engine_a
+                    .write_file(&path, name.as_bytes(), 0, None)
+                    .await
+                    .unwrap();
+                paths.push(path);
+            }
+            engine_a.hash_ring.write().replace(HashRing::new(vec![
+                (address_a.to_string(), 100),
+                (address_b.to_string(), 100),
+            ]));
+
+            let migrate =
+                |engine: &Arc<DistributedEngine<FileEngine>>, volume: &str, target: &str| {
+                    let handler = admin_handler(engine.clone());
+                    let metadata = bincode::serialize(&MigrateVolumeSendMetaData {
+                        volume: volume.to_string(),
+                        target_server: target.to_string(),
+                    })
+                    .unwrap();
+                    async move {
+                        handler
+                            .dispatch(
+                                0,
+                                ManagerOperationType::MigrateVolume.into(),
+                                0,
+                                vec![],
+                                vec![],
+                                metadata,
+                            )
+                            .await
+                            .unwrap()
+                            .0
+                    }
+                };
+            assert_eq!(
+                migrate(&engine_a, volume, "127.0.0.1:1").await,
+                libc::EINVAL
+            );
+            assert_eq!(
+                migrate(&engine_a, "missing_volume", address_b).await,
+                libc::ENOENT
+            );
+            assert_eq!(migrate(&engine_a, volume, address_b).await, 0);
+            assert_eq!(migrate(&engine_b, volume, address_b).await, 0);
+
+            for path in &paths {
+                let name = path.rsplit('/').next().unwrap();
+                assert!(engine_a.meta_engine.get_file_attr(path).is_err());
+                assert_eq!(
+                    engine_b.read_file(path, 4096, 0).await.unwrap(),
+                    name.as_bytes()
+                );
+                // the source sends callers to the target, which serves the file itself
+                assert_eq!(engine_a.moved_to(path).unwrap().new_server, address_b);
+                assert!(engine_b.moved_to(path).is_none());
+            }
+        }
+        destroy_db(db_path_a);
+        destroy_db(db_path_b);
+    }
+
+    async fn copy_file_range(
+        handler: &FileRequestHandler<FileEngine>,
+        src_path: &str,
+        src_offset: i64,
+        dst_path: &str,
+        dst_offset: i64,
+        length: u64,
+    ) -> (i32, u64) {
+        let metadata = bincode::serialize(&CopyFileRangeSendMetaData {
+            src_ino: 2,
+            src_path: src_path.to_string(),
+            src_offset,
+            dst_ino: 3,
+            dst_offset,
+            length,
+        })
+        .unwrap();
+        let (status, _, meta_data_length, _, meta_data, _) = handler
+            .dispatch(
+                0,
+                OperationType::CopyFileRange.into(),
+                0,
+                dst_path.as_bytes().to_vec(),
+                vec![],
+                metadata,
+            )
+            .await
+            .unwrap();
+        match status {
+            0 => (
+                0,
+                u64::from_le_bytes(meta_data[..meta_data_length].try_into().unwrap()),
+            ),
+            _ => (status, 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_range() {
+        let db_path = "/tmp/test_copy_file_range_db";
+        {
+            let engine = new_engine("/tmp/test_copy_file_range", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            // more than one chunk of the copy
+            let data: Vec<u8> = (0..(5 << 19)).map(|i| (i % 251) as u8).collect();
+            for name in ["src", "dst"] {
+                let (status, _) = create_and_write(&handler, name, vec![]).await;
+                assert_eq!(status, 0);
+            }
+            engine
+                .write_file("test_volume/src", &data, 0, None)
+                .await
+                .unwrap();
+
+            let copied = copy_file_range(
+                &handler,
+                "test_volume/src",
+                0,
+                "test_volume/dst",
+                0,
+                data.len() as u64,
+            )
+            .await;
+            assert_eq!(copied, (0, data.len() as u64));
+            assert!(
+                engine
+                    .read_file("test_volume/dst", data.len() as u32 + 1, 0)
+                    .await
+                    .unwrap()
+                    == data
+            );
+
+            // a range running past the end of the source is copied up to it
+            let copied = copy_file_range(
+                &handler,
+                "test_volume/src",
+                100,
+                "test_volume/dst",
+                10,
+                1 << 30,
+            )
+            .await;
+            assert_eq!(copied, (0, data.len() as u64 - 100));
+            let dst = engine
+                .read_file("test_volume/dst", data.len() as u32 + 1, 0)
+                .await
+                .unwrap();
+            // the copy ends 90 bytes short of the old end of the destination
+            let end = data.len() - 90;
+            assert_eq!(dst.len(), data.len());
+            assert!(dst[..10] == data[..10]);
+            assert!(dst[10..end] == data[100..]);
+            assert!(dst[end..] == data[end..]);
+            assert_eq!(
+                copy_file_range(
+                    &handler,
+                    "test_volume/src",
+                    data.len() as i64,
+                    "test_volume/dst",
+                    0,
+                    10
+                )
+                .await,
+                (0, 0)
+            );
+
+            // overlapping ranges of one file, negative offsets and a missing source
+            assert_eq!(
+                copy_file_range(&handler, "test_volume/src", 0, "test_volume/src", 10, 20).await,
+                (libc::EINVAL, 0)
+            );
+            assert_eq!(
+                copy_file_range(&handler, "test_volume/src", -1, "test_volume/dst", 0, 20).await,
+                (libc::EINVAL, 0)
+            );
+            assert_eq!(
+                copy_file_range(&handler, "test_volume/missing", 0, "test_volume/dst", 0, 20)
+                    .await
+                    .0,
+                libc::ENOENT
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_fallocate() {
+        let db_path = "/tmp/test_fallocate_db";
+        {
+            let engine = new_engine("/tmp/test_fallocate", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "a", vec![7u8; 3 * 4096]).await;
+            assert_eq!(status, 0);
+            let fallocate = |offset: i64, length: i64, mode: i32| {
+                handler.dispatch(
+                    0,
+                    OperationType::Fallocate.into(),
+                    0,
+                    "test_volume/a".as_bytes().to_vec(),
+                    vec![],
+                    bincode::serialize(&FallocateSendMetaData {
+                        offset,
+                        length,
+                        mode: mode as u32,
+                    })
+                    .unwrap(),
+                )
+            };
+            let attr = || engine.meta_engine.get_file_attr("test_volume/a").unwrap();
+            let used_size = || {
+                engine
+                    .meta_engine
+                    .volumes
+                    .get("test_volume")
+                    .unwrap()
+                    .used_size
+            };
+
+            // space kept past the end shows in blocks, not in the size
+            let blocks = attr().blocks;
+            assert_eq!(
+                fallocate(3 * 4096, 1 << 20, libc::FALLOC_FL_KEEP_SIZE)
+                    .await
+                    .unwrap()
+                    .0,
+                0
+            );
+            assert!(attr().blocks >= blocks + (1 << 20) / 512);
+            assert_eq!(attr().size, 3 * 4096);
+            assert_eq!(used_size(), 3 * 4096);
+
+            // without FALLOC_FL_KEEP_SIZE the file grows, and is charged for it
+            assert_eq!(fallocate(3 * 4096, 4096, 0).await.unwrap().0, 0);
+            assert_eq!(attr().size, 4 * 4096);
+            assert_eq!(used_size(), 4 * 4096);
+
+            let punch = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+            assert_eq!(fallocate(4096, 4096, punch).await.unwrap().0, 0);
+            assert_eq!(attr().size, 4 * 4096);
+            let data = engine
+                .read_file("test_volume/a", 5 * 4096, 0)
+                .await
+                .unwrap();
+            assert_eq!(data.len(), 4 * 4096);
+            assert!(data[..4096] == [7u8; 4096]);
+            assert!(data[4096..2 * 4096] == [0u8; 4096]);
+            assert!(data[2 * 4096..3 * 4096] == [7u8; 4096]);
+            assert!(data[3 * 4096..] == [0u8; 4096]);
+
+            assert_eq!(
+                fallocate(0, 4096, libc::FALLOC_FL_PUNCH_HOLE)
+                    .await
+                    .unwrap()
+                    .0,
+                libc::EOPNOTSUPP
+            );
+            assert_eq!(fallocate(-1, 4096, 0).await.unwrap().0, libc::EINVAL);
+            assert_eq!(fallocate(0, 0, 0).await.unwrap().0, libc::EINVAL);
+        }
+        destroy_db(db_path);
+    }
+
+    fn create_file_op(name: &str) -> BatchOp {
+        BatchOp {
+            operation_type: OperationType::CreateFile.into(),
+            path: "test_volume".to_owned(),
+            meta_data: bincode::serialize(&CreateFileSendMetaData {
+                mode: 0o644,
+                umask: 0,
+                flags: libc::O_CREAT | libc::O_EXCL,
+                name: name.to_owned(),
+                tmpfile: false,
+            })
+            .unwrap(),
+        }
+    }
+
+    fn list_volume(entries: Vec<u8>) -> Vec<String> {
+        let mut names: Vec<String> = DirentDecoder::new(entries.as_slice())
+            .map(|entry| entry.unwrap().1)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let address = "127.0.0.1:50074";
+        let db_path = "/tmp/test_batch_db";
+        {
+            let engine = new_cluster_engine(address, &[address], "/tmp/test_batch", db_path);
+            let server = RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let client = Client::new();
+            client.client.add_connection(address).await.unwrap();
+
+            let names: Vec<String> = (0..10).map(|i| format!("file{}", i)).collect();
+            let ops = names.iter().map(|name| create_file_op(name)).collect();
+            let result = client
+                .sender
+                .batch(address, "test_volume", ops)
+                .await
+                .unwrap();
+            assert_eq!(result.completed, 10);
+            assert_eq!(result.results.len(), 10);
+            for result in result.results {
+                assert_eq!(result.status, 0);
+                assert_eq!(
+                    bytes_as_file_attr(&result.meta_data).kind,
+                    FileType::RegularFile
+                );
+            }
+            let entries = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+            assert_eq!(list_volume(entries), names);
+
+            // an operation a batch can not carry fails the whole batch up front
+            let ops = vec![
+                create_file_op("other"),
+                BatchOp {
+                    operation_type: OperationType::Batch.into(),
+                    path: "test_volume".to_owned(),
+                    meta_data: vec![],
+                },
+            ];
+            assert_eq!(
+                client.sender.batch(address, "test_volume", ops).await,
+                Err(libc::EINVAL)
+            );
+            assert_eq!(
+                engine.get_file_attr("test_volume/other").await,
+                Err(libc::ENOENT)
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_batch_stops_at_failure() {
+        let db_path = "/tmp/test_batch_failure_db";
+        {
+            let engine = new_engine("/tmp/test_batch_failure", db_path);
+            let handler = FileRequestHandler::new(engine.clone());
+            // the fifth creates the file the first did
+            let names: Vec<String> = (0..10)
+                .map(|i| format!("file{}", if i == 4 { 0 } else { i }))
+                .collect();
+            let data = bincode::serialize(&BatchSendData {
+                ops: names.iter().map(|name| create_file_op(name)).collect(),
+            })
+            .unwrap();
+            let (status, _, _, data_length, _, data) = handler
+                .dispatch(
+                    0,
+                    OperationType::Batch.into(),
+                    0,
+                    "test_volume".as_bytes().to_vec(),
+                    data,
+                    vec![],
+                )
+                .await
+                .unwrap();
+            assert_eq!(status, 0);
+            let result: BatchRecvData = bincode::deserialize(&data[..data_length]).unwrap();
+            assert_eq!(result.completed, 4);
+            assert_eq!(result.results.len(), 5);
+            assert!(result.results[..4].iter().all(|result| result.status == 0));
+            assert_eq!(result.results[4].status, libc::EEXIST);
+
+            let entries = engine.read_dir("test_volume", 4096, 0).await.unwrap();
+            assert_eq!(list_volume(entries), names[..4]);
+            for name in &names[5..] {
+                assert_eq!(
+                    engine.get_file_attr(&format!("test_volume/{}", name)).await,
+                    Err(libc::ENOENT)
+                );
+            }
+        }
+        destroy_db(db_path);
+    }
+
+    // relays one direction of a connection, flipping the last byte of every
+    // message that carries data when corrupt is set
+    async fn forward(
+        mut read: tokio::net::tcp::OwnedReadHalf,
+        mut write: tokio::net::tcp::OwnedWriteHalf,
+        header_size: usize,
+        corrupt: bool,
+    ) {
+        let mut header = vec![0u8; header_size];
+        while read.read_exact(&mut header).await.is_ok() {
+            let total_length = u32::from_le_bytes(header[16..20].try_into().unwrap());
+            let data_length =
+                u32::from_le_bytes(header[header_size - 5..header_size - 1].try_into().unwrap());
+            let mut body = vec![0u8; total_length as usize];
+            if read.read_exact(&mut body).await.is_err() {
+                return;
+            }
+            if corrupt && data_length > 0 {
+                *body.last_mut().unwrap() ^= 1;
+            }
+            if write.write_all(&header).await.is_err() || write.write_all(&body).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn corrupting_proxy(listen: &str, address: &'static str, requests: bool) {
+        let listener = tokio::net::TcpListener::bind(listen).await.unwrap();
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let server = tokio::net::TcpStream::connect(address).await.unwrap();
+                let (client_read, client_write) = client.into_split();
+                let (server_read, server_write) = server.into_split();
+                tokio::spawn(forward(
+                    client_read,
+                    server_write,
+                    REQUEST_HEADER_SIZE,
+                    requests,
+                ));
+                tokio::spawn(forward(
+                    server_read,
+                    client_write,
+                    RESPONSE_HEADER_SIZE,
+                    !requests,
+                ));
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_checksum_in_transit() {
+        let address = "127.0.0.1:50075";
+        let write_proxy = "127.0.0.1:50076";
+        let read_proxy = "127.0.0.1:50077";
+        let db_path = "/tmp/test_checksum_in_transit_db";
+        {
+            let engine = new_cluster_engine(
+                address,
+                &[address],
+                "/tmp/test_checksum_in_transit",
+                db_path,
+            );
+            let handler = Arc::new(FileRequestHandler::new(engine.clone()));
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 100]).await;
+            assert_eq!(status, 0);
+            let server = RpcServer::new(handler, address);
+            tokio::spawn(async move { server.run().await });
+            corrupting_proxy(write_proxy, address, true).await;
+            corrupting_proxy(read_proxy, address, false).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let client = Client::new();
+            client.client.add_connection(write_proxy).await.unwrap();
+            client.client.add_connection(read_proxy).await.unwrap();
+
+            // data damaged on the way to the server is never written
+            assert_eq!(
+                client
+                    .sender
+                    .write_file(write_proxy, "test_volume/file", &[2u8; 50], 0, 0)
+                    .await,
+                Err(libc::EIO)
+            );
+            assert_eq!(
+                engine.read_file("test_volume/file", 200, 0).await.unwrap(),
+                vec![1u8; 100]
+            );
+            assert_eq!(
+                client
+                    .sender
+                    .write_file(read_proxy, "test_volume/file", &[2u8; 50], 0, 0)
+                    .await,
+                Ok(50)
+            );
+
+            // and data damaged on the way back is never handed out
+            assert_eq!(
+                client
+                    .sender
+                    .read_file(read_proxy, "test_volume/file", 200, 0)
+                    .await,
+                Err(libc::EIO)
+            );
+            let mut expected = vec![2u8; 50];
+            expected.extend_from_slice(&[1u8; 50]);
+            assert_eq!(
+                client
+                    .sender
+                    .read_file(write_proxy, "test_volume/file", 200, 0)
+                    .await,
+                Ok(expected)
+            );
+        }
+        destroy_db(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_copy_file_range_cross_node() {
+        let (address_a, address_b) = ("127.0.0.1:50072", "127.0.0.1:50073");
+        let (db_path_a, db_path_b) = ("/tmp/test_copy_a_db", "/tmp/test_copy_b_db");
+        {
+            let engine_a =
+                new_cluster_engine(address_a, &[address_a], "/tmp/test_copy_a", db_path_a);
+            let engine_b =
+                new_cluster_engine(address_b, &[address_b], "/tmp/test_copy_b", db_path_b);
+            for (engine, address) in [(&engine_a, address_a), (&engine_b, address_b)] {
+                let server =
+                    RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+                tokio::spawn(async move { server.run().await });
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            engine_b
+                .add_connection(address_a.to_string())
+                .await
+                .unwrap();
+
+            let data: Vec<u8> = (0..(3 << 19)).map(|i| (i % 253) as u8).collect();
+            let handler_a = FileRequestHandler::new(engine_a.clone());
+            let (status, _) = create_and_write(&handler_a, "src", vec![]).await;
+            assert_eq!(status, 0);
+            engine_a
+                .write_file("test_volume/src", &data, 0, None)
+                .await
+                .unwrap();
+            // the destination volume is held by engine_b, which sends whatever names
+            // the source volume to engine_a
+            engine_b
+                .create_volume("copy_volume", 0, AtimePolicy::default(), None, None)
+                .unwrap();
+            engine_b
+                .create_file(
+                    bincode::serialize(&CreateFileSendMetaData {
+                        mode: 0o644,
+                        umask: 0,
+                        flags: libc::O_CREAT | libc::O_RDWR,
+                        name: "dst".to_string(),
+                        tmpfile: false,
+                    })
+                    .unwrap(),
+                    "copy_volume",
+                    "dst",
+                    libc::O_CREAT | libc::O_RDWR,
+                    0,
+                    0o644,
+                )
+                .await
+                .unwrap();
+            engine_b
+                .volume_placement
+                .insert("test_volume".to_string(), address_a.to_string());
+
+            let handler_b = FileRequestHandler::new(engine_b.clone());
+            let copied = copy_file_range(
+                &handler_b,
+                "test_volume/src",
+                4096,
+                "copy_volume/dst",
+                0,
+                data.len() as u64,
+            )
+            .await;
+            assert_eq!(copied, (0, data.len() as u64 - 4096));
+            assert!(
+                engine_b
+                    .read_file("copy_volume/dst", data.len() as u32, 0)
+                    .await
+                    .unwrap()
+                    == data[4096..]
+            );
+            assert_eq!(
+                copy_file_range(
+                    &handler_b,
+                    "test_volume/missing",
+                    0,
+                    "copy_volume/dst",
+                    0,
+                    10
+                )
+                .await
+                .0,
+                libc::ENOENT
+            );
+        }
+        destroy_db(db_path_a);
+        destroy_db(db_path_b);
+    }
+
+    // writes a self-signed certificate of 127.0.0.1 and its key, returns their paths
+    fn self_signed(name: &str) -> (String, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_owned()]).unwrap();
+        let (cert_path, key_path) = (format!("/tmp/{}.pem", name), format!("/tmp/{}.key", name));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let address = "127.0.0.1:50071";
+        let db_path = "/tmp/test_tls_db";
+        {
+            let (cert, key) = self_signed("test_tls");
+            // the certificate is its own ca, and the client shows it back
+            let tls = TlsOptions {
+                cert: Some(cert.clone()),
+                key: Some(key.clone()),
+                ca: Some(cert.clone()),
+            };
+            let engine = new_cluster_engine(address, &[address], "/tmp/test_tls", db_path);
+            let mut server =
+                RpcServer::new(Arc::new(FileRequestHandler::new(engine.clone())), address);
+            server.set_tls(tls.acceptor().unwrap());
+            tokio::spawn(async move { server.run().await });
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let handler = FileRequestHandler::new(engine.clone());
+            let (status, _) = create_and_write(&handler, "file", vec![1u8; 10]).await;
+            assert_eq!(status, 0);
+
+            let client = Arc::new(Client::new());
+            client.client.set_tls(tls.connector().unwrap());
+            client.client.add_connection(address).await.unwrap();
+            client
+                .hash_ring
+                .write()
+                .replace(HashRing::new(vec![(address.to_owned(), 100)]));
+            client
+                .cluster_status
+                .store(ClusterStatus::Idle.into(), Ordering::Relaxed);
+            let attr = client.get_attr("test_volume/file").await.unwrap();
+            assert_eq!(attr.size, 10);
+
+            // a client that trusts another ca, or that has no certificate to show, is refused
+            let (other_cert, other_key) = self_signed("test_tls_other");
+            let untrusting = TlsOptions {
+                cert: Some(other_cert.clone()),
+                key: Some(other_key),
+                ca: Some(other_cert),
+            };
+            assert!(
+                TcpStreamCreator::create_stream(address, untrusting.connector().unwrap())
+                    .await
+                    .is_err()
+            );
+            let anonymous = TlsOptions {
+                ca: Some(cert.clone()),
+                ..Default::default()
+            };
+            let (mut read_stream, mut write_stream) =
+                TcpStreamCreator::create_stream(address, anonymous.connector().unwrap())
+                    .await
+                    .unwrap();
+            // tls 1.3 finishes the client side of the handshake before the server checks it
+            let _ = write_stream.write_all(&[0u8; 64]).await;
+            let mut buf = [0u8; 64];
+            assert!(!matches!(read_stream.read(&mut buf).await, Ok(n) if n > 0));
+
+            // options that do not make up a listener or a client
+            let keyless = TlsOptions {
+                cert: Some(cert),
+                ..Default::default()
+            };
+            assert!(keyless.acceptor().is_err());
+            assert!(keyless.connector().is_err());
+            assert!(TlsOptions::default().acceptor().unwrap().is_none());
+            assert!(TlsOptions::default().connector().unwrap().is_none());
+        }
+        destroy_db(db_path);
+    }
+}
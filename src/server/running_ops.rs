@@ -0,0 +1,230 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// requests being handled by this server, so that an operator can find the ones
+// that hang and kill them. a killed request is dropped at its next await point and
+// answered with ECANCELED, so only requests that change nothing can be killed, the
+// others could be stopped halfway. requests slower than the threshold are logged as
+// they end
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use dashmap::DashMap;
+use log::warn;
+use tokio::sync::Notify;
+
+use crate::common::serialization::{OperationType, RunningOpInfo};
+
+struct RunningOp {
+    op: u32,
+    path: String,
+    client: u32,
+    started: Instant,
+    cancel: Arc<Notify>,
+    cancelled: bool,
+    killable: bool,
+}
+
+// removes the request from the list however its handling ends
+pub struct RunningOpGuard<'a> {
+    ops: &'a RunningOps,
+    request_id: u64,
+    cancel: Arc<Notify>,
+}
+
+impl RunningOpGuard<'_> {
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+
+    // resolves once the request is killed
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+}
+
+impl Drop for RunningOpGuard<'_> {
+    fn drop(&mut self) {
+        self.ops.finish(self.request_id);
+    }
+}
+
+#[derive(Default)]
+pub struct RunningOps {
+    next_request_id: AtomicU64,
+    ops: DashMap<u64, RunningOp>,
+    // 0 logs none
+    slow_op_threshold_ms: AtomicU64,
+}
+
+impl RunningOps {
+    pub fn set_slow_op_threshold(&self, threshold_ms: u64) {
+        self.slow_op_threshold_ms
+            .store(threshold_ms, Ordering::Relaxed);
+    }
+
+    pub fn slow_op_threshold(&self) -> u64 {
+        self.slow_op_threshold_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn start(&self, op: u32, path: &str, client: u32) -> RunningOpGuard<'_> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let cancel = Arc::new(Notify::new());
+        self.ops.insert(
+            request_id,
+            RunningOp {
+                op,
+                path: path.to_owned(),
+                client,
+                started: Instant::now(),
+                cancel: cancel.clone(),
+                cancelled: false,
+                killable: OperationType::try_from(op).is_ok_and(|r#type| !r#type.is_mutating()),
+            },
+        );
+        RunningOpGuard {
+            ops: self,
+            request_id,
+            cancel,
+        }
+    }
+
+    fn finish(&self, request_id: u64) {
+        let op = match self.ops.remove(&request_id) {
+            Some((_, op)) => op,
+            None => return,
+        };
+        let threshold = self.slow_op_threshold();
+        let elapsed_ms = op.started.elapsed().as_millis() as u64;
+        if threshold > 0 && elapsed_ms >= threshold {
+            let name = match OperationType::try_from(op.op) {
+                Ok(r#type) => r#type.as_str().to_owned(),
+                Err(_) => op.op.to_string(),
+            };
+            warn!(
+                "slow op: {}, path: {}, client: {}, elapsed: {}ms",
+                name, op.path, op.client, elapsed_ms
+            );
+        }
+    }
+
+    pub fn kill(&self, request_id: u64) -> Result<(), i32> {
+        match self.ops.get_mut(&request_id) {
+            Some(op) if !op.killable => Err(libc::EBUSY),
+            Some(mut op) => {
+                op.cancelled = true;
+                op.cancel.notify_one();
+                Ok(())
+            }
+            None => Err(libc::ESRCH),
+        }
+    }
+
+    // the longest running first
+    pub fn list(&self) -> Vec<RunningOpInfo> {
+        let mut ops: Vec<RunningOpInfo> = self
+            .ops
+            .iter()
+            .map(|kv| RunningOpInfo {
+                request_id: *kv.key(),
+                op: kv.op,
+                path: kv.path.clone(),
+                client: kv.client,
+                elapsed_ms: kv.started.elapsed().as_millis() as u64,
+                cancelled: kv.cancelled,
+                killable: kv.killable,
+            })
+            .collect();
+        ops.sort_by_key(|op| std::cmp::Reverse(op.elapsed_ms));
+        ops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use log::{Level, Log, Metadata, Record};
+    use parking_lot::Mutex;
+
+    use super::RunningOps;
+    use crate::common::serialization::OperationType;
+
+    struct Capture(Mutex<Vec<(Level, String)>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Warn
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                self.0
+                    .lock()
+                    .push((record.level(), record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    fn slow_ops_logged(path: &str) -> Vec<(Level, String)> {
+        CAPTURE
+            .0
+            .lock()
+            .iter()
+            .filter(|(_, message)| message.contains(path))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_slow_op_log() {
+        // other tests leave logging alone, this one is the only logger
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let ops = RunningOps::default();
+        ops.set_slow_op_threshold(20);
+        // a handler holding the request past the threshold
+        {
+            let _op = ops.start(OperationType::ReadFile.into(), "volume/slow", 7);
+            sleep(Duration::from_millis(30));
+        }
+        {
+            let _op = ops.start(OperationType::ReadFile.into(), "volume/fast", 7);
+        }
+        assert!(ops.list().is_empty());
+
+        let logged = slow_ops_logged("volume/slow");
+        assert_eq!(logged.len(), 1);
+        let (level, message) = &logged[0];
+        assert_eq!(*level, Level::Warn);
+        assert!(message.starts_with("slow op: ReadFile, path: volume/slow, client: 7"));
+        let elapsed: u64 = message
+            .rsplit("elapsed: ")
+            .next()
+            .and_then(|elapsed| elapsed.strip_suffix("ms"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(elapsed >= 30);
+        assert!(slow_ops_logged("volume/fast").is_empty());
+
+        ops.set_slow_op_threshold(0);
+        {
+            let _op = ops.start(OperationType::ReadFile.into(), "volume/unlogged", 7);
+            sleep(Duration::from_millis(30));
+        }
+        assert!(slow_ops_logged("volume/unlogged").is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// the packed dirents of directories read in one go, so reading a directory that
+// has not changed does not walk the store again. bounded by the bytes it holds,
+// the directory read least recently is evicted first
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+
+// the records of a whole directory and where each of them ends
+#[derive(Default)]
+pub struct Dirents {
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl Dirents {
+    pub fn push(&mut self, record: &[u8]) {
+        self.data.extend_from_slice(record);
+        self.ends.push(self.data.len());
+    }
+
+    // the whole records after the first offset that fit in size bytes
+    pub fn read(&self, size: u32, offset: i64) -> Vec<u8> {
+        let offset = offset.max(0) as usize;
+        if offset >= self.ends.len() {
+            return Vec::new();
+        }
+        let start = match offset {
+            0 => 0,
+            _ => self.ends[offset - 1],
+        };
+        let end = self.ends[offset..]
+            .iter()
+            .take_while(|end| **end - start <= size as usize)
+            .last()
+            .copied()
+            .unwrap_or(start);
+        self.data[start..end].to_vec()
+    }
+}
+
+struct Cached {
+    dirents: Arc<Dirents>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Cached>,
+    bytes: usize,
+    tick: u64,
+    // bumped by every invalidation
+    epoch: u64,
+}
+
+pub struct DirentCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    misses: AtomicU64,
+}
+
+impl DirentCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<Arc<Dirents>> {
+        let mut inner = self.inner.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        match inner.entries.get_mut(path) {
+            Some(cached) => {
+                cached.last_used = tick;
+                Some(cached.dirents.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    // taken before walking the store, the dirents read are only kept if no
+    // directory changed meanwhile
+    pub fn epoch(&self) -> u64 {
+        self.inner.lock().epoch
+    }
+
+    pub fn insert(&self, path: &str, dirents: Dirents, epoch: u64) {
+        if dirents.data.len() > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        if inner.epoch != epoch {
+            return;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some(old) = inner.entries.remove(path) {
+            inner.bytes -= old.dirents.data.len();
+        }
+        while inner.bytes + dirents.data.len() > self.capacity {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone())
+                .unwrap();
+            let evicted = inner.entries.remove(&oldest).unwrap();
+            inner.bytes -= evicted.dirents.data.len();
+        }
+        inner.bytes += dirents.data.len();
+        inner.entries.insert(
+            path.to_owned(),
+            Cached {
+                dirents: Arc::new(dirents),
+                last_used: tick,
+            },
+        );
+    }
+
+    // called whenever an entry of the directory is added or removed
+    pub fn invalidate(&self, path: &str) {
+        let mut inner = self.inner.lock();
+        inner.epoch += 1;
+        if let Some(old) = inner.entries.remove(path) {
+            inner.bytes -= old.dirents.data.len();
+        }
+    }
+
+    // reads that had to walk the store
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirentCache, Dirents};
+
+    fn dirents(records: &[&[u8]]) -> Dirents {
+        let mut dirents = Dirents::default();
+        for record in records {
+            dirents.push(record);
+        }
+        dirents
+    }
+
+    #[test]
+    fn test_read() {
+        let dirents = dirents(&[b"aaa", b"bb", b"cccc"]);
+        assert_eq!(dirents.read(4096, 0), b"aaabbcccc");
+        assert_eq!(dirents.read(5, 0), b"aaabb");
+        assert_eq!(dirents.read(4, 0), b"aaa");
+        assert_eq!(dirents.read(2, 0), b"");
+        assert_eq!(dirents.read(4096, 1), b"bbcccc");
+        assert_eq!(dirents.read(4096, 3), b"");
+    }
+
+    #[test]
+    fn test_evict_least_recently_read() {
+        let cache = DirentCache::new(10);
+        cache.insert("volume/a", dirents(&[b"aaaa"]), 0);
+        cache.insert("volume/b", dirents(&[b"bbbb"]), 0);
+        assert!(cache.get("volume/a").is_some());
+        cache.insert("volume/c", dirents(&[b"cccc"]), 0);
+        assert!(cache.get("volume/b").is_none());
+        assert!(cache.get("volume/a").is_some());
+        assert!(cache.get("volume/c").is_some());
+
+        // a directory larger than the whole cache is never kept
+        cache.insert("volume/d", dirents(&[b"ddddddddddd"]), 0);
+        assert!(cache.get("volume/d").is_none());
+        assert!(cache.get("volume/a").is_some());
+
+        // nor one read while a directory changed
+        let epoch = cache.epoch();
+        cache.invalidate("volume/c");
+        cache.insert("volume/e", dirents(&[b"e"]), epoch);
+        assert!(cache.get("volume/e").is_none());
+    }
+}
@@ -2,30 +2,51 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::common::util::empty_file;
+use crate::common::serialization::{
+    FileAttrSimple, FileTypeSimple, DATA_REMOTE, INLINE_DATA, UNLINKED,
+};
 use crate::common::{cache::LRUCache, errors::status_to_string};
 
 use super::meta_engine::MetaEngine;
 use super::StorageEngine;
-use log::{debug, error};
+use fuser::{FileAttr, FileType};
+use log::{debug, error, info};
 use nix::errno::errno;
 use nix::{
     fcntl::OFlag,
     sys::stat::Mode,
     unistd::{self, mkdir},
 };
+use parking_lot::RwLock;
 use std::ffi::CString;
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
     path::Path,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
+// the data of a file is checksummed in blocks of this size. a block is checksummed
+// zero padded to the full size, so a file growing past its end keeps it valid
+pub const CHECKSUM_BLOCK_SIZE: u64 = 4096;
+// the crc32c of every block of a data file is kept next to it, in a file named
+// like it with this suffix. 0 stands for a block never checksummed
+const CHECKSUM_SUFFIX: &str = ".crc";
+// checksum updates and the reads checking them are serialized on one of these
+const CHECKSUM_LOCKS: usize = 64;
+// blocks checksummed at a time
+const CHECKSUM_BATCH: u64 = 256;
+
 pub struct FileEngine {
     pub meta_engine: Arc<MetaEngine>,
     pub root: String,
     pub cache: LRUCache<FileDescriptor>,
+    // files no larger than this keep their data with their attr, 0 disables inlining
+    pub inline_threshold: AtomicUsize,
+    checksum_locks: Vec<RwLock<()>>,
 }
 
 #[derive(Debug, Clone)]
@@ -57,6 +78,8 @@ impl StorageEngine for FileEngine {
             meta_engine,
             root: root.to_string(),
             cache: LRUCache::new(512),
+            inline_threshold: AtomicUsize::new(0),
+            checksum_locks: (0..CHECKSUM_LOCKS).map(|_| RwLock::new(())).collect(),
         }
     }
 
@@ -69,6 +92,11 @@ impl StorageEngine for FileEngine {
         if self.meta_engine.is_dir(path)? {
             return Err(libc::EISDIR);
         }
+        if let Some(inline_data) = self.meta_engine.inline_data(path)? {
+            let start = (offset.max(0) as usize).min(inline_data.len());
+            let end = (start + size as usize).min(inline_data.len());
+            return Ok(inline_data[start..end].to_vec());
+        }
 
         let local_file_name = generate_local_file_name(&self.root, path);
         let oflag = OFlag::O_RDWR;
@@ -101,13 +129,23 @@ impl StorageEngine for FileEngine {
                 fd
             }
         };
-        let mut data = vec![0; size as usize];
+        let _lock = self.checksum_lock(&local_file_name).read();
+        let checksum_fd = self.checksum_fd(&local_file_name, false)?;
+        // whole blocks are read to check them against their checksums
+        let (start, end) = match checksum_fd {
+            Some(_) if offset >= 0 => (
+                offset as u64 / CHECKSUM_BLOCK_SIZE * CHECKSUM_BLOCK_SIZE,
+                (offset as u64 + size as u64).div_ceil(CHECKSUM_BLOCK_SIZE) * CHECKSUM_BLOCK_SIZE,
+            ),
+            _ => (offset as u64, offset as u64 + size as u64),
+        };
+        let mut data = vec![0; (end - start) as usize];
         let real_size = unsafe {
             libc::pread(
                 fd,
                 data.as_mut_slice().as_mut_ptr() as *mut libc::c_void,
-                size as usize,
-                offset,
+                data.len(),
+                start as i64,
             )
         };
         if real_size < 0 {
@@ -115,20 +153,38 @@ impl StorageEngine for FileEngine {
             error!("read file error: {:?}", status_to_string(f_errno));
             return Err(f_errno);
         };
+        let real_size = real_size as usize;
+        if let Some(checksum_fd) = checksum_fd {
+            self.verify_checksums(path, checksum_fd, start, &data[..real_size])?;
+        }
+        let begin = ((offset as u64 - start) as usize).min(real_size);
+        let real_size = (begin + size as usize).min(real_size);
         debug!(
             "read_file path: {}, size: {}, offset: {}, data: {:?}",
-            path, real_size, offset, data
+            path,
+            real_size - begin,
+            offset,
+            &data[begin..real_size]
         );
 
         // this is a temporary solution, which results in an extra memory copy.
         // TODO: optimize it by return the hole data vector and the real size both.
-        Ok(data[..real_size as usize].to_vec())
+        Ok(data[begin..real_size].to_vec())
     }
 
     fn write_file(&self, path: &str, data: &[u8], offset: i64) -> Result<usize, i32> {
         if self.meta_engine.is_dir(path)? {
             return Err(libc::EISDIR);
         }
+        if let Some(inline_data) = self.meta_engine.inline_data(path)? {
+            if offset < 0 {
+                return Err(libc::EINVAL);
+            }
+            if offset as usize + data.len() <= self.inline_threshold.load(Ordering::Relaxed) {
+                return self.meta_engine.write_inline(path, data, offset as usize);
+            }
+            self.spill_inline(path, &inline_data)?;
+        }
 
         let local_file_name = generate_local_file_name(&self.root, path);
         let oflag = OFlag::O_RDWR;
@@ -161,6 +217,16 @@ impl StorageEngine for FileEngine {
                 fd
             }
         };
+        let _lock = self.checksum_lock(&local_file_name).write();
+        if offset >= 0 {
+            self.verify_partial_blocks(
+                path,
+                fd,
+                &local_file_name,
+                offset as u64,
+                data.len() as u64,
+            )?;
+        }
         let write_size =
             unsafe { libc::pwrite(fd, data.as_ptr() as *const libc::c_void, data.len(), offset) };
         if write_size < 0 {
@@ -168,6 +234,7 @@ impl StorageEngine for FileEngine {
             error!("write file error: {:?}", status_to_string(f_errno));
             return Err(f_errno);
         }
+        self.update_checksums(fd, &local_file_name, offset as u64, write_size as u64)?;
 
         debug!(
             "write_file path: {}, write_size: {}, data_len: {}",
@@ -182,8 +249,15 @@ impl StorageEngine for FileEngine {
         Ok(write_size as usize)
     }
 
-    fn create_file(&self, path: &str, _oflag: i32, _umask: u32, mode: u32) -> Result<Vec<u8>, i32> {
+    fn create_file(&self, path: &str, _oflag: i32, umask: u32, mode: u32) -> Result<Vec<u8>, i32> {
         let local_file_name = generate_local_file_name(&self.root, path);
+        let mut attr: FileAttr =
+            FileAttrSimple::with_mode(FileTypeSimple::RegularFile, mode, umask).into();
+        // the data file is only made once the file outgrows the threshold
+        if self.inline_threshold.load(Ordering::Relaxed) > 0 {
+            attr.flags |= INLINE_DATA;
+            return self.meta_engine.create_file(attr, &local_file_name, path);
+        }
         let oflag = OFlag::O_CREAT | OFlag::O_RDWR;
         match self.cache.get(local_file_name.as_bytes()) {
             Some(_) => {}
@@ -207,13 +281,18 @@ impl StorageEngine for FileEngine {
                     .insert(local_file_name.as_bytes(), FileDescriptor::new(fd));
             }
         };
-        self.meta_engine
-            .create_file(empty_file(), &local_file_name, path)
+        self.meta_engine.create_file(attr, &local_file_name, path)
     }
 
     fn delete_file(&self, path: &str) -> Result<(), i32> {
         let local_file_name = generate_local_file_name(&self.root, path);
+        if self.meta_engine.inline_data(path)?.is_some() {
+            return self.meta_engine.delete_file(&local_file_name, path);
+        }
         self.cache.remove(local_file_name.as_bytes());
+        let checksum_file_name = local_file_name.clone() + CHECKSUM_SUFFIX;
+        self.cache.remove(checksum_file_name.as_bytes());
+        let _ = unistd::unlink(checksum_file_name.as_str());
         let status = unsafe {
             libc::unlink(
                 CString::new(local_file_name.clone())
@@ -232,23 +311,150 @@ impl StorageEngine for FileEngine {
     }
 
     fn truncate_file(&self, path: &str, length: i64) -> Result<(), i32> {
+        if let Some(inline_data) = self.meta_engine.inline_data(path)? {
+            if length < 0 {
+                return Err(libc::EINVAL);
+            }
+            if length as usize <= self.inline_threshold.load(Ordering::Relaxed) {
+                return self.meta_engine.truncate_inline(path, length as usize);
+            }
+            self.spill_inline(path, &inline_data)?;
+        }
         let local_file_name = generate_local_file_name(&self.root, path);
-        let status = unsafe {
-            libc::truncate(
-                CString::new(local_file_name).unwrap().as_c_str().as_ptr() as *const i8,
-                length,
-            )
-        };
-        if status < 0 {
-            let f_errno = errno();
-            error!("truncate file error: {:?}", status_to_string(f_errno));
-            return Err(f_errno);
-        };
-        // TODO: update file attr
+        let _lock = self.checksum_lock(&local_file_name).write();
+        let checksum_fd = self.checksum_fd(&local_file_name, false)?;
+        if checksum_fd.is_some() && length >= 0 {
+            self.with_data_fd(&local_file_name, |fd| {
+                self.verify_partial_blocks(path, fd, &local_file_name, length as u64, 0)
+            })?;
+        }
+        if let Err(e) = unistd::truncate(local_file_name.as_str(), length) {
+            error!("truncate file error: {:?}", status_to_string(e as i32));
+            return Err(e as i32);
+        }
+        // the block the file now ends in lost its tail, the ones past it are gone
+        if let Some(checksum_fd) = checksum_fd {
+            let blocks = (length as u64).div_ceil(CHECKSUM_BLOCK_SIZE);
+            let checksums_size = nix::sys::stat::fstat(checksum_fd)
+                .map_err(|e| e as i32)?
+                .st_size as u64;
+            if checksums_size > blocks * 4 {
+                unistd::ftruncate(checksum_fd, (blocks * 4) as i64).map_err(|e| e as i32)?;
+            }
+            let tail = length as u64 % CHECKSUM_BLOCK_SIZE;
+            self.with_data_fd(&local_file_name, |fd| {
+                self.update_checksums(fd, &local_file_name, length as u64 - tail, tail)
+            })?;
+        }
         Ok(())
     }
 
+    fn fallocate(&self, path: &str, mode: i32, offset: i64, length: i64) -> Result<u64, i32> {
+        // inline data has no blocks to preallocate or punch
+        if let Some(inline_data) = self.meta_engine.inline_data(path)? {
+            self.spill_inline(path, &inline_data)?;
+        }
+        let local_file_name = generate_local_file_name(&self.root, path);
+        let _lock = self.checksum_lock(&local_file_name).write();
+        let punch_hole = mode & libc::FALLOC_FL_PUNCH_HOLE != 0;
+        self.with_data_fd(&local_file_name, |fd| {
+            if punch_hole && offset >= 0 && length > 0 {
+                self.verify_partial_blocks(
+                    path,
+                    fd,
+                    &local_file_name,
+                    offset as u64,
+                    length as u64,
+                )?;
+            }
+            if unsafe { libc::fallocate(fd, mode, offset, length) } < 0 {
+                let f_errno = errno();
+                error!("fallocate file error: {:?}", status_to_string(f_errno));
+                return Err(f_errno);
+            }
+            let stat = nix::sys::stat::fstat(fd).map_err(|e| e as i32)?;
+            // a punched hole reads as zeros now
+            if punch_hole && offset < stat.st_size {
+                let end = offset.saturating_add(length).min(stat.st_size);
+                self.update_checksums(fd, &local_file_name, offset as u64, (end - offset) as u64)?;
+            }
+            Ok(stat.st_blocks as u64)
+        })
+    }
+
+    // EROFS for a read-only mount, EACCES when the root lost its permissions
+    fn rename_file(&self, path: &str, new_path: &str) -> Result<(), i32> {
+        let local_file_name = generate_local_file_name(&self.root, path);
+        let new_local_file_name = generate_local_file_name(&self.root, new_path);
+        let checksum_file_name = local_file_name.clone() + CHECKSUM_SUFFIX;
+        let new_checksum_file_name = new_local_file_name.clone() + CHECKSUM_SUFFIX;
+        let _lock = self.checksum_lock(&new_local_file_name).write();
+        for name in [
+            &local_file_name,
+            &checksum_file_name,
+            &new_local_file_name,
+            &new_checksum_file_name,
+        ] {
+            self.cache.remove(name.as_bytes());
+        }
+        if self.meta_engine.inline_data(path)?.is_some() {
+            self.meta_engine
+                .rename_file(&local_file_name, &new_local_file_name, path, new_path)?;
+            // the data files of a replaced file that was not inline
+            let _ = unistd::unlink(new_local_file_name.as_str());
+            let _ = unistd::unlink(new_checksum_file_name.as_str());
+            return Ok(());
+        }
+        std::fs::rename(&local_file_name, &new_local_file_name).map_err(|e| {
+            error!("rename file error: {:?}", e);
+            e.raw_os_error().unwrap_or(libc::EIO)
+        })?;
+        if std::fs::rename(&checksum_file_name, &new_checksum_file_name).is_err() {
+            let _ = unistd::unlink(new_checksum_file_name.as_str());
+        }
+        self.meta_engine
+            .rename_file(&local_file_name, &new_local_file_name, path, new_path)
+    }
+
+    fn is_writable(&self) -> bool {
+        unistd::access(self.root.as_str(), unistd::AccessFlags::W_OK).is_ok()
+    }
+
+    fn sync_file(&self, path: &str) -> Result<(), i32> {
+        if self.meta_engine.inline_data(path)?.is_some() {
+            return Ok(());
+        }
+        let local_file_name = generate_local_file_name(&self.root, path);
+        // the checksums go to disk with the data they were computed from
+        if let Some(checksum_fd) = self.checksum_fd(&local_file_name, false)? {
+            unistd::fsync(checksum_fd).map_err(|e| e as i32)?;
+        }
+        if let Some(value) = self.cache.get(local_file_name.as_bytes()) {
+            return unistd::fsync(value.fd).map_err(|e| e as i32);
+        }
+        let fd = nix::fcntl::open(local_file_name.as_str(), OFlag::O_RDONLY, Mode::empty())
+            .map_err(|e| e as i32)?;
+        let result = unistd::fsync(fd).map_err(|e| e as i32);
+        let _ = unistd::close(fd);
+        result
+    }
+
+    fn sync_all(&self) -> Result<(), i32> {
+        let fd = nix::fcntl::open(
+            self.root.as_str(),
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+            Mode::empty(),
+        )
+        .map_err(|e| e as i32)?;
+        let result = unistd::syncfs(fd).map_err(|e| e as i32);
+        let _ = unistd::close(fd);
+        result
+    }
+
     fn open_file(&self, path: &str, _flags: i32, mode: u32) -> Result<(), i32> {
+        if self.meta_engine.inline_data(path)?.is_some() {
+            return Ok(());
+        }
         let local_file_name = generate_local_file_name(&self.root, path);
 
         let oflag = OFlag::O_RDWR;
@@ -274,6 +480,240 @@ impl StorageEngine for FileEngine {
 }
 
 impl FileEngine {
+    pub fn set_inline_threshold(&self, threshold: usize) {
+        self.inline_threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    // moves the data of an inline file that outgrew the threshold to its data file
+    fn spill_inline(&self, path: &str, data: &[u8]) -> Result<(), i32> {
+        let local_file_name = generate_local_file_name(&self.root, path);
+        let mode = Mode::S_IRUSR
+            | Mode::S_IWUSR
+            | Mode::S_IRGRP
+            | Mode::S_IWGRP
+            | Mode::S_IROTH
+            | Mode::S_IWOTH;
+        let fd = nix::fcntl::open(
+            local_file_name.as_str(),
+            OFlag::O_CREAT | OFlag::O_RDWR,
+            mode,
+        )
+        .map_err(|e| e as i32)?;
+        let file = FileDescriptor::new(fd);
+        let mut written = 0;
+        while written < data.len() {
+            match nix::sys::uio::pwrite(fd, &data[written..], written as i64) {
+                Ok(0) => return Err(libc::EIO),
+                Ok(n) => written += n,
+                Err(e) => {
+                    error!("spill inline data error: {:?}", e);
+                    return Err(e as i32);
+                }
+            }
+        }
+        {
+            let _lock = self.checksum_lock(&local_file_name).write();
+            self.update_checksums(fd, &local_file_name, 0, data.len() as u64)?;
+        }
+        self.cache.insert(local_file_name.as_bytes(), file);
+        debug!("spill_inline path: {}, size: {}", path, data.len());
+        self.meta_engine.clear_inline(path)
+    }
+
+    fn checksum_lock(&self, local_file_name: &str) -> &RwLock<()> {
+        let mut hasher = DefaultHasher::new();
+        local_file_name.hash(&mut hasher);
+        &self.checksum_locks[hasher.finish() as usize % CHECKSUM_LOCKS]
+    }
+
+    // the checksums of the data file, None when there are none and create is false
+    fn checksum_fd(&self, local_file_name: &str, create: bool) -> Result<Option<i32>, i32> {
+        let checksum_file_name = local_file_name.to_owned() + CHECKSUM_SUFFIX;
+        if let Some(value) = self.cache.get(checksum_file_name.as_bytes()) {
+            return Ok(Some(value.fd));
+        }
+        let oflag = match create {
+            true => OFlag::O_CREAT | OFlag::O_RDWR,
+            false => OFlag::O_RDWR,
+        };
+        let mode = Mode::S_IRUSR | Mode::S_IWUSR;
+        match nix::fcntl::open(checksum_file_name.as_str(), oflag, mode) {
+            Ok(fd) => {
+                self.cache
+                    .insert(checksum_file_name.as_bytes(), FileDescriptor::new(fd));
+                Ok(Some(fd))
+            }
+            Err(nix::errno::Errno::ENOENT) if !create => Ok(None),
+            Err(e) => {
+                error!("open checksums error: {:?}", e);
+                Err(e as i32)
+            }
+        }
+    }
+
+    // runs f on the data file, open already or for as long as f runs
+    fn with_data_fd<T>(
+        &self,
+        local_file_name: &str,
+        f: impl FnOnce(i32) -> Result<T, i32>,
+    ) -> Result<T, i32> {
+        if let Some(value) = self.cache.get(local_file_name.as_bytes()) {
+            return f(value.fd);
+        }
+        let fd = nix::fcntl::open(local_file_name, OFlag::O_RDWR, Mode::empty())
+            .map_err(|e| e as i32)?;
+        let result = f(fd);
+        let _ = unistd::close(fd);
+        result
+    }
+
+    // a change of the data from offset to offset + length keeps the rest of the
+    // blocks it only partly covers, which are checked before they are checksummed
+    // again so that a corrupted block is not taken for good
+    fn verify_partial_blocks(
+        &self,
+        path: &str,
+        fd: i32,
+        local_file_name: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(), i32> {
+        let Some(checksum_fd) = self.checksum_fd(local_file_name, false)? else {
+            return Ok(());
+        };
+        let end = offset + length;
+        let mut blocks = Vec::new();
+        if !offset.is_multiple_of(CHECKSUM_BLOCK_SIZE) {
+            blocks.push(offset / CHECKSUM_BLOCK_SIZE);
+        }
+        if !end.is_multiple_of(CHECKSUM_BLOCK_SIZE)
+            && blocks.last() != Some(&(end / CHECKSUM_BLOCK_SIZE))
+        {
+            blocks.push(end / CHECKSUM_BLOCK_SIZE);
+        }
+        for block in blocks {
+            let mut data = vec![0u8; CHECKSUM_BLOCK_SIZE as usize];
+            let read = nix::sys::uio::pread(fd, &mut data, (block * CHECKSUM_BLOCK_SIZE) as i64)
+                .map_err(|e| e as i32)?;
+            self.verify_checksums(
+                path,
+                checksum_fd,
+                block * CHECKSUM_BLOCK_SIZE,
+                &data[..read],
+            )?;
+        }
+        Ok(())
+    }
+
+    // checksums again the blocks of the data in fd from offset to offset + length,
+    // reading them back. the caller holds the checksum lock of the file
+    fn update_checksums(
+        &self,
+        fd: i32,
+        local_file_name: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<(), i32> {
+        if length == 0 {
+            return Ok(());
+        }
+        let checksum_fd = self.checksum_fd(local_file_name, true)?.unwrap();
+        let last = (offset + length - 1) / CHECKSUM_BLOCK_SIZE;
+        let mut block = offset / CHECKSUM_BLOCK_SIZE;
+        while block <= last {
+            let count = (last + 1 - block).min(CHECKSUM_BATCH);
+            let mut data = vec![0u8; (count * CHECKSUM_BLOCK_SIZE) as usize];
+            let read = nix::sys::uio::pread(fd, &mut data, (block * CHECKSUM_BLOCK_SIZE) as i64)
+                .map_err(|e| e as i32)?;
+            data.truncate(read);
+            let checksums: Vec<u8> = (0..count as usize)
+                .flat_map(|n| {
+                    let start = (n * CHECKSUM_BLOCK_SIZE as usize).min(read);
+                    let end = (start + CHECKSUM_BLOCK_SIZE as usize).min(read);
+                    block_checksum(&data[start..end]).to_le_bytes()
+                })
+                .collect();
+            nix::sys::uio::pwrite(checksum_fd, &checksums, (block * 4) as i64).map_err(|e| {
+                error!("write checksums error: {:?}", e);
+                e as i32
+            })?;
+            block += count;
+        }
+        Ok(())
+    }
+
+    // EIO when a block of data, which starts at offset of a block, does not match
+    // the checksum it was written with
+    fn verify_checksums(
+        &self,
+        path: &str,
+        checksum_fd: i32,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), i32> {
+        let first = offset / CHECKSUM_BLOCK_SIZE;
+        let mut checksums = vec![0u8; data.len().div_ceil(CHECKSUM_BLOCK_SIZE as usize) * 4];
+        // blocks past the end of the checksums were never checksummed
+        nix::sys::uio::pread(checksum_fd, &mut checksums, (first * 4) as i64)
+            .map_err(|e| e as i32)?;
+        for (n, block) in data.chunks(CHECKSUM_BLOCK_SIZE as usize).enumerate() {
+            let checksum = u32::from_le_bytes(checksums[n * 4..n * 4 + 4].try_into().unwrap());
+            if checksum != 0 && checksum != block_checksum(block) {
+                error!(
+                    "checksum mismatch, path: {}, block: {}",
+                    path,
+                    first + n as u64
+                );
+                return Err(libc::EIO);
+            }
+        }
+        Ok(())
+    }
+
+    // zeroes the checksums of the blocks of a data file that do not match it, which a
+    // crash between a write and its checksums leaves behind. the data can not tell
+    // such a block from a corrupted one, so both are kept unchecked until written
+    // again. returns how many were cleared
+    fn clear_stale_checksums(&self, local_file_name: &str) -> Result<u64, i32> {
+        let _lock = self.checksum_lock(local_file_name).write();
+        let Some(checksum_fd) = self.checksum_fd(local_file_name, false)? else {
+            return Ok(0);
+        };
+        self.with_data_fd(local_file_name, |fd| {
+            let mut cleared = 0;
+            let mut block = 0;
+            loop {
+                let mut data = vec![0u8; (CHECKSUM_BATCH * CHECKSUM_BLOCK_SIZE) as usize];
+                let read =
+                    nix::sys::uio::pread(fd, &mut data, (block * CHECKSUM_BLOCK_SIZE) as i64)
+                        .map_err(|e| e as i32)?;
+                if read == 0 {
+                    return Ok(cleared);
+                }
+                let mut checksums = vec![0u8; read.div_ceil(CHECKSUM_BLOCK_SIZE as usize) * 4];
+                nix::sys::uio::pread(checksum_fd, &mut checksums, (block * 4) as i64)
+                    .map_err(|e| e as i32)?;
+                for (n, data) in data[..read]
+                    .chunks(CHECKSUM_BLOCK_SIZE as usize)
+                    .enumerate()
+                {
+                    let checksum =
+                        u32::from_le_bytes(checksums[n * 4..n * 4 + 4].try_into().unwrap());
+                    if checksum != 0 && checksum != block_checksum(data) {
+                        nix::sys::uio::pwrite(
+                            checksum_fd,
+                            &[0u8; 4],
+                            ((block + n as u64) * 4) as i64,
+                        )
+                        .map_err(|e| e as i32)?;
+                        cleared += 1;
+                    }
+                }
+                block += CHECKSUM_BATCH;
+            }
+        })
+    }
+
     fn fsck(&self) -> Result<(), i32> {
         let entries = match std::fs::read_dir(&self.root) {
             Ok(entries) => entries,
@@ -288,7 +728,10 @@ impl FileEngine {
                 libc::EIO
             })?;
             let file_name = format!("{}/{}", self.root, entry.file_name().to_str().unwrap());
-            if self.meta_engine.check_file(&file_name) {
+            let data_file_name = file_name
+                .strip_suffix(CHECKSUM_SUFFIX)
+                .unwrap_or(&file_name);
+            if self.meta_engine.check_file(data_file_name) {
                 continue;
             }
             let _ = std::fs::remove_file(entry.path());
@@ -298,6 +741,105 @@ impl FileEngine {
 
         Ok(())
     }
+
+    // run once at startup before serving, returns the number of repairs. the fast pass
+    // removes the tmpfiles left behind and rebuilds what derives from the attrs; the
+    // thorough one first checks every file's size and checksums against its data,
+    // which a crash between a write and its attr or checksum update leaves behind, and
+    // every directory's entry count against its stored entries
+    pub fn check_consistency(&self, thorough: bool) -> usize {
+        let mut repairs = 0;
+        // only the client that made a tmpfile, or the rename that staged it, could
+        // still name it, and they went with the last run
+        let tmpfiles: Vec<String> = self
+            .meta_engine
+            .file_indexs
+            .iter()
+            .filter(|kv| kv.file_attr.flags & UNLINKED != 0)
+            .map(|kv| kv.key().clone())
+            .collect();
+        for path in tmpfiles {
+            info!("remove tmpfile {}", path);
+            match self.delete_file(&path) {
+                Ok(()) => repairs += 1,
+                Err(e) => error!("check consistency: remove tmpfile {} failed: {}", path, e),
+            }
+        }
+        if thorough {
+            let files: Vec<(String, u64)> = self
+                .meta_engine
+                .file_indexs
+                .iter()
+                .filter(|kv| {
+                    kv.file_attr.kind == FileType::RegularFile
+                        && kv.file_attr.flags & (DATA_REMOTE | INLINE_DATA) == 0
+                })
+                .map(|kv| (kv.key().clone(), kv.file_attr.size))
+                .collect();
+            for (path, size) in files {
+                let local_file_name = generate_local_file_name(&self.root, &path);
+                let actual = match std::fs::metadata(&local_file_name) {
+                    Ok(metadata) => metadata.len(),
+                    Err(e) => {
+                        error!("check consistency: no data for {}: {:?}", path, e);
+                        continue;
+                    }
+                };
+                if actual != size {
+                    info!("repair size of {}: {} -> {}", path, size, actual);
+                    if self.meta_engine.set_size(&path, actual).is_ok() {
+                        repairs += 1;
+                    }
+                }
+                match self.clear_stale_checksums(&local_file_name) {
+                    Ok(0) => {}
+                    Ok(cleared) => {
+                        info!("repair checksums of {}: {} blocks cleared", path, cleared);
+                        repairs += 1;
+                    }
+                    Err(e) => error!("check consistency: checksums of {}: {}", path, e),
+                }
+            }
+
+            let dirs: Vec<String> = self
+                .meta_engine
+                .file_indexs
+                .iter()
+                .filter(|kv| kv.file_attr.kind == FileType::Directory)
+                .map(|kv| kv.key().clone())
+                .collect();
+            for path in dirs {
+                // entries held by other shards can only be counted by asking them
+                if self.meta_engine.is_sharded_directory(&path) {
+                    continue;
+                }
+                let actual = self.meta_engine.count_directory_entries(&path);
+                if self.meta_engine.directory_entry_count(&path) != Ok(actual) {
+                    info!("repair entry count of {}: {}", path, actual);
+                    if self
+                        .meta_engine
+                        .set_directory_entry_count(&path, actual)
+                        .is_ok()
+                    {
+                        repairs += 1;
+                    }
+                }
+            }
+        }
+        repairs += self.meta_engine.recompute_used_size().len();
+        if repairs > 0 {
+            info!("startup consistency check: {} repairs", repairs);
+        }
+        repairs
+    }
+}
+
+fn block_checksum(block: &[u8]) -> u32 {
+    const ZEROS: [u8; CHECKSUM_BLOCK_SIZE as usize] = [0; CHECKSUM_BLOCK_SIZE as usize];
+    crc32c::crc32c_append(
+        crc32c::crc32c(block),
+        &ZEROS[..CHECKSUM_BLOCK_SIZE as usize - block.len()],
+    )
 }
 
 #[inline]
@@ -309,8 +851,9 @@ fn generate_local_file_name(root: &str, path: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use std::{path::Path, sync::Arc};
+    use std::{os::unix::fs::FileExt, path::Path, sync::Arc};
 
+    use crate::common::serialization::{AtimePolicy, INLINE_DATA};
     use crate::server::storage_engine::meta_engine::MetaEngine;
     use fuser::FileType;
     use libc::mode_t;
@@ -361,6 +904,229 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn test_check_consistency() {
+        let root = "/tmp/test_check_consistency";
+        let db_path = "/tmp/test_check_consistency_db";
+        {
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            meta_engine
+                .create_volume("volume", 0, AtimePolicy::default(), None, None)
+                .unwrap();
+            let oflag: i32 = OFlag::O_CREAT.bits() | OFlag::O_RDWR.bits();
+            engine.create_file("volume/a.txt", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/a.txt", &[1u8; 100], 0).unwrap();
+            // the server died after the data was written but before its attr was
+            meta_engine.set_size("volume/a.txt", 10).unwrap();
+            // and with a tmpfile never linked
+            engine.create_file("volume/tmp", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/tmp", &[2u8; 50], 0).unwrap();
+            meta_engine.set_unlinked("volume/tmp", true).unwrap();
+            // and after the data of a block was written but before its checksum was
+            engine.create_file("volume/b.txt", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/b.txt", &[3u8; 8192], 0).unwrap();
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(generate_local_file_name(root, "volume/b.txt"))
+                .unwrap();
+            file.write_at(&[4u8; 10], 5000).unwrap();
+        }
+
+        for thorough in [false, true] {
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            assert_eq!(meta_engine.volumes.get("volume").unwrap().used_size, 0);
+            let repairs = engine.check_consistency(thorough);
+            let size = if thorough { 100 } else { 10 };
+            // the tmpfile is gone after the first pass, the checksum is only cleared
+            // by the thorough one
+            assert_eq!(repairs, if thorough { 3 } else { 2 });
+            match thorough {
+                true => assert_eq!(
+                    engine.read_file("volume/b.txt", 10, 5000).unwrap(),
+                    [4u8; 10]
+                ),
+                false => assert_eq!(engine.read_file("volume/b.txt", 10, 5000), Err(libc::EIO)),
+            }
+            assert_eq!(
+                meta_engine.get_file_attr("volume/tmp").unwrap_err(),
+                libc::ENOENT
+            );
+            assert_eq!(
+                meta_engine.get_file_attr("volume/a.txt").unwrap().size,
+                size
+            );
+            assert_eq!(
+                meta_engine.volumes.get("volume").unwrap().used_size,
+                8192 + size
+            );
+            assert_eq!(engine.check_consistency(thorough), 0);
+        }
+        {
+            // the blocks that matched are still checked
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(generate_local_file_name(root, "volume/b.txt"))
+                .unwrap();
+            file.write_at(&[4u8; 10], 0).unwrap();
+            assert_eq!(engine.read_file("volume/b.txt", 10, 0), Err(libc::EIO));
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_inline_data() {
+        let root = "/tmp/test_inline_data";
+        let db_path = "/tmp/test_inline_data_db";
+        {
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            engine.set_inline_threshold(4096);
+            meta_engine.create_directory("volume", 0o777).unwrap();
+            let oflag: i32 = OFlag::O_CREAT.bits() | OFlag::O_RDWR.bits();
+
+            engine.create_file("volume/small", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/small", &[1u8; 100], 0).unwrap();
+            assert_eq!(
+                meta_engine.inline_data("volume/small").unwrap(),
+                Some(vec![1u8; 100])
+            );
+            let local_file_name = generate_local_file_name(root, "volume/small");
+            assert!(!Path::new(&local_file_name).exists());
+            assert_eq!(
+                engine.read_file("volume/small", 200, 0).unwrap(),
+                vec![1u8; 100]
+            );
+            assert_eq!(engine.read_file("volume/small", 10, 95).unwrap(), [1u8; 5]);
+
+            engine.create_file("volume/big", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/big", &[1u8; 100], 0).unwrap();
+            let data: Vec<u8> = (0..10 * 1024).map(|i| i as u8).collect();
+            engine.write_file("volume/big", &data, 100).unwrap();
+            assert_eq!(meta_engine.inline_data("volume/big").unwrap(), None);
+            let local_file_name = generate_local_file_name(root, "volume/big");
+            assert!(Path::new(&local_file_name).is_file());
+            let attr = meta_engine.get_file_attr("volume/big").unwrap();
+            assert_eq!(attr.size, 100 + 10 * 1024);
+            assert_eq!(attr.flags & INLINE_DATA, 0);
+            let read = engine.read_file("volume/big", 20 * 1024, 0).unwrap();
+            assert_eq!(&read[..100], &[1u8; 100]);
+            assert_eq!(&read[100..], &data[..]);
+        }
+
+        {
+            // inline data outlives a restart
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            engine.set_inline_threshold(4096);
+            assert_eq!(
+                engine.read_file("volume/small", 200, 0).unwrap(),
+                vec![1u8; 100]
+            );
+            assert_eq!(meta_engine.get_file_attr("volume/small").unwrap().size, 100);
+            engine.truncate_file("volume/small", 10).unwrap();
+            assert_eq!(
+                meta_engine.inline_data("volume/small").unwrap(),
+                Some(vec![1u8; 10])
+            );
+            engine.delete_file("volume/small").unwrap();
+            assert_eq!(meta_engine.inline_data("volume/small"), Err(libc::ENOENT));
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_block_checksums() {
+        let root = "/tmp/test_block_checksums";
+        let db_path = "/tmp/test_block_checksums_db";
+        let data: Vec<u8> = (0..3 * 4096 + 100).map(|i| (i % 251) as u8).collect();
+        let local_file_name = generate_local_file_name(root, "volume/file");
+        {
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            meta_engine.create_directory("volume", 0o777).unwrap();
+            let oflag: i32 = OFlag::O_CREAT.bits() | OFlag::O_RDWR.bits();
+            engine.create_file("volume/file", oflag, 0, 0o644).unwrap();
+            engine.write_file("volume/file", &data, 0).unwrap();
+            assert!(engine.read_file("volume/file", 5000, 3000).unwrap() == data[3000..8000]);
+
+            // a bit flipped on disk in the second block
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(&local_file_name)
+                .unwrap();
+            file.write_at(&[data[5000] ^ 1], 5000).unwrap();
+            assert_eq!(engine.read_file("volume/file", 100, 4500), Err(libc::EIO));
+            assert!(engine.read_file("volume/file", 4096, 0).unwrap() == data[..4096]);
+            assert!(engine.read_file("volume/file", 200, 8192).unwrap() == data[8192..8392]);
+            // a write over part of the block keeps the rest, so it is refused
+            assert_eq!(
+                engine.write_file("volume/file", &[0u8; 10], 4100),
+                Err(libc::EIO)
+            );
+            engine
+                .write_file("volume/file", &data[4096..8192], 4096)
+                .unwrap();
+            assert!(engine.read_file("volume/file", 20000, 0).unwrap() == data);
+
+            // the block a truncate ends in, and a file grown past it
+            engine.truncate_file("volume/file", 5000).unwrap();
+            engine
+                .write_file("volume/file", &[1u8; 10], 3 * 4096)
+                .unwrap();
+            let read = engine.read_file("volume/file", 20000, 0).unwrap();
+            assert_eq!(read.len(), 3 * 4096 + 10);
+            assert!(read[..5000] == data[..5000]);
+            assert!(read[5000..3 * 4096] == [0u8; 3 * 4096 - 5000]);
+            assert!(read[3 * 4096..] == [1u8; 10]);
+        }
+
+        {
+            // the checksums outlive a restart
+            let meta_engine = Arc::new(MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024));
+            let engine = FileEngine::new(root, meta_engine.clone());
+            engine.init();
+            let checksum_file_name = format!("{}.crc", local_file_name);
+            assert!(Path::new(&checksum_file_name).is_file());
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(&local_file_name)
+                .unwrap();
+            file.write_at(&[2u8], 10).unwrap();
+            assert_eq!(engine.read_file("volume/file", 10, 0), Err(libc::EIO));
+            engine.delete_file("volume/file").unwrap();
+            assert!(!Path::new(&checksum_file_name).exists());
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
     #[test]
     fn test_create_delete_file() {
         let root = "/tmp/test_create_delete_file";
@@ -1,9 +1,12 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::atomic::{AtomicU32, Ordering},
+    time::SystemTime,
+};
 
 use bytes::BufMut;
 use dashmap::DashMap;
 use fuser::{FileAttr, FileType};
-use libc::{DT_DIR, DT_LNK, DT_REG};
 use log::{debug, error, info};
 #[cfg(feature = "mem-db")]
 use pegasusdb::DB;
@@ -11,14 +14,37 @@ use rocksdb::{BlockBasedOptions, WriteBatch};
 #[cfg(feature = "disk-db")]
 use rocksdb::{Cache, IteratorMode, Options, DB};
 
+use super::dirent_cache::{DirentCache, Dirents};
 use crate::common::{
     errors::{DATABASE_ERROR, SERIALIZATION_ERROR},
-    serialization::{bytes_as_file_attr, file_attr_as_bytes, FileTypeSimple, Volume},
-    util::{empty_dir, path_split},
+    serialization::{
+        bytes_as_file_attr, file_attr_as_bytes, AtimePolicy, FileAttrSimple, FileTypeSimple,
+        ReadDirShardRecvMetaData, Volume, DATA_REMOTE, DIR_SHARDED, INLINE_DATA, UNLINKED,
+    },
+    util::path_split,
+    varint,
 };
 
 const INIT_SUB_FILES_NUM: u32 = 2;
 
+// bytes of packed dirents kept for directories read in one go
+const DIRENT_CACHE_CAPACITY: usize = 16 << 20;
+
+fn entry_type(key: &[u8], path: &str) -> Result<u8, i32> {
+    match FileTypeSimple::try_from(*key.last().unwrap()) {
+        Ok(file_type) => Ok(file_type.to_dirent_type()),
+        Err(e) => {
+            error!(
+                "read directory error: {}, path: {}, key as string: {}",
+                e,
+                path,
+                String::from_utf8(key.to_vec()).unwrap()
+            );
+            Err(SERIALIZATION_ERROR)
+        }
+    }
+}
+
 #[cfg(feature = "disk-db")]
 pub struct Database {
     pub db: DB,
@@ -35,6 +61,39 @@ pub struct FileIndex {
     pub file_attr: FileAttr,
     pub status: u32,
     pub sub_files_num: AtomicU32,
+    // bumped on every change to the attr or the data of the file
+    pub version: u64,
+    // the data of a small file flagged INLINE_DATA, kept here instead of in the store
+    pub inline_data: Option<Vec<u8>>,
+}
+
+// the version is stored after the attr, attrs stored before it existed read as 0
+fn stored_version(value: &[u8]) -> u64 {
+    let start = std::mem::size_of::<FileAttr>();
+    value
+        .get(start..start + 8)
+        .map_or(0, |bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// inline data follows the version
+fn stored_inline_data(value: &[u8], attr: &FileAttr) -> Option<Vec<u8>> {
+    if attr.flags & INLINE_DATA == 0 {
+        return None;
+    }
+    let start = std::mem::size_of::<FileAttr>() + 8;
+    Some(value.get(start..).unwrap_or_default().to_vec())
+}
+
+// a sharded directory also stores its entry count after the version, the entries
+// held by other shards can't be counted from the local store
+fn stored_entry_count(value: &[u8], attr: &FileAttr) -> Option<u32> {
+    if attr.kind != FileType::Directory || attr.flags & DIR_SHARDED == 0 {
+        return None;
+    }
+    let start = std::mem::size_of::<FileAttr>() + 8;
+    value
+        .get(start..start + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
 }
 
 pub struct MetaEngine {
@@ -43,6 +102,9 @@ pub struct MetaEngine {
     pub file_attr_db: Database,
     pub file_indexs: DashMap<String, FileIndex>,
     pub volumes: DashMap<String, Volume>,
+    // servers holding the data of files flagged DATA_REMOTE
+    pub data_owners: DashMap<String, String>,
+    pub dirent_cache: DirentCache,
 }
 
 impl MetaEngine {
@@ -121,10 +183,13 @@ impl MetaEngine {
             file_attr_db,
             file_indexs: DashMap::new(),
             volumes: DashMap::new(),
+            data_owners: DashMap::new(),
+            dirent_cache: DirentCache::new(DIRENT_CACHE_CAPACITY),
         }
     }
 
     pub fn init(&self) {
+        let mut counted = HashSet::new();
         for file_name in self.file_attr_db.db.iterator(IteratorMode::Start) {
             let (k, v) = file_name.unwrap();
             let k = String::from_utf8(k.to_vec()).unwrap();
@@ -139,26 +204,41 @@ impl MetaEngine {
                             file_attr: *attr,
                             status: 0,
                             sub_files_num: AtomicU32::new(0),
+                            version: stored_version(&v),
+                            inline_data: stored_inline_data(&v, attr),
                         },
                     );
                 }
                 FileType::Directory => {
                     // Directory
+                    let entries = stored_entry_count(&v, attr);
+                    if entries.is_some() {
+                        counted.insert(k.clone());
+                    }
                     self.file_indexs.insert(
                         k.clone(),
                         FileIndex {
                             file_attr: *attr,
                             status: 0,
-                            sub_files_num: AtomicU32::new(INIT_SUB_FILES_NUM),
+                            sub_files_num: AtomicU32::new(
+                                INIT_SUB_FILES_NUM + entries.unwrap_or_default(),
+                            ),
+                            version: stored_version(&v),
+                            inline_data: None,
                         },
                     );
                     if !k.contains('/') {
                         self.volumes.insert(
                             k.clone(),
+                            // the quota is not stored, a reloaded volume has none
+                            // until it is resized
                             Volume {
                                 name: k,
-                                size: 10000000,
+                                size: 0,
                                 used_size: 0,
+                                atime_policy: AtimePolicy::default(),
+                                max_file_size: None,
+                                allowed_ops: None,
                             },
                         );
                     }
@@ -171,10 +251,14 @@ impl MetaEngine {
             let sub_dir_info = String::from_utf8(dir_name.unwrap().0.to_vec()).unwrap();
             let list = sub_dir_info.split('$').collect::<Vec<&str>>();
             info!("list: {:?}", list);
-            let file_index = self
-                .file_indexs
-                .get(list.first().unwrap().to_owned())
-                .unwrap();
+            let parent = list.first().unwrap().to_owned();
+            // entries this server holds as a shard of a directory owned elsewhere
+            let Some(file_index) = self.file_indexs.get(parent) else {
+                continue;
+            };
+            if counted.contains(parent) {
+                continue;
+            }
             file_index.sub_files_num.fetch_add(1, Ordering::Relaxed);
             info!(
                 "file_index.sub_files_num: {:}",
@@ -183,6 +267,30 @@ impl MetaEngine {
         }
     }
 
+    // (name, used bytes, files) of every volume with files on this server, directories
+    // count as files
+    pub fn volume_usage(&self) -> Vec<(String, u64, u64)> {
+        let mut usage: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for kv in self.file_indexs.iter() {
+            let volume = kv.key().split('/').next().unwrap();
+            let entry = usage.entry(volume.to_owned()).or_insert((0, 0));
+            if kv.value().file_attr.kind == FileType::RegularFile {
+                entry.0 += kv.value().file_attr.size;
+            }
+            entry.1 += 1;
+        }
+        usage
+            .into_iter()
+            .map(|(name, (used, files))| (name, used, files))
+            .collect()
+    }
+
+    pub fn volume_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.volumes.iter().map(|kv| kv.key().clone()).collect();
+        names.sort();
+        names
+    }
+
     pub fn get_file_map(&self) -> Result<Vec<String>, i32> {
         let mut file_map = Vec::new();
         self.file_attr_db
@@ -209,6 +317,8 @@ impl MetaEngine {
                 file_attr,
                 status: 0,
                 sub_files_num: AtomicU32::new(INIT_SUB_FILES_NUM),
+                version: 0,
+                inline_data: (file_attr.flags & INLINE_DATA != 0).then(Vec::new),
             },
         ) {
             Some(_) => Err(libc::EEXIST),
@@ -223,6 +333,7 @@ impl MetaEngine {
     }
 
     pub fn delete_file(&self, local_file_name: &str, path: &str) -> Result<(), i32> {
+        self.data_owners.remove(path);
         match self.file_indexs.remove(path) {
             Some(_) => match self.file_db.db.delete(local_file_name) {
                 Ok(_) => {
@@ -238,6 +349,44 @@ impl MetaEngine {
         }
     }
 
+    // moves the file at path to new_path, in place of the file there if any. a moved
+    // tmpfile is named by a directory entry from now on
+    pub fn rename_file(
+        &self,
+        local_file_name: &str,
+        new_local_file_name: &str,
+        path: &str,
+        new_path: &str,
+    ) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.flags &= !UNLINKED;
+                self.put_indexed_attr(new_path, &value)?;
+            }
+            None => return Err(libc::ENOENT),
+        }
+        if let Some((_, index)) = self.file_indexs.remove(path) {
+            self.file_indexs.insert(new_path.to_owned(), index);
+        }
+        self.data_owners.remove(new_path);
+        if let Some((_, owner)) = self.data_owners.remove(path) {
+            self.data_owners.insert(new_path.to_owned(), owner);
+        }
+        self.delete_file_attr(path)?;
+        match self
+            .file_db
+            .db
+            .delete(local_file_name)
+            .and_then(|_| self.file_db.db.put(new_local_file_name, new_path))
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("rename file error: {}", e);
+                Err(DATABASE_ERROR)
+            }
+        }
+    }
+
     pub fn is_exist(&self, path: &str) -> Result<bool, i32> {
         match self.file_indexs.get(path) {
             Some(_) => Ok(true),
@@ -246,20 +395,20 @@ impl MetaEngine {
     }
 
     // this function does not need to be thread safe
-    pub fn create_directory(&self, path: &str, _mode: u32) -> Result<Vec<u8>, i32> {
+    pub fn create_directory(&self, path: &str, mode: u32) -> Result<Vec<u8>, i32> {
+        let attr: FileAttr = FileAttrSimple::with_mode(FileTypeSimple::Directory, mode, 0).into();
         match self.file_indexs.insert(
             path.to_owned(),
             FileIndex {
-                file_attr: empty_dir(),
+                file_attr: attr,
                 status: 0,
                 sub_files_num: AtomicU32::new(INIT_SUB_FILES_NUM),
+                version: 0,
+                inline_data: None,
             },
         ) {
             Some(_) => Err(libc::EEXIST),
-            None => {
-                let attr = empty_dir();
-                self.put_file_attr(path, &attr)
-            }
+            None => self.put_file_attr(path, &attr),
         }
     }
 
@@ -272,6 +421,7 @@ impl MetaEngine {
                 } else {
                     drop(value);
                     self.file_indexs.remove(path).unwrap();
+                    self.dirent_cache.invalidate(path);
                     self.delete_file_attr(path)
                 }
             }
@@ -283,6 +433,7 @@ impl MetaEngine {
         if self.file_indexs.remove(path).is_none() {
             return Err(libc::ENOENT);
         }
+        self.dirent_cache.invalidate(path);
 
         // delete sub file index in dir_db with prefix "path_"
         let (start_key, end_key) = (path.to_owned() + "$", path.to_owned() + "$~");
@@ -309,6 +460,13 @@ impl MetaEngine {
             None => return Err(libc::ENOENT),
         }
 
+        if let Some(dirents) = self.dirent_cache.get(path) {
+            return Ok(dirents.read(size, offset));
+        }
+        // a directory read whole from its start is cached
+        let whole = offset == 0;
+        let epoch = self.dirent_cache.epoch();
+        let mut dirents = Dirents::default();
         let mut offset = offset;
 
         // TODO: optimize the situation while offset is not 0
@@ -341,36 +499,257 @@ impl MetaEngine {
                 continue;
             }
             let (key, value) = item.unwrap();
-            let ty = {
-                match (*key.last().unwrap()).try_into() {
-                    Ok(FileTypeSimple::RegularFile) => DT_REG,
-                    Ok(FileTypeSimple::Directory) => DT_DIR,
-                    Ok(FileTypeSimple::Symlink) => DT_LNK,
-                    Ok(_) => DT_REG,
-                    Err(e) => {
-                        error!(
-                            "read directory error: {}, path: {}, key as string: {}",
-                            e,
-                            path,
-                            String::from_utf8(key.to_vec()).unwrap()
-                        );
-                        return Err(SERIALIZATION_ERROR);
-                    }
-                }
-            };
-            let rec_len = value.len() + 3;
+            let ty = entry_type(&key, path)?;
+            let rec_len = 1 + varint::encoded_len(value.len()) + value.len();
             total += rec_len;
             if total > size as usize {
                 break;
             }
+            let start = result.len();
             result.put_u8(ty);
-            result.put((value.len() as u16).to_le_bytes().as_ref());
+            varint::encode_len(value.len(), &mut result);
             result.put(value.as_ref());
+            if whole {
+                dirents.push(&result[start..]);
+            }
             index_num -= 1;
         }
+        if whole && index_num == INIT_SUB_FILES_NUM {
+            self.dirent_cache.insert(path, dirents, epoch);
+        }
+        Ok(result)
+    }
+
+    // the entries after the one named cursor, found by seeking to its key so a page
+    // deep into a large directory costs no more than the first one. entries added or
+    // removed before the cursor move nothing after it
+    pub fn read_directory_after(
+        &self,
+        path: &str,
+        size: u32,
+        cursor: &str,
+    ) -> Result<Vec<u8>, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => {
+                if value.file_attr.kind != FileType::Directory {
+                    return Err(libc::ENOTDIR);
+                }
+            }
+            None => return Err(libc::ENOENT),
+        }
+
+        let prefix = format!("{}$", path);
+        let start = format!("{}{}$", prefix, cursor);
+        let mut result = Vec::with_capacity(size as usize);
+        for item in self.dir_db.db.iterator(IteratorMode::From(
+            start.as_bytes(),
+            rocksdb::Direction::Forward,
+        )) {
+            let (key, value) = item.unwrap();
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            // the cursor entry itself, its key ends with the type
+            if key.len() == start.len() + 1 && key.starts_with(start.as_bytes()) {
+                continue;
+            }
+            let ty = entry_type(&key, path)?;
+            if result.len() + 1 + varint::encoded_len(value.len()) + value.len() > size as usize {
+                break;
+            }
+            result.put_u8(ty);
+            varint::encode_len(value.len(), &mut result);
+            result.put(value.as_ref());
+        }
         Ok(result)
     }
 
+    // the entries of a directory shard held by this server, found by key prefix since
+    // the entry count of a sharded directory lives on its owner
+    pub fn read_directory_shard(
+        &self,
+        path: &str,
+        size: u32,
+        offset: i64,
+    ) -> Result<(Vec<u8>, ReadDirShardRecvMetaData), i32> {
+        let prefix = format!("{}$", path);
+        let mut result = Vec::new();
+        let mut md = ReadDirShardRecvMetaData {
+            total: 0,
+            returned: 0,
+        };
+        let mut full = false;
+        for item in self.dir_db.db.iterator(IteratorMode::From(
+            prefix.as_bytes(),
+            rocksdb::Direction::Forward,
+        )) {
+            let (key, value) = item.unwrap();
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            md.total += 1;
+            if full || (md.total as i64) <= offset {
+                continue;
+            }
+            let ty = entry_type(&key, path)?;
+            if result.len() + 1 + varint::encoded_len(value.len()) + value.len() > size as usize {
+                full = true;
+                continue;
+            }
+            result.put_u8(ty);
+            varint::encode_len(value.len(), &mut result);
+            result.put(value.as_ref());
+            md.returned += 1;
+        }
+        Ok((result, md))
+    }
+
+    pub fn is_sharded_directory(&self, path: &str) -> bool {
+        self.file_indexs
+            .get(path)
+            .is_some_and(|value| value.file_attr.flags & DIR_SHARDED != 0)
+    }
+
+    pub fn shard_directory(&self, path: &str) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.flags |= DIR_SHARDED;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub fn directory_entry_count(&self, path: &str) -> Result<u32, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => Ok(value.sub_files_num.load(Ordering::Relaxed) - INIT_SUB_FILES_NUM),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // entries of the directory held in the local store, counted by key prefix
+    pub fn count_directory_entries(&self, path: &str) -> u32 {
+        let prefix = format!("{}$", path);
+        self.dir_db
+            .db
+            .iterator(IteratorMode::From(
+                prefix.as_bytes(),
+                rocksdb::Direction::Forward,
+            ))
+            .map_while(|item| item.ok())
+            .take_while(|(key, _)| key.starts_with(prefix.as_bytes()))
+            .count() as u32
+    }
+
+    pub fn set_directory_entry_count(&self, path: &str, count: u32) -> Result<(), i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => {
+                self.dirent_cache.invalidate(path);
+                value
+                    .sub_files_num
+                    .store(count + INIT_SUB_FILES_NUM, Ordering::Relaxed);
+            }
+            None => return Err(libc::ENOENT),
+        }
+        self.persist_entry_count(path)
+    }
+
+    // the count of a sharded directory is stored with its attr
+    fn persist_entry_count(&self, path: &str) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(value) if value.file_attr.flags & DIR_SHARDED != 0 => {
+                self.put_indexed_attr(path, &value).map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn has_directory_entry(&self, parent_dir: &str, file_name: &str, file_type: u8) -> bool {
+        matches!(
+            self.dir_db.db.get(format!(
+                "{}${}${}",
+                parent_dir, file_name, file_type as char
+            )),
+            Ok(Some(_))
+        )
+    }
+
+    // the type of the entry named file_name whatever kind of file it is
+    pub fn lookup_directory_entry(&self, parent_dir: &str, file_name: &str) -> Option<u8> {
+        let start = format!("{}${}$", parent_dir, file_name);
+        let (key, _) = self
+            .dir_db
+            .db
+            .iterator(IteratorMode::From(
+                start.as_bytes(),
+                rocksdb::Direction::Forward,
+            ))
+            .next()?
+            .ok()?;
+        match key.len() == start.len() + 1 && key.starts_with(start.as_bytes()) {
+            true => Some(key[start.len()]),
+            false => None,
+        }
+    }
+
+    // an entry of a sharded directory stored on another server, only counted here
+    pub fn count_shard_entry(&self, parent_dir: &str, added: bool) -> Result<(), i32> {
+        match self.file_indexs.get(parent_dir) {
+            Some(value) => {
+                self.dirent_cache.invalidate(parent_dir);
+                if added {
+                    value.sub_files_num.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    value.sub_files_num.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+            None => return Err(libc::ENOENT),
+        }
+        self.persist_entry_count(parent_dir)
+    }
+
+    // the directory itself lives on another server
+    pub fn shard_add_entry(
+        &self,
+        parent_dir: &str,
+        file_name: &str,
+        file_type: u8,
+    ) -> Result<(), i32> {
+        self.dirent_cache.invalidate(parent_dir);
+        self.dir_db
+            .db
+            .put(
+                format!("{}${}${}", parent_dir, file_name, file_type as char),
+                file_name,
+            )
+            .map_err(|e| {
+                error!("shard add entry error: {}", e);
+                DATABASE_ERROR
+            })
+    }
+
+    pub fn shard_delete_entry(
+        &self,
+        parent_dir: &str,
+        file_name: &str,
+        file_type: u8,
+    ) -> Result<(), i32> {
+        if !self.has_directory_entry(parent_dir, file_name, file_type) {
+            return Err(libc::ENOENT);
+        }
+        self.dirent_cache.invalidate(parent_dir);
+        self.dir_db
+            .db
+            .delete(format!(
+                "{}${}${}",
+                parent_dir, file_name, file_type as char
+            ))
+            .map_err(|e| {
+                error!("shard delete entry error: {}", e);
+                DATABASE_ERROR
+            })
+    }
+
     pub fn directory_add_entry(
         &self,
         parent_dir: &str,
@@ -393,13 +772,14 @@ impl MetaEngine {
                     }
                 }
                 value.sub_files_num.fetch_add(1, Ordering::Relaxed);
-                Ok(())
+                self.dirent_cache.invalidate(parent_dir);
             }
             None => {
                 error!("directory add entry error: {}", libc::ENOENT);
-                Err(libc::ENOENT)
+                return Err(libc::ENOENT);
             }
         }
+        self.persist_entry_count(parent_dir)
     }
 
     pub fn directory_delete_entry(
@@ -425,13 +805,58 @@ impl MetaEngine {
                 }
                 //assert!(value.sub_files_num > INIT_SUB_FILES_NUM);
                 value.sub_files_num.fetch_sub(1, Ordering::Relaxed);
-                Ok(())
+                self.dirent_cache.invalidate(parent_dir);
             }
             None => {
                 error!("directory delete entry error: {}", libc::ENOENT);
-                Err(libc::ENOENT)
+                return Err(libc::ENOENT);
+            }
+        }
+        self.persist_entry_count(parent_dir)
+    }
+
+    // moves the entry file_name to new_name in one write, the entry of type replaced
+    // held by new_name is dropped in it
+    pub fn directory_rename_entry(
+        &self,
+        parent_dir: &str,
+        file_name: &str,
+        new_name: &str,
+        file_type: u8,
+        replaced: Option<u8>,
+    ) -> Result<(), i32> {
+        match self.file_indexs.get(parent_dir) {
+            Some(value) => {
+                if value.file_attr.kind != FileType::Directory {
+                    return Err(libc::ENOTDIR);
+                }
+                let mut batch = WriteBatch::default();
+                if let Some(replaced) = replaced {
+                    batch.delete(format!("{}${}${}", parent_dir, new_name, replaced as char));
+                }
+                batch.delete(format!(
+                    "{}${}${}",
+                    parent_dir, file_name, file_type as char
+                ));
+                batch.put(
+                    format!("{}${}${}", parent_dir, new_name, file_type as char),
+                    new_name,
+                );
+                if let Err(e) = self.dir_db.db.write(batch) {
+                    error!("directory rename entry error: {}", e);
+                    return Err(DATABASE_ERROR);
+                }
+                if replaced.is_some() {
+                    value.sub_files_num.fetch_sub(1, Ordering::Relaxed);
+                }
+                self.dirent_cache.invalidate(parent_dir);
+            }
+            None => {
+                error!("directory rename entry error: {}", libc::ENOENT);
+                return Err(libc::ENOENT);
             }
         }
+        self.persist_entry_count(parent_dir)
     }
 
     pub fn delete_from_parent(&self, path: &str, file_type: u8) -> Result<(), i32> {
@@ -447,10 +872,11 @@ impl MetaEngine {
                     return Err(DATABASE_ERROR);
                 }
                 value.sub_files_num.fetch_sub(1, Ordering::Relaxed);
-                Ok(())
+                self.dirent_cache.invalidate(&parent);
             }
-            None => Err(libc::ENOENT),
+            None => return Err(libc::ENOENT),
         }
+        self.persist_entry_count(&parent)
     }
 
     pub fn put_file_attr(&self, path: &str, attr: &FileAttr) -> Result<Vec<u8>, i32> {
@@ -464,6 +890,90 @@ impl MetaEngine {
         }
     }
 
+    // stores the attr of a file with its version and inline data, or the entry count
+    // of a sharded directory, returns the attr
+    fn put_indexed_attr(&self, path: &str, index: &FileIndex) -> Result<Vec<u8>, i32> {
+        let value = file_attr_as_bytes(&index.file_attr).to_vec();
+        let mut stored = value.clone();
+        stored.extend_from_slice(&index.version.to_le_bytes());
+        if let Some(data) = &index.inline_data {
+            stored.extend_from_slice(data);
+        }
+        if index.file_attr.kind == FileType::Directory && index.file_attr.flags & DIR_SHARDED != 0 {
+            let entries = index.sub_files_num.load(Ordering::Relaxed) - INIT_SUB_FILES_NUM;
+            stored.extend_from_slice(&entries.to_le_bytes());
+        }
+        match self.file_attr_db.db.put(path, &stored) {
+            Ok(_) => Ok(value),
+            Err(e) => {
+                error!("put_file_attr error: {}", e);
+                Err(DATABASE_ERROR)
+            }
+        }
+    }
+
+    // a change to the file bumps its version
+    fn put_changed_attr(&self, path: &str, index: &mut FileIndex) -> Result<Vec<u8>, i32> {
+        index.version += 1;
+        self.put_indexed_attr(path, index)
+    }
+
+    pub fn version(&self, path: &str) -> Result<u64, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => Ok(value.version),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub fn inline_data(&self, path: &str) -> Result<Option<Vec<u8>>, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => Ok(value.inline_data.clone()),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // writes into the inline data of a file, growing it and its size as needed
+    pub fn write_inline(&self, path: &str, data: &[u8], offset: usize) -> Result<usize, i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                let inline_data = value.inline_data.as_mut().ok_or(libc::EINVAL)?;
+                let end = offset + data.len();
+                if inline_data.len() < end {
+                    inline_data.resize(end, 0);
+                }
+                inline_data[offset..end].copy_from_slice(data);
+                value.file_attr.size = inline_data.len() as u64;
+                self.put_changed_attr(path, &mut value)?;
+                Ok(data.len())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub fn truncate_inline(&self, path: &str, length: usize) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                let inline_data = value.inline_data.as_mut().ok_or(libc::EINVAL)?;
+                inline_data.resize(length, 0);
+                value.file_attr.size = length as u64;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // the inline data was written to the store, the file is served from there from now on
+    pub fn clear_inline(&self, path: &str) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.inline_data = None;
+                value.file_attr.flags &= !INLINE_DATA;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
     pub fn update_size(&self, path: &str, size: u64) -> Result<(), i32> {
         match self.file_indexs.get_mut(path) {
             Some(mut value) => {
@@ -471,7 +981,7 @@ impl MetaEngine {
                     return Ok(());
                 }
                 value.file_attr.size = size;
-                match self.put_file_attr(path, &value.file_attr) {
+                match self.put_changed_attr(path, &mut value) {
                     Ok(_) => Ok(()),
                     Err(e) => Err(e),
                 }
@@ -480,6 +990,210 @@ impl MetaEngine {
         }
     }
 
+    // sets the size whether it grows or shrinks, for repairs
+    pub fn set_size(&self, path: &str, size: u64) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.size = size;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub fn set_blocks(&self, path: &str, blocks: u64) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.blocks = blocks;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // rebuilds the used size of every volume from the attrs of its files here,
+    // returns the volumes whose used size changed
+    pub fn recompute_used_size(&self) -> Vec<String> {
+        let usage: BTreeMap<String, u64> = self
+            .volume_usage()
+            .into_iter()
+            .map(|(name, used, _)| (name, used))
+            .collect();
+        let mut repaired = Vec::new();
+        for mut volume in self.volumes.iter_mut() {
+            let used_size = usage.get(&volume.name).copied().unwrap_or(0);
+            if volume.used_size != used_size {
+                info!(
+                    "repair used size of volume {}: {} -> {}",
+                    volume.name, volume.used_size, used_size
+                );
+                volume.used_size = used_size;
+                repaired.push(volume.name.clone());
+            }
+        }
+        repaired
+    }
+
+    pub fn touch_atime(
+        &self,
+        path: &str,
+        atime_policy: AtimePolicy,
+        now: SystemTime,
+    ) -> Result<(), i32> {
+        // most reads leave the atime as it is, they only take the shared lock
+        let should_update =
+            |attr: &FileAttr| atime_policy.should_update(attr.atime, attr.mtime, attr.ctime, now);
+        match self.file_indexs.get(path) {
+            Some(value) if !should_update(&value.file_attr) => return Ok(()),
+            Some(_) => {}
+            None => return Err(libc::ENOENT),
+        }
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                if !should_update(&value.file_attr) {
+                    return Ok(());
+                }
+                value.file_attr.atime = now;
+                self.put_indexed_attr(path, &value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // a write changes both the data and the inode
+    pub fn touch_mtime(&self, path: &str, now: SystemTime) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.mtime = now;
+                value.file_attr.ctime = now;
+                self.put_changed_attr(path, &mut value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // flags a file as a tmpfile not named by any directory entry, returns the new attr
+    pub fn set_unlinked(&self, path: &str, unlinked: bool) -> Result<Vec<u8>, i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                if unlinked {
+                    value.file_attr.flags |= UNLINKED;
+                } else {
+                    value.file_attr.flags &= !UNLINKED;
+                }
+                self.put_changed_attr(path, &mut value)
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // gives a file moved here the type, owner, permissions, times and version it had,
+    // what it holds stays as written here
+    pub fn set_moved_attr(&self, path: &str, attr: FileAttrSimple) -> Result<Vec<u8>, i32> {
+        let version = attr.version;
+        let moved: FileAttr = attr.into();
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                let attr = &mut value.file_attr;
+                attr.kind = moved.kind;
+                attr.perm = moved.perm;
+                attr.uid = moved.uid;
+                attr.gid = moved.gid;
+                attr.rdev = moved.rdev;
+                attr.atime = moved.atime;
+                attr.mtime = moved.mtime;
+                attr.ctime = moved.ctime;
+                attr.crtime = moved.crtime;
+                value.version = version;
+                self.put_indexed_attr(path, &value)
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // turns a file just created with its target as data into a symlink, returns the new attr
+    pub fn set_symlink(&self, path: &str) -> Result<Vec<u8>, i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                value.file_attr.kind = FileType::Symlink;
+                value.file_attr.perm = 0o777;
+                self.put_changed_attr(path, &mut value)
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // changes the fields given, every change to the attr bumps the ctime
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_attr(
+        &self,
+        path: &str,
+        perm: Option<u16>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+        now: SystemTime,
+    ) -> Result<Vec<u8>, i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                let attr = &mut value.file_attr;
+                if let Some(perm) = perm {
+                    attr.perm = perm & 0o7777;
+                }
+                if let Some(uid) = uid {
+                    attr.uid = uid;
+                }
+                if let Some(gid) = gid {
+                    attr.gid = gid;
+                }
+                if let Some(atime) = atime {
+                    attr.atime = atime;
+                }
+                if let Some(mtime) = mtime {
+                    attr.mtime = mtime;
+                }
+                attr.ctime = now;
+                self.put_changed_attr(path, &mut value)
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // marks the data of the file as held by owner, None brings it back here
+    pub fn set_data_owner(&self, path: &str, owner: Option<&str>) -> Result<(), i32> {
+        match self.file_indexs.get_mut(path) {
+            Some(mut value) => {
+                match owner {
+                    Some(owner) => {
+                        value.file_attr.flags |= DATA_REMOTE;
+                        self.data_owners.insert(path.to_owned(), owner.to_owned());
+                    }
+                    None => {
+                        value.file_attr.flags &= !DATA_REMOTE;
+                        self.data_owners.remove(path);
+                    }
+                }
+                self.put_indexed_attr(path, &value).map(|_| ())
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    // the owner is only known in memory, a flagged file whose owner was lost
+    // answers EREMOTE rather than serving the local copy
+    pub fn data_owner(&self, path: &str) -> Result<Option<String>, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) if value.file_attr.flags & DATA_REMOTE != 0 => {
+                match self.data_owners.get(path) {
+                    Some(owner) => Ok(Some(owner.clone())),
+                    None => Err(libc::EREMOTE),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
     pub fn get_file_attr(&self, path: &str) -> Result<FileAttr, i32> {
         match self.file_indexs.get(path) {
             Some(value) => Ok(value.file_attr),
@@ -494,9 +1208,30 @@ impl MetaEngine {
         }
     }
 
+    // the attr followed by the version of the file, as GetFileAttr answers
+    pub fn get_file_attr_versioned(&self, path: &str) -> Result<Vec<u8>, i32> {
+        match self.file_indexs.get(path) {
+            Some(value) => {
+                let mut attr = file_attr_as_bytes(&value.file_attr).to_vec();
+                attr.extend_from_slice(&value.version.to_le_bytes());
+                Ok(attr)
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
     pub fn complete_transfer_file(&self, path: &str, file_attr: &FileAttr) -> Result<(), i32> {
-        let value = file_attr_as_bytes(file_attr);
-        match self.file_attr_db.db.put(path, value) {
+        // whether the data is inline is up to this server, not the one it came from
+        let mut attr = *file_attr;
+        attr.flags &= !INLINE_DATA;
+        if let Some(mut index) = self.file_indexs.get_mut(path) {
+            if index.inline_data.is_some() {
+                attr.flags |= INLINE_DATA;
+            }
+            index.file_attr = attr;
+            return self.put_indexed_attr(path, &index).map(|_| ());
+        }
+        match self.file_attr_db.db.put(path, file_attr_as_bytes(&attr)) {
             Ok(_) => Ok(()),
             Err(e) => {
                 error!("complete_transfer_file error: {}", e);
@@ -515,7 +1250,14 @@ impl MetaEngine {
         }
     }
 
-    pub fn create_volume(&self, name: &str) -> Result<(), i32> {
+    pub fn create_volume(
+        &self,
+        name: &str,
+        size: u64,
+        atime_policy: AtimePolicy,
+        max_file_size: Option<u64>,
+        allowed_ops: Option<Vec<u32>>,
+    ) -> Result<(), i32> {
         if self.volumes.contains_key(name) {
             return Err(libc::EEXIST);
         }
@@ -523,8 +1265,11 @@ impl MetaEngine {
             name.to_owned(),
             Volume {
                 name: name.to_owned(),
-                size: 100000000,
+                size,
                 used_size: 0,
+                atime_policy,
+                max_file_size,
+                allowed_ops,
             },
         );
         match self.create_directory(name, 0o755) {
@@ -533,6 +1278,27 @@ impl MetaEngine {
         }
     }
 
+    // adds delta to the used size of a volume held here, growing it past its size
+    // fails with ENOSPC while giving bytes back always succeeds
+    pub fn charge_volume(&self, name: &str, delta: i64) -> Result<(), i32> {
+        let mut volume = self.volumes.get_mut(name).ok_or(libc::ENOENT)?;
+        let used_size = volume.used_size.saturating_add_signed(delta);
+        if delta > 0 && volume.size != 0 && used_size > volume.size {
+            return Err(libc::ENOSPC);
+        }
+        volume.used_size = used_size;
+        Ok(())
+    }
+
+    pub fn resize_volume(&self, name: &str, new_size: u64) -> Result<(), i32> {
+        let mut volume = self.volumes.get_mut(name).ok_or(libc::ENOENT)?;
+        if new_size != 0 && new_size < volume.used_size {
+            return Err(libc::ENOSPC);
+        }
+        volume.size = new_size;
+        Ok(())
+    }
+
     pub fn list_volumes(&self) -> Result<Vec<u8>, i32> {
         let mut volumes = Vec::new();
         for kv in self.volumes.iter() {
@@ -611,10 +1377,11 @@ impl MetaEngine {
 #[cfg(test)]
 mod tests {
 
-    use std::sync::atomic::Ordering;
+    use std::{collections::BTreeSet, sync::atomic::Ordering};
 
     use libc::mode_t;
 
+    use crate::common::dirent::DirentDecoder;
     use crate::server::storage_engine::meta_engine::{MetaEngine, INIT_SUB_FILES_NUM};
 
     #[test]
@@ -713,4 +1480,119 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    fn test_read_directory_cache() {
+        let db_path = "/tmp/test_dirent_cache_db";
+        {
+            let engine = MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024);
+            engine.init();
+            engine.create_directory("test1", 0o777).unwrap();
+            engine.directory_add_entry("test1", "a", 3).unwrap();
+            engine.directory_add_entry("test1", "b", 3).unwrap();
+
+            let first = engine.read_directory("test1", 4096, 0).unwrap();
+            assert_eq!(engine.dirent_cache.misses(), 1);
+            assert_eq!(engine.read_directory("test1", 4096, 0).unwrap(), first);
+            assert_eq!(engine.dirent_cache.misses(), 1);
+            // later pages come from the cache as well
+            assert!(engine.read_directory("test1", 4096, 2).unwrap().is_empty());
+            assert_eq!(engine.dirent_cache.misses(), 1);
+
+            engine.directory_add_entry("test1", "c", 3).unwrap();
+            let second = engine.read_directory("test1", 4096, 0).unwrap();
+            assert_eq!(engine.dirent_cache.misses(), 2);
+            assert!(second.len() > first.len());
+            assert!(second.ends_with(b"c"));
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_read_directory_after() {
+        let db_path = "/tmp/test_read_directory_after_db";
+        {
+            let engine = MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024);
+            engine.init();
+            engine.create_directory("test1", 0o777).unwrap();
+            for i in 0..10000 {
+                engine
+                    .directory_add_entry("test1", &format!("entry_{}", i), 3)
+                    .unwrap();
+            }
+
+            let mut listed = BTreeSet::new();
+            let mut page = engine.read_directory("test1", 4096, 0).unwrap();
+            let mut pages = 0;
+            while !page.is_empty() {
+                pages += 1;
+                let mut cursor = String::new();
+                for entry in DirentDecoder::new(page.as_slice()) {
+                    let (_, name) = entry.unwrap();
+                    assert!(listed.insert(name.clone()), "{} listed twice", name);
+                    cursor = name;
+                }
+                // changes before the cursor move nothing after it
+                if pages == 2 {
+                    engine
+                        .directory_delete_entry("test1", "entry_0", 3)
+                        .unwrap();
+                    engine.directory_add_entry("test1", "a_new", 3).unwrap();
+                }
+                page = engine.read_directory_after("test1", 4096, &cursor).unwrap();
+            }
+            assert!(pages > 2);
+            assert_eq!(listed.len(), 10000);
+            assert!((0..10000).all(|i| listed.contains(&format!("entry_{}", i))));
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_sharded_directory_restart() {
+        let db_path = "/tmp/test_sharded_directory_restart_db";
+        {
+            let engine = MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024);
+            engine.init();
+            engine.create_directory("test1", 0o777).unwrap();
+            engine.directory_add_entry("test1", "local", 3).unwrap();
+            engine.shard_directory("test1").unwrap();
+            engine.count_shard_entry("test1", true).unwrap();
+            engine.count_shard_entry("test1", true).unwrap();
+            // an entry held as a shard of a directory owned by another server
+            engine.shard_add_entry("remote_dir", "file", 3).unwrap();
+        }
+        {
+            let engine = MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024);
+            engine.init();
+            assert_eq!(engine.directory_entry_count("test1"), Ok(3));
+            assert!(engine.is_sharded_directory("test1"));
+            assert_eq!(engine.delete_directory("test1"), Err(libc::ENOTEMPTY));
+            engine.count_shard_entry("test1", false).unwrap();
+        }
+        {
+            let engine = MetaEngine::new(db_path, 128 << 20, 128 * 1024 * 1024);
+            engine.init();
+            assert_eq!(engine.directory_entry_count("test1"), Ok(2));
+        }
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_dir", db_path)).unwrap();
+        rocksdb::DB::destroy(&rocksdb::Options::default(), format!("{}_file", db_path)).unwrap();
+        rocksdb::DB::destroy(
+            &rocksdb::Options::default(),
+            format!("{}_file_attr", db_path),
+        )
+        .unwrap();
+    }
 }
@@ -7,6 +7,7 @@ use std::sync::Arc;
 use self::meta_engine::MetaEngine;
 
 pub mod block_engine;
+mod dirent_cache;
 pub mod file_engine;
 pub mod meta_engine;
 
@@ -26,4 +27,27 @@ pub trait StorageEngine {
     fn delete_file(&self, path: &str) -> Result<(), i32>;
 
     fn truncate_file(&self, path: &str, length: i64) -> Result<(), i32>;
+
+    // fallocate(2) on the data of path, returns the 512 byte blocks it takes after
+    fn fallocate(&self, _path: &str, _mode: i32, _offset: i64, _length: i64) -> Result<u64, i32> {
+        Err(libc::EOPNOTSUPP)
+    }
+
+    // moves the file at path to new_path, replacing the file there if any
+    fn rename_file(&self, _path: &str, _new_path: &str) -> Result<(), i32> {
+        Err(libc::EOPNOTSUPP)
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn sync_file(&self, _path: &str) -> Result<(), i32> {
+        Ok(())
+    }
+
+    // flush everything written to the store so far
+    fn sync_all(&self) -> Result<(), i32> {
+        Ok(())
+    }
 }
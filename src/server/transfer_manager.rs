@@ -2,11 +2,16 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use dashmap::DashMap;
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use crate::common::serialization::TransferProgress;
+
 pub struct LockPool {
     locks: HashMap<String, RwLock<()>>,
 }
@@ -14,6 +19,8 @@ pub struct LockPool {
 pub struct TransferManager {
     transferring_locks: *const LockPool,
     transferring_status: DashMap<String, bool>,
+    total_bytes: AtomicU64,
+    remaining_bytes: AtomicU64,
 }
 
 unsafe impl std::marker::Sync for TransferManager {}
@@ -32,6 +39,8 @@ impl TransferManager {
                 locks: HashMap::new(),
             })),
             transferring_status: DashMap::new(),
+            total_bytes: AtomicU64::new(0),
+            remaining_bytes: AtomicU64::new(0),
         }
     }
 
@@ -68,4 +77,25 @@ impl TransferManager {
     pub fn set_status(&self, path: &str, status: bool) {
         self.transferring_status.insert(path.to_string(), status);
     }
+
+    pub fn start_progress(&self, total_bytes: u64) {
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+        self.remaining_bytes.store(total_bytes, Ordering::Relaxed);
+    }
+
+    pub fn advance_progress(&self, bytes: u64) {
+        let _ =
+            self.remaining_bytes
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                    Some(remaining.saturating_sub(bytes))
+                });
+    }
+
+    pub fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            remaining_bytes: self.remaining_bytes.load(Ordering::Relaxed),
+            bytes_per_sec: 0,
+        }
+    }
 }
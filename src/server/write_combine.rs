@@ -0,0 +1,209 @@
+// Copyright 2022 labring. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// small sequential writes to a file are held back and handed to the store as one
+// write once the buffer fills, the flush timer fires or the file is synced, read
+// or changed in any other way
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+
+struct Pending {
+    offset: i64,
+    data: Vec<u8>,
+}
+
+impl Pending {
+    fn end(&self) -> i64 {
+        self.offset + self.data.len() as i64
+    }
+}
+
+pub struct WriteCombiner {
+    // bytes held per file before they are written, 0 disables combining
+    capacity: AtomicUsize,
+    pending: DashMap<String, Pending>,
+    store_writes: AtomicU64,
+}
+
+impl Default for WriteCombiner {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl WriteCombiner {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: AtomicUsize::new(capacity),
+            pending: DashMap::new(),
+            store_writes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_len(&self, path: &str) -> usize {
+        self.pending
+            .get(path)
+            .map_or(0, |pending| pending.data.len())
+    }
+
+    // where the buffered bytes of the file end
+    pub fn pending_end(&self, path: &str) -> Option<i64> {
+        self.pending.get(path).map(|pending| pending.end())
+    }
+
+    pub fn store_writes(&self) -> u64 {
+        self.store_writes.load(Ordering::Relaxed)
+    }
+
+    // returns the bytes accepted, which are either written or buffered; a write that
+    // does not continue the buffered one writes the buffer out first
+    pub fn write<F>(&self, path: &str, data: &[u8], offset: i64, store: F) -> Result<usize, i32>
+    where
+        F: Fn(&[u8], i64) -> Result<usize, i32>,
+    {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 || data.len() >= capacity {
+            self.flush(path, &store)?;
+            self.store_writes.fetch_add(1, Ordering::Relaxed);
+            return store(data, offset);
+        }
+
+        let mut pending = self.pending.entry(path.to_owned()).or_insert(Pending {
+            offset,
+            data: Vec::with_capacity(capacity),
+        });
+        if pending.end() != offset || pending.data.len() + data.len() > capacity {
+            self.write_pending(&pending, &store)?;
+            pending.offset = offset;
+            pending.data.clear();
+        }
+        pending.data.extend_from_slice(data);
+        if pending.data.len() == capacity {
+            self.write_pending(&pending, &store)?;
+            pending.data.clear();
+            drop(pending);
+            self.pending
+                .remove_if(path, |_, pending| pending.data.is_empty());
+        }
+        Ok(data.len())
+    }
+
+    // writes out what is buffered for the file, on failure it stays buffered
+    pub fn flush<F>(&self, path: &str, store: F) -> Result<(), i32>
+    where
+        F: Fn(&[u8], i64) -> Result<usize, i32>,
+    {
+        if let Some(mut pending) = self.pending.get_mut(path) {
+            self.write_pending(&pending, &store)?;
+            pending.data.clear();
+        }
+        self.pending
+            .remove_if(path, |_, pending| pending.data.is_empty());
+        Ok(())
+    }
+
+    pub fn flush_all<F>(&self, store: F) -> Result<(), i32>
+    where
+        F: Fn(&str, &[u8], i64) -> Result<usize, i32>,
+    {
+        let paths: Vec<String> = self.pending.iter().map(|kv| kv.key().clone()).collect();
+        for path in paths {
+            self.flush(&path, |data, offset| store(&path, data, offset))?;
+        }
+        Ok(())
+    }
+
+    // drops what is buffered for a file being deleted
+    pub fn discard(&self, path: &str) {
+        self.pending.remove(path);
+    }
+
+    fn write_pending<F>(&self, pending: &Pending, store: &F) -> Result<(), i32>
+    where
+        F: Fn(&[u8], i64) -> Result<usize, i32>,
+    {
+        let mut written = 0;
+        while written < pending.data.len() {
+            self.store_writes.fetch_add(1, Ordering::Relaxed);
+            match store(&pending.data[written..], pending.offset + written as i64)? {
+                0 => return Err(libc::EIO),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::WriteCombiner;
+
+    fn store(file: &RefCell<Vec<u8>>) -> impl Fn(&[u8], i64) -> Result<usize, i32> + '_ {
+        |data, offset| {
+            let mut file = file.borrow_mut();
+            let end = offset as usize + data.len();
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[offset as usize..end].copy_from_slice(data);
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn test_sequential_appends() {
+        let combiner = WriteCombiner::new(256);
+        let file = RefCell::new(Vec::new());
+        let mut expected = Vec::new();
+        for i in 0..100u8 {
+            let data = [i; 10];
+            let offset = expected.len() as i64;
+            assert_eq!(
+                combiner.write("volume/log", &data, offset, store(&file)),
+                Ok(10)
+            );
+            expected.extend_from_slice(&data);
+        }
+        combiner.flush("volume/log", store(&file)).unwrap();
+        assert_eq!(*file.borrow(), expected);
+        assert_eq!(combiner.store_writes(), 4);
+        assert_eq!(combiner.pending_len("volume/log"), 0);
+    }
+
+    #[test]
+    fn test_non_sequential_write() {
+        let combiner = WriteCombiner::new(256);
+        let file = RefCell::new(Vec::new());
+        combiner
+            .write("volume/a", b"hello", 0, store(&file))
+            .unwrap();
+        assert!(file.borrow().is_empty());
+        // a write elsewhere in the file puts the buffered one in the store first
+        combiner
+            .write("volume/a", b"world", 10, store(&file))
+            .unwrap();
+        assert_eq!(&file.borrow()[..5], b"hello");
+        assert_eq!(combiner.pending_len("volume/a"), 5);
+
+        assert_eq!(
+            combiner.flush("volume/a", |_, _| Err(libc::EIO)),
+            Err(libc::EIO)
+        );
+        assert_eq!(combiner.pending_len("volume/a"), 5);
+        combiner.flush("volume/a", store(&file)).unwrap();
+        assert_eq!(&file.borrow()[10..], b"world");
+    }
+}